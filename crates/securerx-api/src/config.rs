@@ -0,0 +1,135 @@
+use ed25519_dalek::SigningKey;
+use securerx_core::crypto::signing_key_from_hex;
+use securerx_core::genesis::GenesisSpec;
+use securerx_core::mempool::{DEFAULT_BLOCK_INTERVAL, DEFAULT_MAX_BLOCK_TXS, DEFAULT_MEMPOOL_SIZE};
+use securerx_core::storage::StorageBackend;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// API configuration loaded from environment variables
+#[derive(Clone)]
+pub struct ApiConfig {
+    /// Enables the legacy `POST /prescription` endpoint, which signs with
+    /// server-held keys. Never enable outside local development.
+    pub dev_mode: bool,
+    /// Genesis spec loaded from `GENESIS_FILE`. When unset, the development
+    /// genesis with `ADMIN_KEYS` as its authorities is used.
+    pub genesis: GenesisSpec,
+    /// Directory the chain is persisted to (`DATA_DIR`); kept in memory when unset
+    pub data_dir: Option<PathBuf>,
+    /// Block store used under `data_dir` (`STORAGE_BACKEND`: `file` or `kv`)
+    pub storage_backend: StorageBackend,
+    /// Key this node seals blocks with (`VALIDATOR_KEY`, hex secret key); must be a genesis authority
+    pub validator_key: Option<SigningKey>,
+    /// Raft cluster nodes (`RAFT_NODES`, comma-separated `host:port`). When set, writes are
+    /// forwarded to the cluster's leader instead of sealed here. Ignored in dev mode.
+    pub raft_nodes: Vec<String>,
+    /// Most prescriptions waiting for a block (`MEMPOOL_SIZE`)
+    pub mempool_size: usize,
+    /// Most prescriptions sealed into one block (`MAX_BLOCK_TXS`)
+    pub max_block_txs: usize,
+    /// Interval between blocks of pending prescriptions (`BLOCK_INTERVAL_MS`)
+    pub block_interval: Duration,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            dev_mode: false,
+            genesis: GenesisSpec::default(),
+            data_dir: None,
+            storage_backend: StorageBackend::default(),
+            validator_key: None,
+            raft_nodes: vec![],
+            mempool_size: DEFAULT_MEMPOOL_SIZE,
+            max_block_txs: DEFAULT_MAX_BLOCK_TXS,
+            block_interval: DEFAULT_BLOCK_INTERVAL,
+        }
+    }
+}
+
+impl ApiConfig {
+    pub fn from_env() -> Self {
+        let dev_mode = matches!(
+            std::env::var("SECURERX_DEV_MODE").unwrap_or_default().as_str(),
+            "1" | "true" | "yes"
+        );
+        let genesis = match std::env::var("GENESIS_FILE") {
+            Ok(path) => GenesisSpec::load(&path).unwrap_or_else(|e| panic!("{}: {}", path, e)),
+            Err(_) => GenesisSpec::with_authorities(
+                parse_keys(&std::env::var("ADMIN_KEYS").unwrap_or_default())
+                    .expect("ADMIN_KEYS must be a comma-separated list of hex Ed25519 public keys"),
+            ),
+        };
+        let data_dir = std::env::var_os("DATA_DIR").map(PathBuf::from);
+        let storage_backend = std::env::var("STORAGE_BACKEND")
+            .map(|backend| backend.parse().expect("STORAGE_BACKEND must be 'file' or 'kv'"))
+            .unwrap_or_default();
+        let validator_key = std::env::var("VALIDATOR_KEY").ok().map(|key| {
+            signing_key_from_hex(&key).expect("VALIDATOR_KEY must be a hex-encoded 32-byte Ed25519 secret key")
+        });
+        let raft_nodes = std::env::var("RAFT_NODES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        let mempool_size = number_var("MEMPOOL_SIZE", DEFAULT_MEMPOOL_SIZE as u64) as usize;
+        let max_block_txs = number_var("MAX_BLOCK_TXS", DEFAULT_MAX_BLOCK_TXS as u64) as usize;
+        let block_interval = Duration::from_millis(number_var("BLOCK_INTERVAL_MS", DEFAULT_BLOCK_INTERVAL.as_millis() as u64));
+        Self {
+            dev_mode,
+            genesis,
+            data_dir,
+            storage_backend,
+            validator_key,
+            raft_nodes,
+            mempool_size,
+            max_block_txs,
+            block_interval,
+        }
+    }
+}
+
+/// Positive integer environment variable `name`, or `default` when unset
+fn number_var(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(value) => value.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| panic!("{} must be a positive integer", name)),
+        Err(_) => default,
+    }
+}
+
+/// Parse a comma-separated list of hex-encoded 32-byte public keys
+pub fn parse_keys(spec: &str) -> Result<Vec<Vec<u8>>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|key_hex| {
+            let key = hex::decode(key_hex).map_err(|e| format!("bad key '{}': {}", key_hex, e))?;
+            if key.len() != 32 {
+                return Err(format!("key '{}' must be 32 bytes, got {}", key_hex, key.len()));
+            }
+            Ok(key)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        let spec = format!("{}, {}", "11".repeat(32), "22".repeat(32));
+        let keys = parse_keys(&spec).unwrap();
+        assert_eq!(keys, vec![vec![0x11; 32], vec![0x22; 32]]);
+    }
+
+    #[test]
+    fn test_parse_keys_rejects_bad_entries() {
+        assert!(parse_keys("zz").is_err());
+        assert!(parse_keys("abcd").is_err());
+        assert!(parse_keys("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+use ed25519_dalek::SigningKey;
+use securerx_core::blockchain::{Blockchain, ChainError};
+use securerx_core::crypto::{generate_keypair, random_nonce};
+use securerx_core::registry::{RegistryOp, RegistryTransaction};
+use std::collections::HashMap;
+
+/// Server-held keys backing the dev-mode `POST /prescription` endpoint.
+///
+/// Holds a registry admin key and one signing key per doctor, registering each
+/// doctor on chain the first time it is used so dev chains still validate. The
+/// admin key is also the dev chain's only validator.
+pub struct DevSigner {
+    admin: SigningKey,
+    doctors: HashMap<String, SigningKey>,
+}
+
+impl DevSigner {
+    pub fn new() -> Self {
+        Self { admin: generate_keypair(), doctors: HashMap::new() }
+    }
+
+    /// Public key to add to the chain's registry admins
+    pub fn admin_pubkey(&self) -> Vec<u8> {
+        self.admin.verifying_key().to_bytes().to_vec()
+    }
+
+    /// Key the dev chain's blocks are sealed with
+    pub fn validator_key(&self) -> SigningKey {
+        self.admin.clone()
+    }
+
+    /// Signing key for `doctor_id`, registered on `blockchain` if it is new
+    pub fn doctor_key(&mut self, doctor_id: &str, blockchain: &mut Blockchain) -> Result<SigningKey, ChainError> {
+        if let Some(key) = self.doctors.get(doctor_id) {
+            return Ok(key.clone());
+        }
+
+        let key = generate_keypair();
+        let mut reg_tx = RegistryTransaction {
+            chain_id: blockchain.genesis().chain_id.clone(),
+            op: RegistryOp::RegisterDoctor {
+                doctor_id: doctor_id.to_string(),
+                license_number: format!("DEV-{}", doctor_id),
+                pubkey: key.verifying_key().to_bytes().to_vec(),
+            },
+            nonce: random_nonce(),
+            admin_pubkey: vec![],
+            signature: vec![],
+        };
+        reg_tx.sign(&self.admin);
+        blockchain.add_registry_block(vec![reg_tx])?;
+
+        self.doctors.insert(doctor_id.to_string(), key.clone());
+        Ok(key)
+    }
+}
+
+impl Default for DevSigner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,89 @@
+use axum::{Json, http::StatusCode, response::{IntoResponse, Response}};
+use serde::Serialize;
+use securerx_core::blockchain::ChainError;
+use securerx_core::registry::RegistryError;
+use securerx_core::storage::StorageError;
+use securerx_core::validation::ValidationError;
+
+/// Structured error body returned by API endpoints: `{"error": "<code>", "message": "<detail>"}`
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: StatusCode,
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, error: &'static str, message: impl Into<String>) -> Self {
+        Self { status, error, message: message.into() }
+    }
+
+    pub fn bad_request(error: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, error, message)
+    }
+
+    pub fn unauthorized(error: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, error, message)
+    }
+
+    pub fn forbidden(error: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, error, message)
+    }
+}
+
+impl From<RegistryError> for ApiError {
+    fn from(err: RegistryError) -> Self {
+        let message = err.to_string();
+        match err {
+            RegistryError::UnknownAdmin => Self::forbidden("unknown_admin", message),
+            RegistryError::WrongChain(_) => Self::bad_request("wrong_chain", message),
+            RegistryError::InvalidSignature => Self::unauthorized("invalid_signature", message),
+            RegistryError::Replayed => Self::new(StatusCode::CONFLICT, "replayed", message),
+            RegistryError::DoctorExists(_) => Self::new(StatusCode::CONFLICT, "doctor_exists", message),
+            RegistryError::UnknownDoctor(_) => Self::new(StatusCode::NOT_FOUND, "unknown_doctor", message),
+            RegistryError::KeyInUse => Self::new(StatusCode::CONFLICT, "key_in_use", message),
+            RegistryError::MalformedKey => Self::bad_request("malformed_key", message),
+            RegistryError::UnknownKey => Self::new(StatusCode::NOT_FOUND, "unknown_key", message),
+            RegistryError::KeyRevoked => Self::new(StatusCode::CONFLICT, "key_revoked", message),
+            RegistryError::RetroactiveRevocation { .. } => Self::bad_request("retroactive_revocation", message),
+        }
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(err: StorageError) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_error", err.to_string())
+    }
+}
+
+impl From<ValidationError> for ApiError {
+    fn from(err: ValidationError) -> Self {
+        Self::new(StatusCode::CONFLICT, err.code(), err.to_string())
+    }
+}
+
+impl From<ChainError> for ApiError {
+    fn from(err: ChainError) -> Self {
+        match err {
+            ChainError::Registry(e) => e.into(),
+            ChainError::Storage(e) => e.into(),
+            ChainError::Invalid(e) => e.into(),
+            ChainError::NotProposer { .. } => {
+                Self::new(StatusCode::SERVICE_UNAVAILABLE, "not_proposer", err.to_string())
+            }
+            ChainError::RevertsFinalized { .. } => {
+                Self::new(StatusCode::CONFLICT, "reverts_finalized", err.to_string())
+            }
+            ChainError::UnknownParent { .. } => {
+                Self::new(StatusCode::CONFLICT, "unknown_parent", err.to_string())
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
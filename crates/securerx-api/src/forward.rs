@@ -0,0 +1,115 @@
+//! Forwarding writes to the leader of a Raft-replicated node cluster.
+//!
+//! With `RAFT_NODES` set, the API does not build blocks itself: it checks each
+//! write against its copy of the chain as usual, submits it to the cluster's
+//! leader at `POST /raft/submit`, and then copies the leader's chain. Nodes
+//! that are not the leader answer `421` naming the leader, which is tried next.
+
+use axum::http::StatusCode;
+use securerx_core::block::Block;
+use securerx_core::registry::RegistryTransaction;
+use securerx_core::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use crate::error::ApiError;
+
+/// Body of `POST /raft/submit`
+#[derive(Serialize)]
+struct Submission {
+    transactions: Vec<Transaction>,
+    registry_txs: Vec<RegistryTransaction>,
+}
+
+#[derive(Deserialize)]
+struct Submitted {
+    block_index: u64,
+}
+
+#[derive(Deserialize)]
+struct Redirect {
+    leader: Option<String>,
+}
+
+/// Client for the Raft cluster that remembers the last known leader
+pub struct LeaderClient {
+    nodes: Vec<String>,
+    leader: Mutex<Option<String>>,
+    client: reqwest::Client,
+}
+
+impl LeaderClient {
+    /// Client for the nodes at `nodes` (`host:port`)
+    pub fn new(nodes: Vec<String>) -> Self {
+        Self { nodes, leader: Mutex::new(None), client: reqwest::Client::new() }
+    }
+
+    /// The node that last accepted a write
+    pub fn leader(&self) -> Option<String> {
+        self.leader.lock().unwrap().clone()
+    }
+
+    /// Replicate the transactions in a new block through the leader, returning its index
+    pub async fn submit(
+        &self,
+        transactions: Vec<Transaction>,
+        registry_txs: Vec<RegistryTransaction>,
+    ) -> Result<u64, ApiError> {
+        let submission = Submission { transactions, registry_txs };
+        let mut candidates: VecDeque<String> = self.leader().into_iter().chain(self.nodes.iter().cloned()).collect();
+        for _ in 0..=self.nodes.len() {
+            let Some(node) = candidates.pop_front() else { break };
+            let url = format!("http://{}/raft/submit", node);
+            let response = match self.client.post(&url).json(&submission).send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    eprintln!("Cannot reach Raft node {}: {}", node, e);
+                    continue;
+                }
+            };
+            match response.status() {
+                StatusCode::CREATED => {
+                    let submitted: Submitted = response.json().await.map_err(bad_gateway)?;
+                    *self.leader.lock().unwrap() = Some(node);
+                    return Ok(submitted.block_index);
+                }
+                StatusCode::MISDIRECTED_REQUEST => {
+                    if let Ok(Redirect { leader: Some(leader) }) = response.json().await {
+                        candidates.push_front(leader);
+                    }
+                }
+                StatusCode::GATEWAY_TIMEOUT => {
+                    return Err(ApiError::new(
+                        StatusCode::GATEWAY_TIMEOUT,
+                        "commit_timeout",
+                        "the Raft cluster did not commit the block in time",
+                    ));
+                }
+                status => eprintln!("Raft node {} answered {}", node, status),
+            }
+        }
+        *self.leader.lock().unwrap() = None;
+        Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "no_leader", "no Raft leader accepted the write"))
+    }
+
+    /// Fetch the chain of the last known leader, or of the first node that answers
+    pub async fn fetch_chain(&self) -> Result<Vec<Block>, ApiError> {
+        let mut last_error = None;
+        for node in self.leader().iter().chain(&self.nodes) {
+            let url = format!("http://{}/blocks", node);
+            match self.client.get(&url).send().await.and_then(reqwest::Response::error_for_status) {
+                Ok(response) => return response.json().await.map_err(bad_gateway),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no_leader",
+            last_error.map_or("no Raft nodes configured".to_string(), |e| e.to_string()),
+        ))
+    }
+}
+
+fn bad_gateway(err: reqwest::Error) -> ApiError {
+    ApiError::new(StatusCode::BAD_GATEWAY, "bad_node_response", err.to_string())
+}
@@ -1,14 +1,173 @@
-use axum::{Json, extract::Path, response::IntoResponse, http::StatusCode};
+use axum::{Json, extract::{Path, Query, rejection::JsonRejection}, response::IntoResponse, http::StatusCode};
 use serde::{Deserialize, Serialize};
 use securerx_core::transaction::Transaction;
-use securerx_core::crypto::{generate_keypair, sign_message};
+use securerx_core::crypto::random_nonce;
+use securerx_core::registry::{DoctorRecord, DoctorStatus, RegistryTransaction};
 use std::sync::{Arc, Mutex};
-use securerx_core::blockchain::Blockchain;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use securerx_core::blockchain::{Blockchain, ChainError};
+use securerx_core::mempool::{Mempool, MempoolError};
+use securerx_core::proof::PrescriptionProof;
+use securerx_core::storage::open_store;
+use securerx_core::validation::ValidationError;
+use crate::config::ApiConfig;
+use crate::dev::DevSigner;
+use crate::error::ApiError;
+use crate::forward::LeaderClient;
+
+/// How long a prescription stays dispensable when the request does not say (30 days)
+pub const DEFAULT_VALIDITY_SECS: u64 = 30 * 24 * 60 * 60;
 
 /// Shared application state
 #[derive(Clone)]
 pub struct AppState {
     pub blockchain: Arc<Mutex<Blockchain>>,
+    pub config: Arc<ApiConfig>,
+    /// Present only in dev mode
+    pub dev_signer: Option<Arc<Mutex<DevSigner>>>,
+    /// Raft cluster that writes are forwarded to, when `config.raft_nodes` is set outside dev mode
+    pub leader: Option<Arc<LeaderClient>>,
+    /// Prescriptions waiting for a block, see [`securerx_core::mempool`]; lock `blockchain` first
+    pub mempool: Arc<Mutex<Mempool>>,
+    /// Wakes the block producer once a full block is pending, see [`AppState::start_producer`]
+    block_ready: Arc<Notify>,
+}
+
+impl AppState {
+    /// Build the state for `config`, creating a dev signer whose admin key is
+    /// trusted by the chain when dev mode is on, and loading the chain from
+    /// `config.data_dir` when set. Blocks are sealed with the dev signer's
+    /// admin key in dev mode and with `config.validator_key` otherwise.
+    pub fn new(config: ApiConfig) -> Result<Self, ChainError> {
+        let mut genesis = config.genesis.clone();
+        let dev_signer = config.dev_mode.then(DevSigner::new);
+        if let Some(signer) = &dev_signer {
+            genesis.authorities.push(signer.admin_pubkey());
+        }
+        // The dev admin key is regenerated on every boot, so a dev chain has a
+        // fresh genesis each time and is never persisted
+        let mut blockchain = match &config.data_dir {
+            Some(data_dir) if !config.dev_mode => {
+                Blockchain::open(genesis, open_store(data_dir, config.storage_backend)?)?
+            }
+            _ => Blockchain::from_genesis(genesis)?,
+        };
+        let validator_key = match &dev_signer {
+            Some(signer) => Some(signer.validator_key()),
+            None => config.validator_key.clone(),
+        };
+        if let Some(key) = validator_key {
+            blockchain.set_validator_key(key);
+        }
+        let leader = (!config.dev_mode && !config.raft_nodes.is_empty())
+            .then(|| Arc::new(LeaderClient::new(config.raft_nodes.clone())));
+        Ok(Self {
+            blockchain: Arc::new(Mutex::new(blockchain)),
+            mempool: Arc::new(Mutex::new(Mempool::new(config.mempool_size))),
+            block_ready: Arc::new(Notify::new()),
+            config: Arc::new(config),
+            dev_signer: dev_signer.map(|s| Arc::new(Mutex::new(s))),
+            leader,
+        })
+    }
+
+    /// Admit a prescription that can go in the next block of `blockchain` to
+    /// the mempool, waking the producer once a full block is pending
+    fn submit(&self, blockchain: &Blockchain, tx: Transaction) -> Result<(), ApiError> {
+        let mut mempool = self.mempool.lock().unwrap();
+        match mempool.insert(tx.clone(), 0, blockchain) {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    "duplicate_transaction",
+                    "a prescription with this id is already pending",
+                ));
+            }
+            Err(MempoolError::Invalid(e)) => return Err(prescription_error(blockchain, &tx, e)),
+            Err(e @ MempoolError::Full) => {
+                return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e.code(), e.to_string()));
+            }
+        }
+        if mempool.len() >= self.config.max_block_txs {
+            self.block_ready.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Seal pending prescriptions into blocks every `config.block_interval`,
+    /// or as soon as `config.max_block_txs` of them are pending
+    pub fn start_producer(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(state.config.block_interval) => {}
+                    _ = state.block_ready.notified() => {}
+                }
+                match state.produce_block().await {
+                    Ok(Some(index)) => println!("Produced block {}", index),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Failed to produce a block: {}", e.message),
+                }
+            }
+        });
+    }
+
+    /// Append the next prescriptions of the mempool in a new block, see
+    /// [`AppState::append`], returning its index or `None` if none are
+    /// pending. They stay pending if the block cannot be appended.
+    pub async fn produce_block(&self) -> Result<Option<u64>, ApiError> {
+        let transactions = {
+            let blockchain = self.blockchain.lock().unwrap();
+            self.mempool.lock().unwrap().next_block(&blockchain, self.config.max_block_txs)
+        };
+        if transactions.is_empty() {
+            return Ok(None);
+        }
+        let index = self.append(transactions, vec![]).await?;
+        let blockchain = self.blockchain.lock().unwrap();
+        self.mempool.lock().unwrap().remove_confirmed(&blockchain);
+        Ok(Some(index))
+    }
+
+    /// Append the transactions in a new block, returning its index. With a
+    /// Raft cluster configured, the block is built and replicated by the
+    /// cluster's leader and the chain is then copied from it.
+    async fn append(
+        &self,
+        transactions: Vec<Transaction>,
+        registry_txs: Vec<RegistryTransaction>,
+    ) -> Result<u64, ApiError> {
+        let Some(leader) = &self.leader else {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            let block = if registry_txs.is_empty() {
+                blockchain.add_block(transactions)?
+            } else {
+                blockchain.add_registry_block(registry_txs)?
+            };
+            return Ok(block.index);
+        };
+        let index = leader.submit(transactions, registry_txs).await?;
+        if let Err(e) = self.sync_from_leader().await {
+            eprintln!("Block {} was committed but the chain could not be copied: {}", index, e.message);
+        }
+        Ok(index)
+    }
+
+    /// Copy the Raft leader's chain if it is longer than ours
+    pub async fn sync_from_leader(&self) -> Result<(), ApiError> {
+        let Some(leader) = &self.leader else {
+            return Ok(());
+        };
+        let blocks = leader.fetch_chain().await?;
+        let mut blockchain = self.blockchain.lock().unwrap();
+        if blocks.len() > blockchain.chain.len() {
+            blockchain.replace_chain(blocks)?;
+        }
+        Ok(())
+    }
 }
 
 /// Request payload to issue a prescription
@@ -17,13 +176,20 @@ pub struct PrescriptionRequest {
     pub doctor_id: String,
     pub patient_id: String,
     pub drug: String,
+    #[serde(default)]
+    pub dosage: String,
 }
 
 /// Response payload for submission
 #[derive(Serialize)]
 pub struct PrescriptionResponse {
     pub status: String,
-    pub block_index: u64,
+    /// Block holding the submission; absent while a prescription is pending
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_index: Option<u64>,
+    /// Hex transaction id (see [`Transaction::id`]), used to request an inclusion proof
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_id: Option<String>,
 }
 
 /// Endpoint: Health check
@@ -31,29 +197,159 @@ pub async fn health() -> impl IntoResponse {
     (StatusCode::OK, Json(serde_json::json!({"status": "ok"})))
 }
 
-/// Endpoint: Submit a prescription
+/// Endpoint: Submit a prescription signed by the server (dev mode only). It
+/// waits in the mempool for the next produced block.
 pub async fn submit_prescription(
     state: axum::extract::Extension<AppState>,
     Json(payload): Json<PrescriptionRequest>,
-) -> impl IntoResponse {
-    let keypair = generate_keypair(); // Simulated signing per doctor
-    let sig = sign_message(&keypair, payload.drug.as_bytes());
+) -> Result<(StatusCode, Json<PrescriptionResponse>), ApiError> {
+    let Some(dev_signer) = &state.dev_signer else {
+        return Err(ApiError::forbidden(
+            "dev_mode_disabled",
+            "server-side signing is disabled; submit a client-signed transaction to POST /prescriptions",
+        ));
+    };
+
+    let mut blockchain = state.blockchain.lock().unwrap();
+    let keypair = dev_signer.lock().unwrap().doctor_key(&payload.doctor_id, &mut blockchain)?;
+    let issued_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
 
-    let tx = Transaction {
+    let mut tx = Transaction {
+        chain_id: state.config.genesis.chain_id.clone(),
         doctor_id: payload.doctor_id,
         patient_id: payload.patient_id,
         drug: payload.drug,
-        signature: sig.to_bytes().to_vec(),
-        pubkey: keypair.verifying_key().to_bytes().to_vec(),
+        dosage: payload.dosage,
+        issued_at,
+        expires_at: issued_at + DEFAULT_VALIDITY_SECS,
+        nonce: random_nonce(),
+        signature: vec![],
+        pubkey: vec![],
     };
+    tx.sign(&keypair);
+    let tx_id = hex::encode(tx.id());
+    state.submit(&blockchain, tx)?;
 
-    let mut blockchain = state.blockchain.lock().unwrap();
-    let block = blockchain.add_block(vec![tx]);
+    Ok((StatusCode::ACCEPTED, Json(PrescriptionResponse {
+        status: "pending".to_string(),
+        block_index: None,
+        tx_id: Some(tx_id),
+    })))
+}
+
+/// Endpoint: Submit a prescription signed on the doctor's machine. It waits
+/// in the mempool for the next produced block.
+pub async fn submit_signed_prescription(
+    state: axum::extract::Extension<AppState>,
+    payload: Result<Json<Transaction>, JsonRejection>,
+) -> Result<(StatusCode, Json<PrescriptionResponse>), ApiError> {
+    let Json(tx) = payload.map_err(|e| ApiError::bad_request("malformed_transaction", e.to_string()))?;
+
+    let tx_id = hex::encode(tx.id());
+    state.submit(&state.blockchain.lock().unwrap(), tx)?;
+
+    Ok((StatusCode::ACCEPTED, Json(PrescriptionResponse {
+        status: "pending".to_string(),
+        block_index: None,
+        tx_id: Some(tx_id),
+    })))
+}
+
+/// Map the reason `chain` refused `tx` for its next block to the API's error
+/// codes, which tell an unknown doctor, a suspended doctor and an
+/// unauthorized key apart
+fn prescription_error(chain: &Blockchain, tx: &Transaction, error: ValidationError) -> ApiError {
+    let record = chain.registry().get(&tx.doctor_id);
+    match error {
+        ValidationError::WrongChain { chain_id, .. } => ApiError::bad_request(
+            "wrong_chain",
+            format!("transaction is for chain '{}', this node serves '{}'", chain_id, chain.genesis().chain_id),
+        ),
+        ValidationError::InvalidValidityWindow { .. } => {
+            ApiError::bad_request("invalid_validity_window", "expires_at must be after issued_at")
+        }
+        ValidationError::BadSignature { .. } => {
+            ApiError::unauthorized("invalid_signature", "signature does not verify against pubkey")
+        }
+        ValidationError::UnknownSigner { doctor_id, .. } if record.is_none() => {
+            ApiError::forbidden("unknown_doctor", format!("doctor '{}' is not registered", doctor_id))
+        }
+        ValidationError::UnauthorizedSigner { doctor_id, .. }
+            if record.is_some_and(|record| record.status != DoctorStatus::Active) =>
+        {
+            ApiError::forbidden("doctor_suspended", format!("doctor '{}' may not currently prescribe", doctor_id))
+        }
+        ValidationError::UnknownSigner { doctor_id, .. } | ValidationError::UnauthorizedSigner { doctor_id, .. } => {
+            ApiError::forbidden(
+                "key_not_authorized",
+                format!("signing key is not registered to doctor '{}' or has been revoked", doctor_id),
+            )
+        }
+        error => error.into(),
+    }
+}
+
+/// Endpoint: Submit an admin-signed doctor registry transaction
+pub async fn submit_registry_transaction(
+    state: axum::extract::Extension<AppState>,
+    payload: Result<Json<RegistryTransaction>, JsonRejection>,
+) -> Result<(StatusCode, Json<PrescriptionResponse>), ApiError> {
+    let Json(reg_tx) = payload.map_err(|e| ApiError::bad_request("malformed_transaction", e.to_string()))?;
 
-    (StatusCode::CREATED, Json(PrescriptionResponse {
+    state.blockchain.lock().unwrap().check_registry_transaction(&reg_tx)?;
+    let block_index = state.append(vec![], vec![reg_tx]).await?;
+
+    Ok((StatusCode::CREATED, Json(PrescriptionResponse {
         status: "success".to_string(),
-        block_index: block.index,
-    }))
+        block_index: Some(block_index),
+        tx_id: None,
+    })))
+}
+
+/// Endpoint: Look up a registered doctor
+pub async fn get_doctor(
+    state: axum::extract::Extension<AppState>,
+    Path(doctor_id): Path<String>,
+) -> Result<Json<DoctorRecord>, ApiError> {
+    let blockchain = state.blockchain.lock().unwrap();
+    blockchain.registry().get(&doctor_id).cloned().map(Json).ok_or_else(|| {
+        ApiError::new(StatusCode::NOT_FOUND, "unknown_doctor", format!("doctor '{}' is not registered", doctor_id))
+    })
+}
+
+/// Query parameters for a prescription proof
+#[derive(Deserialize)]
+pub struct ProofQuery {
+    /// Height of the checkpoint block the proof links to; defaults to the tip
+    pub checkpoint: Option<u64>,
+}
+
+/// Endpoint: Prove a prescription is on chain, for offline verification
+pub async fn get_prescription_proof(
+    state: axum::extract::Extension<AppState>,
+    Path(tx_id): Path<String>,
+    Query(query): Query<ProofQuery>,
+) -> Result<Json<PrescriptionProof>, ApiError> {
+    let mut id = [0u8; 32];
+    hex::decode_to_slice(&tx_id, &mut id)
+        .map_err(|_| ApiError::bad_request("malformed_tx_id", "tx_id must be 64 hex characters"))?;
+
+    let blockchain = state.blockchain.lock().unwrap();
+    let tip = blockchain.chain.len() as u64 - 1;
+    let checkpoint = query.checkpoint.unwrap_or(tip);
+    if checkpoint > tip {
+        return Err(ApiError::bad_request(
+            "bad_checkpoint",
+            format!("checkpoint {} is beyond the chain tip {}", checkpoint, tip),
+        ));
+    }
+    blockchain.prescription_proof(&id, checkpoint).map(Json).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "unknown_transaction",
+            format!("no prescription {} at or below block {}", tx_id, checkpoint),
+        )
+    })
 }
 
 /// Endpoint: Query blockchain
@@ -64,6 +360,40 @@ pub async fn get_chain(
     Json(blockchain.chain.clone())
 }
 
+/// Result of validating the whole chain
+#[derive(Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub height: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ValidationFailure>,
+}
+
+/// The first problem found in an invalid chain
+#[derive(Serialize)]
+pub struct ValidationFailure {
+    pub code: &'static str,
+    pub message: String,
+    pub block: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx: Option<usize>,
+}
+
+impl From<ValidationError> for ValidationFailure {
+    fn from(err: ValidationError) -> Self {
+        Self { code: err.code(), message: err.to_string(), block: err.block(), tx: err.tx() }
+    }
+}
+
+/// Endpoint: Validate the chain from genesis
+pub async fn validate_chain(
+    state: axum::extract::Extension<AppState>,
+) -> Json<ValidationReport> {
+    let blockchain = state.blockchain.lock().unwrap();
+    let error = blockchain.validate_chain().err().map(ValidationFailure::from);
+    Json(ValidationReport { valid: error.is_none(), height: blockchain.chain.len() as u64, error })
+}
+
 /// Endpoint: Get a specific block by index
 pub async fn get_block(
     state: axum::extract::Extension<AppState>,
@@ -88,17 +418,27 @@ mod tests {
         Extension,
     };
     use tower::ServiceExt;
-    use std::sync::{Arc, Mutex};
+    use ed25519_dalek::SigningKey;
+    use securerx_core::crypto::generate_keypair;
+    use securerx_core::genesis::GenesisSpec;
+    use securerx_core::registry::RegistryOp;
+    use securerx_core::transaction::DEFAULT_CHAIN_ID;
 
     fn create_app() -> Router {
-        let blockchain = Arc::new(Mutex::new(Blockchain::new()));
-        let app_state = AppState { blockchain };
-        
+        router(AppState::new(ApiConfig { dev_mode: true, ..Default::default() }).unwrap())
+    }
+
+    fn router(app_state: AppState) -> Router {
         Router::new()
             .route("/health", get(health))
             .route("/prescription", post(submit_prescription))
+            .route("/prescriptions", post(submit_signed_prescription))
+            .route("/prescriptions/:tx_id/proof", get(get_prescription_proof))
+            .route("/registry", post(submit_registry_transaction))
+            .route("/registry/doctors/:doctor_id", get(get_doctor))
             .route("/blocks", get(get_chain))
             .route("/blocks/:index", get(get_block))
+            .route("/chain/validate", get(validate_chain))
             .layer(Extension(app_state))
     }
 
@@ -134,7 +474,7 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
     }
 
     #[tokio::test]
@@ -189,4 +529,504 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    fn signed_tx(keypair: &SigningKey, doctor_id: &str) -> Transaction {
+        let mut tx = Transaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            doctor_id: doctor_id.to_string(),
+            patient_id: "patient1".to_string(),
+            drug: "Aspirin".to_string(),
+            dosage: "100mg once daily".to_string(),
+            issued_at: 1_700_000_000,
+            expires_at: 1_700_000_000 + DEFAULT_VALIDITY_SECS,
+            nonce: 7,
+            signature: vec![],
+            pubkey: vec![],
+        };
+        tx.sign(keypair);
+        tx
+    }
+
+    fn registry_tx(admin: &SigningKey, op: RegistryOp) -> RegistryTransaction {
+        let mut tx = RegistryTransaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            op,
+            nonce: random_nonce(),
+            admin_pubkey: vec![],
+            signature: vec![],
+        };
+        tx.sign(admin);
+        tx
+    }
+
+    fn register(admin: &SigningKey, doctor_id: &str, doctor: &SigningKey) -> RegistryTransaction {
+        registry_tx(admin, RegistryOp::RegisterDoctor {
+            doctor_id: doctor_id.to_string(),
+            license_number: format!("LIC-{}", doctor_id),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        })
+    }
+
+    /// Production-mode state trusting `admin` for registry changes and sealing blocks with it
+    fn state_with_admin(admin: &SigningKey) -> AppState {
+        let genesis = GenesisSpec::with_authorities(vec![admin.verifying_key().to_bytes().to_vec()]);
+        AppState::new(ApiConfig { genesis, validator_key: Some(admin.clone()), ..Default::default() }).unwrap()
+    }
+
+    /// Production-mode state where `keypair` is registered to doctor1
+    fn state_with_doctor(keypair: &SigningKey) -> AppState {
+        let admin = generate_keypair();
+        let state = state_with_admin(&admin);
+        state.blockchain.lock().unwrap().add_registry_block(vec![register(&admin, "doctor1", keypair)]).unwrap();
+        state
+    }
+
+    /// Production-mode app where `keypair` is registered to doctor1
+    fn app_with_doctor(keypair: &SigningKey) -> Router {
+        router(state_with_doctor(keypair))
+    }
+
+    async fn post_json(app: Router, uri: &str, body: String) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_server_signing_requires_dev_mode() {
+        let app = router(AppState::new(ApiConfig::default()).unwrap());
+        let payload = serde_json::json!({
+            "doctor_id": "doctor1",
+            "patient_id": "patient1",
+            "drug": "Aspirin"
+        });
+
+        let (status, body) = post_json(app, "/prescription", payload.to_string()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "dev_mode_disabled");
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription() {
+        let keypair = generate_keypair();
+        let app = app_with_doctor(&keypair);
+        let tx = signed_tx(&keypair, "doctor1");
+
+        let (status, body) = post_json(app, "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["tx_id"], hex::encode(tx.id()));
+        assert!(body.get("block_index").is_none(), "No block is sealed on submission");
+    }
+
+    #[tokio::test]
+    async fn test_submissions_are_batched_into_one_block() {
+        let keypair = generate_keypair();
+        let state = state_with_doctor(&keypair);
+        let first = signed_tx(&keypair, "doctor1");
+        let mut second = first.clone();
+        second.nonce += 1;
+        second.sign(&keypair);
+        for tx in [&first, &second] {
+            let (status, _) = post_json(router(state.clone()), "/prescriptions", serde_json::to_string(tx).unwrap()).await;
+            assert_eq!(status, StatusCode::ACCEPTED);
+        }
+        assert_eq!(state.blockchain.lock().unwrap().chain.len(), 2, "Submissions wait in the mempool");
+        assert_eq!(state.mempool.lock().unwrap().len(), 2);
+
+        assert_eq!(state.produce_block().await.unwrap(), Some(2));
+        assert_eq!(state.produce_block().await.unwrap(), None, "Nothing is left pending");
+        let blockchain = state.blockchain.lock().unwrap();
+        assert_eq!(blockchain.chain.len(), 3);
+        assert_eq!(blockchain.transaction_height(&first.id()), Some(2));
+        assert_eq!(blockchain.transaction_height(&second.id()), Some(2));
+        assert!(state.mempool.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription_tampered() {
+        let keypair = generate_keypair();
+        let app = app_with_doctor(&keypair);
+        let mut tx = signed_tx(&keypair, "doctor1");
+        tx.drug = "Oxycodone".to_string();
+
+        let (status, body) = post_json(app, "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "invalid_signature");
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription_unregistered_key() {
+        let keypair = generate_keypair();
+        let app = app_with_doctor(&keypair);
+        // Validly signed, but by a key that does not belong to doctor1
+        let tx = signed_tx(&generate_keypair(), "doctor1");
+
+        let (status, body) = post_json(app, "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "key_not_authorized");
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription_unknown_doctor() {
+        let keypair = generate_keypair();
+        let app = app_with_doctor(&keypair);
+        let tx = signed_tx(&keypair, "doctor2");
+
+        let (status, body) = post_json(app, "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "unknown_doctor");
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription_malformed() {
+        let app = app_with_doctor(&generate_keypair());
+        let (status, body) = post_json(app, "/prescriptions", "{\"doctor_id\": 5}".to_string()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "malformed_transaction");
+    }
+
+    #[tokio::test]
+    async fn test_dev_mode_chain_validates() {
+        let state = AppState::new(ApiConfig { dev_mode: true, ..Default::default() }).unwrap();
+        let app = router(state.clone());
+        for patient in ["patient1", "patient2"] {
+            let payload = serde_json::json!({"doctor_id": "doctor1", "patient_id": patient, "drug": "Aspirin"});
+            let (status, _) = post_json(app.clone(), "/prescription", payload.to_string()).await;
+            assert_eq!(status, StatusCode::ACCEPTED);
+        }
+        state.produce_block().await.unwrap();
+
+        let blockchain = state.blockchain.lock().unwrap();
+        assert_eq!(blockchain.chain.len(), 3, "Doctor is registered once, then both prescriptions in one block");
+        assert!(blockchain.validate_chain().is_ok(), "Dev-signed prescriptions come from registered keys");
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription_suspended_doctor() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let state = state_with_admin(&admin);
+        let suspend = registry_tx(&admin, RegistryOp::SetStatus {
+            doctor_id: "doctor1".to_string(),
+            status: DoctorStatus::Suspended,
+        });
+        state.blockchain.lock().unwrap().add_registry_block(vec![register(&admin, "doctor1", &doctor), suspend]).unwrap();
+
+        let tx = signed_tx(&doctor, "doctor1");
+        let (status, body) = post_json(router(state), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "doctor_suspended");
+    }
+
+    #[tokio::test]
+    async fn test_registry_submission_and_lookup() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let state = state_with_admin(&admin);
+
+        let reg_tx = register(&admin, "doctor1", &doctor);
+        let (status, _) = post_json(router(state.clone()), "/registry", serde_json::to_string(&reg_tx).unwrap()).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let response = router(state.clone())
+            .oneshot(Request::builder().uri("/registry/doctors/doctor1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let record: DoctorRecord = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(record.license_number, "LIC-doctor1");
+
+        let tx = signed_tx(&doctor, "doctor1");
+        let (status, _) = post_json(router(state), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn test_registry_submission_requires_admin() {
+        let state = state_with_admin(&generate_keypair());
+        let reg_tx = register(&generate_keypair(), "doctor1", &generate_keypair());
+
+        let (status, body) = post_json(router(state.clone()), "/registry", serde_json::to_string(&reg_tx).unwrap()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "unknown_admin");
+
+        let response = router(state)
+            .oneshot(Request::builder().uri("/registry/doctors/doctor1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_transactions_for_another_chain_are_rejected() {
+        let admin = generate_keypair();
+        let keypair = generate_keypair();
+        let state = state_with_admin(&admin);
+        state.blockchain.lock().unwrap().add_registry_block(vec![register(&admin, "doctor1", &keypair)]).unwrap();
+
+        let mut tx = signed_tx(&keypair, "doctor1");
+        tx.chain_id = "securerx-other".to_string();
+        tx.sign(&keypair);
+        let (status, body) = post_json(router(state.clone()), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "wrong_chain");
+
+        let mut reg_tx = register(&admin, "doctor2", &generate_keypair());
+        reg_tx.chain_id = "securerx-other".to_string();
+        reg_tx.sign(&admin);
+        let (status, body) = post_json(router(state.clone()), "/registry", serde_json::to_string(&reg_tx).unwrap()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "wrong_chain");
+        assert_eq!(state.blockchain.lock().unwrap().chain.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription_rotated_key() {
+        let admin = generate_keypair();
+        let old = generate_keypair();
+        let new = generate_keypair();
+        let state = state_with_admin(&admin);
+        let rotate = registry_tx(&admin, RegistryOp::RotateKey {
+            doctor_id: "doctor1".to_string(),
+            old_pubkey: old.verifying_key().to_bytes().to_vec(),
+            new_pubkey: new.verifying_key().to_bytes().to_vec(),
+        });
+        {
+            let mut blockchain = state.blockchain.lock().unwrap();
+            blockchain.add_registry_block(vec![register(&admin, "doctor1", &old)]).unwrap();
+            blockchain.add_registry_block(vec![rotate]).unwrap();
+        }
+
+        let tx = signed_tx(&old, "doctor1");
+        let (status, body) = post_json(router(state.clone()), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "key_not_authorized");
+
+        let tx = signed_tx(&new, "doctor1");
+        let (status, _) = post_json(router(state), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn test_chain_survives_restart_with_data_dir() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let data_dir = std::env::temp_dir().join(format!("securerx-api-restart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let config = ApiConfig {
+            genesis: GenesisSpec::with_authorities(vec![admin.verifying_key().to_bytes().to_vec()]),
+            data_dir: Some(data_dir),
+            validator_key: Some(admin.clone()),
+            ..Default::default()
+        };
+
+        {
+            let state = AppState::new(config.clone()).unwrap();
+            state.blockchain.lock().unwrap().add_registry_block(vec![register(&admin, "doctor1", &doctor)]).unwrap();
+            let tx = signed_tx(&doctor, "doctor1");
+            let (status, _) = post_json(router(state.clone()), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+            assert_eq!(status, StatusCode::ACCEPTED);
+            state.produce_block().await.unwrap();
+        }
+
+        let state = AppState::new(config).unwrap();
+        let blockchain = state.blockchain.lock().unwrap();
+        assert_eq!(blockchain.chain.len(), 3, "Registry and prescription blocks should be reloaded");
+        assert!(blockchain.registry().get("doctor1").is_some());
+        assert!(blockchain.validate_chain().is_ok());
+    }
+
+    async fn get_json(app: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_submit_signed_prescription_duplicate() {
+        let keypair = generate_keypair();
+        let state = state_with_doctor(&keypair);
+        let body = serde_json::to_string(&signed_tx(&keypair, "doctor1")).unwrap();
+
+        let (status, _) = post_json(router(state.clone()), "/prescriptions", body.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, resp) = post_json(router(state.clone()), "/prescriptions", body.clone()).await;
+        assert_eq!(status, StatusCode::CONFLICT, "Already pending");
+        assert_eq!(resp["error"], "duplicate_transaction");
+
+        state.produce_block().await.unwrap();
+        let (status, resp) = post_json(router(state), "/prescriptions", body).await;
+        assert_eq!(status, StatusCode::CONFLICT, "Already on chain");
+        assert_eq!(resp["error"], "duplicate_transaction");
+    }
+
+    #[tokio::test]
+    async fn test_validate_chain_endpoint() {
+        let keypair = generate_keypair();
+        let admin = generate_keypair();
+        let state = state_with_admin(&admin);
+        state.blockchain.lock().unwrap().add_registry_block(vec![register(&admin, "doctor1", &keypair)]).unwrap();
+        let (status, _) = post_json(
+            router(state.clone()),
+            "/prescriptions",
+            serde_json::to_string(&signed_tx(&keypair, "doctor1")).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        state.produce_block().await.unwrap();
+
+        let (status, report) = get_json(router(state.clone()), "/chain/validate").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report, serde_json::json!({"valid": true, "height": 3}));
+
+        state.blockchain.lock().unwrap().chain[2].transactions[0].drug = "Oxycodone".to_string();
+        let (_, report) = get_json(router(state), "/chain/validate").await;
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["error"]["code"], "bad_signature");
+        assert_eq!(report["error"]["block"], 2);
+        assert_eq!(report["error"]["tx"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_prescription_proof_endpoint() {
+        let keypair = generate_keypair();
+        let state = state_with_doctor(&keypair);
+        let app = router(state.clone());
+        let tx = signed_tx(&keypair, "doctor1");
+        let (status, resp) = post_json(app.clone(), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let tx_id = resp["tx_id"].as_str().unwrap().to_string();
+        assert_eq!(tx_id, hex::encode(tx.id()));
+        state.produce_block().await.unwrap();
+
+        let (status, body) = get_json(app.clone(), &format!("/prescriptions/{}/proof", tx_id)).await;
+        assert_eq!(status, StatusCode::OK);
+        let proof: PrescriptionProof = serde_json::from_value(body).unwrap();
+        let (_, block) = get_json(app.clone(), "/blocks/2").await;
+        let block: securerx_core::block::Block = serde_json::from_value(block).unwrap();
+        assert_eq!(proof.verify(&block.calculate_hash()), Ok(2));
+
+        let (status, resp) = get_json(app.clone(), &format!("/prescriptions/{}/proof?checkpoint=1", tx_id)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(resp["error"], "unknown_transaction");
+        let (status, resp) = get_json(app.clone(), &format!("/prescriptions/{}/proof?checkpoint=9", tx_id)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(resp["error"], "bad_checkpoint");
+        let (status, resp) = get_json(app, "/prescriptions/xyz/proof").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(resp["error"], "malformed_tx_id");
+    }
+
+    /// Serve `app` on a loopback port, returning its address
+    async fn serve(app: Router) -> String {
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr().to_string();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_forwarded_to_the_raft_leader() {
+        use securerx_core::block::Block;
+
+        #[derive(Deserialize)]
+        struct Submission {
+            transactions: Vec<Transaction>,
+        }
+
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let genesis = GenesisSpec::with_authorities(vec![admin.verifying_key().to_bytes().to_vec()]);
+
+        // A leader node that seals submitted prescriptions, and a follower redirecting to it
+        let mut chain = Blockchain::from_genesis(genesis.clone()).unwrap();
+        chain.set_validator_key(admin.clone());
+        chain.add_registry_block(vec![register(&admin, "doctor1", &doctor)]).unwrap();
+        let chain = Arc::new(Mutex::new(chain));
+        let blocks = get(|Extension(chain): Extension<Arc<Mutex<Blockchain>>>| async move {
+            Json::<Vec<Block>>(chain.lock().unwrap().chain.clone())
+        });
+        let leader = serve(
+            Router::new()
+                .route("/raft/submit", post(|Extension(chain): Extension<Arc<Mutex<Blockchain>>>, Json(s): Json<Submission>| async move {
+                    let index = chain.lock().unwrap().add_block(s.transactions).unwrap().index;
+                    (StatusCode::CREATED, Json(serde_json::json!({ "block_index": index })))
+                }))
+                .route("/blocks", blocks.clone())
+                .layer(Extension(chain.clone())),
+        )
+        .await;
+        let redirect = serde_json::json!({ "error": "not_leader", "message": "not the leader", "leader": leader });
+        let follower = serve(
+            Router::new()
+                .route("/raft/submit", post(move || async move { (StatusCode::MISDIRECTED_REQUEST, Json(redirect)) }))
+                .route("/blocks", blocks)
+                .layer(Extension(chain.clone())),
+        )
+        .await;
+
+        let config = ApiConfig { genesis, raft_nodes: vec![follower], ..Default::default() };
+        let state = AppState::new(config).unwrap();
+        state.sync_from_leader().await.unwrap();
+        assert!(state.blockchain.lock().unwrap().registry().get("doctor1").is_some());
+
+        let tx = signed_tx(&doctor, "doctor1");
+        let (status, body) = post_json(router(state.clone()), "/prescriptions", serde_json::to_string(&tx).unwrap()).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+        assert_eq!(state.produce_block().await.unwrap(), Some(2));
+        assert_eq!(state.leader.as_ref().unwrap().leader(), Some(leader));
+        let blockchain = state.blockchain.lock().unwrap();
+        assert!(blockchain.contains_transaction(&tx), "The leader's chain is copied after the write");
+        assert_eq!(blockchain.chain.len(), chain.lock().unwrap().chain.len());
+    }
+
+    #[tokio::test]
+    async fn test_forwarding_without_reachable_nodes() {
+        let admin = generate_keypair();
+        let genesis = GenesisSpec::with_authorities(vec![admin.verifying_key().to_bytes().to_vec()]);
+        let config = ApiConfig { genesis, raft_nodes: vec!["127.0.0.1:1".to_string()], ..Default::default() };
+        let app = router(AppState::new(config).unwrap());
+
+        let reg_tx = register(&admin, "doctor1", &generate_keypair());
+        let (status, body) = post_json(app, "/registry", serde_json::to_string(&reg_tx).unwrap()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"], "no_leader");
+    }
+
+    #[tokio::test]
+    async fn test_submit_requires_validator_key() {
+        let keypair = generate_keypair();
+        let mut genesis = GenesisSpec::with_authorities(vec![generate_keypair().verifying_key().to_bytes().to_vec()]);
+        genesis.doctors.push(securerx_core::genesis::GenesisDoctor {
+            doctor_id: "doctor1".to_string(),
+            license_number: "LIC-1".to_string(),
+            pubkey: keypair.verifying_key().to_bytes().to_vec(),
+        });
+        let state = AppState::new(ApiConfig { genesis, ..Default::default() }).unwrap();
+
+        let body = serde_json::to_string(&signed_tx(&keypair, "doctor1")).unwrap();
+        let (status, _) = post_json(router(state.clone()), "/prescriptions", body).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let err = state.produce_block().await.unwrap_err();
+        assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.error, "not_proposer");
+        assert_eq!(state.mempool.lock().unwrap().len(), 1, "The prescription stays pending");
+    }
 }
@@ -1,2 +1,5 @@
+pub mod config;
+pub mod dev;
+pub mod error;
+pub mod forward;
 pub mod handlers;
-
@@ -3,20 +3,50 @@ use axum::{
     Router, Extension,
 };
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use securerx_core::blockchain::Blockchain;
-use securerx_api::handlers::{health, submit_prescription, get_chain, get_block, AppState};
+use securerx_api::config::ApiConfig;
+use securerx_api::handlers::{
+    health, submit_prescription, submit_signed_prescription, submit_registry_transaction,
+    get_doctor, get_chain, get_block, get_prescription_proof, validate_chain, AppState,
+};
 
 #[tokio::main]
 async fn main() {
-    let blockchain = Arc::new(Mutex::new(Blockchain::new()));
-    let app_state = AppState { blockchain };
+    let config = ApiConfig::from_env();
+    if config.dev_mode {
+        println!("WARNING: dev mode enabled, POST /prescription signs with server-held keys");
+    }
+    if config.dev_mode && config.data_dir.is_some() {
+        println!("WARNING: dev mode keeps the chain in memory, DATA_DIR is ignored");
+    }
+    if config.dev_mode && !config.raft_nodes.is_empty() {
+        println!("WARNING: dev mode seals blocks locally, RAFT_NODES is ignored");
+    }
+    let app_state = AppState::new(config).expect("failed to load the blockchain from DATA_DIR");
+    app_state.start_producer();
+
+    // Keep the chain up to date with writes made through other API servers
+    if app_state.leader.is_some() {
+        let state = app_state.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = state.sync_from_leader().await {
+                    eprintln!("Failed to sync from the Raft cluster: {}", e.message);
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            }
+        });
+    }
 
     let app = Router::new()
         .route("/health", get(health))
         .route("/prescription", post(submit_prescription))
+        .route("/prescriptions", post(submit_signed_prescription))
+        .route("/prescriptions/:tx_id/proof", get(get_prescription_proof))
+        .route("/registry", post(submit_registry_transaction))
+        .route("/registry/doctors/:doctor_id", get(get_doctor))
         .route("/blocks", get(get_chain))
         .route("/blocks/:index", get(get_block))
+        .route("/chain/validate", get(validate_chain))
         .layer(Extension(app_state));
 
     let addr: SocketAddr = "0.0.0.0:8080".parse().unwrap();
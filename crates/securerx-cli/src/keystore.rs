@@ -0,0 +1,377 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Current on-disk keystore format
+const KEYSTORE_VERSION: u32 = 1;
+
+/// Associated data for the passphrase verifier entry
+const VERIFIER_AAD: &[u8] = b"securerx/keystore/verifier";
+
+/// Argon2id cost parameters stored alongside the salt
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// OWASP's recommended Argon2id baseline (19 MiB, 2 passes)
+    fn default() -> Self {
+        Self { m_cost: 19 * 1024, t_cost: 2, p_cost: 1 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct KdfConfig {
+    algorithm: String,
+    salt: String,
+    #[serde(flatten)]
+    params: KdfParams,
+}
+
+/// A signing key encrypted with XChaCha20-Poly1305. The name and public key
+/// are bound in as associated data so entries cannot be swapped around.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyEntry {
+    pub name: String,
+    pub public_key: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct KeystoreFile {
+    version: u32,
+    kdf: KdfConfig,
+    /// Encryption of the empty string, used to reject a wrong passphrase up front
+    verifier: Sealed,
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Sealed {
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Errors raised while reading or modifying a keystore
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(std::io::Error),
+    Format(String),
+    WrongPassphrase,
+    KeyExists(String),
+    KeyNotFound(String),
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeystoreError::Io(e) => write!(f, "keystore I/O error: {}", e),
+            KeystoreError::Format(e) => write!(f, "invalid keystore file: {}", e),
+            KeystoreError::WrongPassphrase => write!(f, "wrong keystore passphrase"),
+            KeystoreError::KeyExists(name) => write!(f, "a key named '{}' already exists", name),
+            KeystoreError::KeyNotFound(name) => write!(f, "no key named '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for KeystoreError {}
+
+impl From<std::io::Error> for KeystoreError {
+    fn from(e: std::io::Error) -> Self {
+        KeystoreError::Io(e)
+    }
+}
+
+/// Passphrase-encrypted store of Ed25519 signing keys.
+///
+/// The passphrase is stretched with Argon2id into a single 256-bit key held
+/// for the lifetime of the value; each entry is sealed with its own random nonce.
+pub struct Keystore {
+    file: KeystoreFile,
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl Keystore {
+    /// Create an empty keystore protected by `passphrase`
+    pub fn create(passphrase: &str, params: KdfParams) -> Result<Self, KeystoreError> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt, params)?;
+        let verifier = seal(&key, b"", VERIFIER_AAD)?;
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            kdf: KdfConfig { algorithm: "argon2id".to_string(), salt: hex::encode(salt), params },
+            verifier,
+            keys: vec![],
+        };
+        Ok(Self { file, key })
+    }
+
+    /// Open the keystore at `path`, checking the passphrase
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self, KeystoreError> {
+        let file = read_file(path)?;
+        let salt = decode_hex(&file.kdf.salt)?;
+        let key = derive_key(passphrase, &salt, file.kdf.params)?;
+        open_sealed(&key, &file.verifier, VERIFIER_AAD)?;
+        Ok(Self { file, key })
+    }
+
+    /// Open the keystore at `path`, or create an empty one if the file does not exist yet
+    pub fn open_or_create(path: &Path, passphrase: &str) -> Result<Self, KeystoreError> {
+        if path.exists() {
+            Self::open(path, passphrase)
+        } else {
+            Self::create(passphrase, KdfParams::default())
+        }
+    }
+
+    /// List entries without needing the passphrase
+    pub fn list(path: &Path) -> Result<Vec<KeyEntry>, KeystoreError> {
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        Ok(read_file(path)?.keys)
+    }
+
+    /// Write the keystore to `path`, replacing the previous file atomically
+    pub fn save(&self, path: &Path) -> Result<(), KeystoreError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec_pretty(&self.file).map_err(|e| KeystoreError::Format(e.to_string()))?;
+        let tmp = path.with_extension("tmp");
+        // A temp file left by an interrupted save may have looser permissions
+        if let Err(e) = fs::remove_file(&tmp) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        let mut file = create_private(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        sync_dir(path)?;
+        Ok(())
+    }
+
+    /// Generate and store a new signing key under `name`
+    pub fn generate(&mut self, name: &str) -> Result<SigningKey, KeystoreError> {
+        if self.entry(name).is_some() {
+            return Err(KeystoreError::KeyExists(name.to_string()));
+        }
+        let signing_key = securerx_core::crypto::generate_keypair();
+        let public_key = hex::encode(signing_key.verifying_key().to_bytes());
+        let sealed = seal(&self.key, &signing_key.to_bytes(), &entry_aad(name, &public_key))?;
+        self.file.keys.push(KeyEntry {
+            name: name.to_string(),
+            public_key,
+            nonce: sealed.nonce,
+            ciphertext: sealed.ciphertext,
+        });
+        Ok(signing_key)
+    }
+
+    /// Decrypt the signing key stored under `name`
+    pub fn signing_key(&self, name: &str) -> Result<SigningKey, KeystoreError> {
+        let entry = self.entry(name).ok_or_else(|| KeystoreError::KeyNotFound(name.to_string()))?;
+        let sealed = Sealed { nonce: entry.nonce.clone(), ciphertext: entry.ciphertext.clone() };
+        let secret = open_sealed(&self.key, &sealed, &entry_aad(&entry.name, &entry.public_key))?;
+        let bytes: [u8; 32] = secret
+            .as_slice()
+            .try_into()
+            .map_err(|_| KeystoreError::Format(format!("key '{}' has the wrong length", name)))?;
+        Ok(SigningKey::from_bytes(&bytes))
+    }
+
+    /// Remove the key stored under `name`
+    pub fn delete(&mut self, name: &str) -> Result<(), KeystoreError> {
+        let before = self.file.keys.len();
+        self.file.keys.retain(|k| k.name != name);
+        if self.file.keys.len() == before {
+            return Err(KeystoreError::KeyNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Look up an entry by name
+    pub fn entry(&self, name: &str) -> Option<&KeyEntry> {
+        self.file.keys.iter().find(|k| k.name == name)
+    }
+}
+
+fn read_file(path: &Path) -> Result<KeystoreFile, KeystoreError> {
+    let data = fs::read(path)?;
+    let file: KeystoreFile = serde_json::from_slice(&data).map_err(|e| KeystoreError::Format(e.to_string()))?;
+    if file.version != KEYSTORE_VERSION {
+        return Err(KeystoreError::Format(format!("unsupported keystore version {}", file.version)));
+    }
+    if file.kdf.algorithm != "argon2id" {
+        return Err(KeystoreError::Format(format!("unsupported KDF '{}'", file.kdf.algorithm)));
+    }
+    Ok(file)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<Zeroizing<[u8; 32]>, KeystoreError> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|e| KeystoreError::Format(e.to_string()))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| KeystoreError::Format(e.to_string()))?;
+    Ok(key)
+}
+
+fn entry_aad(name: &str, public_key: &str) -> Vec<u8> {
+    format!("securerx/keystore/key\0{}\0{}", name, public_key).into_bytes()
+}
+
+fn seal(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Sealed, KeystoreError> {
+    let mut nonce = [0u8; 24];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(&XNonce::from(nonce), Payload { msg: plaintext, aad })
+        .map_err(|_| KeystoreError::Format("encryption failed".to_string()))?;
+    Ok(Sealed { nonce: hex::encode(nonce), ciphertext: hex::encode(ciphertext) })
+}
+
+fn open_sealed(key: &[u8; 32], sealed: &Sealed, aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, KeystoreError> {
+    let nonce: [u8; 24] = decode_hex(&sealed.nonce)?
+        .try_into()
+        .map_err(|_| KeystoreError::Format("nonce must be 24 bytes".to_string()))?;
+    let ciphertext = decode_hex(&sealed.ciphertext)?;
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(&XNonce::from(nonce), Payload { msg: &ciphertext, aad })
+        .map(Zeroizing::new)
+        .map_err(|_| KeystoreError::WrongPassphrase)
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, KeystoreError> {
+    hex::decode(value).map_err(|e| KeystoreError::Format(e.to_string()))
+}
+
+/// Create `path`, readable and writable by its owner only from the start
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Flush the directory entry of `path`, so a rename into it survives a crash
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => fs::File::open(dir)?.sync_all(),
+        None => fs::File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Cheap parameters so tests stay fast
+    const TEST_PARAMS: KdfParams = KdfParams { m_cost: 64, t_cost: 1, p_cost: 1 };
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("securerx-keystore-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("keystore.json")
+    }
+
+    #[test]
+    fn test_generate_save_and_reopen() {
+        let path = temp_path("roundtrip");
+        let mut keystore = Keystore::create("correct horse", TEST_PARAMS).unwrap();
+        let generated = keystore.generate("dr-smith").unwrap();
+        keystore.save(&path).unwrap();
+
+        let reopened = Keystore::open(&path, "correct horse").unwrap();
+        let loaded = reopened.signing_key("dr-smith").unwrap();
+        assert_eq!(loaded.to_bytes(), generated.to_bytes());
+
+        let listed = Keystore::list(&path).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].public_key, hex::encode(generated.verifying_key().to_bytes()));
+
+        let raw = fs::read_to_string(&path).unwrap();
+        assert!(!raw.contains(&hex::encode(generated.to_bytes())), "Secret key must not be stored in the clear");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_saved_keystore_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_path("private");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let stale = path.with_extension("tmp");
+        fs::write(&stale, b"left by an interrupted save").unwrap();
+        fs::set_permissions(&stale, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut keystore = Keystore::create("correct horse", TEST_PARAMS).unwrap();
+        keystore.generate("dr-smith").unwrap();
+        keystore.save(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!stale.exists());
+        assert!(Keystore::open(&path, "correct horse").is_ok());
+    }
+
+    #[test]
+    fn test_wrong_passphrase_rejected() {
+        let path = temp_path("wrong-pass");
+        let mut keystore = Keystore::create("correct horse", TEST_PARAMS).unwrap();
+        keystore.generate("dr-smith").unwrap();
+        keystore.save(&path).unwrap();
+
+        assert!(matches!(Keystore::open(&path, "battery staple"), Err(KeystoreError::WrongPassphrase)));
+    }
+
+    #[test]
+    fn test_swapped_entries_fail_to_decrypt() {
+        let mut keystore = Keystore::create("pass", TEST_PARAMS).unwrap();
+        keystore.generate("a").unwrap();
+        keystore.generate("b").unwrap();
+        // Point entry "a" at the ciphertext of "b"
+        let b = keystore.file.keys[1].clone();
+        keystore.file.keys[0].nonce = b.nonce;
+        keystore.file.keys[0].ciphertext = b.ciphertext;
+
+        assert!(keystore.signing_key("a").is_err());
+        assert!(keystore.signing_key("b").is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_and_delete() {
+        let mut keystore = Keystore::create("pass", TEST_PARAMS).unwrap();
+        keystore.generate("dr-smith").unwrap();
+        assert!(matches!(keystore.generate("dr-smith"), Err(KeystoreError::KeyExists(_))));
+
+        keystore.delete("dr-smith").unwrap();
+        assert!(keystore.entry("dr-smith").is_none());
+        assert!(matches!(keystore.delete("dr-smith"), Err(KeystoreError::KeyNotFound(_))));
+    }
+}
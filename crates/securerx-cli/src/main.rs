@@ -1,7 +1,15 @@
+mod keystore;
+
 use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use reqwest::blocking::Client;
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use securerx_core::crypto::random_nonce;
+use securerx_core::proof::PrescriptionProof;
+use securerx_core::transaction::{Transaction, DEFAULT_CHAIN_ID};
+use keystore::Keystore;
 
 /// CLI for SecureRx blockchain
 #[derive(Parser)]
@@ -13,15 +21,34 @@ struct Cli {
     /// Node API URL (default: http://localhost:8080)
     #[clap(long, default_value="http://localhost:8080")]
     node_url: String,
+
+    /// Keystore file (default: ~/.securerx/keystore.json)
+    #[clap(long, env = "SECURERX_KEYSTORE")]
+    keystore: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Issue a new prescription
+    /// Issue a new prescription, signed locally with a keystore key
     IssuePrescription {
         doctor_id: String,
         patient_id: String,
         drug: String,
+        /// Name of the keystore key to sign with
+        #[clap(long)]
+        key: String,
+        #[clap(long, default_value = "")]
+        dosage: String,
+        /// Days until the prescription expires
+        #[clap(long, default_value_t = 30)]
+        valid_days: u64,
+        #[clap(long, default_value = DEFAULT_CHAIN_ID)]
+        chain_id: String,
+    },
+    /// Manage local signing keys
+    Keys {
+        #[clap(subcommand)]
+        command: KeysCommand,
     },
     /// Query all blocks
     GetBlocks,
@@ -29,52 +56,169 @@ enum Commands {
     GetBlock {
         index: usize,
     },
+    /// Validate the node's chain from genesis
+    ValidateChain,
+    /// Fetch an inclusion proof for a prescription
+    GetProof {
+        /// Transaction id printed when the prescription was submitted
+        tx_id: String,
+        /// Height of the checkpoint block to link to (default: the node's tip)
+        #[clap(long)]
+        checkpoint: Option<u64>,
+    },
+    /// Verify an inclusion proof offline against a trusted checkpoint block hash
+    VerifyProof {
+        file: PathBuf,
+        /// Hash of the checkpoint block, obtained from a source you trust
+        #[clap(long)]
+        checkpoint: String,
+    },
     /// Health check
     Health,
 }
 
-/// Prescription submission payload
-#[derive(Serialize)]
-struct PrescriptionRequest {
-    doctor_id: String,
-    patient_id: String,
-    drug: String,
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Generate a new Ed25519 signing key
+    Generate {
+        name: String,
+    },
+    /// List stored keys and their public keys
+    List,
+    /// Print a key's public key as hex, e.g. for registering it with an admin
+    ExportPublic {
+        name: String,
+    },
+    /// Delete a key
+    Delete {
+        name: String,
+    },
 }
 
 /// Response for prescription submission
 #[derive(Deserialize)]
 struct PrescriptionResponse {
     status: String,
-    block_index: u64,
+    block_index: Option<u64>,
+    tx_id: Option<String>,
+}
+
+/// Response of `GET /chain/validate`
+#[derive(Deserialize)]
+struct ValidationReport {
+    valid: bool,
+    height: u64,
+    error: Option<ValidationFailure>,
+}
+
+#[derive(Deserialize)]
+struct ValidationFailure {
+    code: String,
+    message: String,
+    block: u64,
+    tx: Option<usize>,
+}
+
+/// Error body returned by the API
+#[derive(Deserialize)]
+struct ApiErrorResponse {
+    error: String,
+    message: String,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
     let client = Client::new();
+    let keystore_path = cli.keystore.clone().unwrap_or_else(default_keystore_path);
 
     match cli.command {
-        Commands::IssuePrescription { doctor_id, patient_id, drug } => {
-            let payload = PrescriptionRequest { doctor_id, patient_id, drug };
-            let resp = client.post(&format!("{}/prescription", cli.node_url))
-                .json(&payload)
-                .send()?
-                .json::<PrescriptionResponse>()?;
-            println!("Prescription submitted successfully. Block index: {}", resp.block_index);
+        Commands::IssuePrescription { doctor_id, patient_id, drug, key, dosage, valid_days, chain_id } => {
+            let keystore = Keystore::open(&keystore_path, &passphrase(false)?)?;
+            let signing_key = keystore.signing_key(&key)?;
+
+            let issued_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let mut tx = Transaction {
+                chain_id,
+                doctor_id,
+                patient_id,
+                drug,
+                dosage,
+                issued_at,
+                expires_at: issued_at + valid_days * 24 * 60 * 60,
+                nonce: random_nonce(),
+                signature: vec![],
+                pubkey: vec![],
+            };
+            tx.sign(&signing_key);
+
+            let resp = client.post(format!("{}/prescriptions", cli.node_url))
+                .json(&tx)
+                .send()?;
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let err = resp.json::<ApiErrorResponse>()?;
+                return Err(format!("submission rejected ({}): {}: {}", status, err.error, err.message).into());
+            }
+            let resp = resp.json::<PrescriptionResponse>()?;
+            println!("Prescription submitted: {}", resp.status);
+            if let Some(block_index) = resp.block_index {
+                println!("Block index: {}", block_index);
+            }
+            if let Some(tx_id) = resp.tx_id {
+                println!("Transaction id: {}", tx_id);
+            }
         }
+        Commands::Keys { command } => run_keys(command, &keystore_path)?,
         Commands::GetBlocks => {
-            let resp = client.get(&format!("{}/blocks", cli.node_url))
+            let resp = client.get(format!("{}/blocks", cli.node_url))
                 .send()?
                 .text()?;
             println!("{}", resp);
         }
         Commands::GetBlock { index } => {
-            let resp = client.get(&format!("{}/blocks/{}", cli.node_url, index))
+            let resp = client.get(format!("{}/blocks/{}", cli.node_url, index))
                 .send()?
                 .text()?;
             println!("{}", resp);
         }
+        Commands::ValidateChain => {
+            let report = client.get(format!("{}/chain/validate", cli.node_url))
+                .send()?
+                .json::<ValidationReport>()?;
+            match report.error {
+                None if report.valid => println!("Chain is valid ({} blocks)", report.height),
+                None => return Err("chain reported invalid without a reason".into()),
+                Some(err) => {
+                    let location = match err.tx {
+                        Some(tx) => format!("block {}, tx {}", err.block, tx),
+                        None => format!("block {}", err.block),
+                    };
+                    return Err(format!("chain is invalid at {} ({}): {}", location, err.code, err.message).into());
+                }
+            }
+        }
+        Commands::GetProof { tx_id, checkpoint } => {
+            let mut url = format!("{}/prescriptions/{}/proof", cli.node_url, tx_id);
+            if let Some(checkpoint) = checkpoint {
+                url.push_str(&format!("?checkpoint={}", checkpoint));
+            }
+            let resp = client.get(url).send()?;
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let err = resp.json::<ApiErrorResponse>()?;
+                return Err(format!("proof request rejected ({}): {}: {}", status, err.error, err.message).into());
+            }
+            println!("{}", resp.text()?);
+        }
+        Commands::VerifyProof { file, checkpoint } => {
+            let proof: PrescriptionProof = serde_json::from_slice(&std::fs::read(&file)?)?;
+            let height = proof.verify(&checkpoint).map_err(|e| format!("proof is invalid: {}", e))?;
+            let tx = &proof.transaction;
+            println!("Proof is valid: prescription {} is in block {}", hex::encode(tx.id()), height);
+            println!("Doctor: {}, patient: {}, drug: {}", tx.doctor_id, tx.patient_id, tx.drug);
+        }
         Commands::Health => {
-            let resp = client.get(&format!("{}/health", cli.node_url))
+            let resp = client.get(format!("{}/health", cli.node_url))
                 .send()?
                 .text()?;
             println!("Health status: {}", resp);
@@ -83,3 +227,53 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+fn run_keys(command: KeysCommand, path: &Path) -> Result<(), Box<dyn Error>> {
+    match command {
+        KeysCommand::Generate { name } => {
+            let mut keystore = Keystore::open_or_create(path, &passphrase(!path.exists())?)?;
+            let signing_key = keystore.generate(&name)?;
+            keystore.save(path)?;
+            println!("Generated key '{}'", name);
+            println!("Public key: {}", hex::encode(signing_key.verifying_key().to_bytes()));
+        }
+        KeysCommand::List => {
+            for entry in Keystore::list(path)? {
+                println!("{}\t{}", entry.name, entry.public_key);
+            }
+        }
+        KeysCommand::ExportPublic { name } => {
+            let entry = Keystore::list(path)?
+                .into_iter()
+                .find(|e| e.name == name)
+                .ok_or_else(|| keystore::KeystoreError::KeyNotFound(name.clone()))?;
+            println!("{}", entry.public_key);
+        }
+        KeysCommand::Delete { name } => {
+            let mut keystore = Keystore::open(path, &passphrase(false)?)?;
+            keystore.delete(&name)?;
+            keystore.save(path)?;
+            println!("Deleted key '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+/// Read the keystore passphrase from `SECURERX_KEYSTORE_PASSPHRASE` or the terminal
+fn passphrase(confirm: bool) -> Result<String, Box<dyn Error>> {
+    if let Ok(passphrase) = std::env::var("SECURERX_KEYSTORE_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Keystore passphrase: ")?;
+    if confirm && rpassword::prompt_password("Confirm passphrase: ")? != passphrase {
+        return Err("passphrases do not match".into());
+    }
+    Ok(passphrase)
+}
+
+fn default_keystore_path() -> PathBuf {
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".securerx").join("keystore.json"),
+        None => PathBuf::from("securerx-keystore.json"),
+    }
+}
@@ -1,7 +1,16 @@
 use serde::{Serialize, Deserialize};
 use crate::transaction::Transaction;
+use crate::registry::RegistryTransaction;
+use crate::consensus::CommitCertificate;
+use crate::crypto::{sign_message, verify_message};
+use crate::encoding::{put_bytes, put_u64};
+use crate::merkle::{merkle_root, MerkleProof};
+use ed25519_dalek::SigningKey;
 use sha2::{Sha256, Digest};
 
+/// Domain separator and version of the canonical header encoding, see `docs/encoding.md`
+const HEADER_ENCODING_DOMAIN: &[u8] = b"securerx/header/v2";
+
 /// Represents a blockchain block
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Block {
@@ -9,17 +18,116 @@ pub struct Block {
     pub prev_hash: String,
     pub timestamp: u64,
     pub transactions: Vec<Transaction>,
+    /// Registry changes, applied before the block's prescriptions are checked
+    #[serde(default)]
+    pub registry_txs: Vec<RegistryTransaction>,
     pub nonce: u64,
+    /// Public key of the validator that proposed the block; empty for the genesis block
+    #[serde(default)]
+    pub proposer: Vec<u8>,
+    /// Proposer's signature over the header encoding
+    #[serde(default)]
+    pub signature: Vec<u8>,
+    /// BFT precommits finalizing the block. Like `signature`, not covered by the hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<CommitCertificate>,
 }
 
-impl Block {
-    /// Calculate SHA256 hash of the block
-    pub fn calculate_hash(&self) -> String {
-        let data = serde_json::to_string(&self).unwrap();
+/// The part of a block that its hash covers. The body is committed to through
+/// Merkle roots, so a single transaction can be proven to be in a block
+/// without shipping the rest of it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub index: u64,
+    pub prev_hash: String,
+    pub timestamp: u64,
+    pub nonce: u64,
+    /// Merkle root over the hashes of the block's prescriptions
+    #[serde(with = "crate::merkle::hex_hash")]
+    pub tx_root: [u8; 32],
+    /// Merkle root over the hashes of the block's registry transactions
+    #[serde(with = "crate::merkle::hex_hash")]
+    pub registry_root: [u8; 32],
+    pub proposer: Vec<u8>,
+    /// Not covered by the hash: it signs the encoding the hash is taken over
+    pub signature: Vec<u8>,
+}
+
+impl BlockHeader {
+    /// Canonical binary encoding of the header (version 2): the domain
+    /// separator, `index`, `prev_hash`, `timestamp`, `nonce`, `tx_root`,
+    /// `registry_root` and `proposer`. This is also the payload the proposer
+    /// signs.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(256);
+        put_bytes(&mut buf, HEADER_ENCODING_DOMAIN);
+        put_u64(&mut buf, self.index);
+        put_bytes(&mut buf, self.prev_hash.as_bytes());
+        put_u64(&mut buf, self.timestamp);
+        put_u64(&mut buf, self.nonce);
+        put_bytes(&mut buf, &self.tx_root);
+        put_bytes(&mut buf, &self.registry_root);
+        put_bytes(&mut buf, &self.proposer);
+        buf
+    }
+
+    /// Whether `signature` is the proposer's signature over this header
+    pub fn verify_signature(&self) -> bool {
+        verify_message(&self.proposer, &self.encode(), &self.signature)
+    }
+
+    /// SHA-256 of the canonical encoding, as lowercase hex. This is the block hash.
+    pub fn hash(&self) -> String {
         let mut hasher = Sha256::new();
-        hasher.update(data.as_bytes());
+        hasher.update(self.encode());
         format!("{:x}", hasher.finalize())
     }
+
+    /// Whether `proof` shows that `tx` is one of this block's prescriptions
+    pub fn verify_transaction(&self, tx: &Transaction, proof: &MerkleProof) -> bool {
+        proof.verify(&tx.hash(), &self.tx_root)
+    }
+}
+
+impl Block {
+    /// Header committing to this block's contents
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            index: self.index,
+            prev_hash: self.prev_hash.clone(),
+            timestamp: self.timestamp,
+            nonce: self.nonce,
+            tx_root: merkle_root(&self.tx_hashes()),
+            registry_root: merkle_root(&self.registry_txs.iter().map(RegistryTransaction::hash).collect::<Vec<_>>()),
+            proposer: self.proposer.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+
+    /// BFT round the block was committed in; 0 for blocks without a commit certificate
+    pub fn round(&self) -> u64 {
+        self.commit.as_ref().map_or(0, |commit| commit.round)
+    }
+
+    /// Set `proposer` to the validator's public key and sign the header
+    pub fn sign(&mut self, validator: &SigningKey) {
+        self.proposer = validator.verifying_key().to_bytes().to_vec();
+        self.signature = sign_message(validator, &self.header().encode()).to_bytes().to_vec();
+    }
+
+    /// Calculate the block hash, which covers only the header
+    pub fn calculate_hash(&self) -> String {
+        self.header().hash()
+    }
+
+    /// Inclusion proof for the prescription at `position`, or `None` if there is none
+    pub fn transaction_proof(&self, position: usize) -> Option<MerkleProof> {
+        MerkleProof::build(&self.tx_hashes(), position)
+    }
+
+    fn tx_hashes(&self) -> Vec<[u8; 32]> {
+        self.transactions.iter().map(Transaction::hash).collect()
+    }
 }
 
 #[cfg(test)]
@@ -33,7 +141,11 @@ mod tests {
             prev_hash: "0".to_string(),
             timestamp: 1234567890,
             transactions: vec![],
+            registry_txs: vec![],
             nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
         };
         let hash1 = block.calculate_hash();
         let hash2 = block.calculate_hash();
@@ -48,15 +160,129 @@ mod tests {
             prev_hash: "0".to_string(),
             timestamp: 1234567890,
             transactions: vec![],
+            registry_txs: vec![],
             nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
         };
         let block2 = Block {
             index: 1,
             prev_hash: "0".to_string(),
             timestamp: 1234567890,
             transactions: vec![],
+            registry_txs: vec![],
             nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
         };
         assert_ne!(block1.calculate_hash(), block2.calculate_hash(), "Different blocks should have different hashes");
     }
+
+    #[test]
+    fn test_golden_vector_empty_block() {
+        let block = Block {
+            index: 0,
+            prev_hash: "0".to_string(),
+            timestamp: 1234567890,
+            transactions: vec![],
+            registry_txs: vec![],
+            nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
+        };
+        assert_eq!(
+            hex::encode(block.header().encode()),
+            concat!(
+                "0000001273656375726572782f6865616465722f7632",
+                "0000000000000000",
+                "0000000130",
+                "00000000499602d2",
+                "0000000000000000",
+                "00000020e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                "00000020e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                "00000000",
+            )
+        );
+        assert_eq!(block.calculate_hash(), "c6f39d42fc231ac6b71e88470531852b94d9a5ba9eb68419db73a6b36c64c07e");
+    }
+
+    /// Golden vectors from `docs/encoding.md`
+    #[test]
+    fn test_golden_vector_block_with_transactions() {
+        use crate::genesis::GenesisSpec;
+        use crate::registry::RegistryOp;
+        use ed25519_dalek::SigningKey;
+
+        let doctor = SigningKey::from_bytes(&[1u8; 32]);
+        let admin = SigningKey::from_bytes(&[2u8; 32]);
+        let genesis = GenesisSpec::with_authorities(vec![admin.verifying_key().to_bytes().to_vec()]).block();
+        assert_eq!(genesis.calculate_hash(), "df0a428b211081bfe4188b5853aca1d13c413c662e3828f957b6f6bf622570e0");
+
+        let mut tx = Transaction {
+            chain_id: "securerx-dev".to_string(),
+            doctor_id: "doctor1".to_string(),
+            patient_id: "patient1".to_string(),
+            drug: "Aspirin".to_string(),
+            dosage: "100mg once daily".to_string(),
+            issued_at: 1_700_000_000,
+            expires_at: 1_702_592_000,
+            nonce: 42,
+            signature: vec![],
+            pubkey: vec![],
+        };
+        tx.sign(&doctor);
+        let mut reg_tx = RegistryTransaction {
+            chain_id: "securerx-dev".to_string(),
+            op: RegistryOp::RegisterDoctor {
+                doctor_id: "doctor1".to_string(),
+                license_number: "LIC-1".to_string(),
+                pubkey: doctor.verifying_key().to_bytes().to_vec(),
+            },
+            nonce: 1,
+            admin_pubkey: vec![],
+            signature: vec![],
+        };
+        reg_tx.sign(&admin);
+
+        let mut block = Block {
+            index: 1,
+            prev_hash: genesis.calculate_hash(),
+            timestamp: 1_700_000_100,
+            transactions: vec![tx],
+            registry_txs: vec![reg_tx],
+            nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
+        };
+        block.sign(&admin);
+        assert!(block.header().verify_signature());
+        assert_eq!(
+            hex::encode(&block.signature),
+            "1919a5f8dce23950307ede4160cb675518d343f55fe94accb353d2c3dc98a2f87432d28026571a05675b8ec3d607bfde50452ef1dfed737f747b9a81b3c1a903"
+        );
+        assert_eq!(hex::encode(block.header().tx_root), "1a951aee8295eba3a8829e4848cb5a94a2713c72a7af5bdd9555bdb61d6f990a");
+        assert_eq!(hex::encode(block.header().registry_root), "ffce5f38917ed70601cbb84b5f4b4002eea2a740acfa69f595de6f7295acab49");
+        assert_eq!(block.calculate_hash(), "b0415ea82fa96168f7f470a3f45ba6afb755154c2e7b6f1e425d5578e5256f9f");
+    }
+
+    #[test]
+    fn test_block_hash_ignores_json_formatting() {
+        let block = Block {
+            index: 3,
+            prev_hash: "ab".repeat(32),
+            timestamp: 1_700_000_000,
+            transactions: vec![],
+            registry_txs: vec![],
+            nonce: 9,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
+        };
+        let reparsed: Block = serde_json::from_str(&serde_json::to_string_pretty(&block).unwrap()).unwrap();
+        assert_eq!(block.calculate_hash(), reparsed.calculate_hash());
+    }
 }
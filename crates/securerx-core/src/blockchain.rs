@@ -1,186 +1,1632 @@
-use crate::block::Block;
+use crate::block::{Block, BlockHeader};
+use crate::consensus::{check_proposer, check_seal, proposer_for};
+use crate::merkle::MerkleProof;
+use crate::pow::{self, PowParams};
+use crate::proof::PrescriptionProof;
 use crate::transaction::Transaction;
+use crate::genesis::GenesisSpec;
+use crate::registry::{DoctorRegistry, RegistryError, RegistryTransaction};
+use crate::storage::{BlockStore, StorageError};
+use crate::validation::ValidationError;
+use ed25519_dalek::SigningKey;
+use std::collections::HashMap;
+use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// How far ahead of the local clock, in seconds, a block may be timestamped
+pub const MAX_CLOCK_DRIFT: u64 = 60;
+
+/// Errors raised when changing or loading the chain
+#[derive(Debug)]
+pub enum ChainError {
+    Registry(RegistryError),
+    Storage(StorageError),
+    /// A stored or received chain is not valid for this network
+    Invalid(ValidationError),
+    /// This node has no validator key, or it is another validator's turn to propose block `height`
+    NotProposer { height: u64 },
+    /// The received chain does not contain the finalized block at `height`
+    RevertsFinalized { height: u64 },
+    /// Block `block` does not follow any block this node knows
+    UnknownParent { block: u64 },
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::Registry(e) => write!(f, "{}", e),
+            ChainError::Storage(e) => write!(f, "{}", e),
+            ChainError::Invalid(e) => write!(f, "invalid chain: {}", e),
+            ChainError::NotProposer { height } => write!(f, "this node may not propose block {}", height),
+            ChainError::RevertsFinalized { height } => {
+                write!(f, "chain does not contain finalized block {}", height)
+            }
+            ChainError::UnknownParent { block } => write!(f, "block {} does not follow a known block", block),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+impl From<RegistryError> for ChainError {
+    fn from(e: RegistryError) -> Self {
+        ChainError::Registry(e)
+    }
+}
+
+impl From<ValidationError> for ChainError {
+    fn from(e: ValidationError) -> Self {
+        ChainError::Invalid(e)
+    }
+}
+
+impl From<StorageError> for ChainError {
+    fn from(e: StorageError) -> Self {
+        ChainError::Storage(e)
+    }
+}
+
+/// Evidence that a prescription is part of a block: the block's header and
+/// the Merkle path from the prescription's hash to the header's `tx_root`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionProof {
+    pub header: BlockHeader,
+    pub proof: MerkleProof,
+}
+
 #[derive(Debug, serde::Serialize)]
 pub struct Blockchain {
     pub chain: Vec<Block>,
+    /// Network parameters the genesis block was derived from
+    genesis: GenesisSpec,
+    /// Doctor registry as of the chain tip
+    #[serde(skip)]
+    registry: DoctorRegistry,
+    /// Height of the block holding each prescription in the chain, by transaction id
+    #[serde(skip)]
+    tx_index: HashMap<[u8; 32], u64>,
+    /// Height of each block in the chain, by block hash
+    #[serde(skip)]
+    block_heights: HashMap<String, u64>,
+    /// Height of the block holding each registry transaction in the chain, by digest
+    #[serde(skip)]
+    registry_index: HashMap<[u8; 32], u64>,
+    /// Durable copy of `chain`; `None` keeps the chain in memory only
+    #[serde(skip)]
+    store: Option<Box<dyn BlockStore>>,
+    /// Key this node seals its blocks with; `None` for nodes that only follow the chain
+    #[serde(skip)]
+    validator_key: Option<SigningKey>,
+    /// Height of the last block with a commit certificate; it and every block before it are final
+    #[serde(skip)]
+    finalized_height: u64,
+    /// Leading zero bits required of the next block, 0 without proof of work
+    #[serde(skip)]
+    required_bits: u32,
 }
 
 impl Blockchain {
-    /// Initialize blockchain with a genesis block
+    /// Initialize blockchain from the default development genesis
     pub fn new() -> Self {
-        let genesis_block = Block {
-            index: 0,
-            prev_hash: String::from("0"),
-            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
-            transactions: vec![],
-            nonce: 0,
-        };
-        Self { chain: vec![genesis_block] }
+        Self::with_admins(vec![])
+    }
+
+    /// Initialize blockchain from a development genesis with the given registry admin keys
+    pub fn with_admins(admin_keys: Vec<Vec<u8>>) -> Self {
+        Self::from_genesis(GenesisSpec::with_authorities(admin_keys))
+            .expect("a genesis without doctors always has a valid registry")
+    }
+
+    /// Initialize an in-memory blockchain holding only the genesis block of `genesis`
+    pub fn from_genesis(genesis: GenesisSpec) -> Result<Self, ChainError> {
+        let registry = genesis.registry()?;
+        let block = genesis.block();
+        let required_bits = genesis.pow.as_ref().map_or(0, |params| pow::required_bits(params, std::slice::from_ref(&block)));
+        Ok(Self {
+            block_heights: HashMap::from([(block.calculate_hash(), 0)]),
+            registry_index: HashMap::new(),
+            chain: vec![block],
+            genesis,
+            registry,
+            tx_index: HashMap::new(),
+            store: None,
+            validator_key: None,
+            finalized_height: 0,
+            required_bits,
+        })
+    }
+
+    /// Load the chain persisted in `store`, or start and persist a new one
+    /// from `genesis` if the store is empty. Every stored block is validated
+    /// as it is replayed, which also rebuilds the registry.
+    pub fn open(genesis: GenesisSpec, mut store: Box<dyn BlockStore>) -> Result<Self, ChainError> {
+        let mut chain = store.load()?;
+        if chain.is_empty() {
+            let block = genesis.block();
+            store.append(&block)?;
+            chain.push(block);
+        }
+        let mut blockchain = Self::from_genesis(genesis)?;
+        let (registry, tx_index) = blockchain.check_chain(&chain)?;
+        blockchain.finalized_height = finalized_height(&chain);
+        blockchain.block_heights = block_heights(&chain);
+        blockchain.registry_index = registry_index(&chain);
+        blockchain.required_bits = blockchain.bits_after(&chain);
+        blockchain.chain = chain;
+        blockchain.registry = registry;
+        blockchain.tx_index = tx_index;
+        blockchain.store = Some(store);
+        Ok(blockchain)
+    }
+
+    /// Network parameters the chain was started from
+    pub fn genesis(&self) -> &GenesisSpec {
+        &self.genesis
+    }
+
+    /// Hash of the genesis block, identical on every node of the network
+    pub fn genesis_hash(&self) -> String {
+        self.chain[0].calculate_hash()
+    }
+
+    /// Public keys allowed to sign registry transactions
+    pub fn admin_keys(&self) -> &[Vec<u8>] {
+        &self.genesis.authorities
+    }
+
+    /// Validators that take turns sealing blocks: the genesis authorities
+    pub fn validators(&self) -> &[Vec<u8>] {
+        &self.genesis.authorities
+    }
+
+    /// Proof-of-work parameters of this network, if blocks must carry proof of work
+    pub fn pow(&self) -> Option<&PowParams> {
+        self.genesis.pow.as_ref()
+    }
+
+    /// Leading zero bits the next block's hash needs, 0 without proof of work
+    pub fn required_bits(&self) -> u32 {
+        self.required_bits
+    }
+
+    /// Leading zero bits required of the block following `chain`, replaying
+    /// every retarget; 0 without proof of work
+    fn bits_after(&self, chain: &[Block]) -> u32 {
+        self.pow().map_or(0, |params| pow::required_bits(params, chain))
+    }
+
+    /// Record `block`, about to be appended, in the block and registry indexes
+    fn index_block(&mut self, block: &Block) {
+        self.block_heights.insert(block.calculate_hash(), block.index);
+        self.registry_index.extend(block.registry_txs.iter().map(|tx| (tx.hash(), block.index)));
+    }
+
+    /// Move the required bits on past the block just appended to the chain
+    fn step_required_bits(&mut self) {
+        if let Some(params) = &self.genesis.pow {
+            self.required_bits = pow::next_bits(params, self.required_bits, &self.chain);
+        }
+    }
+
+    /// Cumulative work of the chain, see [`pow::chain_work`]
+    pub fn total_work(&self) -> u128 {
+        pow::chain_work(self.pow(), &self.chain)
+    }
+
+    /// Seal the blocks this node proposes with `key`
+    pub fn set_validator_key(&mut self, key: SigningKey) {
+        self.validator_key = Some(key);
     }
 
-    /// Add a block to the chain
-    pub fn add_block(&mut self, transactions: Vec<Transaction>) -> &Block {
+    /// Whether this node's validator key may propose the next block outside of BFT rounds
+    pub fn is_next_proposer(&self) -> bool {
+        self.is_proposer(0)
+    }
+
+    /// Whether this node's validator key may propose the next block in BFT round `round`
+    pub fn is_proposer(&self, round: u64) -> bool {
+        let height = self.chain.len() as u64;
+        self.validator_key.as_ref().is_some_and(|key| {
+            proposer_for(self.validators(), height, round) == Some(&key.verifying_key().to_bytes()[..])
+        })
+    }
+
+    /// Height of the last finalized block. Blocks up to it are never replaced.
+    pub fn finalized_height(&self) -> u64 {
+        self.finalized_height
+    }
+
+    /// Doctor registry as of the chain tip
+    pub fn registry(&self) -> &DoctorRegistry {
+        &self.registry
+    }
+
+    /// Whether a prescription with the id of `tx` is already in the chain
+    pub fn contains_transaction(&self, tx: &Transaction) -> bool {
+        self.tx_index.contains_key(&tx.id())
+    }
+
+    /// Height of the block holding the prescription with id `tx_id`
+    pub fn transaction_height(&self, tx_id: &[u8; 32]) -> Option<u64> {
+        self.tx_index.get(tx_id).copied()
+    }
+
+    /// Height of the block holding the registry transaction with digest
+    /// `digest` (see [`RegistryTransaction::hash`])
+    pub fn registry_transaction_height(&self, digest: &[u8; 32]) -> Option<u64> {
+        self.registry_index.get(digest).copied()
+    }
+
+    /// Height of the block with hash `hash`, if it is in the chain
+    pub fn height_of(&self, hash: &str) -> Option<u64> {
+        self.block_heights.get(hash).copied()
+    }
+
+    /// The block with hash `hash`, if it is in the chain
+    pub fn block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.chain.get(self.height_of(hash)? as usize)
+    }
+
+    /// The block holding the prescription with id `tx_id` and its position in the block
+    pub fn find_transaction(&self, tx_id: &[u8; 32]) -> Option<(&Block, usize)> {
+        let block = self.chain.get(self.transaction_height(tx_id)? as usize)?;
+        let position = block.transactions.iter().position(|tx| &tx.id() == tx_id)?;
+        Some((block, position))
+    }
+
+    /// Propose a block holding `transactions`, sealed with this node's validator key
+    pub fn add_block(&mut self, transactions: Vec<Transaction>) -> Result<&Block, ChainError> {
+        self.push_block(transactions, vec![])
+    }
+
+    /// Apply admin-signed registry transactions and record them in a new block.
+    /// Nothing is appended if any of them is rejected.
+    pub fn add_registry_block(&mut self, registry_txs: Vec<RegistryTransaction>) -> Result<&Block, ChainError> {
+        let height = self.chain.len() as u64;
+        let mut registry = self.registry.clone();
+        for tx in &registry_txs {
+            self.apply_registry_tx(&mut registry, tx, height)?;
+        }
+        self.push_block(vec![], registry_txs)
+    }
+
+    /// Replace the chain with `blocks`, e.g. a snapshot received from a Raft
+    /// leader. Chains built on a different genesis, with any block that fails
+    /// validation (see [`Blockchain::validate_chain`]), or that would revert a
+    /// finalized block, are refused. Only the blocks after the common prefix
+    /// are rewritten in the store.
+    pub fn replace_chain(&mut self, blocks: Vec<Block>) -> Result<(), ChainError> {
+        let (registry, index) = self.check_chain(&blocks)?;
+        let finalized = &self.chain[self.finalized_height as usize];
+        if blocks.get(finalized.index as usize).map(Block::calculate_hash) != Some(finalized.calculate_hash()) {
+            return Err(ChainError::RevertsFinalized { height: finalized.index });
+        }
+        if let Some(store) = &mut self.store {
+            let common = self.chain.iter()
+                .zip(&blocks)
+                .take_while(|(ours, theirs)| ours.calculate_hash() == theirs.calculate_hash())
+                .count();
+            store.truncate(common as u64)?;
+            for block in &blocks[common..] {
+                store.append(block)?;
+            }
+        }
+        self.tx_index = index;
+        self.finalized_height = finalized_height(&blocks);
+        self.block_heights = block_heights(&blocks);
+        self.registry_index = registry_index(&blocks);
+        self.required_bits = self.bits_after(&blocks);
+        self.chain = blocks;
+        self.registry = registry;
+        Ok(())
+    }
+
+    /// Remove every block above `height`, e.g. to switch to another branch,
+    /// and return them oldest first. The registry and prescription index are
+    /// rebuilt as of the new tip. Finalized blocks are never removed.
+    pub fn rollback(&mut self, height: u64) -> Result<Vec<Block>, ChainError> {
+        if height < self.finalized_height {
+            return Err(ChainError::RevertsFinalized { height: self.finalized_height });
+        }
+        let len = (height as usize + 1).min(self.chain.len());
+        if let Some(store) = &mut self.store {
+            store.truncate(len as u64)?;
+        }
+        let reverted = self.chain.split_off(len);
+        self.registry = self.replay_registry(&self.chain)?;
+        self.required_bits = self.bits_after(&self.chain);
+        for block in &reverted {
+            self.block_heights.remove(&block.calculate_hash());
+            for tx in &block.transactions {
+                self.tx_index.remove(&tx.id());
+            }
+            for tx in &block.registry_txs {
+                self.registry_index.remove(&tx.hash());
+            }
+        }
+        Ok(reverted)
+    }
+
+    /// Append a block committed by BFT consensus, after checking it against
+    /// the tip together with its commit certificate. The block becomes final.
+    pub fn commit_block(&mut self, block: Block) -> Result<&Block, ChainError> {
+        if block.commit.is_none() {
+            return Err(ValidationError::BadCommitCertificate { block: block.index }.into());
+        }
+        self.append_block(block)
+    }
+
+    /// Append a sealed block built elsewhere, e.g. replicated from a Raft
+    /// leader, after checking it against the tip like [`Blockchain::validate_block`].
+    /// A block carrying a commit certificate becomes final.
+    pub fn append_block(&mut self, block: Block) -> Result<&Block, ChainError> {
+        let tip = self.chain.last().unwrap();
+        let (registry, index) = self.check_successor(tip, &block, block.round())?;
+        if let Some(store) = &mut self.store {
+            store.append(&block)?;
+        }
+        self.registry = registry;
+        self.tx_index = index;
+        self.index_block(&block);
+        if block.commit.is_some() {
+            self.finalized_height = block.index;
+        }
+        self.chain.push(block);
+        self.step_required_bits();
+        Ok(self.chain.last().unwrap())
+    }
+
+    /// Build and seal the next block without appending it, e.g. to propose it
+    /// in BFT round `round`. Its registry transactions are checked only when
+    /// the block is validated.
+    pub fn propose_block(
+        &self,
+        transactions: Vec<Transaction>,
+        registry_txs: Vec<RegistryTransaction>,
+        round: u64,
+    ) -> Result<Block, ChainError> {
+        if !self.is_proposer(round) {
+            return Err(ChainError::NotProposer { height: self.chain.len() as u64 });
+        }
         let prev_block = self.chain.last().unwrap();
-        let block = Block {
+        let mut block = Block {
             index: prev_block.index + 1,
             prev_hash: prev_block.calculate_hash(),
-            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            // A clock behind the previous proposer's must not make the block invalid
+            timestamp: unix_now().max(prev_block.timestamp),
             transactions,
+            registry_txs,
             nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
         };
+        let key = self.validator_key.as_ref().expect("checked by is_proposer");
+        if self.pow().is_some() {
+            // The proposer is part of the mined header and the seal covers the nonce
+            block.proposer = key.verifying_key().to_bytes().to_vec();
+            pow::mine(&mut block, self.required_bits());
+        }
+        block.sign(key);
+        Ok(block)
+    }
+
+    /// Build and seal the next block, validate it like a received one, persist
+    /// it, then append it to the in-memory chain. Prescriptions already in the
+    /// chain or repeated in `transactions` are refused, so a replayed
+    /// prescription never lands twice.
+    fn push_block(&mut self, transactions: Vec<Transaction>, registry_txs: Vec<RegistryTransaction>) -> Result<&Block, ChainError> {
+        let block = self.propose_block(transactions, registry_txs, 0)?;
+        let (registry, index) = self.check_successor(self.chain.last().unwrap(), &block, 0)?;
+        if let Some(store) = &mut self.store {
+            store.append(&block)?;
+        }
+        self.registry = registry;
+        self.tx_index = index;
+        self.index_block(&block);
         self.chain.push(block);
-        self.chain.last().unwrap()
+        self.step_required_bits();
+        Ok(self.chain.last().unwrap())
     }
 
-    /// Validate the blockchain integrity
-    pub fn validate_chain(&self) -> bool {
-        for i in 1..self.chain.len() {
-            let prev = &self.chain[i - 1];
-            let curr = &self.chain[i];
+    /// Build an inclusion proof for the prescription with id `tx_id`
+    pub fn prove_transaction(&self, tx_id: &[u8; 32]) -> Option<TransactionProof> {
+        let (block, position) = self.find_transaction(tx_id)?;
+        Some(TransactionProof { header: block.header(), proof: block.transaction_proof(position)? })
+    }
 
-            // Validate block hash chain
-            if curr.prev_hash != prev.calculate_hash() {
-                return false;
+    /// Build a self-contained proof that the prescription with id `tx_id` is
+    /// in a block at or below `checkpoint`, linked by headers to the block at
+    /// `checkpoint`
+    pub fn prescription_proof(&self, tx_id: &[u8; 32], checkpoint: u64) -> Option<PrescriptionProof> {
+        let blocks = self.chain.get(..=checkpoint as usize)?;
+        let (block, position) = self.find_transaction(tx_id).filter(|(block, _)| block.index <= checkpoint)?;
+        Some(PrescriptionProof {
+            transaction: block.transactions[position].clone(),
+            proof: block.transaction_proof(position)?,
+            headers: blocks[block.index as usize..].iter().map(Block::header).collect(),
+        })
+    }
+
+    /// Whether `proof` shows `tx` is included in a block of this chain
+    pub fn verify_transaction_proof(&self, tx: &Transaction, proof: &TransactionProof) -> bool {
+        let Some(block) = self.chain.get(proof.header.index as usize) else {
+            return false;
+        };
+        proof.header.hash() == block.calculate_hash() && proof.header.verify_transaction(tx, &proof.proof)
+    }
+
+    /// Validate the blockchain integrity, reporting the first problem found.
+    ///
+    /// Every block after genesis must be sealed by the validator whose turn it
+    /// was (see [`crate::consensus`]) and, on networks with proof of work,
+    /// carry the work required at its height (see [`crate::pow`]).
+    ///
+    /// The registry is replayed from genesis so each prescription is checked
+    /// against the prescriber keys that were valid at its block's height: a
+    /// key revoked at height `h` invalidates prescriptions from `h` on, while
+    /// earlier prescriptions signed with it remain valid.
+    pub fn validate_chain(&self) -> Result<(), ValidationError> {
+        self.check_chain(&self.chain).map(|_| ())
+    }
+
+    /// Check every block of `chain` from this network's genesis, returning
+    /// the registry and prescription index as of its last block
+    fn check_chain(&self, chain: &[Block]) -> Result<(DoctorRegistry, HashMap<[u8; 32], u64>), ValidationError> {
+        check_genesis(&self.genesis, chain)?;
+        let mut registry = self.genesis.registry().expect("genesis registry was checked on construction");
+        let mut index = HashMap::new();
+        let mut bits = self.pow().map_or(0, |params| params.initial_bits);
+        for height in 1..chain.len() {
+            let block = &chain[height];
+            bits = self.pow().map_or(0, |params| pow::next_bits(params, bits, &chain[..height]));
+            self.check_block(&chain[..height], block, block.round(), bits, &mut registry, &mut index)?;
+        }
+        Ok((registry, index))
+    }
+
+    /// Validate `block` as the successor of `prev`, which must be the current
+    /// tip, without modifying the chain. Blocks following any other block are
+    /// refused as [`ValidationError::UnknownParent`].
+    pub fn validate_block(&self, prev: &Block, block: &Block) -> Result<(), ValidationError> {
+        self.check_successor(prev, block, block.round()).map(|_| ())
+    }
+
+    /// Validate `block` as the successor of `branch`, a chain from this
+    /// network's genesis that may fork from this one, e.g. a competing
+    /// branch received from a peer. Every block of `branch` must be valid.
+    pub fn validate_branch_block(&self, branch: &[Block], block: &Block) -> Result<(), ChainError> {
+        let mut registry = self.replay_registry(branch)?;
+        let mut index = tx_index(branch);
+        self.check_block(branch, block, block.round(), self.bits_after(branch), &mut registry, &mut index)?;
+        Ok(())
+    }
+
+    /// Validate `block`, proposed in BFT round `round`, as the successor of
+    /// the current tip without modifying the chain
+    pub fn validate_proposal(&self, block: &Block, round: u64) -> Result<(), ValidationError> {
+        self.check_successor(self.chain.last().unwrap(), block, round).map(|_| ())
+    }
+
+    /// Check `block`, proposed in BFT round `round`, as the successor of
+    /// `prev`, which must be the current tip, returning the registry and
+    /// prescription index as of `block`
+    fn check_successor(
+        &self,
+        prev: &Block,
+        block: &Block,
+        round: u64,
+    ) -> Result<(DoctorRegistry, HashMap<[u8; 32], u64>), ValidationError> {
+        let tip = self.chain.last().unwrap();
+        if prev.index != tip.index || prev.calculate_hash() != tip.calculate_hash() {
+            return Err(ValidationError::UnknownParent { block: block.index });
+        }
+        let mut registry = self.registry.clone();
+        let mut index = self.tx_index.clone();
+        self.check_block(&self.chain, block, round, self.required_bits, &mut registry, &mut index)?;
+        Ok((registry, index))
+    }
+
+    /// Check that `tx` can be included in the next block: addressed to this
+    /// network, signed by a key of its doctor authorized at that height, and
+    /// not already in the chain. Errors name the next block and position 0.
+    pub fn check_transaction(&self, tx: &Transaction) -> Result<(), ValidationError> {
+        let height = self.chain.len() as u64;
+        self.check_prescription(&self.registry, height, 0, tx)?;
+        if self.contains_transaction(tx) {
+            return Err(ValidationError::DuplicateTransaction { block: height, tx: 0 });
+        }
+        Ok(())
+    }
+
+    /// Check that the registry transaction `tx` can be included in the next
+    /// block: addressed to this network, signed by an admin key and applicable
+    /// to the registry at the tip
+    pub fn check_registry_transaction(&self, tx: &RegistryTransaction) -> Result<(), RegistryError> {
+        if tx.chain_id != self.genesis.chain_id {
+            return Err(RegistryError::WrongChain(tx.chain_id.clone()));
+        }
+        self.registry.check(tx, self.admin_keys(), self.chain.len() as u64)
+    }
+
+    /// Check that `tx`, at position `i` of block `height`, is addressed to
+    /// this network, expires after it is issued and is signed by a key
+    /// `registry` authorizes at that height
+    fn check_prescription(
+        &self,
+        registry: &DoctorRegistry,
+        height: u64,
+        i: usize,
+        tx: &Transaction,
+    ) -> Result<(), ValidationError> {
+        if tx.chain_id != self.genesis.chain_id {
+            return Err(ValidationError::WrongChain { block: height, tx: i, chain_id: tx.chain_id.clone() });
+        }
+        if tx.expires_at <= tx.issued_at {
+            return Err(ValidationError::InvalidValidityWindow { block: height, tx: i });
+        }
+        if !tx.verify_signature() {
+            return Err(ValidationError::BadSignature { block: height, tx: i });
+        }
+        if !registry.is_authorized(&tx.doctor_id, &tx.pubkey, height) {
+            let registered = registry
+                .get(&tx.doctor_id)
+                .is_some_and(|record| record.keys.iter().any(|k| k.pubkey == tx.pubkey));
+            let doctor_id = tx.doctor_id.clone();
+            return Err(if registered {
+                ValidationError::UnauthorizedSigner { block: height, tx: i, doctor_id }
+            } else {
+                ValidationError::UnknownSigner { block: height, tx: i, doctor_id }
+            });
+        }
+        Ok(())
+    }
+
+    /// Apply `tx`, included at `height`, to `registry` if it is addressed to
+    /// this network and signed by one of its admin keys
+    fn apply_registry_tx(&self, registry: &mut DoctorRegistry, tx: &RegistryTransaction, height: u64) -> Result<(), RegistryError> {
+        if tx.chain_id != self.genesis.chain_id {
+            return Err(RegistryError::WrongChain(tx.chain_id.clone()));
+        }
+        registry.apply(tx, self.admin_keys(), height)
+    }
+
+    /// Rebuild the doctor registry by applying every block's registry
+    /// transactions in order on top of the genesis registry
+    fn replay_registry(&self, chain: &[Block]) -> Result<DoctorRegistry, RegistryError> {
+        let mut registry = self.genesis.registry()?;
+        for block in chain {
+            for tx in &block.registry_txs {
+                self.apply_registry_tx(&mut registry, tx, block.index)?;
             }
+        }
+        Ok(registry)
+    }
+
+    /// Check that `headers` form a chain following the block whose hash is
+    /// `parent_hash` and are sealed by validators, e.g. before downloading the
+    /// blocks they describe. Whose turn it was, proof of work and the bodies
+    /// are checked when the blocks are imported.
+    pub fn check_headers(&self, parent_hash: &str, headers: &[BlockHeader]) -> Result<(), ValidationError> {
+        let mut expected_prev = parent_hash.to_string();
+        for (i, header) in headers.iter().enumerate() {
+            let height = headers[0].index + i as u64;
+            if header.index != height {
+                return Err(ValidationError::BadIndex { block: height, index: header.index });
+            }
+            if header.prev_hash != expected_prev {
+                return Err(ValidationError::BadPrevHash { block: height });
+            }
+            // The latest round accepts any validator as proposer
+            check_seal(self.validators(), header, u64::MAX)?;
+            expected_prev = header.hash();
+        }
+        Ok(())
+    }
 
-            // Validate transactions
-            for tx in &curr.transactions {
-                if !tx.verify_signature() {
-                    return false;
-                }
+    /// Check `block`, proposed in BFT round `round`, as the successor of the
+    /// last block of `chain` carrying `bits` of proof of work, applying its
+    /// registry changes to `registry` and recording its prescriptions in
+    /// `index`, which must hold those of `chain`. A prescription whose id is
+    /// already indexed is a replay.
+    fn check_block(
+        &self,
+        chain: &[Block],
+        block: &Block,
+        round: u64,
+        bits: u32,
+        registry: &mut DoctorRegistry,
+        index: &mut HashMap<[u8; 32], u64>,
+    ) -> Result<(), ValidationError> {
+        let prev = chain.last().expect("a chain holds at least its genesis block");
+        let height = prev.index + 1;
+        if block.index != height {
+            return Err(ValidationError::BadIndex { block: height, index: block.index });
+        }
+        if block.prev_hash != prev.calculate_hash() {
+            return Err(ValidationError::BadPrevHash { block: height });
+        }
+        if block.timestamp < prev.timestamp {
+            return Err(ValidationError::NonMonotonicTimestamp {
+                block: height,
+                timestamp: block.timestamp,
+                prev_timestamp: prev.timestamp,
+            });
+        }
+        if block.timestamp > unix_now() + MAX_CLOCK_DRIFT {
+            return Err(ValidationError::FutureTimestamp { block: height, timestamp: block.timestamp });
+        }
+        let header = block.header();
+        check_proposer(self.validators(), &header, round)?;
+        check_work(&header, bits)?;
+
+        // Replay registry changes before checking prescriptions
+        for (i, reg_tx) in block.registry_txs.iter().enumerate() {
+            self.apply_registry_tx(registry, reg_tx, height)
+                .map_err(|error| ValidationError::InvalidRegistryTx { block: height, tx: i, error })?;
+        }
+
+        for (i, tx) in block.transactions.iter().enumerate() {
+            self.check_prescription(registry, height, i, tx)?;
+            if index.insert(tx.id(), height).is_some() {
+                return Err(ValidationError::DuplicateTransaction { block: height, tx: i });
             }
         }
-        true
+
+        // The seal is checked last so a tampered transaction is reported as such
+        if !header.verify_signature() {
+            return Err(ValidationError::BadBlockSignature { block: height });
+        }
+        if let Some(commit) = &block.commit {
+            commit.verify(self.validators(), height, &header.hash())?;
+        }
+        Ok(())
+    }
+}
+
+/// Ensure `chain` starts with the genesis block derived from `genesis`
+fn check_genesis(genesis: &GenesisSpec, chain: &[Block]) -> Result<(), ValidationError> {
+    let expected = genesis.block().calculate_hash();
+    let found = chain.first().map(Block::calculate_hash).unwrap_or_default();
+    if found != expected {
+        return Err(ValidationError::GenesisMismatch { expected, found });
+    }
+    Ok(())
+}
+
+/// Ensure `header` carries `bits` leading zero bits of proof of work; 0 requires none
+fn check_work(header: &BlockHeader, bits: u32) -> Result<(), ValidationError> {
+    if bits > 0 && !pow::meets_target(header, bits) {
+        return Err(ValidationError::InsufficientWork { block: header.index, bits });
+    }
+    Ok(())
+}
+
+/// Current Unix time in seconds
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Height of the last block in `chain` with a commit certificate, or 0
+fn finalized_height(chain: &[Block]) -> u64 {
+    chain.iter().rev().find(|block| block.commit.is_some()).map_or(0, |block| block.index)
+}
+
+/// Height of each block of `chain`, by block hash
+fn block_heights(chain: &[Block]) -> HashMap<String, u64> {
+    chain.iter().map(|block| (block.calculate_hash(), block.index)).collect()
+}
+
+/// Height of the block holding each registry transaction of `chain`, by digest
+fn registry_index(chain: &[Block]) -> HashMap<[u8; 32], u64> {
+    chain.iter().flat_map(|block| block.registry_txs.iter().map(|tx| (tx.hash(), block.index))).collect()
+}
+
+/// Height of the block holding each prescription of `chain`, by transaction id
+fn tx_index(chain: &[Block]) -> HashMap<[u8; 32], u64> {
+    chain.iter().flat_map(|block| block.transactions.iter().map(|tx| (tx.id(), block.index))).collect()
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::{generate_keypair, sign_message};
+    use crate::crypto::generate_keypair;
+    use crate::registry::RegistryOp;
+    use crate::transaction::DEFAULT_CHAIN_ID;
+    use ed25519_dalek::SigningKey;
+
+    fn signed_tx(keypair: &SigningKey, doctor_id: &str, patient_id: &str, drug: &str) -> Transaction {
+        let mut tx = Transaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            doctor_id: doctor_id.to_string(),
+            patient_id: patient_id.to_string(),
+            drug: drug.to_string(),
+            dosage: "1 tablet daily".to_string(),
+            issued_at: 1_700_000_000,
+            expires_at: 1_702_592_000,
+            nonce: 1,
+            signature: vec![],
+            pubkey: vec![],
+        };
+        tx.sign(keypair);
+        tx
+    }
+
+    fn registry_tx(admin: &SigningKey, op: RegistryOp) -> RegistryTransaction {
+        let mut tx = RegistryTransaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            op,
+            nonce: 1,
+            admin_pubkey: vec![],
+            signature: vec![],
+        };
+        tx.sign(admin);
+        tx
+    }
+
+    fn register(admin: &SigningKey, doctor_id: &str, doctor: &SigningKey) -> RegistryTransaction {
+        registry_tx(admin, RegistryOp::RegisterDoctor {
+            doctor_id: doctor_id.to_string(),
+            license_number: format!("LIC-{}", doctor_id),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        })
+    }
+
+    /// Chain with a single authority whose key seals every block, plus that key
+    fn validator_chain() -> (Blockchain, SigningKey) {
+        let validator = generate_keypair();
+        let mut blockchain = Blockchain::with_admins(vec![validator.verifying_key().to_bytes().to_vec()]);
+        blockchain.set_validator_key(validator.clone());
+        (blockchain, validator)
+    }
+
+    /// Chain whose block 1 registers the given doctors, plus its admin key,
+    /// which is also the only validator
+    fn registered_chain(doctors: &[(&str, &SigningKey)]) -> (Blockchain, SigningKey) {
+        let (mut blockchain, admin) = validator_chain();
+        let registrations = doctors.iter().map(|(id, key)| register(&admin, id, key)).collect();
+        blockchain.add_registry_block(registrations).unwrap();
+        (blockchain, admin)
+    }
+
+    /// Seal `transactions` into the next block without validating them, as a
+    /// faulty or malicious validator would
+    fn force_block(blockchain: &mut Blockchain, transactions: Vec<Transaction>) {
+        let block = blockchain.propose_block(transactions, vec![], 0).unwrap();
+        blockchain.chain.push(block);
+    }
 
     #[test]
     fn test_blockchain_initialization() {
         let blockchain = Blockchain::new();
         assert_eq!(blockchain.chain.len(), 1, "Blockchain should start with genesis block");
         assert_eq!(blockchain.chain[0].index, 0, "Genesis block should have index 0");
-        assert_eq!(blockchain.chain[0].prev_hash, "0", "Genesis block should have prev_hash '0'");
+        assert_eq!(
+            blockchain.chain[0].prev_hash,
+            hex::encode(GenesisSpec::default().digest()),
+            "Genesis block should commit to the genesis spec"
+        );
     }
 
     #[test]
-    fn test_add_block() {
-        let mut blockchain = Blockchain::new();
+    fn test_genesis_is_shared_across_nodes() {
+        let node1 = Blockchain::new();
+        let node2 = Blockchain::new();
+        assert_eq!(node1.genesis_hash(), node2.genesis_hash(), "Nodes with the same spec should agree on genesis");
+        assert_ne!(node1.genesis_hash(), Blockchain::with_admins(vec![vec![1; 32]]).genesis_hash());
+    }
+
+    #[test]
+    fn test_genesis_doctors_can_prescribe() {
         let keypair = generate_keypair();
-        let sig = sign_message(&keypair, b"Aspirin");
-        
-        let tx = Transaction {
+        let validator = generate_keypair();
+        let mut genesis = GenesisSpec::with_authorities(vec![validator.verifying_key().to_bytes().to_vec()]);
+        genesis.doctors.push(crate::genesis::GenesisDoctor {
             doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: "Aspirin".to_string(),
-            signature: sig.to_bytes().to_vec(),
+            license_number: "LIC-1".to_string(),
             pubkey: keypair.verifying_key().to_bytes().to_vec(),
-        };
+        });
+        let mut blockchain = Blockchain::from_genesis(genesis).unwrap();
+        blockchain.set_validator_key(validator);
 
-        let genesis_hash = blockchain.chain[0].calculate_hash();
+        blockchain.add_block(vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")]).unwrap();
+        assert!(blockchain.validate_chain().is_ok(), "Doctors from the genesis spec need no registry block");
+    }
+
+    #[test]
+    fn test_replace_chain_refuses_other_genesis() {
+        let mut local = Blockchain::new();
+        let (mut remote, _) = validator_chain();
+        remote.add_block(vec![]).unwrap();
+
+        let result = local.replace_chain(remote.chain.clone());
+        assert!(matches!(result, Err(ChainError::Invalid(ValidationError::GenesisMismatch { .. }))));
+        assert_eq!(local.chain.len(), 1, "Chain from another network should not be adopted");
+    }
+
+    #[test]
+    fn test_add_block() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
+
+        let tip_hash = blockchain.chain[1].calculate_hash();
         let block_index = {
-            let block = blockchain.add_block(vec![tx]);
+            let block = blockchain.add_block(vec![tx]).unwrap();
             block.index
         };
-        assert_eq!(blockchain.chain.len(), 2, "Blockchain should have 2 blocks after adding one");
-        assert_eq!(block_index, 1, "New block should have index 1");
-        assert_eq!(blockchain.chain[1].prev_hash, genesis_hash, "New block should reference previous block hash");
+        assert_eq!(blockchain.chain.len(), 3, "Blockchain should have 3 blocks after adding one");
+        assert_eq!(block_index, 2, "New block should have index 2");
+        assert_eq!(blockchain.chain[2].prev_hash, tip_hash, "New block should reference previous block hash");
+    }
+
+    #[test]
+    fn test_add_block_refuses_invalid_transactions() {
+        let (mut blockchain, _) = registered_chain(&[]);
+        let tx = signed_tx(&generate_keypair(), "doctor1", "patient1", "Aspirin");
+
+        let result = blockchain.add_block(vec![tx]);
+        assert!(matches!(result, Err(ChainError::Invalid(ValidationError::UnknownSigner { block: 2, tx: 0, .. }))));
+        assert_eq!(blockchain.chain.len(), 2, "A block that fails validation should not be appended");
+    }
+
+    #[test]
+    fn test_proposed_timestamp_never_goes_backwards() {
+        let (mut blockchain, _) = validator_chain();
+        let ahead = unix_now() + MAX_CLOCK_DRIFT / 2;
+        blockchain.chain[0].timestamp = ahead;
+
+        let block = blockchain.add_block(vec![]).unwrap();
+        assert_eq!(block.timestamp, ahead, "A tip stamped ahead of the local clock should not make the next block invalid");
+    }
+
+    #[test]
+    fn test_validate_rejects_future_timestamp() {
+        let (blockchain, validator) = validator_chain();
+        let mut block = blockchain.propose_block(vec![], vec![], 0).unwrap();
+        block.timestamp = unix_now() + MAX_CLOCK_DRIFT + 60;
+        block.sign(&validator);
+
+        assert!(matches!(
+            blockchain.validate_block(&blockchain.chain[0], &block),
+            Err(ValidationError::FutureTimestamp { block: 1, .. })
+        ));
     }
 
     #[test]
     fn test_validate_valid_chain() {
-        let mut blockchain = Blockchain::new();
         let keypair = generate_keypair();
-        let sig = sign_message(&keypair, b"Aspirin");
-        
-        let tx = Transaction {
-            doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: "Aspirin".to_string(),
-            signature: sig.to_bytes().to_vec(),
-            pubkey: keypair.verifying_key().to_bytes().to_vec(),
-        };
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
 
-        blockchain.add_block(vec![tx]);
-        assert!(blockchain.validate_chain(), "Valid chain should pass validation");
+        blockchain.add_block(vec![tx]).unwrap();
+        assert!(blockchain.validate_chain().is_ok(), "Valid chain should pass validation");
     }
 
     #[test]
     fn test_validate_invalid_chain_broken_link() {
-        let mut blockchain = Blockchain::new();
         let keypair = generate_keypair();
-        let sig = sign_message(&keypair, b"Aspirin");
-        
-        let tx = Transaction {
-            doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: "Aspirin".to_string(),
-            signature: sig.to_bytes().to_vec(),
-            pubkey: keypair.verifying_key().to_bytes().to_vec(),
-        };
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
 
-        blockchain.add_block(vec![tx]);
+        blockchain.add_block(vec![tx]).unwrap();
         // Corrupt the chain by modifying prev_hash
-        blockchain.chain[1].prev_hash = "corrupted".to_string();
-        assert!(!blockchain.validate_chain(), "Chain with broken link should fail validation");
+        blockchain.chain[2].prev_hash = "corrupted".to_string();
+        assert_eq!(
+            blockchain.validate_chain(),
+            Err(ValidationError::BadPrevHash { block: 2 }),
+            "Chain with broken link should fail validation"
+        );
     }
 
     #[test]
     fn test_validate_invalid_chain_invalid_transaction() {
-        let mut blockchain = Blockchain::new();
         let keypair = generate_keypair();
-        let sig = sign_message(&keypair, b"Aspirin");
-        
-        let mut tx = Transaction {
-            doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: "Aspirin".to_string(),
-            signature: sig.to_bytes().to_vec(),
-            pubkey: keypair.verifying_key().to_bytes().to_vec(),
-        };
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let mut tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
 
-        blockchain.add_block(vec![tx.clone()]);
+        blockchain.add_block(vec![tx.clone()]).unwrap();
         // Corrupt the transaction signature
         tx.signature[0] = tx.signature[0].wrapping_add(1);
-        blockchain.chain[1].transactions[0] = tx;
+        blockchain.chain[2].transactions[0] = tx;
         
-        assert!(!blockchain.validate_chain(), "Chain with invalid transaction should fail validation");
+        assert_eq!(
+            blockchain.validate_chain(),
+            Err(ValidationError::BadSignature { block: 2, tx: 0 }),
+            "Chain with invalid transaction should fail validation"
+        );
+    }
+
+    #[test]
+    fn test_validate_invalid_chain_swapped_patient() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
+
+        blockchain.add_block(vec![tx]).unwrap();
+        // Redirect the prescription to another patient without re-signing
+        blockchain.chain[2].transactions[0].patient_id = "patient2".to_string();
+
+        assert_eq!(
+            blockchain.validate_chain(),
+            Err(ValidationError::BadSignature { block: 2, tx: 0 }),
+            "Chain with a tampered prescription should fail validation"
+        );
     }
 
     #[test]
     fn test_multiple_blocks() {
-        let mut blockchain = Blockchain::new();
         let keypair1 = generate_keypair();
         let keypair2 = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair1), ("doctor2", &keypair2)]);
+
+        let tx1 = signed_tx(&keypair1, "doctor1", "patient1", "Aspirin");
+        let tx2 = signed_tx(&keypair2, "doctor2", "patient2", "Ibuprofen");
+
+        blockchain.add_block(vec![tx1]).unwrap();
+        blockchain.add_block(vec![tx2]).unwrap();
+        
+        assert_eq!(blockchain.chain.len(), 4, "Blockchain should have 4 blocks (genesis, registry, 2 prescriptions)");
+        assert!(blockchain.validate_chain().is_ok(), "Multi-block chain should be valid");
+    }
+
+    #[test]
+    fn test_validate_rejects_unregistered_doctor() {
+        let (mut blockchain, _) = registered_chain(&[]);
+        let tx = signed_tx(&generate_keypair(), "doctor1", "patient1", "Aspirin");
+
+        force_block(&mut blockchain, vec![tx]);
+        assert!(
+            matches!(blockchain.validate_chain(), Err(ValidationError::UnknownSigner { block: 2, tx: 0, .. })),
+            "Prescription from an unregistered doctor should fail validation"
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_key_of_other_doctor() {
+        let keypair1 = generate_keypair();
+        let keypair2 = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair1), ("doctor2", &keypair2)]);
+
+        // doctor2's registered key signing on behalf of doctor1
+        force_block(&mut blockchain, vec![signed_tx(&keypair2, "doctor1", "patient1", "Oxycodone")]);
+        assert!(
+            matches!(blockchain.validate_chain(), Err(ValidationError::UnknownSigner { .. })),
+            "A key may only sign for its own doctor id"
+        );
+    }
 
-        let sig1 = sign_message(&keypair1, b"Aspirin");
-        let tx1 = Transaction {
+    #[test]
+    fn test_validate_rejects_suspended_doctor() {
+        let keypair = generate_keypair();
+        let (mut blockchain, admin) = registered_chain(&[("doctor1", &keypair)]);
+        blockchain.add_block(vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")]).unwrap();
+
+        let suspend = registry_tx(&admin, RegistryOp::SetStatus {
             doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: "Aspirin".to_string(),
-            signature: sig1.to_bytes().to_vec(),
-            pubkey: keypair1.verifying_key().to_bytes().to_vec(),
-        };
+            status: crate::registry::DoctorStatus::Suspended,
+        });
+        blockchain.add_registry_block(vec![suspend]).unwrap();
+        assert!(blockchain.validate_chain().is_ok(), "Prescriptions before the suspension stay valid");
+
+        force_block(&mut blockchain, vec![signed_tx(&keypair, "doctor1", "patient2", "Aspirin")]);
+        assert!(
+            matches!(blockchain.validate_chain(), Err(ValidationError::UnauthorizedSigner { block: 4, .. })),
+            "Prescriptions after the suspension should fail validation"
+        );
+    }
+
+    #[test]
+    fn test_registry_block_requires_admin() {
+        let (mut blockchain, _) = registered_chain(&[]);
+        let intruder = generate_keypair();
+
+        let result = blockchain.add_registry_block(vec![register(&intruder, "doctor1", &generate_keypair())]);
+        assert!(matches!(result, Err(ChainError::Registry(RegistryError::UnknownAdmin))));
+        assert_eq!(blockchain.chain.len(), 2, "Rejected registry block should not be appended");
+        assert!(blockchain.registry().get("doctor1").is_none());
+    }
+
+    #[test]
+    fn test_registry_tx_for_other_chain_rejected() {
+        let (mut blockchain, admin) = registered_chain(&[]);
+        let mut foreign = register(&admin, "doctor1", &generate_keypair());
+        foreign.chain_id = "securerx-other".to_string();
+        foreign.sign(&admin);
+
+        assert!(matches!(blockchain.check_registry_transaction(&foreign), Err(RegistryError::WrongChain(_))));
+        let result = blockchain.add_registry_block(vec![foreign.clone()]);
+        assert!(matches!(result, Err(ChainError::Registry(RegistryError::WrongChain(_)))));
+
+        // A block sealed elsewhere with the same admin keys is caught by validation
+        let block = blockchain.propose_block(vec![], vec![foreign], 0).unwrap();
+        blockchain.chain.push(block);
+        assert!(matches!(
+            blockchain.validate_chain(),
+            Err(ValidationError::InvalidRegistryTx { block: 2, tx: 0, error: RegistryError::WrongChain(_) })
+        ));
+    }
+
+    #[test]
+    fn test_validate_rotated_key() {
+        let old = generate_keypair();
+        let new = generate_keypair();
+        let (mut blockchain, admin) = registered_chain(&[("doctor1", &old)]);
+        blockchain.add_block(vec![signed_tx(&old, "doctor1", "patient1", "Aspirin")]).unwrap();
+
+        let rotate = registry_tx(&admin, RegistryOp::RotateKey {
+            doctor_id: "doctor1".to_string(),
+            old_pubkey: old.verifying_key().to_bytes().to_vec(),
+            new_pubkey: new.verifying_key().to_bytes().to_vec(),
+        });
+        blockchain.add_registry_block(vec![rotate]).unwrap();
+        blockchain.add_block(vec![signed_tx(&new, "doctor1", "patient2", "Aspirin")]).unwrap();
+        assert!(blockchain.validate_chain().is_ok(), "History signed with the old key and new prescriptions with the new key are valid");
+
+        force_block(&mut blockchain, vec![signed_tx(&old, "doctor1", "patient3", "Oxycodone")]);
+        assert!(
+            matches!(blockchain.validate_chain(), Err(ValidationError::UnauthorizedSigner { .. })),
+            "Old key may not sign after rotation"
+        );
+    }
+
+    #[test]
+    fn test_validate_revoked_key_after_effective_height() {
+        let keypair = generate_keypair();
+        let (mut blockchain, admin) = registered_chain(&[("doctor1", &keypair)]);
+
+        // Revoke from height 4: blocks 2 and 3 may still use the key
+        let revoke = registry_tx(&admin, RegistryOp::RevokeKey {
+            doctor_id: "doctor1".to_string(),
+            pubkey: keypair.verifying_key().to_bytes().to_vec(),
+            effective_height: 4,
+        });
+        blockchain.add_registry_block(vec![revoke]).unwrap();
+        blockchain.add_block(vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")]).unwrap();
+        assert_eq!(blockchain.chain.len(), 4);
+        assert!(blockchain.validate_chain().is_ok(), "Prescription below the revocation height stays valid");
+
+        force_block(&mut blockchain, vec![signed_tx(&keypair, "doctor1", "patient2", "Aspirin")]);
+        assert!(blockchain.validate_chain().is_err(), "Prescription at the revocation height should fail validation");
+    }
+
+    #[test]
+    fn test_retroactive_revocation_rejected() {
+        let keypair = generate_keypair();
+        let (mut blockchain, admin) = registered_chain(&[("doctor1", &keypair)]);
+        blockchain.add_block(vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")]).unwrap();
+
+        let revoke = registry_tx(&admin, RegistryOp::RevokeKey {
+            doctor_id: "doctor1".to_string(),
+            pubkey: keypair.verifying_key().to_bytes().to_vec(),
+            effective_height: 1,
+        });
+        let result = blockchain.add_registry_block(vec![revoke]);
+        assert!(matches!(
+            result,
+            Err(ChainError::Registry(RegistryError::RetroactiveRevocation { effective_height: 1, current_height: 3 }))
+        ));
+        assert!(blockchain.validate_chain().is_ok());
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("securerx-chain-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_open_reloads_persisted_chain() {
+        use crate::storage::{open_store, StorageBackend};
+
+        for backend in [StorageBackend::File, StorageBackend::Kv] {
+            let dir = temp_dir(&format!("{:?}", backend));
+            let admin = generate_keypair();
+            let genesis = GenesisSpec::with_authorities(vec![admin.verifying_key().to_bytes().to_vec()]);
+            let keypair = generate_keypair();
+
+            let expected = {
+                let mut blockchain = Blockchain::open(genesis.clone(), open_store(&dir, backend).unwrap()).unwrap();
+                blockchain.set_validator_key(admin.clone());
+                blockchain.add_registry_block(vec![register(&admin, "doctor1", &keypair)]).unwrap();
+                blockchain.add_block(vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")]).unwrap();
+                blockchain.chain.iter().map(Block::calculate_hash).collect::<Vec<_>>()
+            };
+
+            let blockchain = Blockchain::open(genesis, open_store(&dir, backend).unwrap()).unwrap();
+            assert_eq!(blockchain.chain.iter().map(Block::calculate_hash).collect::<Vec<_>>(), expected);
+            assert!(blockchain.registry().get("doctor1").is_some(), "Registry should be rebuilt on open");
+            assert!(blockchain.validate_chain().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_replace_chain_rewrites_store() {
+        use crate::storage::{open_store, StorageBackend};
+
+        let dir = temp_dir("replace");
+        let (mut remote, validator) = validator_chain();
+        let genesis = remote.genesis().clone();
+        let mut local = Blockchain::open(genesis.clone(), open_store(&dir, StorageBackend::File).unwrap()).unwrap();
+        local.set_validator_key(validator.clone());
+        local.add_block(vec![]).unwrap();
+
+        remote.add_block(vec![]).unwrap();
+        remote.add_block(vec![]).unwrap();
+        remote.chain[1].nonce = 1;
+        remote.chain[1].sign(&validator);
+        remote.chain[2].prev_hash = remote.chain[1].calculate_hash();
+        remote.chain[2].sign(&validator);
+
+        local.replace_chain(remote.chain.clone()).unwrap();
+        let reopened = Blockchain::open(genesis, open_store(&dir, StorageBackend::File).unwrap()).unwrap();
+        assert_eq!(reopened.chain.len(), 3);
+        assert_eq!(reopened.chain[1].nonce, 1, "Diverging blocks should be replaced on disk");
+    }
+
+    #[test]
+    fn test_rollback_rebuilds_derived_state() {
+        use crate::storage::{open_store, StorageBackend};
+
+        let dir = temp_dir("rollback");
+        let keypair = generate_keypair();
+        let (registered, admin) = registered_chain(&[("doctor1", &keypair)]);
+        let genesis = registered.genesis().clone();
+        let mut blockchain = Blockchain::open(genesis.clone(), open_store(&dir, StorageBackend::File).unwrap()).unwrap();
+        blockchain.set_validator_key(admin);
+        blockchain.append_block(registered.chain[1].clone()).unwrap();
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
+        blockchain.add_block(vec![tx.clone()]).unwrap();
+        let tip = blockchain.chain[2].calculate_hash();
+        assert_eq!(blockchain.height_of(&tip), Some(2));
+        let registration = registered.chain[1].registry_txs[0].hash();
+        assert_eq!(blockchain.registry_transaction_height(&registration), Some(1));
+        assert_eq!(blockchain.block_by_hash(&blockchain.genesis_hash()).map(|block| block.index), Some(0));
+
+        let reverted = blockchain.rollback(0).unwrap();
+        assert_eq!(reverted.iter().map(|block| block.index).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(blockchain.registry().get("doctor1").is_none());
+        assert!(!blockchain.contains_transaction(&tx));
+        assert_eq!(blockchain.height_of(&tip), None, "Rolled back blocks should leave the hash index");
+        assert_eq!(blockchain.registry_transaction_height(&registration), None);
+        assert!(blockchain.rollback(5).unwrap().is_empty());
+        let reopened = Blockchain::open(genesis, open_store(&dir, StorageBackend::File).unwrap()).unwrap();
+        assert_eq!(reopened.chain.len(), 1, "Rolled back blocks should be removed from the store");
+    }
+
+    #[test]
+    fn test_open_refuses_other_genesis() {
+        use crate::storage::{open_store, StorageBackend};
+
+        let dir = temp_dir("other-genesis");
+        drop(Blockchain::open(GenesisSpec::default(), open_store(&dir, StorageBackend::File).unwrap()).unwrap());
+
+        let other = GenesisSpec::with_authorities(vec![vec![1; 32]]);
+        let result = Blockchain::open(other, open_store(&dir, StorageBackend::File).unwrap());
+        assert!(matches!(result, Err(ChainError::Invalid(ValidationError::GenesisMismatch { .. }))));
+    }
 
-        let sig2 = sign_message(&keypair2, b"Ibuprofen");
-        let tx2 = Transaction {
-            doctor_id: "doctor2".to_string(),
-            patient_id: "patient2".to_string(),
-            drug: "Ibuprofen".to_string(),
-            signature: sig2.to_bytes().to_vec(),
-            pubkey: keypair2.verifying_key().to_bytes().to_vec(),
+    #[test]
+    fn test_open_refuses_invalid_stored_blocks() {
+        use crate::storage::{open_store, StorageBackend};
+
+        let dir = temp_dir("tampered-store");
+        let keypair = generate_keypair();
+        let (mut blockchain, admin) = registered_chain(&[("doctor1", &keypair)]);
+        blockchain.add_block(vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")]).unwrap();
+        let genesis = blockchain.genesis().clone();
+        let mut blocks = blockchain.chain.clone();
+        blocks[2].transactions[0].drug = "Oxycodone".to_string();
+        blocks[2].sign(&admin);
+        {
+            let mut store = open_store(&dir, StorageBackend::File).unwrap();
+            for block in &blocks {
+                store.append(block).unwrap();
+            }
+        }
+
+        let result = Blockchain::open(genesis, open_store(&dir, StorageBackend::File).unwrap());
+        assert!(matches!(result, Err(ChainError::Invalid(ValidationError::BadSignature { block: 2, tx: 0 }))));
+    }
+
+    #[test]
+    fn test_validate_rejects_duplicate_transaction() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Oxycodone");
+
+        blockchain.add_block(vec![tx.clone()]).unwrap();
+        assert!(blockchain.contains_transaction(&tx));
+        assert_eq!(blockchain.transaction_height(&tx.id()), Some(2));
+
+        // A replay sealed by a validator that skipped the check is caught by validation
+        let replay = blockchain.propose_block(vec![tx], vec![], 0).unwrap();
+        blockchain.chain.push(replay);
+        assert_eq!(blockchain.validate_chain(), Err(ValidationError::DuplicateTransaction { block: 3, tx: 0 }));
+    }
+
+    #[test]
+    fn test_replayed_prescriptions_are_refused() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Oxycodone");
+        assert!(matches!(
+            blockchain.add_block(vec![tx.clone(), tx.clone()]),
+            Err(ChainError::Invalid(ValidationError::DuplicateTransaction { block: 2, tx: 1 }))
+        ));
+        blockchain.add_block(vec![tx.clone()]).unwrap();
+        assert!(matches!(
+            blockchain.add_block(vec![tx.clone()]),
+            Err(ChainError::Invalid(ValidationError::DuplicateTransaction { block: 3, tx: 0 }))
+        ));
+        assert_eq!(blockchain.chain.len(), 3, "Nothing is appended");
+
+        let mut renewed = tx.clone();
+        renewed.nonce += 1;
+        renewed.sign(&keypair);
+        blockchain.add_block(vec![renewed.clone()]).unwrap();
+        assert_eq!(blockchain.find_transaction(&renewed.id()).map(|(block, i)| (block.index, i)), Some((3, 0)));
+        assert_eq!(blockchain.find_transaction(&tx.id()).map(|(block, i)| (block.index, i)), Some((2, 0)));
+        assert_eq!(blockchain.validate_chain(), Ok(()));
+
+        blockchain.rollback(2).unwrap();
+        assert_eq!(blockchain.transaction_height(&renewed.id()), None, "Rolled back prescriptions leave the index");
+        assert_eq!(blockchain.transaction_height(&tx.id()), Some(2));
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_index_and_timestamp() {
+        let (mut blockchain, validator) = validator_chain();
+        blockchain.add_block(vec![]).unwrap();
+        blockchain.add_block(vec![]).unwrap();
+        let tip = blockchain.chain.last().unwrap();
+        let next = blockchain.propose_block(vec![], vec![], 0).unwrap();
+        assert_eq!(blockchain.validate_block(tip, &next), Ok(()));
+
+        let mut skipped = next.clone();
+        skipped.index = 5;
+        skipped.sign(&validator);
+        assert_eq!(blockchain.validate_block(tip, &skipped), Err(ValidationError::BadIndex { block: 3, index: 5 }));
+
+        let mut rewound = next.clone();
+        rewound.timestamp = tip.timestamp - 1;
+        rewound.sign(&validator);
+        assert!(matches!(
+            blockchain.validate_block(tip, &rewound),
+            Err(ValidationError::NonMonotonicTimestamp { block: 3, .. })
+        ));
+
+        let below_tip = &blockchain.chain[1];
+        assert_eq!(
+            blockchain.validate_block(below_tip, &next),
+            Err(ValidationError::UnknownParent { block: 3 }),
+            "Blocks are only validated on top of the tip"
+        );
+        let mut forged = tip.clone();
+        forged.timestamp += 1;
+        assert_eq!(
+            blockchain.validate_block(&forged, &next),
+            Err(ValidationError::UnknownParent { block: 3 }),
+            "A parent that is not the tip is refused even at the tip's height"
+        );
+        let mut orphan = tip.clone();
+        orphan.index = 7;
+        assert_eq!(blockchain.validate_block(&orphan, &next), Err(ValidationError::UnknownParent { block: 3 }));
+    }
+
+    #[test]
+    fn test_validate_block_extends_tip() {
+        let keypair = generate_keypair();
+        let (blockchain, admin) = registered_chain(&[("doctor1", &keypair)]);
+        let tip = blockchain.chain.last().unwrap();
+        let mut next = Block {
+            index: tip.index + 1,
+            prev_hash: tip.calculate_hash(),
+            timestamp: tip.timestamp,
+            transactions: vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")],
+            registry_txs: vec![],
+            nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
         };
+        next.sign(&admin);
+        assert_eq!(blockchain.validate_block(tip, &next), Ok(()));
 
-        blockchain.add_block(vec![tx1]);
-        blockchain.add_block(vec![tx2]);
-        
-        assert_eq!(blockchain.chain.len(), 3, "Blockchain should have 3 blocks");
-        assert!(blockchain.validate_chain(), "Multi-block chain should be valid");
+        next.transactions[0].chain_id = "securerx-other".to_string();
+        assert!(matches!(blockchain.validate_block(tip, &next), Err(ValidationError::WrongChain { block: 2, tx: 0, .. })));
+
+        next.transactions[0] = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
+        next.transactions[0].expires_at = next.transactions[0].issued_at - 1;
+        next.transactions[0].sign(&keypair);
+        next.sign(&admin);
+        assert_eq!(blockchain.validate_block(tip, &next), Err(ValidationError::InvalidValidityWindow { block: 2, tx: 0 }));
+    }
+
+    #[test]
+    fn test_check_pending_transaction() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
+        assert_eq!(blockchain.check_transaction(&tx), Ok(()));
+
+        let stranger = signed_tx(&generate_keypair(), "doctor2", "patient1", "Aspirin");
+        assert!(matches!(blockchain.check_transaction(&stranger), Err(ValidationError::UnknownSigner { block: 2, tx: 0, .. })));
+        let mut tampered = tx.clone();
+        tampered.drug = "Oxycodone".to_string();
+        assert_eq!(blockchain.check_transaction(&tampered), Err(ValidationError::BadSignature { block: 2, tx: 0 }));
+        let mut inverted = tx.clone();
+        inverted.expires_at = inverted.issued_at;
+        inverted.sign(&keypair);
+        assert_eq!(blockchain.check_transaction(&inverted), Err(ValidationError::InvalidValidityWindow { block: 2, tx: 0 }));
+
+        blockchain.add_block(vec![tx.clone()]).unwrap();
+        assert_eq!(blockchain.check_transaction(&tx), Err(ValidationError::DuplicateTransaction { block: 3, tx: 0 }));
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let (mut blockchain, _) = validator_chain();
+        for _ in 0..3 {
+            blockchain.add_block(vec![]).unwrap();
+        }
+        let genesis_hash = blockchain.genesis_hash();
+        let headers: Vec<BlockHeader> = blockchain.chain[1..].iter().map(Block::header).collect();
+        assert_eq!(blockchain.check_headers(&genesis_hash, &headers), Ok(()));
+        assert_eq!(blockchain.check_headers(&genesis_hash, &headers[1..]), Err(ValidationError::BadPrevHash { block: 2 }));
+
+        let mut skipped = headers.clone();
+        skipped.remove(1);
+        assert_eq!(blockchain.check_headers(&genesis_hash, &skipped), Err(ValidationError::BadIndex { block: 2, index: 3 }));
+
+        let mut forged = headers;
+        forged[2].timestamp += 1;
+        assert_eq!(blockchain.check_headers(&genesis_hash, &forged), Err(ValidationError::BadBlockSignature { block: 3 }));
+    }
+
+    #[test]
+    fn test_transaction_inclusion_proof() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let txs: Vec<_> = ["patient1", "patient2", "patient3"]
+            .iter()
+            .map(|patient| signed_tx(&keypair, "doctor1", patient, "Aspirin"))
+            .collect();
+        blockchain.add_block(txs.clone()).unwrap();
+
+        let proof = blockchain.prove_transaction(&txs[1].id()).unwrap();
+        assert_eq!(proof.header, blockchain.chain[2].header());
+        assert_eq!(proof.proof.index, 1);
+        assert!(blockchain.verify_transaction_proof(&txs[1], &proof));
+        assert!(!blockchain.verify_transaction_proof(&txs[0], &proof), "Proof is only valid for its own transaction");
+
+        let mut forged = proof.clone();
+        forged.header.tx_root = [0; 32];
+        assert!(!blockchain.verify_transaction_proof(&txs[1], &forged), "Header must match the chain");
+
+        let unknown = signed_tx(&keypair, "doctor1", "patient9", "Aspirin");
+        assert!(blockchain.prove_transaction(&unknown.id()).is_none());
+    }
+
+    #[test]
+    fn test_prescription_proof_to_checkpoint() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        let tx = signed_tx(&keypair, "doctor1", "patient1", "Aspirin");
+        blockchain.add_block(vec![tx.clone()]).unwrap();
+        blockchain.add_block(vec![]).unwrap();
+
+        let proof = blockchain.prescription_proof(&tx.id(), 3).unwrap();
+        assert_eq!(proof.headers.len(), 2);
+        assert_eq!(proof.verify(&blockchain.chain[3].calculate_hash()), Ok(2));
+
+        assert!(blockchain.prescription_proof(&tx.id(), 1).is_none(), "Not yet included at the checkpoint");
+        assert!(blockchain.prescription_proof(&tx.id(), 4).is_none(), "Checkpoint is beyond the tip");
+    }
+
+    #[test]
+    fn test_block_hash_commits_to_body() {
+        let keypair = generate_keypair();
+        let (mut blockchain, _) = registered_chain(&[("doctor1", &keypair)]);
+        blockchain.add_block(vec![signed_tx(&keypair, "doctor1", "patient1", "Aspirin")]).unwrap();
+
+        let hash = blockchain.chain[2].calculate_hash();
+        blockchain.chain[2].transactions[0].signature[0] ^= 1;
+        assert_ne!(blockchain.chain[2].calculate_hash(), hash, "Changing a transaction changes the tx root");
+        let hash = blockchain.chain[1].calculate_hash();
+        blockchain.chain[1].registry_txs[0].nonce += 1;
+        assert_ne!(blockchain.chain[1].calculate_hash(), hash, "Changing a registry transaction changes the registry root");
+    }
+
+    #[test]
+    fn test_validators_take_turns() {
+        let keys = [generate_keypair(), generate_keypair()];
+        let mut blockchain = Blockchain::with_admins(keys.iter().map(|k| k.verifying_key().to_bytes().to_vec()).collect());
+        assert!(matches!(blockchain.add_block(vec![]), Err(ChainError::NotProposer { height: 1 })), "Needs a validator key");
+
+        blockchain.set_validator_key(keys[0].clone());
+        assert!(!blockchain.is_next_proposer());
+        assert!(matches!(blockchain.add_block(vec![]), Err(ChainError::NotProposer { height: 1 })));
+        assert_eq!(blockchain.chain.len(), 1, "Out-of-turn block should not be appended");
+
+        for height in 1..=4 {
+            blockchain.set_validator_key(keys[height % 2].clone());
+            assert!(blockchain.is_next_proposer());
+            blockchain.add_block(vec![]).unwrap();
+        }
+        assert_eq!(blockchain.chain[3].proposer, keys[1].verifying_key().to_bytes().to_vec());
+        assert_eq!(blockchain.validate_chain(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_rejects_unsealed_and_out_of_turn_blocks() {
+        let keys = [generate_keypair(), generate_keypair()];
+        let mut blockchain = Blockchain::with_admins(keys.iter().map(|k| k.verifying_key().to_bytes().to_vec()).collect());
+        blockchain.set_validator_key(keys[1].clone());
+        let tip = blockchain.chain[0].clone();
+        let block = blockchain.propose_block(vec![], vec![], 0).unwrap();
+        assert_eq!(blockchain.validate_block(&tip, &block), Ok(()));
+
+        let mut outsider = block.clone();
+        outsider.sign(&generate_keypair());
+        assert!(matches!(
+            blockchain.validate_block(&tip, &outsider),
+            Err(ValidationError::UnknownProposer { block: 1, .. })
+        ));
+
+        let mut out_of_turn = block.clone();
+        out_of_turn.sign(&keys[0]);
+        assert!(matches!(
+            blockchain.validate_block(&tip, &out_of_turn),
+            Err(ValidationError::WrongProposer { block: 1, .. })
+        ));
+
+        let mut unsigned = block.clone();
+        unsigned.signature.clear();
+        assert_eq!(
+            blockchain.validate_block(&tip, &unsigned),
+            Err(ValidationError::BadBlockSignature { block: 1 })
+        );
+
+        let mut local = Blockchain::with_admins(blockchain.validators().to_vec());
+        let result = local.replace_chain(vec![tip, out_of_turn]);
+        assert!(matches!(result, Err(ChainError::Invalid(ValidationError::WrongProposer { .. }))));
+        assert_eq!(local.chain.len(), 1, "Chain sealed out of turn should not be adopted");
+    }
+
+    /// Commit certificate for `block` signed by `keys`
+    fn certify(block: &mut Block, keys: &[SigningKey], round: u64) {
+        use crate::consensus::{CommitCertificate, Vote, VoteKind};
+        let hash = block.calculate_hash();
+        let precommits = keys.iter().map(|k| Vote::new(VoteKind::Precommit, block.index, round, Some(hash.clone()), k)).collect();
+        block.commit = Some(CommitCertificate { round, precommits });
+    }
+
+    #[test]
+    fn test_commit_block_finalizes() {
+        let keys = [generate_keypair(), generate_keypair(), generate_keypair()];
+        let mut blockchain = Blockchain::with_admins(keys.iter().map(|k| k.verifying_key().to_bytes().to_vec()).collect());
+        blockchain.set_validator_key(keys[2].clone());
+
+        // Block 1 is committed in round 1, so it is proposed by validator 2
+        let mut block = blockchain.propose_block(vec![], vec![], 1).unwrap();
+        assert!(blockchain.commit_block(block.clone()).is_err(), "A certificate is required");
+        certify(&mut block, &keys[..2], 1);
+        assert!(matches!(
+            blockchain.commit_block(block.clone()),
+            Err(ChainError::Invalid(ValidationError::BadCommitCertificate { block: 1 }))
+        ));
+        certify(&mut block, &keys, 1);
+        blockchain.commit_block(block).unwrap();
+        assert_eq!(blockchain.finalized_height(), 1);
+        assert_eq!(blockchain.validate_chain(), Ok(()));
+
+        let mut forged = blockchain.chain.clone();
+        forged[1].commit.as_mut().unwrap().round = 0;
+        assert!(Blockchain::with_admins(blockchain.validators().to_vec()).replace_chain(forged).is_err());
+    }
+
+    #[test]
+    fn test_append_block_from_another_node() {
+        let (leader, _) = validator_chain();
+        let mut follower = Blockchain::with_admins(leader.validators().to_vec());
+
+        let block = leader.propose_block(vec![], vec![], 0).unwrap();
+        follower.append_block(block.clone()).unwrap();
+        assert_eq!(follower.chain.len(), 2);
+        assert_eq!(follower.finalized_height(), 0, "Appended blocks carry no certificate");
+        assert!(matches!(
+            follower.append_block(block),
+            Err(ChainError::Invalid(ValidationError::BadIndex { block: 2, index: 1 }))
+        ));
+    }
+
+    #[test]
+    fn test_proof_of_work_is_mined_and_checked() {
+        let validator = generate_keypair();
+        let mut genesis = GenesisSpec::with_authorities(vec![validator.verifying_key().to_bytes().to_vec()]);
+        genesis.pow = Some(PowParams { initial_bits: 8, target_block_secs: 10, retarget_interval: 0 });
+        let mut blockchain = Blockchain::from_genesis(genesis.clone()).unwrap();
+        blockchain.set_validator_key(validator.clone());
+
+        blockchain.add_block(vec![]).unwrap();
+        blockchain.add_block(vec![]).unwrap();
+        assert!(blockchain.chain[1].calculate_hash().starts_with("00"));
+        assert_eq!(blockchain.validate_chain(), Ok(()));
+        assert_eq!(blockchain.total_work(), 2 * 256);
+
+        // A resealed block whose nonce no longer meets the target
+        let mut unmined = blockchain.chain.clone();
+        while pow::meets_target(&unmined[2].header(), 8) {
+            unmined[2].nonce += 1;
+        }
+        unmined[2].sign(&validator);
+        let mut follower = Blockchain::from_genesis(genesis).unwrap();
+        assert!(matches!(
+            follower.replace_chain(unmined.clone()),
+            Err(ChainError::Invalid(ValidationError::InsufficientWork { block: 2, bits: 8 }))
+        ));
+        blockchain.chain = unmined;
+        assert_eq!(blockchain.validate_chain(), Err(ValidationError::InsufficientWork { block: 2, bits: 8 }));
+    }
+
+    #[test]
+    fn test_required_bits_follow_retargets() {
+        let validator = generate_keypair();
+        let mut genesis = GenesisSpec::with_authorities(vec![validator.verifying_key().to_bytes().to_vec()]);
+        let params = PowParams { initial_bits: 1, target_block_secs: 600, retarget_interval: 2 };
+        genesis.pow = Some(params.clone());
+        let mut blockchain = Blockchain::from_genesis(genesis).unwrap();
+        blockchain.set_validator_key(validator);
+
+        for _ in 0..5 {
+            blockchain.add_block(vec![]).unwrap();
+            assert_eq!(blockchain.required_bits(), pow::required_bits(&params, &blockchain.chain));
+        }
+        assert!(blockchain.required_bits() > 1, "Blocks far faster than the target raise the difficulty");
+        assert_eq!(blockchain.validate_chain(), Ok(()));
+        blockchain.rollback(1).unwrap();
+        assert_eq!(blockchain.required_bits(), pow::required_bits(&params, &blockchain.chain));
+    }
+
+    #[test]
+    fn test_replace_chain_never_reverts_finalized_blocks() {
+        let keys = [generate_keypair(), generate_keypair(), generate_keypair()];
+        let validators: Vec<_> = keys.iter().map(|k| k.verifying_key().to_bytes().to_vec()).collect();
+        let mut blockchain = Blockchain::with_admins(validators.clone());
+        blockchain.set_validator_key(keys[1].clone());
+        let mut block = blockchain.propose_block(vec![], vec![], 0).unwrap();
+        certify(&mut block, &keys, 0);
+        blockchain.commit_block(block).unwrap();
+
+        // A longer fork that replaces block 1, sealed by the right proposers but never committed
+        let mut fork = Blockchain::with_admins(validators);
+        for height in 1..=3 {
+            fork.set_validator_key(keys[height % 3].clone());
+            fork.add_block(vec![]).unwrap();
+        }
+        fork.chain[1].nonce = 1;
+        for height in 1..=3 {
+            fork.chain[height].prev_hash = fork.chain[height - 1].calculate_hash();
+            fork.chain[height].sign(&keys[height % 3]);
+        }
+        assert_ne!(fork.chain[1].calculate_hash(), blockchain.chain[1].calculate_hash());
+        assert!(matches!(blockchain.replace_chain(fork.chain.clone()), Err(ChainError::RevertsFinalized { height: 1 })));
+        assert_eq!(blockchain.chain.len(), 2);
+
+        // Extending the finalized block is fine
+        let mut extension = blockchain.chain.clone();
+        fork.replace_chain(extension.clone()).unwrap();
+        fork.set_validator_key(keys[2].clone());
+        extension.push(fork.add_block(vec![]).unwrap().clone());
+        blockchain.replace_chain(extension).unwrap();
+        assert_eq!(blockchain.chain.len(), 3);
+        assert_eq!(blockchain.finalized_height(), 1);
     }
 }
@@ -0,0 +1,255 @@
+//! Proof-of-Authority block sealing and BFT commit certificates.
+//!
+//! The genesis authorities are the validator set. Validators take turns in
+//! round-robin slots: block `h` proposed in BFT round `r` must be signed by
+//! `validators[(h + r) % n]`. A block committed in round `r` may have been
+//! proposed in any earlier round of its height and re-proposed since, so it
+//! is accepted from the proposer of any round up to `r` (only round 0 for
+//! blocks without a commit certificate). The genesis block is the only
+//! unsealed block.
+//!
+//! A [`CommitCertificate`] holds precommit votes for a block from more than
+//! two thirds of the validators. A block with a valid certificate is final:
+//! no honest node will ever replace it.
+
+use crate::block::BlockHeader;
+use crate::crypto::{sign_message, verify_message};
+use crate::encoding::{put_bytes, put_u64, put_u8};
+use crate::validation::ValidationError;
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Domain separator of the vote signing payload, see `docs/encoding.md`
+const VOTE_SIGNING_DOMAIN: &[u8] = b"securerx/vote/v1";
+
+/// Validator whose turn it is to propose the block at `height` in BFT round
+/// `round`, or `None` if there are no validators
+pub fn proposer_for(validators: &[Vec<u8>], height: u64, round: u64) -> Option<&[u8]> {
+    if validators.is_empty() {
+        return None;
+    }
+    let slot = height.wrapping_add(round) % validators.len() as u64;
+    Some(&validators[slot as usize])
+}
+
+/// Number of validators whose votes make a quorum: more than two thirds
+pub fn quorum(validators: usize) -> usize {
+    validators * 2 / 3 + 1
+}
+
+/// Check that `header` names a validator as its proposer and that it was
+/// that validator's turn in `round` or an earlier round
+pub fn check_proposer(validators: &[Vec<u8>], header: &BlockHeader, round: u64) -> Result<(), ValidationError> {
+    let block = header.index;
+    if !validators.contains(&header.proposer) {
+        return Err(ValidationError::UnknownProposer { block, proposer: hex::encode(&header.proposer) });
+    }
+    let rounds = round.min(validators.len() as u64 - 1);
+    if !(0..=rounds).any(|r| proposer_for(validators, block, r) == Some(header.proposer.as_slice())) {
+        let expected = proposer_for(validators, block, round).expect("validator set is not empty");
+        return Err(ValidationError::WrongProposer {
+            block,
+            expected: hex::encode(expected),
+            found: hex::encode(&header.proposer),
+        });
+    }
+    Ok(())
+}
+
+/// Check the proposer and its signature over `header`
+pub fn check_seal(validators: &[Vec<u8>], header: &BlockHeader, round: u64) -> Result<(), ValidationError> {
+    check_proposer(validators, header, round)?;
+    if !header.verify_signature() {
+        return Err(ValidationError::BadBlockSignature { block: header.index });
+    }
+    Ok(())
+}
+
+/// Voting step a [`Vote`] is cast in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteKind {
+    Prevote,
+    Precommit,
+}
+
+/// A validator's signed vote for a block, or for no block (`block_hash: None`), in one BFT round
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Vote {
+    pub kind: VoteKind,
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: Option<String>,
+    pub validator: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl Vote {
+    /// Vote signed by `validator`
+    pub fn new(kind: VoteKind, height: u64, round: u64, block_hash: Option<String>, validator: &SigningKey) -> Self {
+        let mut vote = Self {
+            kind,
+            height,
+            round,
+            block_hash,
+            validator: validator.verifying_key().to_bytes().to_vec(),
+            signature: vec![],
+        };
+        vote.signature = sign_message(validator, &vote.signing_bytes()).to_bytes().to_vec();
+        vote
+    }
+
+    /// Canonical payload covered by the validator's signature: the domain
+    /// separator, kind tag (0 = prevote, 1 = precommit), `height`, `round`,
+    /// `block_hash` (empty for nil) and `validator`
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(160);
+        put_bytes(&mut buf, VOTE_SIGNING_DOMAIN);
+        put_u8(&mut buf, match self.kind {
+            VoteKind::Prevote => 0,
+            VoteKind::Precommit => 1,
+        });
+        put_u64(&mut buf, self.height);
+        put_u64(&mut buf, self.round);
+        put_bytes(&mut buf, self.block_hash.as_deref().unwrap_or_default().as_bytes());
+        put_bytes(&mut buf, &self.validator);
+        buf
+    }
+
+    /// Verify the vote signature
+    pub fn verify_signature(&self) -> bool {
+        verify_message(&self.validator, &self.signing_bytes(), &self.signature)
+    }
+}
+
+/// Precommits for a block from a quorum of validators, all cast in `round`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CommitCertificate {
+    pub round: u64,
+    pub precommits: Vec<Vote>,
+}
+
+impl CommitCertificate {
+    /// Check that the certificate commits the block `block_hash` at `height`
+    pub fn verify(&self, validators: &[Vec<u8>], height: u64, block_hash: &str) -> Result<(), ValidationError> {
+        let mut signers = HashSet::new();
+        for vote in &self.precommits {
+            let valid = vote.kind == VoteKind::Precommit
+                && vote.height == height
+                && vote.round == self.round
+                && vote.block_hash.as_deref() == Some(block_hash)
+                && validators.contains(&vote.validator)
+                && vote.verify_signature();
+            if !valid || !signers.insert(&vote.validator) {
+                return Err(ValidationError::BadCommitCertificate { block: height });
+            }
+        }
+        if signers.len() < quorum(validators.len()) {
+            return Err(ValidationError::BadCommitCertificate { block: height });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::crypto::generate_keypair;
+
+    fn block(index: u64) -> Block {
+        Block {
+            index,
+            prev_hash: "00".repeat(32),
+            timestamp: 1_700_000_000,
+            transactions: vec![],
+            registry_txs: vec![],
+            nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
+        }
+    }
+
+    fn validators(keys: &[SigningKey]) -> Vec<Vec<u8>> {
+        keys.iter().map(|k| k.verifying_key().to_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_round_robin_proposers() {
+        let validators = vec![vec![1; 32], vec![2; 32], vec![3; 32]];
+        let proposers: Vec<_> = (1..=4).map(|h| proposer_for(&validators, h, 0).unwrap()[0]).collect();
+        assert_eq!(proposers, vec![2, 3, 1, 2]);
+        assert_eq!(proposer_for(&validators, 1, 1).unwrap()[0], 3, "Each round moves to the next validator");
+        assert!(proposer_for(&[], 1, 0).is_none());
+    }
+
+    #[test]
+    fn test_check_seal() {
+        let keys = [generate_keypair(), generate_keypair()];
+        let validators = validators(&keys);
+
+        let mut sealed = block(3);
+        sealed.sign(&keys[1]);
+        assert_eq!(check_seal(&validators, &sealed.header(), 0), Ok(()));
+
+        let mut out_of_turn = block(4);
+        out_of_turn.sign(&keys[1]);
+        assert!(matches!(
+            check_seal(&validators, &out_of_turn.header(), 0),
+            Err(ValidationError::WrongProposer { block: 4, .. })
+        ));
+        assert_eq!(check_seal(&validators, &out_of_turn.header(), 1), Ok(()), "Next round has the next proposer");
+        assert_eq!(check_seal(&validators, &sealed.header(), 1), Ok(()), "Re-proposed in a later round");
+
+        let mut outsider = block(3);
+        outsider.sign(&generate_keypair());
+        assert!(matches!(
+            check_seal(&validators, &outsider.header(), 0),
+            Err(ValidationError::UnknownProposer { block: 3, .. })
+        ));
+        assert!(matches!(check_seal(&[], &sealed.header(), 0), Err(ValidationError::UnknownProposer { .. })));
+
+        let mut forged = sealed.clone();
+        forged.timestamp += 1;
+        assert_eq!(check_seal(&validators, &forged.header(), 0), Err(ValidationError::BadBlockSignature { block: 3 }));
+    }
+
+    #[test]
+    fn test_quorum() {
+        assert_eq!([1, 3, 4, 7].map(quorum), [1, 3, 3, 5]);
+    }
+
+    #[test]
+    fn test_commit_certificate() {
+        let keys = [generate_keypair(), generate_keypair(), generate_keypair(), generate_keypair()];
+        let validators = validators(&keys);
+        let hash = "ab".repeat(32);
+        let precommit = |key: &SigningKey| Vote::new(VoteKind::Precommit, 5, 1, Some(hash.clone()), key);
+
+        let cert = CommitCertificate { round: 1, precommits: keys[..3].iter().map(precommit).collect() };
+        assert_eq!(cert.verify(&validators, 5, &hash), Ok(()));
+        assert!(cert.verify(&validators, 5, &"cd".repeat(32)).is_err(), "Certificate is for one block only");
+        assert!(cert.verify(&validators, 6, &hash).is_err());
+
+        let too_few = CommitCertificate { round: 1, precommits: cert.precommits[..2].to_vec() };
+        assert_eq!(too_few.verify(&validators, 5, &hash), Err(ValidationError::BadCommitCertificate { block: 5 }));
+
+        let mut repeated = too_few.clone();
+        repeated.precommits.push(repeated.precommits[0].clone());
+        assert!(repeated.verify(&validators, 5, &hash).is_err(), "A validator counts once");
+
+        let mut prevotes = cert.clone();
+        prevotes.precommits[2] = Vote::new(VoteKind::Prevote, 5, 1, Some(hash.clone()), &keys[2]);
+        assert!(prevotes.verify(&validators, 5, &hash).is_err());
+
+        let mut outsider = too_few.clone();
+        outsider.precommits.push(precommit(&generate_keypair()));
+        assert!(outsider.verify(&validators, 5, &hash).is_err());
+
+        let mut forged = cert;
+        forged.precommits[0].round = 0;
+        assert!(forged.verify(&validators, 5, &hash).is_err());
+    }
+}
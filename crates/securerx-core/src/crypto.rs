@@ -1,5 +1,6 @@
-use ed25519_dalek::{SigningKey, Signature, Signer};
+use ed25519_dalek::{SigningKey, Signature, Signer, VerifyingKey};
 use rand::rngs::OsRng;
+use rand::RngCore;
 
 /// Generate a new Ed25519 keypair
 pub fn generate_keypair() -> SigningKey {
@@ -12,6 +13,41 @@ pub fn sign_message(keypair: &SigningKey, message: &[u8]) -> Signature {
     keypair.sign(message)
 }
 
+/// Verify a signature given the raw public key and signature bytes.
+/// Malformed keys or signatures simply fail verification.
+pub fn verify_message(pubkey: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    // Convert slices to fixed-size arrays
+    let pubkey_bytes: [u8; 32] = match pubkey.try_into() {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+
+    let sig_bytes: [u8; 64] = match signature.try_into() {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+
+    let pubkey = match VerifyingKey::from_bytes(&pubkey_bytes) {
+        Ok(pk) => pk,
+        Err(_) => return false,
+    };
+
+    let sig = Signature::from_bytes(&sig_bytes);
+    pubkey.verify_strict(message, &sig).is_ok()
+}
+
+/// Draw a random transaction nonce from the OS CSPRNG
+pub fn random_nonce() -> u64 {
+    OsRng.next_u64()
+}
+
+/// Parse a signing key from its hex-encoded 32-byte secret, e.g. from a `VALIDATOR_KEY` variable
+pub fn signing_key_from_hex(secret_hex: &str) -> Result<SigningKey, String> {
+    let mut secret = [0u8; 32];
+    hex::decode_to_slice(secret_hex.trim(), &mut secret).map_err(|e| format!("bad secret key: {}", e))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,6 +81,26 @@ mod tests {
         assert!(keypair.verifying_key().verify_strict(message, &signature).is_ok(), "Signature should verify");
     }
 
+    #[test]
+    fn test_signing_key_from_hex() {
+        let key = signing_key_from_hex(&"01".repeat(32)).unwrap();
+        assert_eq!(key.to_bytes(), [1u8; 32]);
+        assert!(signing_key_from_hex("0101").is_err());
+        assert!(signing_key_from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_verify_message() {
+        let keypair = generate_keypair();
+        let pubkey = keypair.verifying_key().to_bytes();
+        let signature = sign_message(&keypair, b"payload").to_bytes();
+
+        assert!(verify_message(&pubkey, b"payload", &signature));
+        assert!(!verify_message(&pubkey, b"other payload", &signature));
+        assert!(!verify_message(&pubkey[..31], b"payload", &signature), "Short key should fail");
+        assert!(!verify_message(&pubkey, b"payload", &signature[..63]), "Short signature should fail");
+    }
+
     #[test]
     fn test_sign_different_messages() {
         let keypair = generate_keypair();
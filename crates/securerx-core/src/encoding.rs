@@ -0,0 +1,18 @@
+//! Helpers for the length-prefixed byte layouts that signatures and block
+//! hashes are computed over. The layouts are specified in `docs/encoding.md`.
+
+/// Append a big-endian `u32` length followed by the bytes themselves
+pub(crate) fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Append a big-endian `u64`
+pub(crate) fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Append a single tag byte, used to distinguish enum variants
+pub(crate) fn put_u8(buf: &mut Vec<u8>, value: u8) {
+    buf.push(value);
+}
@@ -0,0 +1,407 @@
+//! Fork choice between competing branches of the chain.
+//!
+//! A [`BlockTree`] keeps the valid blocks a node received that are not on its
+//! canonical chain, so a competing branch can keep growing until it overtakes
+//! the canonical one. Every block is validated against its own branch before
+//! it is kept. When the tree's [`ForkChoiceRule`] ranks a branch above the
+//! canonical chain, the node reorganizes: it rolls the chain back to the last
+//! block both share and appends the branch, rebuilding the registry and
+//! prescription index on the way. Finalized blocks are never rolled back.
+
+use crate::block::Block;
+use crate::blockchain::{Blockchain, ChainError};
+use crate::pow::{self, PowParams};
+use std::collections::HashMap;
+
+/// Most side-branch blocks a tree keeps; the lowest ones are dropped first
+pub const MAX_SIDE_BLOCKS: usize = 1024;
+
+/// Ranks branches of the chain. The branch with the highest weight is
+/// canonical; on a tie the current canonical chain is kept.
+pub trait ForkChoiceRule: Send + Sync {
+    /// Weight of `chain`, a branch starting at the genesis block
+    fn weight(&self, pow: Option<&PowParams>, chain: &[Block]) -> u128;
+}
+
+/// Prefer the branch with the most cumulative proof of work (see
+/// [`pow::chain_work`]), which is the longest one on networks without it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeaviestChain;
+
+impl ForkChoiceRule for HeaviestChain {
+    fn weight(&self, pow: Option<&PowParams>, chain: &[Block]) -> u128 {
+        pow::chain_work(pow, chain)
+    }
+}
+
+/// Prefer the branch with the most blocks, whatever their proof of work
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LongestChain;
+
+impl ForkChoiceRule for LongestChain {
+    fn weight(&self, _pow: Option<&PowParams>, chain: &[Block]) -> u128 {
+        chain.len().saturating_sub(1) as u128
+    }
+}
+
+/// Switch of the canonical chain from one branch to another
+#[derive(Debug, Clone)]
+pub struct Reorg {
+    /// Height of the last block the old and the new chain share
+    pub common_height: u64,
+    /// Blocks removed from the chain, oldest first
+    pub reverted: Vec<Block>,
+    /// Blocks appended in their place, oldest first
+    pub applied: Vec<Block>,
+}
+
+impl Reorg {
+    /// Number of blocks rolled back
+    pub fn depth(&self) -> u64 {
+        self.reverted.len() as u64
+    }
+}
+
+/// What importing a block changed
+#[derive(Debug, Clone)]
+pub enum Imported {
+    /// The block is already on the chain or in the tree
+    Known,
+    /// The block was appended to the canonical chain
+    Extended,
+    /// The block was kept on a branch that does not outweigh the canonical chain
+    SideBranch,
+    /// The block's branch outweighed the canonical chain and replaced it
+    Reorganized(Reorg),
+}
+
+/// Blocks off the canonical chain, and the rule choosing between branches
+pub struct BlockTree {
+    rule: Box<dyn ForkChoiceRule>,
+    /// Valid blocks that are not on the canonical chain, by hash
+    side: HashMap<String, Block>,
+}
+
+impl BlockTree {
+    /// Empty tree choosing branches by `rule`
+    pub fn new(rule: Box<dyn ForkChoiceRule>) -> Self {
+        Self { rule, side: HashMap::new() }
+    }
+
+    /// Number of blocks kept off the canonical chain
+    pub fn side_blocks(&self) -> usize {
+        self.side.len()
+    }
+
+    /// Whether the block with hash `hash` is kept off the canonical chain
+    pub fn contains(&self, hash: &str) -> bool {
+        self.side.contains_key(hash)
+    }
+
+    /// The block with hash `hash`, if it is kept off the canonical chain
+    pub fn get(&self, hash: &str) -> Option<&Block> {
+        self.side.get(hash)
+    }
+
+    /// Validate `block` against the branch it extends and keep it, switching
+    /// `chain` to that branch if the rule now prefers it. Blocks must arrive
+    /// parent first; blocks whose parent is unknown, that fail validation or
+    /// that fork below the finalized height are refused.
+    pub fn import(&mut self, chain: &mut Blockchain, block: Block) -> Result<Imported, ChainError> {
+        let hash = block.calculate_hash();
+        if self.side.contains_key(&hash) || is_canonical(chain, block.index, &hash) {
+            return Ok(Imported::Known);
+        }
+        if block.prev_hash == chain.chain.last().unwrap().calculate_hash() {
+            chain.append_block(block)?;
+            self.prune(chain.finalized_height());
+            return Ok(Imported::Extended);
+        }
+
+        let mut branch = self.branch(chain, &block)?;
+        chain.validate_branch_block(&branch, &block)?;
+        branch.push(block);
+        if self.rule.weight(chain.pow(), &branch) <= self.rule.weight(chain.pow(), &chain.chain) {
+            self.side.insert(hash, branch.pop().unwrap());
+            self.prune(chain.finalized_height());
+            return Ok(Imported::SideBranch);
+        }
+        let reorg = self.reorganize(chain, branch)?;
+        self.prune(chain.finalized_height());
+        Ok(Imported::Reorganized(reorg))
+    }
+
+    /// The chain from genesis up to the parent of `block`, made of canonical
+    /// blocks up to the fork point and side blocks after it
+    fn branch(&self, chain: &Blockchain, block: &Block) -> Result<Vec<Block>, ChainError> {
+        let mut side = vec![];
+        let mut index = block.index.checked_sub(1);
+        let mut hash = &block.prev_hash;
+        let fork_height = loop {
+            let Some(height) = index else {
+                return Err(ChainError::UnknownParent { block: block.index });
+            };
+            if is_canonical(chain, height, hash) {
+                break height;
+            }
+            let Some(parent) = self.side.get(hash).filter(|parent| parent.index == height) else {
+                return Err(ChainError::UnknownParent { block: block.index });
+            };
+            side.push(parent);
+            index = height.checked_sub(1);
+            hash = &parent.prev_hash;
+        };
+        if fork_height < chain.finalized_height() {
+            return Err(ChainError::RevertsFinalized { height: chain.finalized_height() });
+        }
+        let mut branch = chain.chain[..=fork_height as usize].to_vec();
+        branch.extend(side.into_iter().rev().cloned());
+        Ok(branch)
+    }
+
+    /// Roll `chain` back to the last block it shares with `branch` and append
+    /// the rest of `branch`, whose blocks have all been validated
+    fn reorganize(&mut self, chain: &mut Blockchain, branch: Vec<Block>) -> Result<Reorg, ChainError> {
+        let shared = chain.chain.iter()
+            .zip(&branch)
+            .take_while(|(ours, theirs)| ours.calculate_hash() == theirs.calculate_hash())
+            .count();
+        let common_height = shared as u64 - 1;
+        let reverted = chain.rollback(common_height)?;
+        let applied = branch[shared..].to_vec();
+        for block in &applied {
+            if let Err(e) = chain.append_block(block.clone()) {
+                // Only a failing store gets here: return to the old branch
+                chain.rollback(common_height)?;
+                for block in &reverted {
+                    chain.append_block(block.clone())?;
+                }
+                return Err(e);
+            }
+        }
+        for block in &applied {
+            self.side.remove(&block.calculate_hash());
+        }
+        for block in &reverted {
+            self.side.insert(block.calculate_hash(), block.clone());
+        }
+        Ok(Reorg { common_height, reverted, applied })
+    }
+
+    /// Drop side blocks that can no longer become canonical, then the lowest
+    /// ones while the tree holds more than [`MAX_SIDE_BLOCKS`]
+    fn prune(&mut self, finalized_height: u64) {
+        self.side.retain(|_, block| block.index > finalized_height);
+        while self.side.len() > MAX_SIDE_BLOCKS {
+            let lowest = self.side.iter().min_by_key(|(_, block)| block.index).map(|(hash, _)| hash.clone());
+            self.side.remove(&lowest.unwrap());
+        }
+    }
+}
+
+impl Default for BlockTree {
+    fn default() -> Self {
+        Self::new(Box::new(HeaviestChain))
+    }
+}
+
+/// Whether the block of `chain` at `height` has hash `hash`
+fn is_canonical(chain: &Blockchain, height: u64, hash: &str) -> bool {
+    chain.chain.get(height as usize).is_some_and(|block| block.calculate_hash() == hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{CommitCertificate, Vote, VoteKind};
+    use crate::crypto::generate_keypair;
+    use crate::registry::{RegistryOp, RegistryTransaction};
+    use crate::transaction::{Transaction, DEFAULT_CHAIN_ID};
+    use crate::validation::ValidationError;
+    use ed25519_dalek::SigningKey;
+
+    /// Chain with a single authority whose key seals every block, plus that key
+    fn validator_chain() -> (Blockchain, SigningKey) {
+        let validator = generate_keypair();
+        let mut blockchain = Blockchain::with_admins(vec![validator.verifying_key().to_bytes().to_vec()]);
+        blockchain.set_validator_key(validator.clone());
+        (blockchain, validator)
+    }
+
+    /// `n` empty blocks sealed by `validator` extending `prefix`, a chain from
+    /// genesis, and distinct from any other blocks built on it
+    fn fork(prefix: &[Block], validator: &SigningKey, n: usize) -> Vec<Block> {
+        let mut fork = Blockchain::with_admins(vec![validator.verifying_key().to_bytes().to_vec()]);
+        fork.set_validator_key(validator.clone());
+        fork.replace_chain(prefix.to_vec()).unwrap();
+        for _ in 0..n {
+            fork.add_block(vec![]).unwrap();
+            let block = fork.chain.last_mut().unwrap();
+            block.nonce = 7;
+            block.sign(validator);
+        }
+        fork.chain.split_off(prefix.len())
+    }
+
+    fn hashes(blocks: &[Block]) -> Vec<String> {
+        blocks.iter().map(Block::calculate_hash).collect()
+    }
+
+    /// Register a doctor in one block and add a prescription of theirs in the next
+    fn register_and_prescribe(blockchain: &mut Blockchain, admin: &SigningKey) -> Transaction {
+        let doctor = generate_keypair();
+        let mut registration = RegistryTransaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            op: RegistryOp::RegisterDoctor {
+                doctor_id: "doctor1".to_string(),
+                license_number: "LIC-doctor1".to_string(),
+                pubkey: doctor.verifying_key().to_bytes().to_vec(),
+            },
+            nonce: 1,
+            admin_pubkey: vec![],
+            signature: vec![],
+        };
+        registration.sign(admin);
+        blockchain.add_registry_block(vec![registration]).unwrap();
+
+        let mut tx = Transaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            doctor_id: "doctor1".to_string(),
+            patient_id: "patient1".to_string(),
+            drug: "Aspirin".to_string(),
+            dosage: "1 tablet daily".to_string(),
+            issued_at: 1_700_000_000,
+            expires_at: 1_702_592_000,
+            nonce: 1,
+            signature: vec![],
+            pubkey: vec![],
+        };
+        tx.sign(&doctor);
+        blockchain.add_block(vec![tx.clone()]).unwrap();
+        tx
+    }
+
+    #[test]
+    fn test_lighter_branches_are_kept_aside() {
+        let (mut blockchain, validator) = validator_chain();
+        blockchain.add_block(vec![]).unwrap();
+        blockchain.add_block(vec![]).unwrap();
+        let tip = blockchain.chain.last().unwrap().calculate_hash();
+        let mut tree = BlockTree::default();
+
+        let side = fork(&blockchain.chain[..1], &validator, 1).remove(0);
+        assert!(matches!(tree.import(&mut blockchain, side.clone()), Ok(Imported::SideBranch)));
+        assert!(tree.contains(&side.calculate_hash()));
+        assert!(matches!(tree.import(&mut blockchain, side), Ok(Imported::Known)));
+        let canonical = blockchain.chain[1].clone();
+        assert!(matches!(tree.import(&mut blockchain, canonical), Ok(Imported::Known)));
+        assert_eq!(blockchain.chain.last().unwrap().calculate_hash(), tip, "A lighter branch should not be adopted");
+
+        let next = fork(&blockchain.chain, &validator, 1).remove(0);
+        assert!(matches!(tree.import(&mut blockchain, next), Ok(Imported::Extended)));
+        assert_eq!(blockchain.chain.len(), 4);
+        assert_eq!(tree.side_blocks(), 1);
+    }
+
+    #[test]
+    fn test_heavier_branch_reorganizes_and_rebuilds_state() {
+        let (mut blockchain, validator) = validator_chain();
+        let tx = register_and_prescribe(&mut blockchain, &validator);
+        let old_chain = blockchain.chain.clone();
+        let old_branch = old_chain[1..].to_vec();
+        let mut tree = BlockTree::default();
+
+        let branch = fork(&blockchain.chain[..1], &validator, 3);
+        assert!(matches!(tree.import(&mut blockchain, branch[0].clone()), Ok(Imported::SideBranch)));
+        assert!(matches!(tree.import(&mut blockchain, branch[1].clone()), Ok(Imported::SideBranch)), "Ties keep the chain");
+        let Imported::Reorganized(reorg) = tree.import(&mut blockchain, branch[2].clone()).unwrap() else {
+            panic!("the heavier branch should become canonical");
+        };
+        assert_eq!(reorg.common_height, 0);
+        assert_eq!(reorg.depth(), 2);
+        assert_eq!(hashes(&reorg.reverted), hashes(&old_branch));
+        assert_eq!(hashes(&reorg.applied), hashes(&branch));
+        assert_eq!(hashes(&blockchain.chain[1..]), hashes(&branch));
+        assert!(blockchain.registry().get("doctor1").is_none(), "Reverted registrations should be undone");
+        assert!(!blockchain.contains_transaction(&tx), "Reverted prescriptions should be forgotten");
+        assert_eq!(blockchain.validate_chain(), Ok(()));
+        assert!(old_branch.iter().all(|block| tree.contains(&block.calculate_hash())));
+        assert!(branch.iter().all(|block| !tree.contains(&block.calculate_hash())));
+
+        // The old branch can win back
+        let extension = fork(&old_chain, &validator, 2);
+        assert!(matches!(tree.import(&mut blockchain, extension[0].clone()), Ok(Imported::SideBranch)));
+        assert!(matches!(tree.import(&mut blockchain, extension[1].clone()), Ok(Imported::Reorganized(_))));
+        assert!(blockchain.registry().get("doctor1").is_some());
+        assert!(blockchain.contains_transaction(&tx));
+    }
+
+    #[test]
+    fn test_invalid_and_orphan_blocks_are_refused() {
+        let (mut blockchain, validator) = validator_chain();
+        blockchain.add_block(vec![]).unwrap();
+        let mut tree = BlockTree::default();
+
+        let mut branch = fork(&blockchain.chain[..1], &validator, 2);
+        let mut forged = branch[0].clone();
+        forged.sign(&generate_keypair());
+        assert!(matches!(
+            tree.import(&mut blockchain, forged),
+            Err(ChainError::Invalid(ValidationError::UnknownProposer { block: 1, .. }))
+        ));
+        assert!(matches!(tree.import(&mut blockchain, branch.remove(1)), Err(ChainError::UnknownParent { block: 2 })));
+        assert_eq!(tree.side_blocks(), 0);
+        assert_eq!(blockchain.chain.len(), 2);
+    }
+
+    #[test]
+    fn test_finalized_blocks_are_never_reorganized() {
+        let (mut blockchain, validator) = validator_chain();
+        let mut block = blockchain.propose_block(vec![], vec![], 0).unwrap();
+        let hash = block.calculate_hash();
+        let precommit = Vote::new(VoteKind::Precommit, 1, 0, Some(hash), &validator);
+        block.commit = Some(CommitCertificate { round: 0, precommits: vec![precommit] });
+        blockchain.commit_block(block).unwrap();
+        let mut tree = BlockTree::default();
+
+        let branch = fork(&blockchain.chain[..1], &validator, 3);
+        assert!(matches!(
+            tree.import(&mut blockchain, branch[0].clone()),
+            Err(ChainError::RevertsFinalized { height: 1 })
+        ));
+        assert_eq!(blockchain.chain.len(), 2);
+    }
+
+    #[test]
+    fn test_synced_certified_blocks_are_never_reorganized() {
+        let (mut leader, validator) = validator_chain();
+        let mut block = leader.propose_block(vec![], vec![], 0).unwrap();
+        let hash = block.calculate_hash();
+        let precommit = Vote::new(VoteKind::Precommit, 1, 0, Some(hash), &validator);
+        block.commit = Some(CommitCertificate { round: 0, precommits: vec![precommit] });
+        leader.commit_block(block.clone()).unwrap();
+
+        // A follower learns the committed block from a peer rather than through consensus
+        let mut blockchain = Blockchain::with_admins(leader.validators().to_vec());
+        let mut tree = BlockTree::default();
+        assert!(matches!(tree.import(&mut blockchain, block), Ok(Imported::Extended)));
+        assert_eq!(blockchain.finalized_height(), 1);
+
+        let branch = fork(&blockchain.chain[..1], &validator, 3);
+        assert!(matches!(
+            tree.import(&mut blockchain, branch[0].clone()),
+            Err(ChainError::RevertsFinalized { height: 1 })
+        ));
+        assert_eq!(hashes(&blockchain.chain), hashes(&leader.chain));
+    }
+
+    #[test]
+    fn test_rules() {
+        let (mut blockchain, _) = validator_chain();
+        blockchain.add_block(vec![]).unwrap();
+        let params = PowParams { initial_bits: 3, target_block_secs: 10, retarget_interval: 0 };
+        assert_eq!(LongestChain.weight(Some(&params), &blockchain.chain), 1);
+        assert_eq!(HeaviestChain.weight(Some(&params), &blockchain.chain), 8);
+        assert_eq!(HeaviestChain.weight(None, &blockchain.chain), 1);
+    }
+}
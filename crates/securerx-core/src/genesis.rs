@@ -0,0 +1,251 @@
+//! Genesis specification shared by every node of a network.
+//!
+//! The genesis block is derived entirely from the spec, so nodes loading the
+//! same genesis file agree on the genesis hash, and nodes loading different
+//! files never do.
+
+use crate::block::Block;
+use crate::encoding::{put_bytes, put_u64};
+use crate::pow::{PowParams, MAX_BITS};
+use crate::registry::{DoctorRegistry, RegistryError};
+use crate::transaction::DEFAULT_CHAIN_ID;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+
+const GENESIS_DOMAIN: &[u8] = b"securerx/genesis/v1";
+
+/// Timestamp of the built-in development genesis (2023-11-14T22:13:20Z)
+pub const DEFAULT_GENESIS_TIMESTAMP: u64 = 1_700_000_000;
+
+/// A prescriber registered from the first block on
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GenesisDoctor {
+    pub doctor_id: String,
+    pub license_number: String,
+    #[serde(with = "hex_key")]
+    pub pubkey: Vec<u8>,
+}
+
+/// Parameters every node of a network must agree on, loaded from a JSON genesis file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GenesisSpec {
+    pub chain_id: String,
+    /// Genesis block timestamp, fixed so every node derives the same block
+    pub timestamp: u64,
+    /// Ed25519 public keys of the initial authority set, which may sign registry transactions
+    #[serde(with = "hex_keys")]
+    pub authorities: Vec<Vec<u8>>,
+    /// Doctors registered before the first block
+    #[serde(default)]
+    pub doctors: Vec<GenesisDoctor>,
+    /// Proof-of-work blocks must carry on top of the validator seal, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pow: Option<PowParams>,
+}
+
+/// Errors raised when loading a genesis file
+#[derive(Debug)]
+pub enum GenesisError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisError::Io(e) => write!(f, "cannot read genesis file: {}", e),
+            GenesisError::Parse(e) => write!(f, "malformed genesis file: {}", e),
+            GenesisError::Invalid(e) => write!(f, "invalid genesis: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GenesisError {}
+
+impl GenesisSpec {
+    /// Development spec on the default chain id with the given authorities and no doctors
+    pub fn with_authorities(authorities: Vec<Vec<u8>>) -> Self {
+        Self {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            timestamp: DEFAULT_GENESIS_TIMESTAMP,
+            authorities,
+            doctors: vec![],
+            pow: None,
+        }
+    }
+
+    /// Load and validate a JSON genesis file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GenesisError> {
+        let data = std::fs::read(path).map_err(GenesisError::Io)?;
+        let spec: Self = serde_json::from_slice(&data).map_err(|e| GenesisError::Parse(e.to_string()))?;
+        spec.registry().map_err(|e| GenesisError::Invalid(e.to_string()))?;
+        if let Some(pow) = &spec.pow {
+            if pow.initial_bits > MAX_BITS || pow.target_block_secs == 0 {
+                return Err(GenesisError::Invalid(format!(
+                    "pow needs initial_bits of at most {} and a non-zero target_block_secs",
+                    MAX_BITS
+                )));
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Commitment to every field of the spec, stored as the genesis block's `prev_hash`
+    pub fn digest(&self) -> [u8; 32] {
+        let mut buf = Vec::new();
+        put_bytes(&mut buf, GENESIS_DOMAIN);
+        put_bytes(&mut buf, self.chain_id.as_bytes());
+        put_u64(&mut buf, self.timestamp);
+        put_u64(&mut buf, self.authorities.len() as u64);
+        for authority in &self.authorities {
+            put_bytes(&mut buf, authority);
+        }
+        put_u64(&mut buf, self.doctors.len() as u64);
+        for doctor in &self.doctors {
+            put_bytes(&mut buf, doctor.doctor_id.as_bytes());
+            put_bytes(&mut buf, doctor.license_number.as_bytes());
+            put_bytes(&mut buf, &doctor.pubkey);
+        }
+        // Appended only when set, so specs without proof of work keep their digest
+        if let Some(pow) = &self.pow {
+            put_u64(&mut buf, pow.initial_bits as u64);
+            put_u64(&mut buf, pow.target_block_secs);
+            put_u64(&mut buf, pow.retarget_interval);
+        }
+        Sha256::digest(&buf).into()
+    }
+
+    /// The genesis block this spec defines
+    pub fn block(&self) -> Block {
+        Block {
+            index: 0,
+            prev_hash: hex::encode(self.digest()),
+            timestamp: self.timestamp,
+            transactions: vec![],
+            registry_txs: vec![],
+            nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
+        }
+    }
+
+    /// Doctor registry in effect at the genesis block
+    pub fn registry(&self) -> Result<DoctorRegistry, RegistryError> {
+        let mut registry = DoctorRegistry::default();
+        for doctor in &self.doctors {
+            registry.register_genesis(&doctor.doctor_id, &doctor.license_number, &doctor.pubkey)?;
+        }
+        Ok(registry)
+    }
+}
+
+impl Default for GenesisSpec {
+    fn default() -> Self {
+        Self::with_authorities(vec![])
+    }
+}
+
+mod hex_key {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(key: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(key))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let key = String::deserialize(deserializer)?;
+        hex::decode(&key).map_err(serde::de::Error::custom)
+    }
+}
+
+mod hex_keys {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(keys: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(keys.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|key| hex::decode(key).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+
+    fn spec() -> GenesisSpec {
+        let doctor = generate_keypair();
+        GenesisSpec {
+            chain_id: "securerx-test".to_string(),
+            timestamp: 1_700_000_000,
+            authorities: vec![generate_keypair().verifying_key().to_bytes().to_vec()],
+            doctors: vec![GenesisDoctor {
+                doctor_id: "doctor1".to_string(),
+                license_number: "LIC-1".to_string(),
+                pubkey: doctor.verifying_key().to_bytes().to_vec(),
+            }],
+            pow: None,
+        }
+    }
+
+    #[test]
+    fn test_genesis_block_is_deterministic() {
+        let spec = spec();
+        let reloaded: GenesisSpec = serde_json::from_str(&serde_json::to_string(&spec).unwrap()).unwrap();
+        assert_eq!(spec.block().calculate_hash(), reloaded.block().calculate_hash());
+        assert_eq!(spec.block().timestamp, spec.timestamp);
+    }
+
+    #[test]
+    fn test_genesis_hash_commits_to_spec() {
+        let spec = spec();
+        let mut other_chain = spec.clone();
+        other_chain.chain_id = "securerx-other".to_string();
+        let mut other_authorities = spec.clone();
+        other_authorities.authorities.push(vec![7; 32]);
+        let mut no_doctors = spec.clone();
+        no_doctors.doctors.clear();
+        let mut pow = spec.clone();
+        pow.pow = Some(PowParams { initial_bits: 8, target_block_secs: 10, retarget_interval: 10 });
+
+        for changed in [other_chain, other_authorities, no_doctors, pow] {
+            assert_ne!(spec.block().calculate_hash(), changed.block().calculate_hash());
+        }
+    }
+
+    #[test]
+    fn test_genesis_registry() {
+        let spec = spec();
+        let registry = spec.registry().unwrap();
+        assert!(registry.is_authorized("doctor1", &spec.doctors[0].pubkey, 1));
+
+        let mut duplicate = spec.clone();
+        duplicate.doctors.push(spec.doctors[0].clone());
+        assert!(duplicate.registry().is_err());
+    }
+
+    #[test]
+    fn test_load_genesis_file() {
+        let spec = spec();
+        let path = std::env::temp_dir().join(format!("securerx-genesis-{}.json", std::process::id()));
+        std::fs::write(&path, serde_json::to_string_pretty(&spec).unwrap()).unwrap();
+        assert_eq!(GenesisSpec::load(&path).unwrap(), spec);
+
+        std::fs::write(&path, r#"{"chain_id": "x", "timestamp": 0, "authorities": ["zz"]}"#).unwrap();
+        assert!(matches!(GenesisSpec::load(&path), Err(GenesisError::Parse(_))));
+
+        let bad_pow = r#"{"chain_id": "x", "timestamp": 0, "authorities": [],
+            "pow": {"initial_bits": 8, "target_block_secs": 0, "retarget_interval": 10}}"#;
+        std::fs::write(&path, bad_pow).unwrap();
+        assert!(matches!(GenesisSpec::load(&path), Err(GenesisError::Invalid(_))));
+    }
+}
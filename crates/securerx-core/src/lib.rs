@@ -1,4 +1,17 @@
 pub mod block;
 pub mod transaction;
 pub mod blockchain;
+pub mod consensus;
 pub mod crypto;
+pub mod fork_choice;
+pub mod genesis;
+pub mod mempool;
+pub mod merkle;
+pub mod pow;
+pub mod proof;
+pub mod registry;
+pub mod storage;
+pub mod validation;
+mod encoding;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
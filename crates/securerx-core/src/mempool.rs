@@ -0,0 +1,264 @@
+//! Pending prescriptions waiting to be included in a block.
+//!
+//! A [`Mempool`] only admits prescriptions that could go in the next block
+//! (see [`Blockchain::check_transaction`]) and keeps one copy of each,
+//! identified by its transaction id (see [`Transaction::id`]). Blocks are
+//! filled highest priority first, then in order of arrival. A full mempool
+//! makes room for a prescription by dropping the last one in that order, if
+//! it has a lower priority. Prescriptions leave the mempool once included in
+//! the chain, or when they no longer fit the next block, e.g. because their
+//! signing key was revoked in the meantime.
+
+use crate::blockchain::Blockchain;
+use crate::transaction::Transaction;
+use crate::validation::ValidationError;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+/// Most prescriptions a mempool holds by default
+pub const DEFAULT_MEMPOOL_SIZE: usize = 10_000;
+
+/// Most prescriptions in a produced block by default
+pub const DEFAULT_MAX_BLOCK_TXS: usize = 500;
+
+/// Interval between produced blocks by default
+pub const DEFAULT_BLOCK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Why a prescription was not admitted to the mempool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    /// The prescription cannot go in the next block
+    Invalid(ValidationError),
+    /// The mempool is full of prescriptions with the same or a higher priority
+    Full,
+}
+
+impl MempoolError {
+    /// Short machine-readable identifier, e.g. for API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            MempoolError::Invalid(e) => e.code(),
+            MempoolError::Full => "mempool_full",
+        }
+    }
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Invalid(e) => write!(f, "{}", e),
+            MempoolError::Full => write!(f, "the mempool is full"),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+impl From<ValidationError> for MempoolError {
+    fn from(e: ValidationError) -> Self {
+        MempoolError::Invalid(e)
+    }
+}
+
+/// Position of a prescription in block order: highest priority first, then earliest arrival
+type Slot = (Reverse<u8>, u64, [u8; 32]);
+
+#[derive(Debug)]
+struct Entry {
+    tx: Transaction,
+    slot: Slot,
+}
+
+/// Pending prescriptions by transaction id, see the [module documentation](self)
+#[derive(Debug)]
+pub struct Mempool {
+    capacity: usize,
+    entries: HashMap<[u8; 32], Entry>,
+    order: BTreeSet<Slot>,
+    arrivals: u64,
+}
+
+impl Mempool {
+    /// An empty mempool holding up to `capacity` prescriptions
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, entries: HashMap::new(), order: BTreeSet::new(), arrivals: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the prescription with id `tx_id` is pending
+    pub fn contains(&self, tx_id: &[u8; 32]) -> bool {
+        self.entries.contains_key(tx_id)
+    }
+
+    /// The pending prescription with id `tx_id`
+    pub fn get(&self, tx_id: &[u8; 32]) -> Option<&Transaction> {
+        self.entries.get(tx_id).map(|entry| &entry.tx)
+    }
+
+    /// Ids of the pending prescriptions, in block order
+    pub fn ids(&self) -> Vec<[u8; 32]> {
+        self.order.iter().map(|(_, _, id)| *id).collect()
+    }
+
+    /// Admit `tx` with `priority` if it can go in the block after the tip of
+    /// `chain`, returning whether it is new. A prescription already pending,
+    /// even with another signature, is not admitted again; one already in
+    /// the chain is refused as a duplicate.
+    pub fn insert(&mut self, tx: Transaction, priority: u8, chain: &Blockchain) -> Result<bool, MempoolError> {
+        let id = tx.id();
+        if self.entries.contains_key(&id) {
+            return Ok(false);
+        }
+        chain.check_transaction(&tx)?;
+        if self.entries.len() >= self.capacity {
+            match self.order.last() {
+                Some(&(Reverse(lowest), _, last)) if lowest < priority => {
+                    self.remove(&last);
+                }
+                _ => return Err(MempoolError::Full),
+            }
+        }
+        let slot = (Reverse(priority), self.arrivals, id);
+        self.arrivals += 1;
+        self.order.insert(slot);
+        self.entries.insert(id, Entry { tx, slot });
+        Ok(true)
+    }
+
+    /// Drop the prescription with id `tx_id`, returning it
+    pub fn remove(&mut self, tx_id: &[u8; 32]) -> Option<Transaction> {
+        let entry = self.entries.remove(tx_id)?;
+        self.order.remove(&entry.slot);
+        Some(entry.tx)
+    }
+
+    /// Drop the prescriptions `chain` already includes, returning how many
+    pub fn remove_confirmed(&mut self, chain: &Blockchain) -> usize {
+        let confirmed: Vec<[u8; 32]> =
+            self.entries.keys().filter(|id| chain.transaction_height(id).is_some()).copied().collect();
+        for id in &confirmed {
+            self.remove(id);
+        }
+        confirmed.len()
+    }
+
+    /// Up to `max` prescriptions for the block after the tip of `chain`, in
+    /// block order. Prescriptions that no longer fit that block are dropped;
+    /// the returned ones stay pending until the chain includes them.
+    pub fn next_block(&mut self, chain: &Blockchain, max: usize) -> Vec<Transaction> {
+        let mut selected = vec![];
+        let mut stale = vec![];
+        for (_, _, id) in &self.order {
+            if selected.len() >= max {
+                break;
+            }
+            let tx = &self.entries[id].tx;
+            match chain.check_transaction(tx) {
+                Ok(()) => selected.push(tx.clone()),
+                Err(_) => stale.push(*id),
+            }
+        }
+        for id in &stale {
+            self.remove(id);
+        }
+        selected
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMPOOL_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+    use crate::genesis::{GenesisDoctor, GenesisSpec};
+    use crate::test_support::prescription;
+    use ed25519_dalek::SigningKey;
+
+    /// Chain whose single authority seals blocks, with `doctor` registered as doctor1
+    fn chain(doctor: &SigningKey) -> Blockchain {
+        let validator = generate_keypair();
+        let mut genesis = GenesisSpec::with_authorities(vec![validator.verifying_key().to_bytes().to_vec()]);
+        genesis.doctors.push(GenesisDoctor {
+            doctor_id: "doctor1".to_string(),
+            license_number: "LIC-1".to_string(),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        });
+        let mut chain = Blockchain::from_genesis(genesis).unwrap();
+        chain.set_validator_key(validator);
+        chain
+    }
+
+    #[test]
+    fn test_admits_valid_prescriptions_once_in_block_order() {
+        let doctor = generate_keypair();
+        let chain = chain(&doctor);
+        let mut mempool = Mempool::default();
+        let (a, b, c) = (prescription(&doctor, "patient1"), prescription(&doctor, "patient2"), prescription(&doctor, "patient3"));
+        assert_eq!(mempool.insert(a.clone(), 0, &chain), Ok(true));
+        assert_eq!(mempool.insert(b.clone(), 0, &chain), Ok(true));
+        assert_eq!(mempool.insert(c.clone(), 1, &chain), Ok(true));
+        assert_eq!(mempool.insert(a.clone(), 1, &chain), Ok(false), "Pending prescriptions are not admitted twice");
+        let mut stripped = a.clone();
+        stripped.signature.clear();
+        assert_eq!(mempool.insert(stripped, 1, &chain), Ok(false), "Even with another signature");
+
+        let mut forged = prescription(&generate_keypair(), "patient4");
+        assert!(matches!(mempool.insert(forged.clone(), 0, &chain), Err(MempoolError::Invalid(ValidationError::UnknownSigner { .. }))));
+        forged.signature[0] ^= 1;
+        assert_eq!(mempool.insert(forged, 0, &chain).unwrap_err().code(), "bad_signature");
+
+        assert_eq!(mempool.ids(), vec![c.id(), a.id(), b.id()], "Higher priority first, then first come");
+        assert_eq!(mempool.next_block(&chain, 2).iter().map(Transaction::id).collect::<Vec<_>>(), vec![c.id(), a.id()]);
+        assert_eq!(mempool.len(), 3, "Prescriptions stay pending until included");
+    }
+
+    #[test]
+    fn test_full_mempool_drops_the_lowest_priority() {
+        let doctor = generate_keypair();
+        let chain = chain(&doctor);
+        let mut mempool = Mempool::new(2);
+        let (a, b) = (prescription(&doctor, "patient1"), prescription(&doctor, "patient2"));
+        mempool.insert(a.clone(), 1, &chain).unwrap();
+        mempool.insert(b.clone(), 0, &chain).unwrap();
+        assert_eq!(mempool.insert(prescription(&doctor, "patient3"), 0, &chain), Err(MempoolError::Full));
+
+        let urgent = prescription(&doctor, "patient4");
+        assert_eq!(mempool.insert(urgent.clone(), 2, &chain), Ok(true));
+        assert!(!mempool.contains(&b.id()), "The lowest priority makes room");
+        assert_eq!(mempool.ids(), vec![urgent.id(), a.id()]);
+    }
+
+    #[test]
+    fn test_included_prescriptions_leave_the_mempool() {
+        let doctor = generate_keypair();
+        let mut chain = chain(&doctor);
+        let mut mempool = Mempool::default();
+        let (a, b) = (prescription(&doctor, "patient1"), prescription(&doctor, "patient2"));
+        mempool.insert(a.clone(), 0, &chain).unwrap();
+        mempool.insert(b.clone(), 0, &chain).unwrap();
+
+        let block = mempool.next_block(&chain, 1);
+        chain.add_block(block).unwrap();
+        assert_eq!(mempool.next_block(&chain, 10).iter().map(Transaction::id).collect::<Vec<_>>(), vec![b.id()]);
+        assert!(!mempool.contains(&a.id()), "Included prescriptions are dropped");
+        assert_eq!(mempool.insert(a, 0, &chain).unwrap_err().code(), "duplicate_transaction");
+
+        chain.add_block(vec![b]).unwrap();
+        assert_eq!(mempool.remove_confirmed(&chain), 1);
+        assert!(mempool.is_empty());
+    }
+}
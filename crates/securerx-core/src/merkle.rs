@@ -0,0 +1,205 @@
+//! Merkle trees over transaction hashes, following RFC 6962 / RFC 9162.
+//!
+//! Leaves are hashed as `SHA-256(0x00 || leaf)` and interior nodes as
+//! `SHA-256(0x01 || left || right)`, so a leaf can never be passed off as an
+//! interior node. A tree of `n > 1` leaves is split at the largest power of two
+//! below `n`; unlike Bitcoin-style trees no leaf is ever duplicated. The root of
+//! an empty tree is `SHA-256("")`.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+fn leaf_hash(leaf: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(leaf);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Largest power of two strictly below `n` (`n > 1`)
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+/// Root of the Merkle tree whose leaves are `leaves`
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaf_hash(&leaves[0]),
+        n => {
+            let k = split_point(n);
+            node_hash(&merkle_root(&leaves[..k]), &merkle_root(&leaves[k..]))
+        }
+    }
+}
+
+/// Proof that a leaf is at `index` in a tree of `leaf_count` leaves
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: u64,
+    pub leaf_count: u64,
+    /// Sibling hashes from the leaf up to the root
+    #[serde(with = "hex_hashes")]
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Build the proof for `leaves[index]`, or `None` if `index` is out of range
+    pub fn build(leaves: &[[u8; 32]], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+        let mut siblings = Vec::new();
+        collect_siblings(leaves, index, &mut siblings);
+        Some(Self { index: index as u64, leaf_count: leaves.len() as u64, siblings })
+    }
+
+    /// Whether `leaf` is at `self.index` in the tree with root `root`
+    pub fn verify(&self, leaf: &[u8; 32], root: &[u8; 32]) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+        let mut node = self.index;
+        let mut last = self.leaf_count - 1;
+        let mut hash = leaf_hash(leaf);
+        for sibling in &self.siblings {
+            if last == 0 {
+                return false;
+            }
+            if node & 1 == 1 || node == last {
+                hash = node_hash(sibling, &hash);
+                // Skip levels where this subtree had no right sibling
+                while node & 1 == 0 && node != 0 {
+                    node >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                hash = node_hash(&hash, sibling);
+            }
+            node >>= 1;
+            last >>= 1;
+        }
+        last == 0 && &hash == root
+    }
+}
+
+/// Audit path for `leaves[index]`, deepest sibling first
+fn collect_siblings(leaves: &[[u8; 32]], index: usize, siblings: &mut Vec<[u8; 32]>) {
+    if leaves.len() <= 1 {
+        return;
+    }
+    let k = split_point(leaves.len());
+    if index < k {
+        collect_siblings(&leaves[..k], index, siblings);
+        siblings.push(merkle_root(&leaves[k..]));
+    } else {
+        collect_siblings(&leaves[k..], index - k, siblings);
+        siblings.push(merkle_root(&leaves[..k]));
+    }
+}
+
+/// Serialize 32-byte hashes as lowercase hex strings
+pub(crate) mod hex_hash {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hash: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        let mut hash = [0u8; 32];
+        hex::decode_to_slice(String::deserialize(deserializer)?, &mut hash).map_err(serde::de::Error::custom)?;
+        Ok(hash)
+    }
+}
+
+mod hex_hashes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hashes: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(hashes.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|h| {
+                let mut hash = [0u8; 32];
+                hex::decode_to_slice(h, &mut hash).map_err(serde::de::Error::custom)?;
+                Ok(hash)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<[u8; 32]> {
+        (0..n).map(|i| Sha256::digest([i as u8]).into()).collect()
+    }
+
+    #[test]
+    fn test_root_of_small_trees() {
+        let l = leaves(3);
+        assert_eq!(merkle_root(&[]), <[u8; 32]>::from(Sha256::digest([])));
+        assert_eq!(merkle_root(&l[..1]), leaf_hash(&l[0]));
+        assert_eq!(
+            merkle_root(&l),
+            node_hash(&node_hash(&leaf_hash(&l[0]), &leaf_hash(&l[1])), &leaf_hash(&l[2])),
+            "Odd leaf should be promoted, not duplicated"
+        );
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_leaf() {
+        for n in 1..=17 {
+            let l = leaves(n);
+            let root = merkle_root(&l);
+            for (i, leaf) in l.iter().enumerate() {
+                let proof = MerkleProof::build(&l, i).unwrap();
+                assert!(proof.verify(leaf, &root), "leaf {} of {} should verify", i, n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_proof_rejects_wrong_leaf_position_or_root() {
+        let l = leaves(6);
+        let root = merkle_root(&l);
+        let proof = MerkleProof::build(&l, 2).unwrap();
+
+        assert!(!proof.verify(&l[3], &root), "Another leaf should not verify");
+        assert!(!proof.verify(&l[2], &merkle_root(&l[..5])), "Another root should not verify");
+        assert!(!MerkleProof { index: 3, ..proof.clone() }.verify(&l[2], &root), "Wrong position should not verify");
+        assert!(!MerkleProof { index: 9, ..proof.clone() }.verify(&l[2], &root));
+
+        let mut truncated = proof.clone();
+        truncated.siblings.pop();
+        assert!(!truncated.verify(&l[2], &root));
+        assert!(MerkleProof::build(&l, 6).is_none());
+    }
+
+    #[test]
+    fn test_leaf_cannot_pose_as_interior_node() {
+        let l = leaves(2);
+        let interior = node_hash(&leaf_hash(&l[0]), &leaf_hash(&l[1]));
+        assert_ne!(merkle_root(&[interior]), merkle_root(&l));
+    }
+}
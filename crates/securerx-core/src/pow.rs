@@ -0,0 +1,183 @@
+//! Optional proof-of-work on top of the validator seal.
+//!
+//! A network whose genesis spec sets [`PowParams`] requires every block hash
+//! to start with a number of zero bits, found by searching the header
+//! `nonce`. The requirement starts at [`PowParams::initial_bits`] and is
+//! recomputed from block timestamps every [`PowParams::retarget_interval`]
+//! blocks, one bit up when blocks came more than twice as fast as
+//! [`PowParams::target_block_secs`] and one bit down when they came more than
+//! twice as slow. Chains are compared by their cumulative work, the sum of
+//! `2^bits` over their blocks.
+
+use crate::block::{Block, BlockHeader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Most leading zero bits a block can be required to have
+pub const MAX_BITS: u32 = 96;
+
+/// Proof-of-work parameters of a network
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PowParams {
+    /// Leading zero bits required of the first blocks' hashes
+    pub initial_bits: u32,
+    /// Block time that retargeting aims for, in seconds
+    pub target_block_secs: u64,
+    /// Number of blocks between difficulty adjustments; 0 keeps `initial_bits` forever
+    pub retarget_interval: u64,
+}
+
+/// Number of leading zero bits of `hash`
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Whether the hash of `header` has at least `bits` leading zero bits
+pub fn meets_target(header: &BlockHeader, bits: u32) -> bool {
+    leading_zero_bits(&Sha256::digest(header.encode())) >= bits
+}
+
+/// Search nonces until the hash of `block` has at least `bits` leading zero bits.
+/// The block must be signed afterwards, as the seal covers the nonce.
+pub fn mine(block: &mut Block, bits: u32) {
+    block.nonce = 0;
+    while !meets_target(&block.header(), bits) {
+        block.nonce += 1;
+    }
+}
+
+/// Leading zero bits required of the block following `chain`. Replays every
+/// retarget from genesis; nodes keep the result and step it with [`next_bits`].
+pub fn required_bits(params: &PowParams, chain: &[Block]) -> u32 {
+    (1..=chain.len()).fold(params.initial_bits, |bits, len| next_bits(params, bits, &chain[..len]))
+}
+
+/// Leading zero bits required of the block following `chain`, given `bits`,
+/// those required of its last block (`initial_bits` for genesis)
+pub fn next_bits(params: &PowParams, bits: u32, chain: &[Block]) -> u32 {
+    if params.retarget_interval > 0 && (chain.len() as u64).is_multiple_of(params.retarget_interval) {
+        retarget(params, bits, chain)
+    } else {
+        bits
+    }
+}
+
+/// Difficulty after the last `retarget_interval` blocks of `chain`, not counting genesis
+fn retarget(params: &PowParams, bits: u32, chain: &[Block]) -> u32 {
+    let start = chain.len().saturating_sub(params.retarget_interval as usize).max(1);
+    let window = &chain[start.min(chain.len())..];
+    if window.len() < 2 {
+        return bits;
+    }
+    let actual = window[window.len() - 1].timestamp.saturating_sub(window[0].timestamp);
+    let expected = (window.len() as u64 - 1) * params.target_block_secs;
+    if actual < expected / 2 {
+        (bits + 1).min(MAX_BITS)
+    } else if actual > expected.saturating_mul(2) {
+        bits.saturating_sub(1).max(1)
+    } else {
+        bits
+    }
+}
+
+/// Work represented by a block with `bits` leading zero bits
+pub fn work(bits: u32) -> u128 {
+    1u128 << bits.min(MAX_BITS)
+}
+
+/// Cumulative work of the blocks after genesis in `chain`. Without proof of
+/// work every block counts as one unit, so the longest chain is the heaviest.
+pub fn chain_work(params: Option<&PowParams>, chain: &[Block]) -> u128 {
+    let Some(params) = params else {
+        return chain.len().saturating_sub(1) as u128;
+    };
+    let mut bits = params.initial_bits;
+    let mut total = 0;
+    for height in 1..chain.len() {
+        bits = next_bits(params, bits, &chain[..height]);
+        total += work(bits);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> PowParams {
+        PowParams { initial_bits: 4, target_block_secs: 10, retarget_interval: 5 }
+    }
+
+    /// Genesis followed by `n` blocks spaced `gap` seconds apart
+    fn chain(n: u64, gap: u64) -> Vec<Block> {
+        (0..=n)
+            .map(|index| Block {
+                index,
+                prev_hash: String::new(),
+                timestamp: 1_700_000_000 + index * gap,
+                transactions: vec![],
+                registry_txs: vec![],
+                nonce: 0,
+                proposer: vec![],
+                signature: vec![],
+                commit: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn test_mine_meets_target() {
+        let mut block = chain(1, 10).pop().unwrap();
+        mine(&mut block, 8);
+        assert!(meets_target(&block.header(), 8));
+        assert!(block.calculate_hash().starts_with("00"));
+    }
+
+    #[test]
+    fn test_retargeting() {
+        let params = params();
+        assert_eq!(required_bits(&params, &chain(3, 10)), 4, "No adjustment before the first interval");
+        assert_eq!(required_bits(&params, &chain(4, 1)), 5, "Fast blocks raise the difficulty");
+        assert_eq!(required_bits(&params, &chain(9, 1)), 6);
+        assert_eq!(required_bits(&params, &chain(4, 100)), 3, "Slow blocks lower the difficulty");
+        assert_eq!(required_bits(&params, &chain(9, 10)), 4, "On-target blocks keep it");
+
+        let fixed = PowParams { retarget_interval: 0, ..params };
+        assert_eq!(required_bits(&fixed, &chain(20, 1)), 4);
+    }
+
+    #[test]
+    fn test_next_bits_matches_replay() {
+        let params = params();
+        let chain = chain(23, 1);
+        let mut bits = params.initial_bits;
+        for len in 1..=chain.len() {
+            bits = next_bits(&params, bits, &chain[..len]);
+            assert_eq!(bits, required_bits(&params, &chain[..len]), "after {} blocks", len);
+        }
+    }
+
+    #[test]
+    fn test_chain_work() {
+        let params = params();
+        assert_eq!(chain_work(None, &chain(3, 10)), 3);
+        assert_eq!(chain_work(Some(&params), &chain(3, 10)), 3 * 16);
+        // Blocks 5 to 7 follow a retarget to 5 bits
+        assert_eq!(chain_work(Some(&params), &chain(7, 1)), 4 * 16 + 3 * 32);
+        assert!(chain_work(Some(&params), &chain(4, 1)) > chain_work(Some(&params), &chain(3, 1)));
+    }
+}
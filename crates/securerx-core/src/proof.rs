@@ -0,0 +1,177 @@
+//! Self-contained inclusion proofs that can be checked without a node.
+//!
+//! A [`PrescriptionProof`] carries a prescription, its Merkle path into the
+//! header of the block that contains it, and every header from that block up
+//! to a checkpoint. Whoever trusts the checkpoint's hash (e.g. one published by
+//! the network operator) can check the proof offline.
+
+use crate::block::BlockHeader;
+use crate::merkle::MerkleProof;
+use crate::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Proof that `transaction` is in the chain whose block at `headers.last()` has a known hash
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrescriptionProof {
+    pub transaction: Transaction,
+    /// Merkle path from the transaction hash to `headers[0].tx_root`
+    pub proof: MerkleProof,
+    /// Header of the containing block, followed by each later header up to the checkpoint
+    pub headers: Vec<BlockHeader>,
+}
+
+/// Why a [`PrescriptionProof`] was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    NoHeaders,
+    /// The prescription's signature does not verify
+    BadSignature,
+    /// The Merkle path does not lead to the containing block's `tx_root`
+    NotIncluded,
+    /// `headers[position]` does not follow the header before it
+    BrokenLink { position: usize },
+    /// The last header's hash is not the trusted checkpoint
+    CheckpointMismatch { expected: String, found: String },
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::NoHeaders => write!(f, "proof contains no block headers"),
+            ProofError::BadSignature => write!(f, "prescription signature does not verify"),
+            ProofError::NotIncluded => write!(f, "Merkle path does not match the block's transaction root"),
+            ProofError::BrokenLink { position } => {
+                write!(f, "header {} does not extend the header before it", position)
+            }
+            ProofError::CheckpointMismatch { expected, found } => {
+                write!(f, "header chain ends at {}, not at the trusted checkpoint {}", found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+impl PrescriptionProof {
+    /// Check the proof against a trusted checkpoint block hash, returning the
+    /// height of the block containing the prescription
+    pub fn verify(&self, checkpoint_hash: &str) -> Result<u64, ProofError> {
+        let containing = self.headers.first().ok_or(ProofError::NoHeaders)?;
+        if !self.transaction.verify_signature() {
+            return Err(ProofError::BadSignature);
+        }
+        if !containing.verify_transaction(&self.transaction, &self.proof) {
+            return Err(ProofError::NotIncluded);
+        }
+
+        let mut hash = containing.hash();
+        for (position, pair) in self.headers.windows(2).enumerate() {
+            let (prev, header) = (&pair[0], &pair[1]);
+            if header.index != prev.index + 1 || header.prev_hash != hash {
+                return Err(ProofError::BrokenLink { position: position + 1 });
+            }
+            hash = header.hash();
+        }
+        if hash != checkpoint_hash {
+            return Err(ProofError::CheckpointMismatch { expected: checkpoint_hash.to_string(), found: hash });
+        }
+        Ok(containing.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::crypto::generate_keypair;
+    use crate::transaction::DEFAULT_CHAIN_ID;
+
+    fn tx(patient_id: &str) -> Transaction {
+        let mut tx = Transaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            doctor_id: "doctor1".to_string(),
+            patient_id: patient_id.to_string(),
+            drug: "Aspirin".to_string(),
+            dosage: String::new(),
+            issued_at: 1_700_000_000,
+            expires_at: 1_702_592_000,
+            nonce: 1,
+            signature: vec![],
+            pubkey: vec![],
+        };
+        tx.sign(&generate_keypair());
+        tx
+    }
+
+    /// Three linked blocks; the first holds two prescriptions
+    fn headers() -> (Vec<Block>, Vec<BlockHeader>) {
+        let mut blocks = vec![Block {
+            index: 5,
+            prev_hash: "00".repeat(32),
+            timestamp: 1_700_000_000,
+            transactions: vec![tx("patient1"), tx("patient2")],
+            registry_txs: vec![],
+            nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
+        }];
+        for _ in 0..2 {
+            let prev = blocks.last().unwrap();
+            blocks.push(Block {
+                index: prev.index + 1,
+                prev_hash: prev.calculate_hash(),
+                timestamp: prev.timestamp + 1,
+                transactions: vec![],
+                registry_txs: vec![],
+                nonce: 0,
+                proposer: vec![],
+                signature: vec![],
+                commit: None,
+            });
+        }
+        let headers = blocks.iter().map(Block::header).collect();
+        (blocks, headers)
+    }
+
+    #[test]
+    fn test_verify_proof_to_checkpoint() {
+        let (blocks, headers) = headers();
+        let checkpoint = blocks[2].calculate_hash();
+        let proof = PrescriptionProof {
+            transaction: blocks[0].transactions[1].clone(),
+            proof: blocks[0].transaction_proof(1).unwrap(),
+            headers,
+        };
+        assert_eq!(proof.verify(&checkpoint), Ok(5));
+        assert!(matches!(proof.verify(&blocks[1].calculate_hash()), Err(ProofError::CheckpointMismatch { .. })));
+    }
+
+    #[test]
+    fn test_reject_tampered_proofs() {
+        let (blocks, headers) = headers();
+        let checkpoint = blocks[2].calculate_hash();
+        let proof = PrescriptionProof {
+            transaction: blocks[0].transactions[1].clone(),
+            proof: blocks[0].transaction_proof(1).unwrap(),
+            headers,
+        };
+
+        let mut other_tx = proof.clone();
+        other_tx.transaction = tx("patient3");
+        assert_eq!(other_tx.verify(&checkpoint), Err(ProofError::NotIncluded));
+
+        let mut tampered = proof.clone();
+        tampered.transaction.drug = "Oxycodone".to_string();
+        assert_eq!(tampered.verify(&checkpoint), Err(ProofError::BadSignature));
+
+        let mut gap = proof.clone();
+        gap.headers.remove(1);
+        assert_eq!(gap.verify(&checkpoint), Err(ProofError::BrokenLink { position: 1 }));
+
+        let mut empty = proof;
+        empty.headers.clear();
+        assert_eq!(empty.verify(&checkpoint), Err(ProofError::NoHeaders));
+    }
+}
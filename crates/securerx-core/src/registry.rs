@@ -0,0 +1,596 @@
+use serde::{Serialize, Deserialize};
+use ed25519_dalek::SigningKey;
+use sha2::{Sha256, Digest};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::crypto::{sign_message, verify_message};
+use crate::encoding::{put_bytes, put_u64, put_u8};
+
+/// Domain separator for registry transaction signing payloads
+const REGISTRY_SIGNING_DOMAIN: &[u8] = b"securerx/registry/v1";
+
+/// Whether a registered prescriber may currently sign prescriptions
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DoctorStatus {
+    Active,
+    Suspended,
+}
+
+/// A signing key registered to a prescriber
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DoctorKey {
+    pub pubkey: Vec<u8>,
+    /// Height of the block that registered the key
+    pub added_at: u64,
+    /// First block height at which the key may no longer sign, if revoked
+    pub revoked_at: Option<u64>,
+}
+
+impl DoctorKey {
+    /// Whether the key may sign prescriptions included at `height`
+    pub fn is_valid_at(&self, height: u64) -> bool {
+        self.added_at <= height && !matches!(self.revoked_at, Some(revoked) if height >= revoked)
+    }
+}
+
+/// A prescriber identity as recorded on chain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DoctorRecord {
+    pub doctor_id: String,
+    pub license_number: String,
+    pub keys: Vec<DoctorKey>,
+    pub status: DoctorStatus,
+}
+
+impl DoctorRecord {
+    fn key(&self, pubkey: &[u8]) -> Option<&DoctorKey> {
+        self.keys.iter().find(|k| k.pubkey == pubkey)
+    }
+
+    fn key_mut(&mut self, pubkey: &[u8]) -> Option<&mut DoctorKey> {
+        self.keys.iter_mut().find(|k| k.pubkey == pubkey)
+    }
+}
+
+/// A change to the doctor registry
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum RegistryOp {
+    /// Register a new prescriber with an initial signing key
+    RegisterDoctor {
+        doctor_id: String,
+        license_number: String,
+        pubkey: Vec<u8>,
+    },
+    /// Suspend or reinstate a prescriber
+    SetStatus {
+        doctor_id: String,
+        status: DoctorStatus,
+    },
+    /// Replace a key: `new_pubkey` becomes valid and `old_pubkey` stops being
+    /// valid from the block that includes this transaction
+    RotateKey {
+        doctor_id: String,
+        old_pubkey: Vec<u8>,
+        new_pubkey: Vec<u8>,
+    },
+    /// Revoke a key from `effective_height` on. Prescriptions in earlier
+    /// blocks stay valid; the height may not lie before the including block.
+    /// A key already due for revocation may only have it brought forward.
+    RevokeKey {
+        doctor_id: String,
+        pubkey: Vec<u8>,
+        effective_height: u64,
+    },
+}
+
+/// A registry change signed by one of the chain's admin keys
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RegistryTransaction {
+    pub chain_id: String,
+    pub op: RegistryOp,
+    pub nonce: u64,
+    pub admin_pubkey: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Reasons a registry transaction can be rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    /// `admin_pubkey` is not one of the chain's admin keys
+    UnknownAdmin,
+    /// The transaction was signed for another network
+    WrongChain(String),
+    InvalidSignature,
+    /// The exact same registry transaction was already applied
+    Replayed,
+    DoctorExists(String),
+    UnknownDoctor(String),
+    /// The key is already registered to a doctor
+    KeyInUse,
+    MalformedKey,
+    /// The key is not registered to the doctor
+    UnknownKey,
+    /// The key has already been revoked
+    KeyRevoked,
+    /// Revocation would invalidate blocks already on chain
+    RetroactiveRevocation { effective_height: u64, current_height: u64 },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::UnknownAdmin => write!(f, "registry transaction is not signed by an admin key"),
+            RegistryError::WrongChain(chain_id) => write!(f, "registry transaction is signed for chain '{}'", chain_id),
+            RegistryError::InvalidSignature => write!(f, "registry transaction signature is invalid"),
+            RegistryError::Replayed => write!(f, "registry transaction was already applied"),
+            RegistryError::DoctorExists(id) => write!(f, "doctor '{}' is already registered", id),
+            RegistryError::UnknownDoctor(id) => write!(f, "doctor '{}' is not registered", id),
+            RegistryError::KeyInUse => write!(f, "key is already registered to a doctor"),
+            RegistryError::MalformedKey => write!(f, "public key must be 32 bytes"),
+            RegistryError::UnknownKey => write!(f, "key is not registered to this doctor"),
+            RegistryError::KeyRevoked => write!(f, "key has already been revoked"),
+            RegistryError::RetroactiveRevocation { effective_height, current_height } => write!(
+                f,
+                "revocation at height {} would precede the including block at height {}",
+                effective_height, current_height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl RegistryTransaction {
+    /// Canonical payload covered by the admin signature, laid out like
+    /// `Transaction::signing_bytes` with a tag byte selecting the operation.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(192);
+        put_bytes(&mut buf, REGISTRY_SIGNING_DOMAIN);
+        put_bytes(&mut buf, self.chain_id.as_bytes());
+        match &self.op {
+            RegistryOp::RegisterDoctor { doctor_id, license_number, pubkey } => {
+                put_u8(&mut buf, 0);
+                put_bytes(&mut buf, doctor_id.as_bytes());
+                put_bytes(&mut buf, license_number.as_bytes());
+                put_bytes(&mut buf, pubkey);
+            }
+            RegistryOp::SetStatus { doctor_id, status } => {
+                put_u8(&mut buf, 1);
+                put_bytes(&mut buf, doctor_id.as_bytes());
+                put_u8(&mut buf, match status {
+                    DoctorStatus::Active => 0,
+                    DoctorStatus::Suspended => 1,
+                });
+            }
+            RegistryOp::RotateKey { doctor_id, old_pubkey, new_pubkey } => {
+                put_u8(&mut buf, 2);
+                put_bytes(&mut buf, doctor_id.as_bytes());
+                put_bytes(&mut buf, old_pubkey);
+                put_bytes(&mut buf, new_pubkey);
+            }
+            RegistryOp::RevokeKey { doctor_id, pubkey, effective_height } => {
+                put_u8(&mut buf, 3);
+                put_bytes(&mut buf, doctor_id.as_bytes());
+                put_bytes(&mut buf, pubkey);
+                put_u64(&mut buf, *effective_height);
+            }
+        }
+        put_u64(&mut buf, self.nonce);
+        put_bytes(&mut buf, &self.admin_pubkey);
+        buf
+    }
+
+    /// Canonical encoding of the signed transaction, hashed into blocks: the
+    /// signing payload followed by the length-prefixed signature
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.signing_bytes();
+        put_bytes(&mut buf, &self.signature);
+        buf
+    }
+
+    /// SHA-256 of the canonical encoding, used as the Merkle leaf for this transaction
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(self.encode()).into()
+    }
+
+    /// Set `admin_pubkey` to the keypair's public key and sign the canonical payload
+    pub fn sign(&mut self, admin: &SigningKey) {
+        self.admin_pubkey = admin.verifying_key().to_bytes().to_vec();
+        let sig = sign_message(admin, &self.signing_bytes());
+        self.signature = sig.to_bytes().to_vec();
+    }
+
+    /// Verify the admin signature
+    pub fn verify_signature(&self) -> bool {
+        verify_message(&self.admin_pubkey, &self.signing_bytes(), &self.signature)
+    }
+
+    fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.signing_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Prescriber identities derived by replaying registry transactions in chain order
+#[derive(Debug, Clone, Default)]
+pub struct DoctorRegistry {
+    doctors: HashMap<String, DoctorRecord>,
+    applied: HashSet<[u8; 32]>,
+}
+
+impl DoctorRegistry {
+    /// Look up a prescriber by id
+    pub fn get(&self, doctor_id: &str) -> Option<&DoctorRecord> {
+        self.doctors.get(doctor_id)
+    }
+
+    /// All registered prescribers
+    pub fn doctors(&self) -> impl Iterator<Item = &DoctorRecord> {
+        self.doctors.values()
+    }
+
+    /// Whether `pubkey` may sign prescriptions for `doctor_id` in a block at `height`
+    pub fn is_authorized(&self, doctor_id: &str, pubkey: &[u8], height: u64) -> bool {
+        match self.doctors.get(doctor_id) {
+            Some(record) => {
+                record.status == DoctorStatus::Active && record.key(pubkey).is_some_and(|k| k.is_valid_at(height))
+            }
+            None => false,
+        }
+    }
+
+    /// Check a registry transaction included at `height` against `admin_keys`
+    /// and the current state without applying it
+    pub fn check(&self, tx: &RegistryTransaction, admin_keys: &[Vec<u8>], height: u64) -> Result<(), RegistryError> {
+        if !admin_keys.contains(&tx.admin_pubkey) {
+            return Err(RegistryError::UnknownAdmin);
+        }
+        if !tx.verify_signature() {
+            return Err(RegistryError::InvalidSignature);
+        }
+        if self.applied.contains(&tx.digest()) {
+            return Err(RegistryError::Replayed);
+        }
+        match &tx.op {
+            RegistryOp::RegisterDoctor { doctor_id, pubkey, .. } => {
+                if self.doctors.contains_key(doctor_id) {
+                    return Err(RegistryError::DoctorExists(doctor_id.clone()));
+                }
+                if pubkey.len() != 32 {
+                    return Err(RegistryError::MalformedKey);
+                }
+                if self.key_in_use(pubkey) {
+                    return Err(RegistryError::KeyInUse);
+                }
+            }
+            RegistryOp::SetStatus { doctor_id, .. } => {
+                self.record(doctor_id)?;
+            }
+            RegistryOp::RotateKey { doctor_id, old_pubkey, new_pubkey } => {
+                self.live_key(doctor_id, old_pubkey, height)?;
+                if new_pubkey.len() != 32 {
+                    return Err(RegistryError::MalformedKey);
+                }
+                if self.key_in_use(new_pubkey) {
+                    return Err(RegistryError::KeyInUse);
+                }
+            }
+            RegistryOp::RevokeKey { doctor_id, pubkey, effective_height } => {
+                let key = self.live_key(doctor_id, pubkey, height)?;
+                if *effective_height < height {
+                    return Err(RegistryError::RetroactiveRevocation {
+                        effective_height: *effective_height,
+                        current_height: height,
+                    });
+                }
+                // A scheduled revocation may only be brought forward
+                if key.revoked_at.is_some_and(|revoked| *effective_height >= revoked) {
+                    return Err(RegistryError::KeyRevoked);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate and apply a registry transaction included at `height`
+    pub fn apply(&mut self, tx: &RegistryTransaction, admin_keys: &[Vec<u8>], height: u64) -> Result<(), RegistryError> {
+        self.check(tx, admin_keys, height)?;
+        match &tx.op {
+            RegistryOp::RegisterDoctor { doctor_id, license_number, pubkey } => {
+                self.doctors.insert(doctor_id.clone(), DoctorRecord {
+                    doctor_id: doctor_id.clone(),
+                    license_number: license_number.clone(),
+                    keys: vec![DoctorKey { pubkey: pubkey.clone(), added_at: height, revoked_at: None }],
+                    status: DoctorStatus::Active,
+                });
+            }
+            RegistryOp::SetStatus { doctor_id, status } => {
+                if let Some(record) = self.doctors.get_mut(doctor_id) {
+                    record.status = *status;
+                }
+            }
+            RegistryOp::RotateKey { doctor_id, old_pubkey, new_pubkey } => {
+                if let Some(record) = self.doctors.get_mut(doctor_id) {
+                    if let Some(old) = record.key_mut(old_pubkey) {
+                        old.revoked_at = Some(height);
+                    }
+                    record.keys.push(DoctorKey { pubkey: new_pubkey.clone(), added_at: height, revoked_at: None });
+                }
+            }
+            RegistryOp::RevokeKey { doctor_id, pubkey, effective_height } => {
+                if let Some(key) = self.doctors.get_mut(doctor_id).and_then(|r| r.key_mut(pubkey)) {
+                    key.revoked_at = Some(*effective_height);
+                }
+            }
+        }
+        self.applied.insert(tx.digest());
+        Ok(())
+    }
+
+    /// Register a doctor listed in the genesis spec, valid from height 0
+    pub(crate) fn register_genesis(&mut self, doctor_id: &str, license_number: &str, pubkey: &[u8]) -> Result<(), RegistryError> {
+        if self.doctors.contains_key(doctor_id) {
+            return Err(RegistryError::DoctorExists(doctor_id.to_string()));
+        }
+        if pubkey.len() != 32 {
+            return Err(RegistryError::MalformedKey);
+        }
+        if self.key_in_use(pubkey) {
+            return Err(RegistryError::KeyInUse);
+        }
+        self.doctors.insert(doctor_id.to_string(), DoctorRecord {
+            doctor_id: doctor_id.to_string(),
+            license_number: license_number.to_string(),
+            keys: vec![DoctorKey { pubkey: pubkey.to_vec(), added_at: 0, revoked_at: None }],
+            status: DoctorStatus::Active,
+        });
+        Ok(())
+    }
+
+    fn record(&self, doctor_id: &str) -> Result<&DoctorRecord, RegistryError> {
+        self.doctors.get(doctor_id).ok_or_else(|| RegistryError::UnknownDoctor(doctor_id.to_string()))
+    }
+
+    /// A key of `doctor_id` whose revocation, if any, takes effect after `height`
+    fn live_key(&self, doctor_id: &str, pubkey: &[u8], height: u64) -> Result<&DoctorKey, RegistryError> {
+        let key = self.record(doctor_id)?.key(pubkey).ok_or(RegistryError::UnknownKey)?;
+        if key.revoked_at.is_some_and(|revoked| revoked <= height) {
+            return Err(RegistryError::KeyRevoked);
+        }
+        Ok(key)
+    }
+
+    /// Keys stay reserved after revocation so they can never be handed to another doctor
+    fn key_in_use(&self, pubkey: &[u8]) -> bool {
+        self.doctors.values().any(|r| r.key(pubkey).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_keypair;
+    use crate::transaction::DEFAULT_CHAIN_ID;
+
+    fn signed(admin: &SigningKey, op: RegistryOp, nonce: u64) -> RegistryTransaction {
+        let mut tx = RegistryTransaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
+            op,
+            nonce,
+            admin_pubkey: vec![],
+            signature: vec![],
+        };
+        tx.sign(admin);
+        tx
+    }
+
+    fn register(admin: &SigningKey, doctor_id: &str, doctor: &SigningKey) -> RegistryTransaction {
+        signed(admin, RegistryOp::RegisterDoctor {
+            doctor_id: doctor_id.to_string(),
+            license_number: format!("LIC-{}", doctor_id),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        }, 1)
+    }
+
+    fn admin_keys(admin: &SigningKey) -> Vec<Vec<u8>> {
+        vec![admin.verifying_key().to_bytes().to_vec()]
+    }
+
+    #[test]
+    fn test_register_doctor() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let mut registry = DoctorRegistry::default();
+
+        registry.apply(&register(&admin, "doctor1", &doctor), &admin_keys(&admin), 1).unwrap();
+
+        let record = registry.get("doctor1").unwrap();
+        assert_eq!(record.license_number, "LIC-doctor1");
+        assert_eq!(record.status, DoctorStatus::Active);
+        assert!(registry.is_authorized("doctor1", &doctor.verifying_key().to_bytes(), 1));
+        assert!(!registry.is_authorized("doctor1", &doctor.verifying_key().to_bytes(), 0), "Key is not valid before registration");
+        assert!(!registry.is_authorized("doctor2", &doctor.verifying_key().to_bytes(), 1), "Key is bound to its own doctor id");
+    }
+
+    #[test]
+    fn test_non_admin_cannot_register() {
+        let admin = generate_keypair();
+        let intruder = generate_keypair();
+        let mut registry = DoctorRegistry::default();
+
+        let tx = register(&intruder, "doctor1", &generate_keypair());
+        assert_eq!(registry.apply(&tx, &admin_keys(&admin), 1), Err(RegistryError::UnknownAdmin));
+        assert!(registry.get("doctor1").is_none());
+    }
+
+    #[test]
+    fn test_tampered_registry_transaction_rejected() {
+        let admin = generate_keypair();
+        let mut registry = DoctorRegistry::default();
+
+        let mut tx = register(&admin, "doctor1", &generate_keypair());
+        if let RegistryOp::RegisterDoctor { pubkey, .. } = &mut tx.op {
+            *pubkey = generate_keypair().verifying_key().to_bytes().to_vec();
+        }
+        assert_eq!(registry.apply(&tx, &admin_keys(&admin), 1), Err(RegistryError::InvalidSignature));
+    }
+
+    #[test]
+    fn test_suspended_doctor_not_authorized() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let keys = admin_keys(&admin);
+        let mut registry = DoctorRegistry::default();
+        registry.apply(&register(&admin, "doctor1", &doctor), &keys, 1).unwrap();
+
+        let suspend = signed(&admin, RegistryOp::SetStatus {
+            doctor_id: "doctor1".to_string(),
+            status: DoctorStatus::Suspended,
+        }, 2);
+        registry.apply(&suspend, &keys, 2).unwrap();
+        assert!(!registry.is_authorized("doctor1", &doctor.verifying_key().to_bytes(), 2));
+
+        let reinstate = signed(&admin, RegistryOp::SetStatus {
+            doctor_id: "doctor1".to_string(),
+            status: DoctorStatus::Active,
+        }, 3);
+        registry.apply(&reinstate, &keys, 3).unwrap();
+        assert!(registry.is_authorized("doctor1", &doctor.verifying_key().to_bytes(), 3));
+
+        // Replaying the old suspension must not silently flip the status again
+        assert_eq!(registry.apply(&suspend, &keys, 4), Err(RegistryError::Replayed));
+    }
+
+    #[test]
+    fn test_duplicate_doctor_and_key_rejected() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let keys = admin_keys(&admin);
+        let mut registry = DoctorRegistry::default();
+        registry.apply(&register(&admin, "doctor1", &doctor), &keys, 1).unwrap();
+
+        let again = register(&admin, "doctor1", &generate_keypair());
+        assert_eq!(registry.apply(&again, &keys, 2), Err(RegistryError::DoctorExists("doctor1".to_string())));
+
+        let shared_key = register(&admin, "doctor2", &doctor);
+        assert_eq!(registry.apply(&shared_key, &keys, 2), Err(RegistryError::KeyInUse));
+    }
+
+    fn pubkey(key: &SigningKey) -> Vec<u8> {
+        key.verifying_key().to_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_rotate_key() {
+        let admin = generate_keypair();
+        let old = generate_keypair();
+        let new = generate_keypair();
+        let keys = admin_keys(&admin);
+        let mut registry = DoctorRegistry::default();
+        registry.apply(&register(&admin, "doctor1", &old), &keys, 1).unwrap();
+
+        let rotate = signed(&admin, RegistryOp::RotateKey {
+            doctor_id: "doctor1".to_string(),
+            old_pubkey: pubkey(&old),
+            new_pubkey: pubkey(&new),
+        }, 2);
+        registry.apply(&rotate, &keys, 5).unwrap();
+
+        assert!(registry.is_authorized("doctor1", &pubkey(&old), 4), "Old key stays valid for earlier blocks");
+        assert!(!registry.is_authorized("doctor1", &pubkey(&old), 5), "Old key stops at the rotation block");
+        assert!(registry.is_authorized("doctor1", &pubkey(&new), 5));
+        assert!(!registry.is_authorized("doctor1", &pubkey(&new), 4));
+
+        // A rotated-out key cannot be rotated again or reused by anyone
+        let again = signed(&admin, RegistryOp::RotateKey {
+            doctor_id: "doctor1".to_string(),
+            old_pubkey: pubkey(&old),
+            new_pubkey: pubkey(&generate_keypair()),
+        }, 3);
+        assert_eq!(registry.apply(&again, &keys, 6), Err(RegistryError::KeyRevoked));
+        assert_eq!(registry.apply(&register(&admin, "doctor2", &old), &keys, 6), Err(RegistryError::KeyInUse));
+    }
+
+    #[test]
+    fn test_revoke_key_from_height() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let keys = admin_keys(&admin);
+        let mut registry = DoctorRegistry::default();
+        registry.apply(&register(&admin, "doctor1", &doctor), &keys, 1).unwrap();
+
+        let revoke = signed(&admin, RegistryOp::RevokeKey {
+            doctor_id: "doctor1".to_string(),
+            pubkey: pubkey(&doctor),
+            effective_height: 10,
+        }, 2);
+        registry.apply(&revoke, &keys, 3).unwrap();
+
+        assert!(registry.is_authorized("doctor1", &pubkey(&doctor), 9));
+        assert!(!registry.is_authorized("doctor1", &pubkey(&doctor), 10));
+        assert_eq!(registry.get("doctor1").unwrap().keys[0].revoked_at, Some(10));
+    }
+
+    #[test]
+    fn test_scheduled_revocation_can_be_brought_forward() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let keys = admin_keys(&admin);
+        let mut registry = DoctorRegistry::default();
+        registry.apply(&register(&admin, "doctor1", &doctor), &keys, 1).unwrap();
+        let revoke_at = |effective_height, nonce| signed(&admin, RegistryOp::RevokeKey {
+            doctor_id: "doctor1".to_string(),
+            pubkey: pubkey(&doctor),
+            effective_height,
+        }, nonce);
+        registry.apply(&revoke_at(1000, 2), &keys, 3).unwrap();
+
+        // The key was compromised: revoke it early, then postponing it again is refused
+        registry.apply(&revoke_at(500, 3), &keys, 4).unwrap();
+        assert_eq!(registry.get("doctor1").unwrap().keys[0].revoked_at, Some(500));
+        assert!(!registry.is_authorized("doctor1", &pubkey(&doctor), 500));
+        assert_eq!(registry.apply(&revoke_at(800, 4), &keys, 5), Err(RegistryError::KeyRevoked));
+        assert_eq!(registry.apply(&revoke_at(500, 5), &keys, 5), Err(RegistryError::KeyRevoked));
+
+        // A key due for revocation can still be rotated out before then
+        let new = generate_keypair();
+        let rotate = signed(&admin, RegistryOp::RotateKey {
+            doctor_id: "doctor1".to_string(),
+            old_pubkey: pubkey(&doctor),
+            new_pubkey: pubkey(&new),
+        }, 6);
+        registry.apply(&rotate, &keys, 20).unwrap();
+        assert!(!registry.is_authorized("doctor1", &pubkey(&doctor), 20));
+        assert!(registry.is_authorized("doctor1", &pubkey(&new), 20));
+        assert_eq!(registry.apply(&revoke_at(30, 7), &keys, 21), Err(RegistryError::KeyRevoked));
+    }
+
+    #[test]
+    fn test_revocation_cannot_be_retroactive() {
+        let admin = generate_keypair();
+        let doctor = generate_keypair();
+        let keys = admin_keys(&admin);
+        let mut registry = DoctorRegistry::default();
+        registry.apply(&register(&admin, "doctor1", &doctor), &keys, 1).unwrap();
+
+        let revoke = signed(&admin, RegistryOp::RevokeKey {
+            doctor_id: "doctor1".to_string(),
+            pubkey: pubkey(&doctor),
+            effective_height: 2,
+        }, 2);
+        assert_eq!(
+            registry.apply(&revoke, &keys, 7),
+            Err(RegistryError::RetroactiveRevocation { effective_height: 2, current_height: 7 })
+        );
+
+        let unknown = signed(&admin, RegistryOp::RevokeKey {
+            doctor_id: "doctor1".to_string(),
+            pubkey: pubkey(&generate_keypair()),
+            effective_height: 9,
+        }, 3);
+        assert_eq!(registry.apply(&unknown, &keys, 7), Err(RegistryError::UnknownKey));
+    }
+}
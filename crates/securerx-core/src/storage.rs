@@ -0,0 +1,377 @@
+//! Durable block storage.
+//!
+//! A [`BlockStore`] holds the committed chain in order. Appends are durable
+//! once they return, so a node that crashes mid-write restarts with every
+//! block it acknowledged and at most loses the block it was writing.
+
+use crate::block::Block;
+use redb::{ReadableTable, ReadableTableMetadata};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Errors raised by block stores
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    /// Stored data is damaged somewhere other than a torn final write
+    Corrupt(String),
+    /// Error reported by the embedded database
+    Backend(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "storage I/O error: {}", e),
+            StorageError::Corrupt(e) => write!(f, "block store is corrupt: {}", e),
+            StorageError::Backend(e) => write!(f, "block store error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+/// Ordered, durable storage for committed blocks
+pub trait BlockStore: Send + fmt::Debug {
+    /// Load every stored block, in chain order
+    fn load(&mut self) -> Result<Vec<Block>, StorageError>;
+
+    /// Append a block and sync it to disk before returning
+    fn append(&mut self, block: &Block) -> Result<(), StorageError>;
+
+    /// Drop every block from position `len` on, keeping the first `len`
+    fn truncate(&mut self, len: u64) -> Result<(), StorageError>;
+}
+
+/// Which [`BlockStore`] implementation to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageBackend {
+    /// Append-only log file (`blocks.log`)
+    #[default]
+    File,
+    /// Embedded key-value database (`blocks.redb`)
+    Kv,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(StorageBackend::File),
+            "kv" => Ok(StorageBackend::Kv),
+            other => Err(format!("unknown storage backend '{}', expected 'file' or 'kv'", other)),
+        }
+    }
+}
+
+/// Open the store for `backend` inside `data_dir`, creating the directory if needed
+pub fn open_store(data_dir: &Path, backend: StorageBackend) -> Result<Box<dyn BlockStore>, StorageError> {
+    std::fs::create_dir_all(data_dir)?;
+    Ok(match backend {
+        StorageBackend::File => Box::new(FileBlockStore::open(data_dir.join("blocks.log"))?),
+        StorageBackend::Kv => Box::new(KvBlockStore::open(data_dir.join("blocks.redb"))?),
+    })
+}
+
+/// Length of a log record header: payload length (`u32`) + checksum (8 bytes)
+const RECORD_HEADER_LEN: usize = 12;
+
+/// Append-only log of length-prefixed, checksummed JSON blocks.
+///
+/// Each record is a big-endian `u32` payload length, the first 8 bytes of the
+/// payload's SHA-256, then the payload. On load, an incomplete or mismatching
+/// final record is treated as a torn write and truncated away; damage anywhere
+/// earlier is reported as corruption.
+#[derive(Debug)]
+pub struct FileBlockStore {
+    path: PathBuf,
+    file: File,
+    /// Byte offset at which each stored block's record starts
+    offsets: Vec<u64>,
+}
+
+impl FileBlockStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let path = path.into();
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        Ok(Self { path, file, offsets: vec![] })
+    }
+
+    /// Path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn end_offset(&mut self) -> Result<u64, StorageError> {
+        Ok(self.file.seek(SeekFrom::End(0))?)
+    }
+}
+
+impl BlockStore for FileBlockStore {
+    fn load(&mut self) -> Result<Vec<Block>, StorageError> {
+        let mut data = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut data)?;
+
+        let mut blocks = Vec::new();
+        let mut offsets = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let payload = match parse_record(&data[pos..]) {
+                Record::Valid(payload) => payload,
+                Record::BadChecksum(len) if pos + len < data.len() => {
+                    return Err(StorageError::Corrupt(format!("checksum mismatch at offset {}", pos)));
+                }
+                // Torn final write: drop it so the next append starts on a record boundary
+                Record::BadChecksum(_) | Record::Incomplete => {
+                    self.file.set_len(pos as u64)?;
+                    self.file.sync_all()?;
+                    break;
+                }
+            };
+            let block = serde_json::from_slice::<Block>(payload)
+                .map_err(|e| StorageError::Corrupt(format!("undecodable block at offset {}: {}", pos, e)))?;
+            offsets.push(pos as u64);
+            blocks.push(block);
+            pos += RECORD_HEADER_LEN + payload.len();
+        }
+
+        self.offsets = offsets;
+        Ok(blocks)
+    }
+
+    fn append(&mut self, block: &Block) -> Result<(), StorageError> {
+        let payload = serde_json::to_vec(block).map_err(|e| StorageError::Backend(e.to_string()))?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(&record_checksum(&payload));
+        record.extend_from_slice(&payload);
+
+        let offset = self.end_offset()?;
+        if let Err(e) = self.file.write_all(&record).and_then(|()| self.file.sync_data()) {
+            // Cut the partial record off, or the next append would land after it
+            // and be dropped with it as a torn tail on the next load
+            self.file.set_len(offset)?;
+            return Err(e.into());
+        }
+        self.offsets.push(offset);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), StorageError> {
+        if let Some(&offset) = self.offsets.get(len as usize) {
+            self.file.set_len(offset)?;
+            self.file.sync_all()?;
+            self.offsets.truncate(len as usize);
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of parsing the log record at the start of a buffer
+enum Record<'a> {
+    Valid(&'a [u8]),
+    /// Checksum does not match; carries the full record length
+    BadChecksum(usize),
+    /// The buffer ends before the record does
+    Incomplete,
+}
+
+fn parse_record(data: &[u8]) -> Record<'_> {
+    if data.len() < RECORD_HEADER_LEN {
+        return Record::Incomplete;
+    }
+    let len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+    let Some(payload) = data.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len) else {
+        return Record::Incomplete;
+    };
+    if data[4..RECORD_HEADER_LEN] != record_checksum(payload) {
+        return Record::BadChecksum(RECORD_HEADER_LEN + len);
+    }
+    Record::Valid(payload)
+}
+
+fn record_checksum(payload: &[u8]) -> [u8; 8] {
+    let digest = Sha256::digest(payload);
+    digest[..8].try_into().unwrap()
+}
+
+const BLOCKS_TABLE: redb::TableDefinition<u64, &[u8]> = redb::TableDefinition::new("blocks");
+
+/// Blocks stored in an embedded redb database, keyed by index.
+/// Every append is its own durable write transaction.
+#[derive(Debug)]
+pub struct KvBlockStore {
+    db: redb::Database,
+}
+
+impl KvBlockStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let db = redb::Database::create(path.as_ref()).map_err(backend_error)?;
+        // Make sure the table exists so reads never see TableDoesNotExist
+        let txn = db.begin_write().map_err(backend_error)?;
+        txn.open_table(BLOCKS_TABLE).map_err(backend_error)?;
+        txn.commit().map_err(backend_error)?;
+        Ok(Self { db })
+    }
+}
+
+impl BlockStore for KvBlockStore {
+    fn load(&mut self) -> Result<Vec<Block>, StorageError> {
+        let txn = self.db.begin_read().map_err(backend_error)?;
+        let table = txn.open_table(BLOCKS_TABLE).map_err(backend_error)?;
+        let mut blocks = Vec::new();
+        for entry in table.iter().map_err(backend_error)? {
+            let (key, value) = entry.map_err(backend_error)?;
+            if key.value() != blocks.len() as u64 {
+                return Err(StorageError::Corrupt(format!("missing block {}", blocks.len())));
+            }
+            let block = serde_json::from_slice::<Block>(value.value())
+                .map_err(|e| StorageError::Corrupt(format!("undecodable block {}: {}", key.value(), e)))?;
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    fn append(&mut self, block: &Block) -> Result<(), StorageError> {
+        let payload = serde_json::to_vec(block).map_err(|e| StorageError::Backend(e.to_string()))?;
+        let txn = self.db.begin_write().map_err(backend_error)?;
+        {
+            let mut table = txn.open_table(BLOCKS_TABLE).map_err(backend_error)?;
+            let next = table.len().map_err(backend_error)?;
+            table.insert(next, payload.as_slice()).map_err(backend_error)?;
+        }
+        txn.commit().map_err(backend_error)?;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), StorageError> {
+        let txn = self.db.begin_write().map_err(backend_error)?;
+        {
+            let mut table = txn.open_table(BLOCKS_TABLE).map_err(backend_error)?;
+            table.retain_in(len.., |_, _| false).map_err(backend_error)?;
+        }
+        txn.commit().map_err(backend_error)?;
+        Ok(())
+    }
+}
+
+fn backend_error(e: impl fmt::Display) -> StorageError {
+    StorageError::Backend(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("securerx-storage-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn block(index: u64) -> Block {
+        Block {
+            index,
+            prev_hash: format!("{:064x}", index),
+            timestamp: 1_700_000_000 + index,
+            transactions: vec![],
+            registry_txs: vec![],
+            nonce: 0,
+            proposer: vec![],
+            signature: vec![],
+            commit: None,
+        }
+    }
+
+    fn roundtrip(backend: StorageBackend, dir: &Path) {
+        {
+            let mut store = open_store(dir, backend).unwrap();
+            assert!(store.load().unwrap().is_empty());
+            for i in 0..3 {
+                store.append(&block(i)).unwrap();
+            }
+        }
+
+        let mut store = open_store(dir, backend).unwrap();
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.iter().map(|b| b.index).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        store.truncate(1).unwrap();
+        store.append(&block(7)).unwrap();
+        drop(store);
+        let loaded = open_store(dir, backend).unwrap().load().unwrap();
+        assert_eq!(loaded.iter().map(|b| b.index).collect::<Vec<_>>(), vec![0, 7]);
+    }
+
+    #[test]
+    fn test_file_store_roundtrip() {
+        roundtrip(StorageBackend::File, &temp_dir("file-roundtrip"));
+    }
+
+    #[test]
+    fn test_kv_store_roundtrip() {
+        roundtrip(StorageBackend::Kv, &temp_dir("kv-roundtrip"));
+    }
+
+    #[test]
+    fn test_file_store_recovers_from_torn_write() {
+        let path = temp_dir("torn").join("blocks.log");
+        {
+            let mut store = FileBlockStore::open(&path).unwrap();
+            store.append(&block(0)).unwrap();
+            store.append(&block(1)).unwrap();
+        }
+        let good_len = std::fs::metadata(&path).unwrap().len();
+
+        // Simulate a crash halfway through writing the third record
+        let mut record = Vec::new();
+        let payload = serde_json::to_vec(&block(2)).unwrap();
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(&record_checksum(&payload));
+        record.extend_from_slice(&payload[..payload.len() / 2]);
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&record).unwrap();
+
+        let mut store = FileBlockStore::open(&path).unwrap();
+        assert_eq!(store.load().unwrap().len(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len, "Torn record should be truncated");
+
+        store.append(&block(2)).unwrap();
+        assert_eq!(FileBlockStore::open(&path).unwrap().load().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_file_store_detects_corruption_before_tail() {
+        let path = temp_dir("corrupt").join("blocks.log");
+        {
+            let mut store = FileBlockStore::open(&path).unwrap();
+            store.append(&block(0)).unwrap();
+            store.append(&block(1)).unwrap();
+        }
+        let mut data = std::fs::read(&path).unwrap();
+        data[RECORD_HEADER_LEN + 2] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let result = FileBlockStore::open(&path).unwrap().load();
+        assert!(matches!(result, Err(StorageError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_storage_backend_from_str() {
+        assert_eq!("file".parse::<StorageBackend>(), Ok(StorageBackend::File));
+        assert_eq!("kv".parse::<StorageBackend>(), Ok(StorageBackend::Kv));
+        assert!("sql".parse::<StorageBackend>().is_err());
+    }
+}
@@ -0,0 +1,23 @@
+//! Fixtures shared by the tests of this crate and of the crates built on it,
+//! which enable them through the `test-support` feature.
+
+use crate::transaction::{Transaction, DEFAULT_CHAIN_ID};
+use ed25519_dalek::SigningKey;
+
+/// Prescription of `doctor1` for `patient_id` on the development chain, signed by `doctor`
+pub fn prescription(doctor: &SigningKey, patient_id: &str) -> Transaction {
+    let mut tx = Transaction {
+        chain_id: DEFAULT_CHAIN_ID.to_string(),
+        doctor_id: "doctor1".to_string(),
+        patient_id: patient_id.to_string(),
+        drug: "Amoxicillin".to_string(),
+        dosage: "500mg".to_string(),
+        issued_at: 1_700_000_000,
+        expires_at: 1_702_592_000,
+        nonce: 1,
+        signature: vec![],
+        pubkey: vec![],
+    };
+    tx.sign(doctor);
+    tx
+}
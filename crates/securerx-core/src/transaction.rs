@@ -1,116 +1,244 @@
 use serde::{Serialize, Deserialize};
-use ed25519_dalek::{VerifyingKey, Signature};
+use ed25519_dalek::SigningKey;
+use crate::crypto::{sign_message, verify_message};
+use crate::encoding::{put_bytes, put_u64};
+use sha2::{Digest, Sha256};
+
+/// Chain id used by development networks and tests
+pub const DEFAULT_CHAIN_ID: &str = "securerx-dev";
+
+/// Domain separator prefixed to every prescription signing payload, so a
+/// prescription signature can never be replayed as a signature over some
+/// other kind of message.
+const TX_SIGNING_DOMAIN: &[u8] = b"securerx/prescription/v1";
 
 /// Represents a prescription transaction
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Transaction {
+    pub chain_id: String,
     pub doctor_id: String,
     pub patient_id: String,
     pub drug: String,
+    pub dosage: String,
+    /// Unix time (seconds) at which the prescription was issued
+    pub issued_at: u64,
+    /// Unix time (seconds) after which the prescription may no longer be dispensed
+    pub expires_at: u64,
+    pub nonce: u64,
     pub signature: Vec<u8>,
     pub pubkey: Vec<u8>,
 }
 
 impl Transaction {
+    /// Canonical payload covered by the doctor's signature.
+    ///
+    /// The domain separator is followed by every semantic field in declaration
+    /// order: strings and byte strings are written as a big-endian `u32` length
+    /// followed by their bytes, integers as big-endian `u64`. The signature
+    /// itself is the only field left out.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(256);
+        put_bytes(&mut buf, TX_SIGNING_DOMAIN);
+        put_bytes(&mut buf, self.chain_id.as_bytes());
+        put_bytes(&mut buf, self.doctor_id.as_bytes());
+        put_bytes(&mut buf, self.patient_id.as_bytes());
+        put_bytes(&mut buf, self.drug.as_bytes());
+        put_bytes(&mut buf, self.dosage.as_bytes());
+        put_u64(&mut buf, self.issued_at);
+        put_u64(&mut buf, self.expires_at);
+        put_u64(&mut buf, self.nonce);
+        put_bytes(&mut buf, &self.pubkey);
+        buf
+    }
+
+    /// Canonical encoding of the signed transaction, hashed into blocks: the
+    /// signing payload followed by the length-prefixed signature
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.signing_bytes();
+        put_bytes(&mut buf, &self.signature);
+        buf
+    }
+
+    /// SHA-256 of the canonical encoding, used as the Merkle leaf for this
+    /// transaction. Use [`Transaction::id`] to identify the prescription.
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(self.encode()).into()
+    }
+
+    /// Set `pubkey` to the keypair's public key and sign the canonical payload
+    pub fn sign(&mut self, keypair: &SigningKey) {
+        self.pubkey = keypair.verifying_key().to_bytes().to_vec();
+        let sig = sign_message(keypair, &self.signing_bytes());
+        self.signature = sig.to_bytes().to_vec();
+    }
+
     /// Verify the transaction signature
     pub fn verify_signature(&self) -> bool {
-        // Convert Vec<u8> to fixed-size arrays
-        if self.pubkey.len() != 32 || self.signature.len() != 64 {
-            return false;
-        }
-        
-        let pubkey_bytes: [u8; 32] = match self.pubkey.as_slice().try_into() {
-            Ok(bytes) => bytes,
-            Err(_) => return false,
-        };
-        
-        let sig_bytes: [u8; 64] = match self.signature.as_slice().try_into() {
-            Ok(bytes) => bytes,
-            Err(_) => return false,
-        };
-        
-        let pubkey = match VerifyingKey::from_bytes(&pubkey_bytes) {
-            Ok(pk) => pk,
-            Err(_) => return false,
-        };
-        
-        let sig = Signature::from_bytes(&sig_bytes);
-        pubkey.verify_strict(self.drug.as_bytes(), &sig).is_ok()
+        verify_message(&self.pubkey, &self.signing_bytes(), &self.signature)
+    }
+
+    /// Transaction id: SHA-256 of the signing payload. It leaves out the
+    /// signature, so re-signing a prescription does not give it a new id,
+    /// while the `nonce` tells apart otherwise identical prescriptions. A
+    /// chain holds each id at most once.
+    pub fn id(&self) -> [u8; 32] {
+        Sha256::digest(self.signing_bytes()).into()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::{generate_keypair, sign_message};
+    use crate::crypto::generate_keypair;
 
-    #[test]
-    fn test_valid_transaction_signature() {
-        let keypair = generate_keypair();
-        let drug = "Aspirin";
-        let sig = sign_message(&keypair, drug.as_bytes());
-
-        let tx = Transaction {
+    fn unsigned_tx() -> Transaction {
+        Transaction {
+            chain_id: DEFAULT_CHAIN_ID.to_string(),
             doctor_id: "doctor1".to_string(),
             patient_id: "patient1".to_string(),
-            drug: drug.to_string(),
-            signature: sig.to_bytes().to_vec(),
-            pubkey: keypair.verifying_key().to_bytes().to_vec(),
-        };
+            drug: "Aspirin".to_string(),
+            dosage: "100mg once daily".to_string(),
+            issued_at: 1_700_000_000,
+            expires_at: 1_702_592_000,
+            nonce: 42,
+            signature: vec![],
+            pubkey: vec![],
+        }
+    }
 
+    fn signed_tx() -> Transaction {
+        let mut tx = unsigned_tx();
+        tx.sign(&generate_keypair());
+        tx
+    }
+
+    #[test]
+    fn test_valid_transaction_signature() {
+        let tx = signed_tx();
         assert!(tx.verify_signature(), "Valid signature should verify");
     }
 
     #[test]
     fn test_invalid_transaction_signature_wrong_drug() {
-        let keypair = generate_keypair();
-        let drug = "Aspirin";
-        let sig = sign_message(&keypair, drug.as_bytes());
-
-        let tx = Transaction {
-            doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: "Ibuprofen".to_string(), // Different drug
-            signature: sig.to_bytes().to_vec(),
-            pubkey: keypair.verifying_key().to_bytes().to_vec(),
-        };
-
+        let mut tx = signed_tx();
+        tx.drug = "Ibuprofen".to_string(); // Different drug
         assert!(!tx.verify_signature(), "Signature for wrong drug should fail");
     }
 
     #[test]
     fn test_invalid_transaction_signature_wrong_key() {
-        let keypair1 = generate_keypair();
-        let keypair2 = generate_keypair();
-        let drug = "Aspirin";
-        let sig = sign_message(&keypair1, drug.as_bytes());
-
-        let tx = Transaction {
-            doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: drug.to_string(),
-            signature: sig.to_bytes().to_vec(),
-            pubkey: keypair2.verifying_key().to_bytes().to_vec(), // Wrong public key
-        };
-
+        let mut tx = signed_tx();
+        tx.pubkey = generate_keypair().verifying_key().to_bytes().to_vec(); // Wrong public key
         assert!(!tx.verify_signature(), "Signature with wrong key should fail");
     }
 
     #[test]
     fn test_invalid_transaction_signature_corrupted() {
+        let mut tx = signed_tx();
+        tx.signature[0] = tx.signature[0].wrapping_add(1); // Corrupt signature
+        assert!(!tx.verify_signature(), "Corrupted signature should fail");
+    }
+
+    #[test]
+    fn test_tampering_with_any_field_breaks_signature() {
+        type Tamper = fn(&mut Transaction);
+        let tampered: Vec<(&str, Tamper)> = vec![
+            ("chain_id", |tx| tx.chain_id = "securerx-other".to_string()),
+            ("doctor_id", |tx| tx.doctor_id = "doctor2".to_string()),
+            ("patient_id", |tx| tx.patient_id = "patient2".to_string()),
+            ("drug", |tx| tx.drug = "Oxycodone".to_string()),
+            ("dosage", |tx| tx.dosage = "1000mg once daily".to_string()),
+            ("issued_at", |tx| tx.issued_at += 1),
+            ("expires_at", |tx| tx.expires_at += 86_400),
+            ("nonce", |tx| tx.nonce += 1),
+        ];
+
+        let tx = signed_tx();
+        for (field, tamper) in tampered {
+            let mut copy = tx.clone();
+            tamper(&mut copy);
+            assert!(!copy.verify_signature(), "Tampering with {} should break the signature", field);
+        }
+    }
+
+    #[test]
+    fn test_signing_bytes_are_unambiguous() {
+        // Moving bytes between adjacent fields must change the payload
+        let mut a = unsigned_tx();
+        a.doctor_id = "doc".to_string();
+        a.patient_id = "tor1patient1".to_string();
+        let mut b = unsigned_tx();
+        b.doctor_id = "doctor1".to_string();
+        b.patient_id = "patient1".to_string();
+        assert_ne!(a.signing_bytes(), b.signing_bytes());
+        assert!(a.signing_bytes().starts_with(&(TX_SIGNING_DOMAIN.len() as u32).to_be_bytes()));
+    }
+
+    #[test]
+    fn test_signature_over_drug_only_is_rejected() {
+        // Signatures in the old drug-only format must not verify any more
         let keypair = generate_keypair();
-        let drug = "Aspirin";
-        let mut sig_bytes = sign_message(&keypair, drug.as_bytes()).to_bytes().to_vec();
-        sig_bytes[0] = sig_bytes[0].wrapping_add(1); // Corrupt signature
+        let mut tx = unsigned_tx();
+        tx.pubkey = keypair.verifying_key().to_bytes().to_vec();
+        tx.signature = sign_message(&keypair, tx.drug.as_bytes()).to_bytes().to_vec();
+        assert!(!tx.verify_signature());
+    }
 
-        let tx = Transaction {
-            doctor_id: "doctor1".to_string(),
-            patient_id: "patient1".to_string(),
-            drug: drug.to_string(),
-            signature: sig_bytes,
-            pubkey: keypair.verifying_key().to_bytes().to_vec(),
-        };
+    /// Golden vector from `docs/encoding.md`; changing it breaks every
+    /// independent verifier, so bump the domain version instead
+    #[test]
+    fn test_golden_vector() {
+        let mut tx = unsigned_tx();
+        tx.sign(&SigningKey::from_bytes(&[1u8; 32]));
 
-        assert!(!tx.verify_signature(), "Corrupted signature should fail");
+        assert_eq!(
+            hex::encode(tx.signing_bytes()),
+            concat!(
+                "0000001873656375726572782f707265736372697074696f6e2f7631",
+                "0000000c73656375726572782d646576",
+                "00000007646f63746f7231",
+                "0000000870617469656e7431",
+                "000000074173706972696e",
+                "000000103130306d67206f6e6365206461696c79",
+                "000000006553f100",
+                "00000000657b7e00",
+                "000000000000002a",
+                "000000208a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c",
+            )
+        );
+        assert_eq!(
+            hex::encode(&tx.signature),
+            "06c11e81515934aa5a3b3a79038ab2a307c53c66a1a1b4c5313d5c17fa384a4fd063c582c3f48c238ea1dc0866b07802405cdfe12dedc02a528ac25ed8f8aa00"
+        );
+        assert_eq!(
+            hex::encode(Sha256::digest(tx.encode())),
+            "b3d809ef28fe45137c0539c5cb1dcd1fc79084f2665f871c52b348d82c8fe5ac"
+        );
+    }
+
+    #[test]
+    fn test_golden_vector_id() {
+        let mut tx = unsigned_tx();
+        tx.sign(&SigningKey::from_bytes(&[1u8; 32]));
+        assert_eq!(
+            hex::encode(tx.id()),
+            "d5d17963aa7fd3d92b24a0ae4471c9c3d99e5f51118328a7216e57f6651d8d26"
+        );
+    }
+
+    #[test]
+    fn test_id_ignores_signature_but_not_nonce() {
+        let keypair = generate_keypair();
+        let mut tx = unsigned_tx();
+        tx.sign(&keypair);
+        let mut stripped = tx.clone();
+        stripped.signature.clear();
+        assert_eq!(stripped.id(), tx.id(), "The id does not depend on the signature bytes");
+        assert_ne!(stripped.hash(), tx.hash());
+
+        let mut again = unsigned_tx();
+        again.nonce += 1;
+        again.sign(&keypair);
+        assert_ne!(again.id(), tx.id(), "The nonce makes an identical prescription a new one");
     }
 }
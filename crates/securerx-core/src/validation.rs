@@ -0,0 +1,180 @@
+//! Reasons a block or chain fails validation.
+
+use crate::registry::RegistryError;
+use std::fmt;
+
+/// Why a chain or block was rejected.
+///
+/// `block` is the height of the offending block and `tx` the position of the
+/// offending transaction within that block's `transactions` (or
+/// `registry_txs`, for [`ValidationError::InvalidRegistryTx`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The first block is not the genesis block of this network
+    GenesisMismatch { expected: String, found: String },
+    /// The block's index does not follow its predecessor's
+    BadIndex { block: u64, index: u64 },
+    /// `prev_hash` does not match the hash of the previous block
+    BadPrevHash { block: u64 },
+    /// The block's parent is not on this chain
+    UnknownParent { block: u64 },
+    /// The block is timestamped before its predecessor
+    NonMonotonicTimestamp { block: u64, timestamp: u64, prev_timestamp: u64 },
+    /// The block is timestamped further ahead of this node's clock than the allowed drift
+    FutureTimestamp { block: u64, timestamp: u64 },
+    /// The block's proposer is not a validator
+    UnknownProposer { block: u64, proposer: String },
+    /// The block was proposed by a validator out of turn
+    WrongProposer { block: u64, expected: String, found: String },
+    /// The proposer's signature over the block header does not verify
+    BadBlockSignature { block: u64 },
+    /// The block's commit certificate is not a quorum of valid precommits for it
+    BadCommitCertificate { block: u64 },
+    /// The block hash has fewer leading zero bits than the proof-of-work difficulty requires
+    InsufficientWork { block: u64, bits: u32 },
+    /// A registry transaction was rejected by the registry
+    InvalidRegistryTx { block: u64, tx: usize, error: RegistryError },
+    /// A prescription was signed for another network
+    WrongChain { block: u64, tx: usize, chain_id: String },
+    /// A prescription does not expire after it is issued
+    InvalidValidityWindow { block: u64, tx: usize },
+    /// A prescription's signature does not verify
+    BadSignature { block: u64, tx: usize },
+    /// A prescription names a doctor that is not registered, or is signed with a key not registered to them
+    UnknownSigner { block: u64, tx: usize, doctor_id: String },
+    /// A prescription is signed with a revoked key or by a suspended doctor
+    UnauthorizedSigner { block: u64, tx: usize, doctor_id: String },
+    /// A prescription already appears earlier in the chain
+    DuplicateTransaction { block: u64, tx: usize },
+}
+
+impl ValidationError {
+    /// Short machine-readable identifier, e.g. for API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::GenesisMismatch { .. } => "genesis_mismatch",
+            ValidationError::BadIndex { .. } => "bad_index",
+            ValidationError::BadPrevHash { .. } => "bad_prev_hash",
+            ValidationError::UnknownParent { .. } => "unknown_parent",
+            ValidationError::NonMonotonicTimestamp { .. } => "non_monotonic_timestamp",
+            ValidationError::FutureTimestamp { .. } => "future_timestamp",
+            ValidationError::UnknownProposer { .. } => "unknown_proposer",
+            ValidationError::WrongProposer { .. } => "wrong_proposer",
+            ValidationError::BadBlockSignature { .. } => "bad_block_signature",
+            ValidationError::BadCommitCertificate { .. } => "bad_commit_certificate",
+            ValidationError::InsufficientWork { .. } => "insufficient_work",
+            ValidationError::InvalidRegistryTx { .. } => "invalid_registry_tx",
+            ValidationError::WrongChain { .. } => "wrong_chain",
+            ValidationError::InvalidValidityWindow { .. } => "invalid_validity_window",
+            ValidationError::BadSignature { .. } => "bad_signature",
+            ValidationError::UnknownSigner { .. } => "unknown_signer",
+            ValidationError::UnauthorizedSigner { .. } => "unauthorized_signer",
+            ValidationError::DuplicateTransaction { .. } => "duplicate_transaction",
+        }
+    }
+
+    /// Height of the offending block
+    pub fn block(&self) -> u64 {
+        match self {
+            ValidationError::GenesisMismatch { .. } => 0,
+            ValidationError::BadIndex { block, .. }
+            | ValidationError::BadPrevHash { block }
+            | ValidationError::UnknownParent { block }
+            | ValidationError::NonMonotonicTimestamp { block, .. }
+            | ValidationError::FutureTimestamp { block, .. }
+            | ValidationError::UnknownProposer { block, .. }
+            | ValidationError::WrongProposer { block, .. }
+            | ValidationError::BadBlockSignature { block }
+            | ValidationError::BadCommitCertificate { block }
+            | ValidationError::InsufficientWork { block, .. }
+            | ValidationError::InvalidRegistryTx { block, .. }
+            | ValidationError::WrongChain { block, .. }
+            | ValidationError::InvalidValidityWindow { block, .. }
+            | ValidationError::BadSignature { block, .. }
+            | ValidationError::UnknownSigner { block, .. }
+            | ValidationError::UnauthorizedSigner { block, .. }
+            | ValidationError::DuplicateTransaction { block, .. } => *block,
+        }
+    }
+
+    /// Position of the offending transaction within its block, if a transaction is at fault
+    pub fn tx(&self) -> Option<usize> {
+        match self {
+            ValidationError::InvalidRegistryTx { tx, .. }
+            | ValidationError::WrongChain { tx, .. }
+            | ValidationError::InvalidValidityWindow { tx, .. }
+            | ValidationError::BadSignature { tx, .. }
+            | ValidationError::UnknownSigner { tx, .. }
+            | ValidationError::UnauthorizedSigner { tx, .. }
+            | ValidationError::DuplicateTransaction { tx, .. } => Some(*tx),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::GenesisMismatch { expected, found } => {
+                write!(f, "genesis hash mismatch: expected {}, found {}", expected, found)
+            }
+            ValidationError::BadIndex { block, index } => {
+                write!(f, "block {}: index is {}, expected {}", block, index, block)
+            }
+            ValidationError::BadPrevHash { block } => {
+                write!(f, "block {}: prev_hash does not match the previous block", block)
+            }
+            ValidationError::UnknownParent { block } => {
+                write!(f, "block {}: parent is not on this chain", block)
+            }
+            ValidationError::NonMonotonicTimestamp { block, timestamp, prev_timestamp } => write!(
+                f,
+                "block {}: timestamp {} is before the previous block's {}",
+                block, timestamp, prev_timestamp
+            ),
+            ValidationError::FutureTimestamp { block, timestamp } => {
+                write!(f, "block {}: timestamp {} is too far in the future", block, timestamp)
+            }
+            ValidationError::UnknownProposer { block, proposer } => {
+                write!(f, "block {}: proposer {} is not a validator", block, proposer)
+            }
+            ValidationError::WrongProposer { block, expected, found } => {
+                write!(f, "block {}: proposed by {} out of turn, expected {}", block, found, expected)
+            }
+            ValidationError::BadBlockSignature { block } => {
+                write!(f, "block {}: proposer signature does not verify", block)
+            }
+            ValidationError::BadCommitCertificate { block } => {
+                write!(f, "block {}: commit certificate is not a quorum of valid precommits", block)
+            }
+            ValidationError::InsufficientWork { block, bits } => {
+                write!(f, "block {}: hash does not have the required {} leading zero bits", block, bits)
+            }
+            ValidationError::InvalidRegistryTx { block, tx, error } => {
+                write!(f, "block {}, registry tx {}: {}", block, tx, error)
+            }
+            ValidationError::WrongChain { block, tx, chain_id } => {
+                write!(f, "block {}, tx {}: signed for chain '{}'", block, tx, chain_id)
+            }
+            ValidationError::InvalidValidityWindow { block, tx } => {
+                write!(f, "block {}, tx {}: expires_at is not after issued_at", block, tx)
+            }
+            ValidationError::BadSignature { block, tx } => {
+                write!(f, "block {}, tx {}: signature does not verify", block, tx)
+            }
+            ValidationError::UnknownSigner { block, tx, doctor_id } => {
+                write!(f, "block {}, tx {}: signing key is not registered to doctor '{}'", block, tx, doctor_id)
+            }
+            ValidationError::UnauthorizedSigner { block, tx, doctor_id } => write!(
+                f,
+                "block {}, tx {}: doctor '{}' is suspended or the signing key is revoked",
+                block, tx, doctor_id
+            ),
+            ValidationError::DuplicateTransaction { block, tx } => {
+                write!(f, "block {}, tx {}: transaction already included earlier", block, tx)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
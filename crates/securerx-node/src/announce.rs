@@ -0,0 +1,127 @@
+//! Push announcements of new blocks and transactions between nodes.
+//!
+//! A node that accepts a block or transaction it had not seen sends its hash,
+//! or a transaction's id, to its connected peers (an `inv` message, see [`crate::p2p`]). A peer that
+//! has not seen the hash asks the announcer for the data (a `get_data`
+//! request), checks it and announces it onwards, so every item floods the
+//! network once. Hashes are remembered in a
+//! bounded [`SeenCache`] so nodes neither fetch nor announce an item twice.
+//! Transactions are served from the node's mempool (see
+//! [`securerx_core::mempool`]), blocks from its chain and block tree. The
+//! periodic headers-first sync only catches up on what was missed.
+
+use securerx_core::block::Block;
+use securerx_core::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+
+/// Hashes a node remembers having seen
+pub const SEEN_CACHE_SIZE: usize = 10_000;
+
+/// Most items handled per announcement or data request
+pub const MAX_INV_ITEMS: usize = 1_000;
+
+/// Kind of an announced item
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum InvKind {
+    Block,
+    Transaction,
+}
+
+/// An announced block or transaction, identified by the hex hash of the
+/// block or the hex id of the transaction (see [`Transaction::id`])
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InvItem {
+    pub kind: InvKind,
+    pub hash: String,
+}
+
+impl InvItem {
+    pub fn block(block: &Block) -> Self {
+        Self { kind: InvKind::Block, hash: block.calculate_hash() }
+    }
+
+    pub fn transaction(tx: &Transaction) -> Self {
+        Self { kind: InvKind::Transaction, hash: hex::encode(tx.id()) }
+    }
+
+    /// The announced hash as bytes, `None` if it is not 32 bytes of hex
+    pub fn hash_bytes(&self) -> Option<[u8; 32]> {
+        hex::decode(&self.hash).ok()?.try_into().ok()
+    }
+}
+
+/// Items asked for after an announcement
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GetData {
+    pub items: Vec<InvItem>,
+}
+
+/// Answer to [`GetData`]: the requested items the node has
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Data {
+    pub blocks: Vec<Block>,
+    pub transactions: Vec<Transaction>,
+}
+
+/// Set of the most recently seen items, forgetting the oldest beyond its capacity
+#[derive(Debug)]
+pub struct SeenCache {
+    capacity: usize,
+    order: VecDeque<InvItem>,
+    items: HashSet<InvItem>,
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, order: VecDeque::new(), items: HashSet::new() }
+    }
+
+    /// Whether `item` was seen and not forgotten since
+    pub fn contains(&self, item: &InvItem) -> bool {
+        self.items.contains(item)
+    }
+
+    /// Remember `item`, returning whether it is new
+    pub fn insert(&mut self, item: InvItem) -> bool {
+        if !self.items.insert(item.clone()) {
+            return false;
+        }
+        self.order.push_back(item);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.items.remove(&oldest);
+        }
+        true
+    }
+}
+
+impl Default for SeenCache {
+    fn default() -> Self {
+        Self::new(SEEN_CACHE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(n: u8) -> InvItem {
+        InvItem { kind: InvKind::Block, hash: hex::encode([n; 32]) }
+    }
+
+    #[test]
+    fn test_seen_cache_forgets_oldest() {
+        let mut seen = SeenCache::new(2);
+        assert!(seen.insert(item(1)));
+        assert!(!seen.insert(item(1)), "Items are only new once");
+        assert!(seen.insert(item(2)));
+        assert!(seen.insert(item(3)));
+        assert!(!seen.contains(&item(1)));
+        assert!(seen.contains(&item(2)) && seen.contains(&item(3)));
+
+        let tx = InvItem { kind: InvKind::Transaction, hash: item(3).hash };
+        assert!(seen.insert(tx), "Blocks and transactions are told apart");
+    }
+}
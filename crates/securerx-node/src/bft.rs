@@ -0,0 +1,628 @@
+//! Tendermint-style BFT consensus among the validator set.
+//!
+//! Each height is decided in rounds of propose, prevote and precommit. The
+//! round's proposer (see [`proposer_for`]) broadcasts a block; validators
+//! prevote for it if it is valid and does not conflict with their lock, and
+//! precommit once more than two thirds prevoted for it, locking on it. A block
+//! with precommits from more than two thirds of the validators is committed
+//! together with those precommits as its [`CommitCertificate`] and is final.
+//! Rounds that fail to decide time out and move on to the next proposer.
+//!
+//! [`BftEngine`] is a deterministic state machine: it consumes messages and
+//! timeouts and returns the messages to broadcast and timeouts to schedule,
+//! leaving transport and timers to the caller.
+
+use ed25519_dalek::SigningKey;
+use securerx_core::block::Block;
+use securerx_core::blockchain::Blockchain;
+use securerx_core::consensus::{proposer_for, quorum, CommitCertificate, Vote, VoteKind};
+use securerx_core::mempool::Mempool;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Message exchanged between validators
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConsensusMessage {
+    /// A block proposed in `round`. `valid_round` is set when re-proposing a
+    /// block that had a quorum of prevotes in that earlier round.
+    Proposal { round: u64, valid_round: Option<u64>, block: Block },
+    Vote(Vote),
+}
+
+/// Step of the current round
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Waiting after a commit before starting the next height
+    NewHeight,
+    Propose,
+    Prevote,
+    Precommit,
+}
+
+/// How long each step waits before giving up. The propose, prevote and
+/// precommit timeouts grow linearly with the round number. Once past the
+/// propose step, a validator re-sends its votes every propose timeout until
+/// the round ends.
+#[derive(Clone, Debug)]
+pub struct BftTimeouts {
+    pub propose: Duration,
+    pub prevote: Duration,
+    pub precommit: Duration,
+    /// Pause between committing a block and starting the next height
+    pub commit: Duration,
+}
+
+impl Default for BftTimeouts {
+    fn default() -> Self {
+        Self {
+            propose: Duration::from_secs(3),
+            prevote: Duration::from_secs(1),
+            precommit: Duration::from_secs(1),
+            commit: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Something the caller has to do on the engine's behalf
+#[derive(Clone, Debug)]
+pub enum Output {
+    /// Send to every other validator
+    Broadcast(ConsensusMessage),
+    /// Call [`BftEngine::on_timeout`] with these arguments after `after`
+    ScheduleTimeout { height: u64, round: u64, step: Step, after: Duration },
+    /// The block at `height` was committed to the chain
+    Committed { height: u64 },
+}
+
+/// Consensus state of one validator for the height being decided
+pub struct BftEngine {
+    key: SigningKey,
+    timeouts: BftTimeouts,
+    height: u64,
+    round: u64,
+    step: Step,
+    /// Block this validator precommitted and the round it did so in
+    locked: Option<(u64, Block)>,
+    /// Most recent block seen with a quorum of prevotes, re-proposed by this validator
+    valid: Option<(u64, Block)>,
+    /// Proposals for this height by round
+    proposals: HashMap<u64, (Option<u64>, Block)>,
+    /// Votes for this height by round and kind, one per validator
+    votes: HashMap<(u64, VoteKind), HashMap<Vec<u8>, Vote>>,
+    /// Rounds whose "first time" rules already fired
+    prevote_timeouts: HashSet<u64>,
+    precommit_timeouts: HashSet<u64>,
+    polkas: HashSet<u64>,
+    /// Prescriptions waiting to be proposed, shared with the node; lock the chain first
+    mempool: Arc<Mutex<Mempool>>,
+    /// Most prescriptions this validator puts in a block
+    max_block_txs: usize,
+}
+
+impl BftEngine {
+    /// Engine proposing blocks of up to `max_block_txs` prescriptions from `mempool`
+    pub fn new(key: SigningKey, timeouts: BftTimeouts, mempool: Arc<Mutex<Mempool>>, max_block_txs: usize) -> Self {
+        Self {
+            key,
+            timeouts,
+            height: 0,
+            round: 0,
+            step: Step::NewHeight,
+            locked: None,
+            valid: None,
+            proposals: HashMap::new(),
+            votes: HashMap::new(),
+            prevote_timeouts: HashSet::new(),
+            precommit_timeouts: HashSet::new(),
+            polkas: HashSet::new(),
+            mempool,
+            max_block_txs,
+        }
+    }
+
+    /// Height being decided
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    /// Start deciding the block after the tip of `chain`
+    pub fn start(&mut self, chain: &mut Blockchain) -> Vec<Output> {
+        let mut out = vec![];
+        self.enter_height(chain.chain.len() as u64);
+        self.start_round(chain, 0, &mut out);
+        self.process(chain, &mut out);
+        out
+    }
+
+    /// Handle a message from another validator
+    pub fn handle(&mut self, chain: &mut Blockchain, message: ConsensusMessage) -> Vec<Output> {
+        let mut out = vec![];
+        self.follow_chain(chain, &mut out);
+        match message {
+            ConsensusMessage::Proposal { round, valid_round, block } => {
+                if block.index == self.height {
+                    self.proposals.entry(round).or_insert((valid_round, block));
+                }
+            }
+            ConsensusMessage::Vote(vote) => self.record_vote(chain, vote),
+        }
+        self.process(chain, &mut out);
+        out
+    }
+
+    /// Handle a timeout previously requested with [`Output::ScheduleTimeout`]
+    pub fn on_timeout(&mut self, chain: &mut Blockchain, height: u64, round: u64, step: Step) -> Vec<Output> {
+        let mut out = vec![];
+        self.follow_chain(chain, &mut out);
+        if height != self.height {
+            return out;
+        }
+        match step {
+            Step::NewHeight if self.step == Step::NewHeight => self.start_round(chain, 0, &mut out),
+            Step::Propose if round == self.round => {
+                if self.step == Step::Propose {
+                    self.cast(VoteKind::Prevote, None, &mut out);
+                } else {
+                    self.rebroadcast(&mut out);
+                }
+                self.schedule(Step::Propose, self.timeouts.propose, &mut out);
+            }
+            Step::Prevote if round == self.round && self.step == Step::Prevote => {
+                self.cast(VoteKind::Precommit, None, &mut out)
+            }
+            Step::Precommit if round == self.round => self.start_round(chain, round + 1, &mut out),
+            _ => {}
+        }
+        self.process(chain, &mut out);
+        out
+    }
+
+    /// Reset the round state to decide block `height`
+    fn enter_height(&mut self, height: u64) {
+        self.height = height;
+        self.round = 0;
+        self.step = Step::NewHeight;
+        self.locked = None;
+        self.valid = None;
+        self.proposals.clear();
+        self.votes.clear();
+        self.prevote_timeouts.clear();
+        self.precommit_timeouts.clear();
+        self.polkas.clear();
+    }
+
+    /// Move on if the chain advanced without us, e.g. by syncing certified blocks from a peer
+    fn follow_chain(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) {
+        let height = chain.chain.len() as u64;
+        if height != self.height {
+            self.enter_height(height);
+            self.start_round(chain, 0, out);
+        }
+    }
+
+    fn start_round(&mut self, chain: &mut Blockchain, round: u64, out: &mut Vec<Output>) {
+        self.round = round;
+        self.step = Step::Propose;
+        if chain.is_proposer(round) {
+            let proposal = match &self.valid {
+                Some((valid_round, block)) => Some((Some(*valid_round), block.clone())),
+                None => self.new_block(chain, round).map(|block| (None, block)),
+            };
+            if let Some((valid_round, block)) = proposal {
+                self.proposals.insert(round, (valid_round, block.clone()));
+                out.push(Output::Broadcast(ConsensusMessage::Proposal { round, valid_round, block }));
+            }
+        }
+        self.schedule(Step::Propose, self.timeouts.propose, out);
+    }
+
+    /// Ask for a timeout of `step` in the current round, `base` times the round number plus one
+    fn schedule(&self, step: Step, base: Duration, out: &mut Vec<Output>) {
+        out.push(Output::ScheduleTimeout {
+            height: self.height,
+            round: self.round,
+            step,
+            after: base * (self.round as u32 + 1),
+        });
+    }
+
+    /// Send our proposal and votes for the current round again, for validators
+    /// that missed them, e.g. across a network partition that has since healed
+    fn rebroadcast(&self, out: &mut Vec<Output>) {
+        let me = self.key.verifying_key().to_bytes().to_vec();
+        if let Some((valid_round, block)) = self.proposals.get(&self.round) {
+            if block.proposer == me {
+                let proposal = ConsensusMessage::Proposal { round: self.round, valid_round: *valid_round, block: block.clone() };
+                out.push(Output::Broadcast(proposal));
+            }
+        }
+        for kind in [VoteKind::Prevote, VoteKind::Precommit] {
+            if let Some(vote) = self.votes.get(&(self.round, kind)).and_then(|votes| votes.get(&me)) {
+                out.push(Output::Broadcast(ConsensusMessage::Vote(vote.clone())));
+            }
+        }
+    }
+
+    /// Build a block from the next prescriptions of the mempool, proposing an empty one if they do not make a valid block
+    fn new_block(&mut self, chain: &Blockchain, round: u64) -> Option<Block> {
+        let transactions = self.mempool.lock().unwrap().next_block(chain, self.max_block_txs);
+        let block = chain.propose_block(transactions, vec![], round).ok()?;
+        if let Err(e) = chain.validate_proposal(&block, round) {
+            eprintln!("Proposing an empty block, the pending prescriptions do not make a valid one: {}", e);
+            return chain.propose_block(vec![], vec![], round).ok();
+        }
+        Some(block)
+    }
+
+    /// Sign a vote for the current round, record it and broadcast it
+    fn cast(&mut self, kind: VoteKind, block_hash: Option<String>, out: &mut Vec<Output>) {
+        let vote = Vote::new(kind, self.height, self.round, block_hash, &self.key);
+        self.step = match kind {
+            VoteKind::Prevote => Step::Prevote,
+            VoteKind::Precommit => Step::Precommit,
+        };
+        self.votes.entry((vote.round, kind)).or_default().insert(vote.validator.clone(), vote.clone());
+        out.push(Output::Broadcast(ConsensusMessage::Vote(vote)));
+    }
+
+    fn record_vote(&mut self, chain: &Blockchain, vote: Vote) {
+        if vote.height != self.height || !chain.validators().contains(&vote.validator) || !vote.verify_signature() {
+            return;
+        }
+        self.votes.entry((vote.round, vote.kind)).or_default().entry(vote.validator.clone()).or_insert(vote);
+    }
+
+    /// Whether our lock lets us prevote for the block `hash`: we are unlocked,
+    /// locked on that block, or locked before `valid_round`, in which a quorum
+    /// prevoted for it
+    fn lock_allows(&self, hash: &str, valid_round: Option<u64>) -> bool {
+        match &self.locked {
+            None => true,
+            Some((locked_round, locked)) => {
+                valid_round.is_some_and(|vr| *locked_round <= vr) || locked.calculate_hash() == hash
+            }
+        }
+    }
+
+    /// Number of `kind` votes in `round` for `block_hash` (`None` for nil)
+    fn count(&self, round: u64, kind: VoteKind, block_hash: Option<&str>) -> usize {
+        self.votes
+            .get(&(round, kind))
+            .map_or(0, |votes| votes.values().filter(|v| v.block_hash.as_deref() == block_hash).count())
+    }
+
+    fn total(&self, round: u64, kind: VoteKind) -> usize {
+        self.votes.get(&(round, kind)).map_or(0, HashMap::len)
+    }
+
+    /// Apply every rule whose condition holds until none does
+    fn process(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) {
+        while self.step != Step::NewHeight && self.apply_rule(chain, out) {}
+    }
+
+    /// Apply the first rule whose condition holds, returning whether one did
+    fn apply_rule(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) -> bool {
+        let q = quorum(chain.validators().len());
+        let round = self.round;
+
+        // Commit: a quorum precommitted a block we have, in any round
+        if let Some((commit_round, block)) = self.decided(q) {
+            self.commit(chain, commit_round, block, out);
+            return true;
+        }
+
+        if self.step == Step::Propose {
+            if let Some((valid_round, block)) = self.proposals.get(&round).cloned() {
+                let hash = block.calculate_hash();
+                let prevote = match valid_round {
+                    None if Some(block.proposer.as_slice()) == proposer_for(chain.validators(), self.height, round) => {
+                        Some(self.lock_allows(&hash, None))
+                    }
+                    Some(vr) if vr < round && self.count(vr, VoteKind::Prevote, Some(&hash)) >= q => {
+                        Some(self.lock_allows(&hash, Some(vr)))
+                    }
+                    _ => None,
+                };
+                if let Some(acceptable) = prevote {
+                    let valid = acceptable && chain.validate_proposal(&block, round).is_ok();
+                    self.cast(VoteKind::Prevote, valid.then_some(hash), out);
+                    return true;
+                }
+            }
+        }
+
+        if self.step == Step::Prevote && self.total(round, VoteKind::Prevote) >= q && self.prevote_timeouts.insert(round) {
+            self.schedule(Step::Prevote, self.timeouts.prevote, out);
+            return true;
+        }
+
+        if self.step >= Step::Prevote && !self.polkas.contains(&round) {
+            if let Some((_, block)) = self.proposals.get(&round).cloned() {
+                let hash = block.calculate_hash();
+                if self.count(round, VoteKind::Prevote, Some(&hash)) >= q && chain.validate_proposal(&block, round).is_ok() {
+                    self.polkas.insert(round);
+                    if self.step == Step::Prevote {
+                        self.locked = Some((round, block.clone()));
+                        self.cast(VoteKind::Precommit, Some(hash), out);
+                    }
+                    self.valid = Some((round, block));
+                    return true;
+                }
+            }
+        }
+
+        if self.step == Step::Prevote && self.count(round, VoteKind::Prevote, None) >= q {
+            self.cast(VoteKind::Precommit, None, out);
+            return true;
+        }
+
+        if self.total(round, VoteKind::Precommit) >= q && self.precommit_timeouts.insert(round) {
+            self.schedule(Step::Precommit, self.timeouts.precommit, out);
+            return true;
+        }
+        false
+    }
+
+    /// A proposed block with a quorum of precommits, and the round they were cast in
+    fn decided(&self, q: usize) -> Option<(u64, Block)> {
+        self.proposals.values().find_map(|(_, block)| {
+            let hash = block.calculate_hash();
+            self.votes
+                .iter()
+                .filter(|((_, kind), _)| *kind == VoteKind::Precommit)
+                .find(|((round, _), _)| self.count(*round, VoteKind::Precommit, Some(&hash)) >= q)
+                .map(|((round, _), _)| (*round, block.clone()))
+        })
+    }
+
+    fn commit(&mut self, chain: &mut Blockchain, round: u64, mut block: Block, out: &mut Vec<Output>) {
+        let hash = block.calculate_hash();
+        let precommits = self.votes[&(round, VoteKind::Precommit)]
+            .values()
+            .filter(|vote| vote.block_hash.as_deref() == Some(hash.as_str()))
+            .cloned()
+            .collect();
+        block.commit = Some(CommitCertificate { round, precommits });
+        let height = self.height;
+        match chain.commit_block(block) {
+            Ok(_) => out.push(Output::Committed { height }),
+            Err(e) => eprintln!("Failed to commit block {}: {}", height, e),
+        }
+        self.enter_height(chain.chain.len() as u64);
+        out.push(Output::ScheduleTimeout { height: self.height, round: 0, step: Step::NewHeight, after: self.timeouts.commit });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use securerx_core::blockchain::ChainError;
+    use securerx_core::crypto::generate_keypair;
+    use securerx_core::genesis::{GenesisDoctor, GenesisSpec};
+    use securerx_core::test_support::prescription;
+    use std::collections::VecDeque;
+
+    struct SimNode {
+        chain: Blockchain,
+        mempool: Arc<Mutex<Mempool>>,
+        engine: BftEngine,
+        online: bool,
+    }
+
+    /// In-process network delivering messages in order, with a virtual clock for timeouts
+    struct SimNetwork {
+        nodes: Vec<SimNode>,
+        messages: VecDeque<(usize, ConsensusMessage)>,
+        timers: Vec<(Duration, usize, u64, u64, Step)>,
+        now: Duration,
+        /// Links that drop every message, as (from, to)
+        cut: HashSet<(usize, usize)>,
+    }
+
+    impl SimNetwork {
+        fn new(keys: &[SigningKey], genesis: GenesisSpec) -> Self {
+            let nodes = keys
+                .iter()
+                .map(|key| {
+                    let mut chain = Blockchain::from_genesis(genesis.clone()).unwrap();
+                    chain.set_validator_key(key.clone());
+                    let mempool = Arc::new(Mutex::new(Mempool::default()));
+                    let engine = BftEngine::new(key.clone(), BftTimeouts::default(), mempool.clone(), 100);
+                    SimNode { chain, mempool, engine, online: true }
+                })
+                .collect();
+            Self { nodes, messages: VecDeque::new(), timers: vec![], now: Duration::ZERO, cut: HashSet::new() }
+        }
+
+        fn start(&mut self) {
+            for i in 0..self.nodes.len() {
+                if self.nodes[i].online {
+                    let node = &mut self.nodes[i];
+                    let outputs = node.engine.start(&mut node.chain);
+                    self.dispatch(i, outputs);
+                }
+            }
+        }
+
+        fn dispatch(&mut self, from: usize, outputs: Vec<Output>) {
+            for output in outputs {
+                match output {
+                    Output::Broadcast(message) => {
+                        for to in (0..self.nodes.len()).filter(|to| *to != from && !self.cut.contains(&(from, *to))) {
+                            self.messages.push_back((to, message.clone()));
+                        }
+                    }
+                    Output::ScheduleTimeout { height, round, step, after } => {
+                        self.timers.push((self.now + after, from, height, round, step))
+                    }
+                    Output::Committed { .. } => {}
+                }
+            }
+        }
+
+        /// Deliver messages, firing the earliest timeout whenever none are in flight, until `done` holds
+        fn run_until(&mut self, done: impl Fn(&Self) -> bool) {
+            for _ in 0..100_000 {
+                if done(self) {
+                    return;
+                }
+                if let Some((to, message)) = self.messages.pop_front() {
+                    let node = &mut self.nodes[to];
+                    if node.online {
+                        let outputs = node.engine.handle(&mut node.chain, message);
+                        self.dispatch(to, outputs);
+                    }
+                } else if !self.timers.is_empty() {
+                    let next = (0..self.timers.len()).min_by_key(|i| self.timers[*i].0).unwrap();
+                    let (at, to, height, round, step) = self.timers.remove(next);
+                    self.now = at;
+                    let node = &mut self.nodes[to];
+                    if node.online {
+                        let outputs = node.engine.on_timeout(&mut node.chain, height, round, step);
+                        self.dispatch(to, outputs);
+                    }
+                } else {
+                    break;
+                }
+            }
+            panic!("network stalled");
+        }
+
+        /// Lowest height reached by the online nodes
+        fn height(&self) -> usize {
+            self.nodes.iter().filter(|n| n.online).map(|n| n.chain.chain.len()).min().unwrap()
+        }
+
+        /// Committed blocks at the same height are the same on every node
+        fn assert_agreement(&self) {
+            for a in &self.nodes {
+                for b in &self.nodes {
+                    for (x, y) in a.chain.chain.iter().zip(&b.chain.chain) {
+                        assert_eq!(x.calculate_hash(), y.calculate_hash(), "Nodes committed different blocks at {}", x.index);
+                    }
+                }
+            }
+        }
+    }
+
+    fn validator_keys(n: usize) -> Vec<SigningKey> {
+        (0..n).map(|_| generate_keypair()).collect()
+    }
+
+    fn genesis(keys: &[SigningKey], doctor: &SigningKey) -> GenesisSpec {
+        let mut genesis = GenesisSpec::with_authorities(keys.iter().map(|k| k.verifying_key().to_bytes().to_vec()).collect());
+        genesis.doctors.push(GenesisDoctor {
+            doctor_id: "doctor1".to_string(),
+            license_number: "LIC-1".to_string(),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        });
+        genesis
+    }
+
+    #[test]
+    fn test_three_validators_commit_blocks() {
+        let keys = validator_keys(3);
+        let doctor = generate_keypair();
+        let mut network = SimNetwork::new(&keys, genesis(&keys, &doctor));
+        let tx = prescription(&doctor, "patient1");
+        for node in &mut network.nodes {
+            node.mempool.lock().unwrap().insert(tx.clone(), 0, &node.chain).unwrap();
+        }
+
+        network.start();
+        network.run_until(|net| net.height() >= 4);
+        network.assert_agreement();
+
+        for node in &network.nodes {
+            assert!(node.chain.contains_transaction(&tx));
+            assert_eq!(node.chain.validate_chain(), Ok(()));
+            assert!(node.chain.finalized_height() >= 3);
+            for block in &node.chain.chain[1..] {
+                let cert = block.commit.as_ref().expect("every block carries a commit certificate");
+                assert_eq!(cert.round, 0, "No round should time out with every validator online");
+                assert!(cert.precommits.len() >= quorum(3));
+            }
+        }
+        assert_eq!(network.nodes[0].chain.chain[1].transactions.len(), 1, "Block 1 includes the pending prescription");
+    }
+
+    #[test]
+    fn test_commits_with_a_crashed_proposer() {
+        let keys = validator_keys(4);
+        let doctor = generate_keypair();
+        let mut network = SimNetwork::new(&keys, genesis(&keys, &doctor));
+        let validators = network.nodes[0].chain.validators().to_vec();
+        let crashed = validators.iter().position(|v| Some(v.as_slice()) == proposer_for(&validators, 1, 0)).unwrap();
+        network.nodes[crashed].online = false;
+
+        network.start();
+        network.run_until(|net| net.height() >= 3);
+        network.assert_agreement();
+
+        let block = &network.nodes[(crashed + 1) % 4].chain.chain[1];
+        assert!(block.commit.as_ref().unwrap().round >= 1, "Round 0 had no proposer and must time out");
+        assert_eq!(block.proposer, validators[(crashed + 1) % 4]);
+    }
+
+    #[test]
+    fn test_no_commit_without_quorum() {
+        let keys = validator_keys(4);
+        let doctor = generate_keypair();
+        let mut network = SimNetwork::new(&keys, genesis(&keys, &doctor));
+        // Split the validators into two halves that cannot reach each other
+        for a in 0..4 {
+            for b in 0..4 {
+                if (a < 2) != (b < 2) {
+                    network.cut.insert((a, b));
+                }
+            }
+        }
+
+        network.start();
+        network.run_until(|net| net.now >= Duration::from_secs(60));
+        assert_eq!(network.height(), 1, "Neither half has a quorum");
+        assert!(network.nodes.iter().all(|n| n.chain.finalized_height() == 0));
+
+        network.cut.clear();
+        network.run_until(|net| net.height() >= 3);
+        network.assert_agreement();
+    }
+
+    #[test]
+    fn test_committed_blocks_are_never_reverted() {
+        let keys = validator_keys(3);
+        let doctor = generate_keypair();
+        let spec = genesis(&keys, &doctor);
+        let mut network = SimNetwork::new(&keys, spec.clone());
+        network.start();
+        network.run_until(|net| net.height() >= 2);
+        let committed = network.nodes[0].chain.chain[1].calculate_hash();
+
+        // The validators' keys build a longer sealed fork without certificates
+        let mut fork = Blockchain::from_genesis(spec).unwrap();
+        for height in 1..=3u64 {
+            let validators = fork.validators().to_vec();
+            let turn = validators.iter().position(|v| Some(v.as_slice()) == proposer_for(&validators, height, 0)).unwrap();
+            fork.set_validator_key(keys[turn].clone());
+            fork.add_block(vec![prescription(&doctor, &format!("patient{}", height))]).unwrap();
+        }
+        assert_ne!(fork.chain[1].calculate_hash(), committed);
+
+        let node = &mut network.nodes[0];
+        assert!(fork.chain.len() > node.chain.chain.len());
+        assert!(matches!(node.chain.replace_chain(fork.chain.clone()), Err(ChainError::RevertsFinalized { height: 1 })));
+        assert_eq!(node.chain.chain[1].calculate_hash(), committed);
+    }
+}
@@ -1,24 +1,191 @@
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use securerx_core::crypto::{generate_keypair, signing_key_from_hex};
+use securerx_core::fork_choice::{ForkChoiceRule, HeaviestChain, LongestChain};
+use securerx_core::genesis::GenesisSpec;
+use securerx_core::mempool::{DEFAULT_BLOCK_INTERVAL, DEFAULT_MAX_BLOCK_TXS, DEFAULT_MEMPOOL_SIZE};
+use securerx_core::storage::StorageBackend;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How validators agree on new blocks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConsensusMode {
+    /// Proposers take turns and followers adopt the longest valid chain
+    #[default]
+    Poa,
+    /// Blocks are committed by BFT rounds among the validators and never reverted
+    Bft,
+    /// A Raft leader appends blocks once a majority stored them; tolerates crashes, not malicious nodes
+    Raft,
+}
+
+impl FromStr for ConsensusMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "poa" => Ok(ConsensusMode::Poa),
+            "bft" => Ok(ConsensusMode::Bft),
+            "raft" => Ok(ConsensusMode::Raft),
+            other => Err(format!("unknown consensus mode '{}', expected 'poa', 'bft' or 'raft'", other)),
+        }
+    }
+}
+
+/// Rule choosing between competing branches received from peers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ForkChoice {
+    /// Most cumulative proof of work, or most blocks on networks without it
+    #[default]
+    Heaviest,
+    /// Most blocks, whatever their proof of work
+    Longest,
+}
+
+impl ForkChoice {
+    pub fn rule(self) -> Box<dyn ForkChoiceRule> {
+        match self {
+            ForkChoice::Heaviest => Box::new(HeaviestChain),
+            ForkChoice::Longest => Box::new(LongestChain),
+        }
+    }
+}
+
+impl FromStr for ForkChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "heaviest" => Ok(ForkChoice::Heaviest),
+            "longest" => Ok(ForkChoice::Longest),
+            other => Err(format!("unknown fork choice rule '{}', expected 'heaviest' or 'longest'", other)),
+        }
+    }
+}
+
 /// Node configuration loaded from environment variables
 #[derive(Clone)]
 pub struct NodeConfig {
     pub node_id: String,
     pub data_dir: String,
+    /// Block store used under `data_dir` (`STORAGE_BACKEND`: `file` or `kv`)
+    pub storage_backend: StorageBackend,
     pub api_addr: String,
+    /// Ids of the other Raft cluster members (`PEERS`), which are their API addresses
     pub peers: Vec<String>,
+    /// Address the P2P transport listens on (`P2P_ADDR`)
+    pub p2p_addr: String,
+    /// P2P addresses the peer table starts from (`P2P_PEERS`); every pair of BFT
+    /// validators or Raft members must be linked, in either direction
+    pub p2p_peers: Vec<String>,
+    /// Key this node authenticates its P2P links with (`NODE_KEY`, hex secret key);
+    /// `VALIDATOR_KEY` when unset, else a new key on every start
+    pub node_key: SigningKey,
+    /// Node keys of the peers allowed to connect (`PEER_KEYS`, hex public keys)
+    pub peer_keys: Vec<VerifyingKey>,
+    /// Genesis spec loaded from `GENESIS_FILE`; the development genesis when unset
+    pub genesis: GenesisSpec,
+    /// Key this node seals blocks with (`VALIDATOR_KEY`, hex secret key); unset for nodes that only follow the chain
+    pub validator_key: Option<SigningKey>,
+    /// Consensus protocol (`CONSENSUS`: `poa`, `bft` or `raft`). In Raft mode
+    /// `NODE_ID` must be the address the other nodes list this node under in `PEERS`.
+    pub consensus: ConsensusMode,
+    /// Rule for switching to a competing branch (`FORK_CHOICE`: `heaviest` or `longest`)
+    pub fork_choice: ForkChoice,
+    /// Most prescriptions waiting for a block (`MEMPOOL_SIZE`)
+    pub mempool_size: usize,
+    /// Most prescriptions in a block this node produces (`MAX_BLOCK_TXS`); a
+    /// Proof-of-Authority proposer seals a block as soon as that many are pending
+    pub max_block_txs: usize,
+    /// How often a Proof-of-Authority proposer seals the pending prescriptions
+    /// into a block (`BLOCK_INTERVAL_MS`)
+    pub block_interval: Duration,
+    /// Bearer token the peer admin endpoints require (`ADMIN_TOKEN`); they are
+    /// refused when unset
+    pub admin_token: Option<String>,
 }
 
 impl NodeConfig {
     pub fn from_env() -> Self {
-        let peers = std::env::var("PEERS").unwrap_or_default()
-            .split(',')
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
+        let peers = list_var("PEERS");
+        let storage_backend = std::env::var("STORAGE_BACKEND")
+            .unwrap_or_else(|_| "file".to_string())
+            .parse()
+            .expect("STORAGE_BACKEND must be 'file' or 'kv'");
+        let genesis = match std::env::var("GENESIS_FILE") {
+            Ok(path) => GenesisSpec::load(&path).unwrap_or_else(|e| panic!("{}: {}", path, e)),
+            Err(_) => GenesisSpec::default(),
+        };
+        let validator_key = std::env::var("VALIDATOR_KEY").ok().map(|key| {
+            signing_key_from_hex(&key).expect("VALIDATOR_KEY must be a hex-encoded 32-byte Ed25519 secret key")
+        });
+        let node_key = match std::env::var("NODE_KEY") {
+            Ok(key) => signing_key_from_hex(&key).expect("NODE_KEY must be a hex-encoded 32-byte Ed25519 secret key"),
+            Err(_) => validator_key.clone().unwrap_or_else(|| {
+                let key = generate_keypair();
+                eprintln!(
+                    "WARNING: no NODE_KEY, using the new node key {}; peers must list it in PEER_KEYS",
+                    hex::encode(key.verifying_key().to_bytes())
+                );
+                key
+            }),
+        };
+        let peer_keys = list_var("PEER_KEYS")
+            .iter()
+            .map(|key| {
+                let bytes: [u8; 32] = hex::decode(key).ok().and_then(|bytes| bytes.try_into().ok()).unwrap_or_else(|| {
+                    panic!("PEER_KEYS must list hex-encoded 32-byte Ed25519 public keys, got '{}'", key)
+                });
+                VerifyingKey::from_bytes(&bytes).unwrap_or_else(|_| panic!("PEER_KEYS entry '{}' is not an Ed25519 public key", key))
+            })
             .collect();
+        let consensus = std::env::var("CONSENSUS")
+            .unwrap_or_else(|_| "poa".to_string())
+            .parse()
+            .expect("CONSENSUS must be 'poa', 'bft' or 'raft'");
+        let fork_choice = std::env::var("FORK_CHOICE")
+            .unwrap_or_else(|_| "heaviest".to_string())
+            .parse()
+            .expect("FORK_CHOICE must be 'heaviest' or 'longest'");
+        let mempool_size = number_var("MEMPOOL_SIZE", DEFAULT_MEMPOOL_SIZE as u64) as usize;
+        let max_block_txs = number_var("MAX_BLOCK_TXS", DEFAULT_MAX_BLOCK_TXS as u64) as usize;
+        let block_interval = Duration::from_millis(number_var("BLOCK_INTERVAL_MS", DEFAULT_BLOCK_INTERVAL.as_millis() as u64));
         Self {
             node_id: std::env::var("NODE_ID").unwrap_or_else(|_| "node1".to_string()),
             data_dir: std::env::var("DATA_DIR").unwrap_or_else(|_| "./data".to_string()),
+            storage_backend,
             api_addr: std::env::var("API_ADDR").unwrap_or_else(|_| "0.0.0.0:8081".to_string()),
             peers,
+            p2p_addr: std::env::var("P2P_ADDR").unwrap_or_else(|_| "0.0.0.0:9000".to_string()),
+            p2p_peers: list_var("P2P_PEERS"),
+            node_key,
+            peer_keys,
+            genesis,
+            validator_key,
+            consensus,
+            fork_choice,
+            mempool_size,
+            max_block_txs,
+            block_interval,
+            admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
         }
     }
 }
+
+/// Positive integer value of environment variable `name`, `default` when unset
+fn number_var(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(value) => value.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| panic!("{} must be a positive integer", name)),
+        Err(_) => default,
+    }
+}
+
+/// Comma-separated values of environment variable `name`
+fn list_var(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
@@ -0,0 +1,234 @@
+use crate::network::SubmitError;
+use crate::node::Node;
+use crate::peer_table::PeerStatus;
+use crate::raft::Submission;
+use crate::sync::{self, ChainTip};
+use axum::{extract::Path, Extension, Json, Router, routing::{delete, get, post}, response::{IntoResponse, Response}, http::{header, HeaderMap, StatusCode}};
+use prometheus::{Encoder, TextEncoder};
+use securerx_core::block::Block;
+use securerx_core::mempool::MempoolError;
+use securerx_core::transaction::Transaction;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Metrics, chain, transaction, Raft submission and peer admin endpoints of
+/// `node`; blocks and consensus messages travel over the encrypted P2P
+/// transport instead
+pub fn router(node: Node) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/blocks", get(blocks_handler))
+        .route("/tip", get(tip_handler))
+        .route("/transactions", post(transactions_handler))
+        .route("/transactions/:tx_id", get(transaction_status_handler))
+        .route("/raft/submit", post(raft_submit_handler))
+        .route("/peers", get(peers_handler).post(add_peer_handler))
+        .route("/peers/:addr", delete(remove_peer_handler))
+        .layer(Extension(node))
+}
+
+/// Transactions forwarded by an API server, replicated in the next block when this node is the Raft leader
+async fn raft_submit_handler(Extension(node): Extension<Node>, Json(submission): Json<Submission>) -> impl IntoResponse {
+    match node.submit_raft(submission).await {
+        Ok(block_index) => (StatusCode::CREATED, Json(json!({ "block_index": block_index }))),
+        Err(SubmitError::NotLeader(e)) => (
+            StatusCode::MISDIRECTED_REQUEST,
+            Json(json!({ "error": "not_leader", "message": e.to_string(), "leader": e.leader })),
+        ),
+        Err(SubmitError::Timeout) => (
+            StatusCode::GATEWAY_TIMEOUT,
+            Json(json!({ "error": "commit_timeout", "message": "the block was not committed in time" })),
+        ),
+    }
+}
+
+/// A signed prescription to admit to the mempool and announce to peers
+async fn transactions_handler(Extension(node): Extension<Node>, Json(tx): Json<Transaction>) -> impl IntoResponse {
+    let tx_id = hex::encode(tx.id());
+    match node.submit_transaction(tx, None) {
+        Ok(()) => (StatusCode::ACCEPTED, Json(json!({ "status": "pending", "tx_id": tx_id }))),
+        Err(e @ MempoolError::Full) => {
+            (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": e.code(), "message": e.to_string() })))
+        }
+        Err(e) => (StatusCode::CONFLICT, Json(json!({ "error": e.code(), "message": e.to_string() }))),
+    }
+}
+
+/// Whether the prescription with id `tx_id` is pending or in a block, and which
+async fn transaction_status_handler(Extension(node): Extension<Node>, Path(tx_id): Path<String>) -> impl IntoResponse {
+    let mut id = [0u8; 32];
+    if hex::decode_to_slice(&tx_id, &mut id).is_err() {
+        let message = "tx_id must be 64 hex characters";
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "malformed_tx_id", "message": message })));
+    }
+    let blockchain = node.blockchain.lock().unwrap();
+    if let Some(height) = blockchain.transaction_height(&id) {
+        return (StatusCode::OK, Json(json!({ "status": "confirmed", "tx_id": tx_id, "block_index": height })));
+    }
+    if node.mempool.lock().unwrap().contains(&id) {
+        return (StatusCode::OK, Json(json!({ "status": "pending", "tx_id": tx_id })));
+    }
+    let message = format!("no prescription {}", tx_id);
+    (StatusCode::NOT_FOUND, Json(json!({ "error": "unknown_transaction", "message": message })))
+}
+
+/// Body of `POST /peers`
+#[derive(Deserialize)]
+struct AddPeer {
+    addr: String,
+}
+
+/// The peer table with each address's score and connection state
+async fn peers_handler(Extension(node): Extension<Node>) -> Json<Vec<PeerStatus>> {
+    Json(node.peer_statuses())
+}
+
+/// Refuse a peer admin request unless it carries `Authorization: Bearer <ADMIN_TOKEN>`
+fn authorize_admin(node: &Node, headers: &HeaderMap) -> Result<(), (StatusCode, Json<Value>)> {
+    let Some(token) = &node.config.admin_token else {
+        let message = "peer administration is disabled, set ADMIN_TOKEN to enable it";
+        return Err((StatusCode::FORBIDDEN, Json(json!({ "error": "admin_disabled", "message": message }))));
+    };
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => {
+            let message = "a valid admin bearer token is required";
+            Err((StatusCode::UNAUTHORIZED, Json(json!({ "error": "unauthorized", "message": message }))))
+        }
+    }
+}
+
+/// Compare `a` and `b` in time independent of where they first differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Add a P2P address to the peer table and dial it
+async fn add_peer_handler(Extension(node): Extension<Node>, headers: HeaderMap, Json(body): Json<AddPeer>) -> impl IntoResponse {
+    if let Err(rejection) = authorize_admin(&node, &headers) {
+        return rejection;
+    }
+    match node.add_peer(&body.addr) {
+        Ok(true) => (StatusCode::CREATED, Json(json!({ "status": "added" }))),
+        Ok(false) => (StatusCode::OK, Json(json!({ "status": "known" }))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": "invalid_address", "message": e.to_string() }))),
+    }
+}
+
+/// Forget a P2P address and hang up on it
+async fn remove_peer_handler(Extension(node): Extension<Node>, headers: HeaderMap, Path(addr): Path<String>) -> Response {
+    if let Err(rejection) = authorize_admin(&node, &headers) {
+        return rejection.into_response();
+    }
+    if node.remove_peer(&addr) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// The node's chain
+async fn blocks_handler(Extension(node): Extension<Node>) -> Json<Vec<Block>> {
+    Json(node.blockchain.lock().unwrap().chain.clone())
+}
+
+/// Height and hash of the node's last block
+async fn tip_handler(Extension(node): Extension<Node>) -> Json<ChainTip> {
+    Json(sync::tip(&node.blockchain.lock().unwrap().chain))
+}
+
+/// Prometheus metrics handler
+async fn metrics_handler() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let metric_families = prometheus::gather();
+    let mut buffer = Vec::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NodeConfig;
+    use crate::test_support;
+    use axum::body::Body;
+    use axum::http::Request;
+    use securerx_core::crypto::generate_keypair;
+    use securerx_core::genesis::GenesisSpec;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "s3cret";
+
+    /// A follower node that accepts `admin_token` on its peer admin endpoints
+    fn node(name: &str, admin_token: Option<&str>) -> Node {
+        Node::new(NodeConfig {
+            p2p_peers: vec!["127.0.0.1:1".to_string()],
+            peer_keys: vec![generate_keypair().verifying_key()],
+            admin_token: admin_token.map(str::to_string),
+            ..test_support::config(&format!("http-{}", name), GenesisSpec::default())
+        })
+        .unwrap()
+    }
+
+    fn add_peer(authorization: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method("POST").uri("/peers").header("content-type", "application/json");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.body(Body::from(r#"{"addr": "127.0.0.1:2"}"#)).unwrap()
+    }
+
+    fn remove_peer(authorization: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method("DELETE").uri("/peers/127.0.0.1:1");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    fn known(node: &Node, addr: &str) -> bool {
+        node.peer_statuses().iter().any(|status| status.entry.addr == addr)
+    }
+
+    #[tokio::test]
+    async fn test_peer_admin_requires_token() {
+        let node = node("unauthenticated", Some(TOKEN));
+        for authorization in [None, Some("Bearer wrong"), Some("s3cret"), Some("Basic s3cret")] {
+            let response = router(node.clone()).oneshot(add_peer(authorization)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let response = router(node.clone()).oneshot(remove_peer(authorization)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(!known(&node, "127.0.0.1:2"));
+        assert!(known(&node, "127.0.0.1:1"));
+    }
+
+    #[tokio::test]
+    async fn test_peer_admin_disabled_without_token() {
+        let node = node("disabled", None);
+        let response = router(node.clone()).oneshot(add_peer(Some("Bearer s3cret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = router(node.clone()).oneshot(remove_peer(Some("Bearer s3cret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!known(&node, "127.0.0.1:2"));
+        assert!(known(&node, "127.0.0.1:1"));
+
+        let response = router(node).oneshot(Request::builder().uri("/peers").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_peer_admin_with_token() {
+        let node = node("authenticated", Some(TOKEN));
+        let response = router(node.clone()).oneshot(add_peer(Some("Bearer s3cret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(known(&node, "127.0.0.1:2"));
+        let response = router(node.clone()).oneshot(remove_peer(Some("Bearer s3cret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!known(&node, "127.0.0.1:1"));
+    }
+}
@@ -1,5 +1,16 @@
+pub mod announce;
+pub mod bft;
 pub mod config;
+pub mod http;
 pub mod node;
+pub mod peers;
+pub mod raft;
+pub mod sync;
 pub mod metrics;
 pub mod network;
-
+pub mod p2p;
+pub mod noise;
+pub mod peer_table;
+pub mod producer;
+#[cfg(test)]
+mod test_support;
@@ -1,33 +1,33 @@
-use axum::{Router, routing::get, response::IntoResponse};
+use securerx_node::http;
 use std::net::SocketAddr;
+use tokio::net::TcpListener;
 use tokio::task;
 use securerx_node::{config::NodeConfig, node::Node};
-use prometheus::{Encoder, TextEncoder};
 
 #[tokio::main]
 async fn main() {
     // Load configuration
     let config = NodeConfig::from_env();
-    let node = Node::new(config);
+    let node = Node::new(config).expect("failed to load the blockchain from DATA_DIR");
 
+    // Start the encrypted P2P transport, which carries sync, announcements and consensus messages
+    let listener = TcpListener::bind(&node.config.p2p_addr).await.expect("failed to bind P2P_ADDR");
+    let node_clone = node.clone();
+    task::spawn(async move {
+        node_clone.listen(listener).await;
+    });
     let node_clone = node.clone();
-    // Start gossip loop
     task::spawn(async move {
         node_clone.gossip_loop().await;
     });
 
-    // Metrics endpoint
-    let metrics_route = Router::new().route("/metrics", get(metrics_handler));
+    node.start_producer();
+    node.start_consensus();
+    node.start_raft();
+
+    // Metrics, chain, transaction and Raft submission endpoints
+    let app = http::router(node.clone());
     let addr: SocketAddr = node.config.api_addr.parse().unwrap();
     println!("Node {} listening on {}", node.config.node_id, addr);
-    axum::Server::bind(&addr).serve(metrics_route.into_make_service()).await.unwrap();
-}
-
-/// Prometheus metrics handler
-async fn metrics_handler() -> impl IntoResponse {
-    let encoder = TextEncoder::new();
-    let metric_families = prometheus::gather();
-    let mut buffer = Vec::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-    String::from_utf8(buffer).unwrap()
+    axum::Server::bind(&addr).serve(app.into_make_service()).await.unwrap();
 }
@@ -1,7 +1,10 @@
-use prometheus::{IntCounter, IntGauge, register_int_counter, register_int_gauge};
+use prometheus::{
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec,
+};
 use lazy_static::lazy_static;
 
-/// Prometheus metrics for node observability
+// Prometheus metrics for node observability
 lazy_static! {
     pub static ref BLOCKS_PROCESSED: IntCounter = register_int_counter!(
         "blocks_processed_total",
@@ -17,4 +20,56 @@ lazy_static! {
         "chain_height",
         "Current blockchain height"
     ).unwrap();
+
+    pub static ref REORGS: IntCounter = register_int_counter!(
+        "reorgs_total",
+        "Total number of switches to a competing branch"
+    ).unwrap();
+
+    pub static ref LAST_REORG_DEPTH: IntGauge = register_int_gauge!(
+        "last_reorg_depth",
+        "Number of blocks rolled back by the last switch to a competing branch"
+    ).unwrap();
+
+    pub static ref PEER_BANS: IntCounter = register_int_counter!(
+        "peer_bans_total",
+        "Total number of peers banned for misbehaving"
+    ).unwrap();
+
+    pub static ref PEERS_BANNED: IntGauge = register_int_gauge!(
+        "peers_banned",
+        "Number of peers currently banned"
+    ).unwrap();
+
+    pub static ref PEER_PENALTIES: IntCounterVec = register_int_counter_vec!(
+        "peer_penalties_total",
+        "Total number of penalties given to peers, by misbehavior",
+        &["reason"]
+    ).unwrap();
+
+    pub static ref PEER_SCORE: IntGaugeVec = register_int_gauge_vec!(
+        "peer_score",
+        "Current score of each peer with points to win back",
+        &["peer"]
+    ).unwrap();
+
+    pub static ref PEERS_CONNECTED: IntGauge = register_int_gauge!(
+        "peers_connected",
+        "Number of open P2P connections"
+    ).unwrap();
+
+    pub static ref PEERS_KNOWN: IntGauge = register_int_gauge!(
+        "peers_known",
+        "Number of addresses in the peer table"
+    ).unwrap();
+
+    pub static ref MEMPOOL_SIZE: IntGauge = register_int_gauge!(
+        "mempool_size",
+        "Number of prescriptions waiting for a block"
+    ).unwrap();
+
+    pub static ref SIDE_BLOCKS: IntGauge = register_int_gauge!(
+        "side_blocks",
+        "Number of valid blocks kept off the canonical chain"
+    ).unwrap();
 }
@@ -1,31 +1,1488 @@
+use crate::announce::{Data, GetData, InvItem, InvKind, MAX_INV_ITEMS};
+use crate::bft::{ConsensusMessage, Output};
 use crate::node::Node;
+use crate::p2p::{self, Connection, HandshakeError, Hello, Incoming, Message, Request, RequestError, Response, MAX_INBOUND, MAX_OUTBOUND};
+use crate::peer_table::{is_peer_addr, InvalidPeerAddr, PeerEntry, PeerSource, PeerStatus, PEER_TABLE_FILE};
+use crate::peers::Misbehavior;
+use crate::raft::{self, NotLeader, RaftMessage, Submission};
+use crate::sync::{self, BlockRange, ChainTip, HeadersRequest, MAX_BLOCKS_PER_REQUEST, MAX_HEADERS, PARALLEL_REQUESTS};
+use std::cmp::Reverse;
+use std::path::Path;
 use std::time::Duration;
-use tokio::time::sleep;
-use reqwest::Client;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+use securerx_core::block::{Block, BlockHeader};
+use securerx_core::blockchain::ChainError;
+use securerx_core::fork_choice::Imported;
+use securerx_core::mempool::MempoolError;
+use securerx_core::registry::RegistryTransaction;
+use securerx_core::transaction::Transaction;
+use securerx_core::validation::ValidationError;
 
-/// P2P gossip: periodically sync blocks with peers using http for internal testing
+/// How often configured peers that are not connected are dialed again
+const DIAL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often every peer is asked for its tip, in case an announcement was missed
+const CATCH_UP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long dialing a peer may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Mempool priority of prescriptions submitted to this node rather than relayed by a peer
+const LOCAL_PRIORITY: u8 = 1;
+
+/// Why a request to a peer failed
+#[derive(Debug)]
+enum FetchError {
+    /// The peer is not connected
+    Unreachable,
+    /// The peer did not answer within [`crate::p2p::REQUEST_TIMEOUT`]
+    Timeout,
+    /// The peer answered with another kind of response than was asked for
+    Malformed,
+}
+
+/// Source of a block range request and its answer
+type RangeResponse = (String, Result<Vec<Block>, FetchError>);
+
+/// Send `request` over `connection` and take the answer apart with `answer`,
+/// which returns `None` for a response of the wrong kind
+async fn fetch<T>(connection: Option<Connection>, request: Request, answer: fn(Response) -> Option<T>) -> Result<T, FetchError> {
+    let connection = connection.ok_or(FetchError::Unreachable)?;
+    let response = connection.request(request).await.map_err(|e| match e {
+        RequestError::Closed => FetchError::Unreachable,
+        RequestError::Timeout => FetchError::Timeout,
+    })?;
+    answer(response).ok_or(FetchError::Malformed)
+}
+
+/// P2P links: framed TCP connections to peers, see [`crate::p2p`]
 impl Node {
+    /// Dial the known peers, exchange addresses and catch up with the network until the node stops
     pub async fn gossip_loop(&self) {
-        let client = Client::new();
+        let rounds_per_catch_up = (CATCH_UP_INTERVAL.as_secs() / DIAL_INTERVAL.as_secs()).max(1);
+        let mut round = 0u64;
         loop {
-            for peer in &self.config.peers {
-                let url = format!("http://{}/blocks", peer);
-                if let Ok(resp) = client.get(&url).send().await {
-                    if let Ok(remote_blocks) = resp.json::<Vec<securerx_core::block::Block>>().await {
-                        self.sync_blocks(remote_blocks).await;
+            self.connect_peers().await;
+            if round.is_multiple_of(rounds_per_catch_up) {
+                self.exchange_peers().await;
+                // Raft replicates blocks itself
+                if self.raft.is_none() {
+                    self.sync_round().await;
+                }
+            }
+            self.save_peer_table();
+            self.update_score_metrics();
+            round += 1;
+            sleep(DIAL_INTERVAL).await;
+        }
+    }
+
+    /// Accept connections from other nodes on `listener`, up to [`MAX_INBOUND`] at once
+    pub async fn listen(&self, listener: TcpListener) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("Failed to accept a peer: {}", e);
+                    continue;
+                }
+            };
+            if self.connection_count(true) >= MAX_INBOUND {
+                continue;
+            }
+            let node = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = node.open(stream, None).await {
+                    eprintln!("Refused connection from {}: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Connect to every address in the peer table that is due for a dial and
+    /// neither connected nor banned, up to [`MAX_OUTBOUND`] connections.
+    /// Failed dials back off exponentially.
+    pub async fn connect_peers(&self) {
+        let due = self.peer_table.lock().unwrap().due();
+        for peer in due {
+            if self.connection_count(false) >= MAX_OUTBOUND {
+                break;
+            }
+            if self.connection(&peer).is_some() || self.peer_scores.lock().unwrap().is_banned(&peer) {
+                continue;
+            }
+            let stream = match timeout(CONNECT_TIMEOUT, TcpStream::connect(&peer)).await {
+                Ok(Ok(stream)) => stream,
+                _ => {
+                    self.peer_table.lock().unwrap().failed(&peer);
+                    continue;
+                }
+            };
+            match self.open(stream, Some(peer.clone())).await {
+                Ok(()) => {}
+                Err(HandshakeError::SelfConnection) => self.peer_table.lock().unwrap().remove_own(&peer),
+                Err(e) => {
+                    eprintln!("Failed to connect to {}: {}", peer, e);
+                    self.peer_table.lock().unwrap().failed(&peer);
+                }
+            }
+        }
+    }
+
+    /// Handshake on `stream`, dialed to `dialed` or accepted if `None`, and
+    /// serve the peer's requests until the connection closes. Peers whose node
+    /// key is not allowed or banned are refused, peers on another genesis
+    /// penalized and peers further ahead synced from.
+    async fn open(&self, mut stream: TcpStream, dialed: Option<String>) -> Result<(), HandshakeError> {
+        let ours = Hello::new(&self.config.node_id, &self.blockchain.lock().unwrap());
+        let inbound = dialed.is_none();
+        let (theirs, secured) = match p2p::handshake(&mut stream, &ours, &self.identity, !inbound).await {
+            Ok(handshaken) => handshaken,
+            Err(e) => {
+                if let (Some(peer), HandshakeError::Genesis(_) | HandshakeError::ChainId(_)) = (&dialed, &e) {
+                    self.penalize(peer, Misbehavior::WrongGenesis);
+                }
+                return Err(e);
+            }
+        };
+        if let Some(peer) = &dialed {
+            self.peer_table.lock().unwrap().connected(peer);
+        }
+        // The node_id in a hello is self-reported: peers that dialed in are known by their node key
+        let key = hex::encode(secured.peer_key.to_bytes());
+        let peer = dialed.unwrap_or_else(|| key.clone());
+        {
+            let peer_scores = self.peer_scores.lock().unwrap();
+            if peer_scores.is_banned(&peer) || peer_scores.is_banned(&key) {
+                return Ok(());
+            }
+        }
+        let ahead = theirs.best_height > ours.best_height && self.raft.is_none();
+        let (connection, mut incoming) = Connection::start(stream, secured, peer.clone(), theirs, inbound);
+        {
+            let mut connections = self.connections.lock().unwrap();
+            if connections.get(&peer).is_some_and(|existing| !existing.is_closed()) {
+                connection.close();
+                return Ok(());
+            }
+            connections.insert(peer.clone(), connection.clone());
+            crate::metrics::PEERS_CONNECTED.set(connections.len() as i64);
+        }
+        if ahead {
+            let (node, peer) = (self.clone(), peer.clone());
+            tokio::spawn(async move { node.catch_up(&peer).await });
+        }
+        let (node, asked) = (self.clone(), connection.clone());
+        tokio::spawn(async move { node.exchange_with(asked).await });
+        // The peer may have missed the announcements of the pending prescriptions
+        let pending: Vec<InvItem> = {
+            let mempool = self.mempool.lock().unwrap();
+            let ids = mempool.ids().into_iter().take(MAX_INV_ITEMS);
+            ids.map(|id| InvItem { kind: InvKind::Transaction, hash: hex::encode(id) }).collect()
+        };
+        if !pending.is_empty() {
+            connection.send(Message::Inv(pending));
+        }
+
+        let node = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = incoming.recv().await {
+                match message {
+                    Incoming::Request { id, request } => {
+                        connection.respond(id, node.answer(request));
+                    }
+                    Incoming::Inv(items) => {
+                        if node.raft.is_none() {
+                            let (node, peer) = (node.clone(), peer.clone());
+                            tokio::spawn(async move { node.handle_inventory(&peer, items).await });
+                        }
+                    }
+                    Incoming::Consensus(message) => node.handle_consensus(message),
+                    Incoming::Raft(message) => node.handle_raft(message),
+                    Incoming::Violation(misbehavior, e) => {
+                        eprintln!("Disconnected {}: {}", peer, e);
+                        node.penalize(&peer, misbehavior);
+                    }
+                }
+            }
+            let mut connections = node.connections.lock().unwrap();
+            if connections.get(&peer).is_some_and(|current| current.is(&connection)) {
+                connections.remove(&peer);
+            }
+            crate::metrics::PEERS_CONNECTED.set(connections.len() as i64);
+        });
+        Ok(())
+    }
+
+    /// The open connection to `peer`
+    fn connection(&self, peer: &str) -> Option<Connection> {
+        self.connections.lock().unwrap().get(peer).filter(|connection| !connection.is_closed()).cloned()
+    }
+
+    /// Every open connection
+    fn open_connections(&self) -> Vec<Connection> {
+        self.connections.lock().unwrap().values().filter(|connection| !connection.is_closed()).cloned().collect()
+    }
+
+    /// Open connections the peers opened if `inbound`, else this node
+    fn connection_count(&self, inbound: bool) -> usize {
+        let connections = self.connections.lock().unwrap();
+        connections.values().filter(|connection| connection.inbound == inbound && !connection.is_closed()).count()
+    }
+
+    /// Answer a peer's request from this node's chain
+    fn answer(&self, request: Request) -> Response {
+        match request {
+            Request::Tip => Response::Tip(sync::tip(&self.blockchain.lock().unwrap().chain)),
+            Request::Headers(request) => {
+                let blockchain = self.blockchain.lock().unwrap();
+                Response::Headers(sync::headers_after(&blockchain.chain, &request, |hash| blockchain.height_of(hash)))
+            }
+            Request::Blocks(range) => Response::Blocks(sync::block_range(&self.blockchain.lock().unwrap().chain, range)),
+            Request::GetData(request) => Response::Data(self.get_data(&request)),
+            Request::Peers => Response::Peers(self.peer_table.lock().unwrap().shareable()),
+        }
+    }
+}
+
+/// Peer table: addresses learned from peers or added at runtime, see [`crate::peer_table`]
+impl Node {
+    /// Ask every connected peer for the addresses it knows
+    pub async fn exchange_peers(&self) {
+        let mut exchanges = JoinSet::new();
+        for connection in self.open_connections() {
+            let node = self.clone();
+            exchanges.spawn(async move { node.exchange_with(connection).await });
+        }
+        while exchanges.join_next().await.is_some() {}
+    }
+
+    /// Add the addresses `connection`'s peer knows to the peer table
+    async fn exchange_with(&self, connection: Connection) {
+        match fetch(Some(connection.clone()), Request::Peers, Response::peers).await {
+            Ok(addrs) => {
+                let mut table = self.peer_table.lock().unwrap();
+                if table.learn(&addrs) > 0 {
+                    crate::metrics::PEERS_KNOWN.set(table.len() as i64);
+                }
+            }
+            Err(e) => self.blame(&connection.peer, "peers", &e),
+        }
+    }
+
+    /// Add `addr` to the peer table and dial it, returning whether it is new
+    pub fn add_peer(&self, addr: &str) -> Result<bool, InvalidPeerAddr> {
+        if !is_peer_addr(addr) {
+            return Err(InvalidPeerAddr(addr.to_string()));
+        }
+        let added = {
+            let mut table = self.peer_table.lock().unwrap();
+            let added = table.add(addr, PeerSource::Admin);
+            crate::metrics::PEERS_KNOWN.set(table.len() as i64);
+            added
+        };
+        self.save_peer_table();
+        let node = self.clone();
+        tokio::spawn(async move { node.connect_peers().await });
+        Ok(added)
+    }
+
+    /// Remove `addr` from the peer table and hang up on it, returning whether it was known
+    pub fn remove_peer(&self, addr: &str) -> bool {
+        let removed = {
+            let mut table = self.peer_table.lock().unwrap();
+            let removed = table.remove(addr).is_some();
+            crate::metrics::PEERS_KNOWN.set(table.len() as i64);
+            removed
+        };
+        if let Some(connection) = self.connections.lock().unwrap().remove(addr) {
+            connection.close();
+        }
+        self.save_peer_table();
+        removed
+    }
+
+    /// Every address in the peer table with its current standing
+    pub fn peer_statuses(&self) -> Vec<PeerStatus> {
+        let entries: Vec<PeerEntry> = self.peer_table.lock().unwrap().entries().cloned().collect();
+        entries
+            .into_iter()
+            .map(|mut entry| {
+                let scores = self.peer_scores.lock().unwrap();
+                entry.score = scores.score(&entry.addr);
+                PeerStatus {
+                    connected: self.connection(&entry.addr).is_some(),
+                    banned: scores.is_banned(&entry.addr),
+                    entry,
+                }
+            })
+            .collect()
+    }
+
+    /// Record the current scores in the peer table and save it to `data_dir` if it changed
+    pub fn save_peer_table(&self) {
+        let addrs: Vec<String> = self.peer_table.lock().unwrap().entries().map(|entry| entry.addr.clone()).collect();
+        let scores: Vec<i64> = {
+            let peer_scores = self.peer_scores.lock().unwrap();
+            addrs.iter().map(|addr| peer_scores.score(addr)).collect()
+        };
+        let mut table = self.peer_table.lock().unwrap();
+        for (addr, score) in addrs.iter().zip(scores) {
+            table.set_score(addr, score);
+        }
+        if table.is_changed() {
+            if let Err(e) = table.save(&Path::new(&self.config.data_dir).join(PEER_TABLE_FILE)) {
+                eprintln!("Failed to save the peer table: {}", e);
+            }
+        }
+    }
+}
+
+/// Headers-first catch-up sync over the P2P links, see [`crate::sync`]
+impl Node {
+    /// Ask every connected peer that is not banned for its tip and sync the tips this node does not hold
+    pub async fn sync_round(&self) {
+        let mut peers: Vec<String> = self.connections.lock().unwrap().keys().cloned().collect();
+        // Configured peers first, in the order they are listed
+        peers.sort_by_key(|peer| self.config.p2p_peers.iter().position(|configured| configured == peer).unwrap_or(usize::MAX));
+        let mut tips: Vec<(String, ChainTip)> = vec![];
+        for peer in peers {
+            if self.peer_scores.lock().unwrap().is_banned(&peer) {
+                continue;
+            }
+            if let Some(tip) = self.fetch_tip(&peer).await {
+                tips.push((peer, tip));
+            }
+        }
+        // Highest tips first: syncing one often brings in the others
+        tips.sort_by_key(|(_, tip)| Reverse(tip.height));
+        for (peer, tip) in &tips {
+            if self.peer_scores.lock().unwrap().is_banned(peer) || self.holds(tip) {
+                continue;
+            }
+            // Peers at the same tip have the same blocks to share the download with
+            let sources: Vec<String> = std::iter::once(peer.clone())
+                .chain(tips.iter().filter(|(other, other_tip)| other != peer && other_tip == tip).map(|(other, _)| other.clone()))
+                .collect();
+            self.sync_from(peer, tip, &sources).await;
+        }
+    }
+
+    /// Sync the tip of `peer`, e.g. after it greeted with a higher best height
+    async fn catch_up(&self, peer: &str) {
+        if let Some(tip) = self.fetch_tip(peer).await {
+            if !self.holds(&tip) {
+                self.sync_from(peer, &tip, &[peer.to_string()]).await;
+            }
+        }
+    }
+
+    /// Penalize `peer` for a failed request for `what`: a malformed answer
+    /// or none in time. Peers that are not connected are not to blame.
+    fn blame(&self, peer: &str, what: &str, error: &FetchError) {
+        match error {
+            FetchError::Malformed => {
+                eprintln!("Malformed {} from {}", what, peer);
+                self.penalize(peer, Misbehavior::MalformedResponse);
+            }
+            FetchError::Timeout => {
+                eprintln!("{} did not send {} in time", peer, what);
+                self.penalize(peer, Misbehavior::SlowResponse);
+            }
+            FetchError::Unreachable => {}
+        }
+    }
+
+    /// Tip of `peer`'s chain; `peer` is penalized for a malformed or late answer
+    async fn fetch_tip(&self, peer: &str) -> Option<ChainTip> {
+        fetch(self.connection(peer), Request::Tip, Response::tip).await.map_err(|e| self.blame(peer, "tip", &e)).ok()
+    }
+
+    /// Whether the block at `tip` is on this node's chain or in its block tree
+    fn holds(&self, tip: &ChainTip) -> bool {
+        let blockchain = self.blockchain.lock().unwrap();
+        blockchain.chain.get(tip.height as usize).is_some_and(|block| block.calculate_hash() == tip.hash)
+            || self.fork_choice.lock().unwrap().contains(&tip.hash)
+    }
+
+    /// Fetch the headers from our last shared block up to `tip` from `peer`,
+    /// then the blocks in parallel ranges from `sources`, importing each
+    /// window of ranges as soon as it is complete
+    async fn sync_from(&self, peer: &str, tip: &ChainTip, sources: &[String]) {
+        let Some(headers) = self.fetch_headers(peer, tip).await else {
+            return;
+        };
+        for window in headers.chunks(MAX_BLOCKS_PER_REQUEST as usize * PARALLEL_REQUESTS) {
+            let blocks = self.fetch_blocks(peer, sources, window).await;
+            let complete = blocks.len() == window.len();
+            if !self.import_blocks(peer, blocks) || !complete {
+                return;
+            }
+        }
+    }
+
+    /// Headers of `peer`'s chain after the last block it shares with ours, up
+    /// to `tip`, once they are checked to link up and be sealed by validators
+    async fn fetch_headers(&self, peer: &str, tip: &ChainTip) -> Option<Vec<BlockHeader>> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        while headers.last().is_none_or(|last| last.index < tip.height) {
+            let locator = match headers.last() {
+                Some(last) => vec![last.hash()],
+                None => sync::locator(&self.blockchain.lock().unwrap().chain),
+            };
+            let request = Request::Headers(HeadersRequest { locator, limit: None });
+            let batch = match fetch(self.connection(peer), request, Response::headers).await {
+                Ok(batch) => batch,
+                Err(e) => {
+                    self.blame(peer, "headers", &e);
+                    return None;
+                }
+            };
+            let Some(first) = batch.first() else {
+                break;
+            };
+            let checked = {
+                let blockchain = self.blockchain.lock().unwrap();
+                let parent = match headers.last() {
+                    Some(last) => Some(last.hash()),
+                    // The first batch follows a block of our chain named in the locator
+                    None => first.index.checked_sub(1)
+                        .and_then(|height| blockchain.chain.get(height as usize))
+                        .map(Block::calculate_hash)
+                        .filter(|hash| hash == &first.prev_hash),
+                };
+                match parent {
+                    Some(parent) => blockchain.check_headers(&parent, &batch).map_err(|e| {
+                        eprintln!("Invalid headers from {}: {}", peer, e);
+                        Misbehavior::InvalidBlock
+                    }),
+                    None if first.index == 1 => {
+                        eprintln!("Refusing to sync with {}: it is on a different genesis", peer);
+                        Err(Misbehavior::WrongGenesis)
                     }
+                    None => {
+                        eprintln!("Headers from {} do not follow our chain", peer);
+                        Err(Misbehavior::InvalidBlock)
+                    }
+                }
+            };
+            if let Err(misbehavior) = checked {
+                self.penalize(peer, misbehavior);
+                return None;
+            }
+            let partial = (batch.len() as u64) < MAX_HEADERS;
+            headers.extend(batch);
+            if partial {
+                break;
+            }
+        }
+        Some(headers)
+    }
+
+    /// Download the blocks of `headers` in ranges spread over `sources`,
+    /// refetching from `peer`, which served the headers, the ranges another
+    /// source did not serve. Returns the blocks up to the first one that
+    /// could not be fetched; sources serving a block other than the one its
+    /// header describes are penalized.
+    async fn fetch_blocks(&self, peer: &str, sources: &[String], headers: &[BlockHeader]) -> Vec<Block> {
+        let chunks: Vec<&[BlockHeader]> = headers.chunks(MAX_BLOCKS_PER_REQUEST as usize).collect();
+        let mut requests = JoinSet::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let source = sources[i % sources.len()].clone();
+            let range = BlockRange { from: chunk[0].index, to: chunk[0].index + chunk.len() as u64 };
+            let connection = self.connection(&source);
+            requests.spawn(async move { (i, source, fetch(connection, Request::Blocks(range), Response::blocks).await) });
+        }
+        let mut responses: Vec<Option<RangeResponse>> = chunks.iter().map(|_| None).collect();
+        while let Some(joined) = requests.join_next().await {
+            if let Ok((i, source, response)) = joined {
+                responses[i] = Some((source, response));
+            }
+        }
+
+        let mut blocks = vec![];
+        for (chunk, response) in chunks.into_iter().zip(responses) {
+            let (source, mut fetched) = match response {
+                Some((source, response)) => {
+                    let fetched = self.matching_blocks(&source, response, chunk);
+                    (source, fetched)
                 }
+                None => (String::new(), vec![]),
+            };
+            if fetched.len() < chunk.len() && source != peer {
+                let range = BlockRange { from: chunk[0].index, to: chunk[0].index + chunk.len() as u64 };
+                let response = fetch(self.connection(peer), Request::Blocks(range), Response::blocks).await;
+                fetched = self.matching_blocks(peer, response, chunk);
+            }
+            let complete = fetched.len() == chunk.len();
+            blocks.extend(fetched);
+            if !complete {
+                break;
+            }
+        }
+        blocks
+    }
+
+    /// The leading blocks of `response` whose hashes match `headers`
+    fn matching_blocks(&self, source: &str, response: Result<Vec<Block>, FetchError>, headers: &[BlockHeader]) -> Vec<Block> {
+        let blocks = match response {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                self.blame(source, "blocks", &e);
+                return vec![];
             }
-            sleep(Duration::from_secs(5)).await;
+        };
+        let served = blocks.len().min(headers.len());
+        let matching: Vec<Block> = blocks
+            .into_iter()
+            .zip(headers)
+            .take_while(|(block, header)| block.calculate_hash() == header.hash())
+            .map(|(block, _)| block)
+            .collect();
+        if matching.len() < served {
+            eprintln!("Block {} from {} does not match its header", headers[matching.len()].index, source);
+            self.penalize(source, Misbehavior::InvalidBlock);
         }
+        matching
     }
 
-    async fn sync_blocks(&self, remote_blocks: Vec<securerx_core::block::Block>) {
-        // Simple longest-chain sync
+    /// Import `blocks`, fetched from `peer`, into the block tree, which
+    /// validates each one against its branch (seals, proposer turns, proof of
+    /// work, registry and prescription rules) and switches to the peer's
+    /// branch once the fork choice rule prefers it. Returns whether every
+    /// block was accepted; `peer` is penalized for an invalid one. New blocks
+    /// are announced to the other peers.
+    fn import_blocks(&self, peer: &str, blocks: Vec<Block>) -> bool {
         let mut blockchain = self.blockchain.lock().unwrap();
-        if remote_blocks.len() > blockchain.chain.len() {
-            blockchain.chain = remote_blocks;
-            crate::metrics::CHAIN_HEIGHT.set(blockchain.chain.len() as i64);
+        let mut fork_choice = self.fork_choice.lock().unwrap();
+        let mut rejected = None;
+        let mut accepted = vec![];
+        let mut reverted = vec![];
+        for block in blocks {
+            let index = block.index;
+            let item = InvItem::block(&block);
+            match fork_choice.import(&mut blockchain, block) {
+                Ok(Imported::Known) => {}
+                Ok(Imported::Reorganized(reorg)) => {
+                    println!(
+                        "Switched to the branch of {} at height {}: {} blocks reverted, {} applied",
+                        peer,
+                        reorg.common_height,
+                        reorg.depth(),
+                        reorg.applied.len()
+                    );
+                    reverted.extend(reorg.reverted.iter().flat_map(|block| block.transactions.iter().cloned()));
+                    self.emit_reorg(reorg);
+                    accepted.push(item);
+                }
+                Ok(_) => accepted.push(item),
+                Err(e) => {
+                    eprintln!("Rejected block {} from {}: {}", index, peer, e);
+                    rejected = Some(e);
+                    break;
+                }
+            }
+        }
+        crate::metrics::CHAIN_HEIGHT.set(blockchain.chain.len() as i64);
+        crate::metrics::SIDE_BLOCKS.set(fork_choice.side_blocks() as i64);
+        if !accepted.is_empty() {
+            self.refresh_mempool(&blockchain, reverted);
+        }
+        self.announce(accepted, Some(peer));
+        match rejected {
+            // A failing store is this node's problem, not the peer's
+            Some(ChainError::Storage(_)) => false,
+            Some(_) => {
+                self.penalize(peer, Misbehavior::InvalidBlock);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+/// Push announcements: tell peers about accepted blocks and transactions
+/// right away and fetch the ones they announce, see [`crate::announce`]
+impl Node {
+    /// Send `items` to every connected peer that is not banned, except
+    /// `except` which they came from. Announced items are remembered as seen.
+    pub(crate) fn announce(&self, items: Vec<InvItem>, except: Option<&str>) {
+        if items.is_empty() {
+            return;
+        }
+        {
+            let mut seen = self.seen.lock().unwrap();
+            for item in &items {
+                seen.insert(item.clone());
+            }
+        }
+        let connections: Vec<Connection> = self.connections.lock().unwrap().values().cloned().collect();
+        for connection in connections {
+            if Some(connection.peer.as_str()) == except || self.peer_scores.lock().unwrap().is_banned(&connection.peer) {
+                continue;
+            }
+            if !connection.send(Message::Inv(items.clone())) {
+                eprintln!("Failed to announce to {}", connection.peer);
+            }
+        }
+    }
+
+    /// Fetch the announced `items` this node has not seen from `peer`, which
+    /// announced them. Blocks whose parent is unknown are caught up on with a
+    /// headers-first sync from `peer`.
+    pub async fn handle_inventory(&self, peer: &str, items: Vec<InvItem>) {
+        if self.peer_scores.lock().unwrap().is_banned(peer) {
+            return;
+        }
+        if items.len() > MAX_INV_ITEMS {
+            eprintln!("{} announced {} items at once", peer, items.len());
+            self.penalize(peer, Misbehavior::Spam);
         }
+        let unknown: Vec<InvItem> = items
+            .into_iter()
+            .take(MAX_INV_ITEMS)
+            .filter(|item| item.kind == InvKind::Transaction || !self.holds_block(&item.hash))
+            .collect();
+        let wanted: Vec<InvItem> = {
+            let mempool = self.mempool.lock().unwrap();
+            let mut seen = self.seen.lock().unwrap();
+            let pending = |item: &InvItem| item.hash_bytes().is_some_and(|id| mempool.contains(&id));
+            unknown.into_iter().filter(|item| !pending(item) && seen.insert(item.clone())).collect()
+        };
+        if wanted.is_empty() {
+            return;
+        }
+
+        let request = Request::GetData(GetData { items: wanted.clone() });
+        let data: Data = match fetch(self.connection(peer), request, Response::data).await {
+            Ok(data) => data,
+            Err(e) => {
+                self.blame(peer, "data", &e);
+                return;
+            }
+        };
+        let unrequested = data.blocks.iter().map(InvItem::block).chain(data.transactions.iter().map(InvItem::transaction));
+        if let Some(item) = unrequested.into_iter().find(|item| !wanted.contains(item)) {
+            eprintln!("{} sent {:?} {} that was not asked for", peer, item.kind, item.hash);
+            self.penalize(peer, Misbehavior::MalformedResponse);
+            return;
+        }
+
+        let mut blocks = data.blocks;
+        blocks.sort_by_key(|block| block.index);
+        if let Some(first) = blocks.first() {
+            if self.holds_block(&first.prev_hash) {
+                self.import_blocks(peer, blocks);
+            } else {
+                // We are missing blocks before the announced ones
+                let last = blocks.last().unwrap();
+                let tip = ChainTip { height: last.index, hash: last.calculate_hash() };
+                self.sync_from(peer, &tip, &[peer.to_string()]).await;
+            }
+        }
+        for tx in data.transactions {
+            // Other errors are not the peer's fault: the transaction may have been included in a block since
+            if let Err(MempoolError::Invalid(e @ (ValidationError::BadSignature { .. } | ValidationError::WrongChain { .. }))) =
+                self.submit_transaction(tx, Some(peer))
+            {
+                eprintln!("Invalid transaction from {}: {}", peer, e);
+                self.penalize(peer, Misbehavior::InvalidTransaction);
+            }
+        }
+    }
+
+    /// The items of `request` this node can serve: blocks of its chain or
+    /// block tree, and the transactions in its mempool
+    pub fn get_data(&self, request: &GetData) -> Data {
+        let mut data = Data::default();
+        let blockchain = self.blockchain.lock().unwrap();
+        let fork_choice = self.fork_choice.lock().unwrap();
+        let mempool = self.mempool.lock().unwrap();
+        for item in request.items.iter().take(MAX_INV_ITEMS) {
+            match item.kind {
+                InvKind::Block => {
+                    if let Some(block) = blockchain.block_by_hash(&item.hash).or_else(|| fork_choice.get(&item.hash)) {
+                        data.blocks.push(block.clone());
+                    }
+                }
+                InvKind::Transaction => {
+                    data.transactions.extend(item.hash_bytes().and_then(|id| mempool.get(&id)).cloned());
+                }
+            }
+        }
+        data
+    }
+
+    /// Admit a prescription to the mempool and announce it to every peer
+    /// except `from`, which relayed it. Prescriptions submitted to this node
+    /// go before relayed ones; pending ones are not announced again.
+    pub fn submit_transaction(&self, tx: Transaction, from: Option<&str>) -> Result<(), MempoolError> {
+        let item = InvItem::transaction(&tx);
+        let priority = if from.is_none() { LOCAL_PRIORITY } else { 0 };
+        let pending = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let mut mempool = self.mempool.lock().unwrap();
+            let new = mempool.insert(tx, priority, &blockchain)?;
+            crate::metrics::MEMPOOL_SIZE.set(mempool.len() as i64);
+            new.then_some(mempool.len())
+        };
+        let Some(pending) = pending else {
+            return Ok(());
+        };
+        if pending >= self.config.max_block_txs {
+            self.block_ready.notify_one();
+        }
+        self.announce(vec![item], from);
+        Ok(())
+    }
+
+    /// Whether the block with hash `hash` is on this node's chain or in its block tree
+    fn holds_block(&self, hash: &str) -> bool {
+        self.blockchain.lock().unwrap().chain.iter().rev().any(|block| block.calculate_hash() == hash)
+            || self.fork_choice.lock().unwrap().contains(hash)
+    }
+}
+
+/// BFT consensus: run the engine and carry its messages over the P2P links
+impl Node {
+    /// Start deciding the next block, if this node is a BFT validator
+    pub fn start_consensus(&self) {
+        if let Some(engine) = &self.bft {
+            let outputs = engine.lock().unwrap().start(&mut self.blockchain.lock().unwrap());
+            self.dispatch(outputs);
+        }
+    }
+
+    /// Feed a message from another validator to the engine
+    pub fn handle_consensus(&self, message: ConsensusMessage) {
+        if let Some(engine) = &self.bft {
+            let outputs = engine.lock().unwrap().handle(&mut self.blockchain.lock().unwrap(), message);
+            self.dispatch(outputs);
+        }
+    }
+
+    fn dispatch(&self, outputs: Vec<Output>) {
+        for output in outputs {
+            match output {
+                Output::Broadcast(message) => {
+                    // Nodes outside the validator set ignore it
+                    for connection in self.open_connections() {
+                        if !connection.send(Message::Consensus(message.clone())) {
+                            eprintln!("Failed to send consensus message to {}", connection.peer);
+                        }
+                    }
+                }
+                Output::ScheduleTimeout { height, round, step, after } => {
+                    let node = self.clone();
+                    tokio::spawn(async move {
+                        sleep(after).await;
+                        if let Some(engine) = &node.bft {
+                            let outputs = engine.lock().unwrap().on_timeout(&mut node.blockchain.lock().unwrap(), height, round, step);
+                            node.dispatch(outputs);
+                        }
+                    });
+                }
+                Output::Committed { height } => {
+                    crate::metrics::BLOCKS_PROCESSED.inc();
+                    crate::metrics::CHAIN_HEIGHT.set(height as i64 + 1);
+                    let blockchain = self.blockchain.lock().unwrap();
+                    self.refresh_mempool(&blockchain, vec![]);
+                    // Followers outside the validator set learn of the block without waiting for a sync round
+                    let block = blockchain.chain.get(height as usize).map(InvItem::block);
+                    drop(blockchain);
+                    self.announce(block.into_iter().collect(), None);
+                }
+            }
+        }
+    }
+}
+
+/// How often the Raft clock ticks
+const RAFT_TICK: Duration = Duration::from_millis(100);
+
+/// How long a submission waits for its block to be committed
+const RAFT_COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why transactions submitted to this node were not committed
+#[derive(Debug)]
+pub enum SubmitError {
+    NotLeader(NotLeader),
+    /// The block was not committed in time, e.g. because a majority is down
+    Timeout,
+}
+
+/// Raft replication: drive the node's clock and carry its messages over the P2P links
+impl Node {
+    /// Start the Raft clock, if this node runs in Raft mode
+    pub fn start_raft(&self) {
+        if self.raft.is_none() {
+            return;
+        }
+        let node = self.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(RAFT_TICK).await;
+                let raft = node.raft.as_ref().unwrap();
+                let outputs = raft.lock().unwrap().tick(&mut node.blockchain.lock().unwrap());
+                node.dispatch_raft(outputs);
+            }
+        });
+    }
+
+    /// Feed a message from another cluster member to Raft
+    pub fn handle_raft(&self, message: RaftMessage) {
+        if let Some(raft) = &self.raft {
+            let outputs = raft.lock().unwrap().handle(&mut self.blockchain.lock().unwrap(), message);
+            self.dispatch_raft(outputs);
+        }
+    }
+
+    /// Replicate the submitted transactions in the next block and wait until
+    /// it is appended, returning the index of the last block holding them
+    pub async fn submit_raft(&self, submission: Submission) -> Result<u64, SubmitError> {
+        let Some(raft) = &self.raft else {
+            return Err(SubmitError::NotLeader(NotLeader { leader: None }));
+        };
+        let tx_ids: Vec<[u8; 32]> = submission.transactions.iter().map(Transaction::id).collect();
+        let digests: Vec<[u8; 32]> = submission.registry_txs.iter().map(RegistryTransaction::hash).collect();
+        let outputs = raft
+            .lock()
+            .unwrap()
+            .submit(&mut self.blockchain.lock().unwrap(), submission)
+            .map_err(SubmitError::NotLeader)?;
+        self.dispatch_raft(outputs);
+
+        let deadline = tokio::time::Instant::now() + RAFT_COMMIT_TIMEOUT;
+        while tokio::time::Instant::now() < deadline {
+            sleep(Duration::from_millis(20)).await;
+            let blockchain = self.blockchain.lock().unwrap();
+            let heights: Option<Vec<u64>> = tx_ids
+                .iter()
+                .map(|id| blockchain.transaction_height(id))
+                .chain(digests.iter().map(|digest| blockchain.registry_transaction_height(digest)))
+                .collect();
+            if let Some(heights) = heights {
+                return Ok(heights.into_iter().max().unwrap_or_default());
+            }
+        }
+        Err(SubmitError::Timeout)
+    }
+
+    /// Carry out Raft's outputs in order, stopping at a failed `Persist`
+    fn dispatch_raft(&self, outputs: Vec<raft::Output>) {
+        for output in outputs {
+            match output {
+                raft::Output::Send { to, message } => {
+                    // Members that are not connected miss it; Raft sends again on the next heartbeat
+                    let connection = self.open_connections().into_iter().find(|connection| connection.hello.node_id == to);
+                    if let Some(connection) = connection {
+                        connection.send(Message::Raft(message));
+                    }
+                }
+                raft::Output::Persist => {
+                    let state = self.raft.as_ref().unwrap().lock().unwrap().persistent_state().clone();
+                    if let Err(e) = state.save(&Path::new(&self.config.data_dir).join("raft.json")) {
+                        // Votes and acknowledgements must not leave before the state they promise is stored
+                        eprintln!("Failed to persist Raft state, dropping the messages that depend on it: {}", e);
+                        return;
+                    }
+                }
+                raft::Output::Applied { height } => {
+                    crate::metrics::BLOCKS_PROCESSED.inc();
+                    crate::metrics::CHAIN_HEIGHT.set(height as i64 + 1);
+                    self.refresh_mempool(&self.blockchain.lock().unwrap(), vec![]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConsensusMode, NodeConfig};
+    use crate::noise::Identity;
+    use crate::test_support;
+    use ed25519_dalek::SigningKey;
+    use securerx_core::blockchain::Blockchain;
+    use securerx_core::crypto::generate_keypair;
+    use securerx_core::genesis::{GenesisDoctor, GenesisSpec};
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::AsyncWriteExt;
+    use tokio::sync::mpsc;
+
+    /// Requests a fake peer received
+    #[derive(Default)]
+    struct Hits {
+        tips: AtomicUsize,
+        ranges: AtomicUsize,
+        getdata: AtomicUsize,
+    }
+
+    /// Greeting of a fake peer holding `chain`, claiming `best_height`
+    fn fake_hello(chain: &[Block], best_height: u64) -> Hello {
+        Hello {
+            version: p2p::PROTOCOL_VERSION,
+            node_id: "fake".to_string(),
+            chain_id: GenesisSpec::default().chain_id,
+            genesis_hash: chain[0].calculate_hash(),
+            best_height,
+        }
+    }
+
+    /// Node key of the fake peers
+    fn fake_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    /// Node key of the nodes under test
+    fn test_key() -> SigningKey {
+        SigningKey::from_bytes(&[8; 32])
+    }
+
+    /// A peer holding node key `key`, greeting with `hello` and answering every request with `answer`
+    async fn serve(key: SigningKey, hello: Hello, answer: impl Fn(Request) -> Response + Send + Sync + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let answer = Arc::new(answer);
+        let identity = Arc::new(Identity::new(key, &[test_key().verifying_key()]));
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (hello, answer, identity) = (hello.clone(), answer.clone(), identity.clone());
+                tokio::spawn(async move {
+                    let Ok((theirs, secured)) = p2p::handshake(&mut stream, &hello, &identity, false).await else {
+                        return;
+                    };
+                    let (connection, mut incoming) = Connection::start(stream, secured, theirs.node_id.clone(), theirs, true);
+                    while let Some(message) = incoming.recv().await {
+                        if let Incoming::Request { id, request } = message {
+                            connection.respond(id, answer(request));
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    /// A peer serving the tip and headers of `chain` but the blocks of
+    /// `bodies`; it greets with best height 0 so nodes only sync from it when asked
+    async fn fake_peer(chain: Vec<Block>, bodies: Vec<Block>) -> (String, Arc<Hits>) {
+        fake_peer_at(chain, bodies, 0).await
+    }
+
+    /// A fake peer greeting with `best_height`, see [`fake_peer`]
+    async fn fake_peer_at(chain: Vec<Block>, bodies: Vec<Block>, best_height: u64) -> (String, Arc<Hits>) {
+        let hits = Arc::new(Hits::default());
+        let counter = hits.clone();
+        let hello = fake_hello(&chain, best_height);
+        let addr = serve(fake_key(), hello, move |request| match request {
+            Request::Tip => {
+                counter.tips.fetch_add(1, Ordering::SeqCst);
+                Response::Tip(sync::tip(&chain))
+            }
+            Request::Headers(request) => {
+                let height_of = |hash: &str| chain.iter().position(|block| block.calculate_hash() == hash).map(|height| height as u64);
+                Response::Headers(sync::headers_after(&chain, &request, height_of))
+            }
+            Request::Blocks(range) => {
+                counter.ranges.fetch_add(1, Ordering::SeqCst);
+                Response::Blocks(sync::block_range(&bodies, range))
+            }
+            Request::GetData(request) => {
+                counter.getdata.fetch_add(1, Ordering::SeqCst);
+                let blocks = bodies.iter().filter(|block| request.items.contains(&InvItem::block(block))).cloned().collect();
+                Response::Data(Data { blocks, transactions: vec![] })
+            }
+            Request::Peers => Response::Peers(vec![]),
+        })
+        .await;
+        (addr, hits)
+    }
+
+    /// Configuration of a follower node of `genesis` connecting to `peers`
+    fn config(name: &str, genesis: GenesisSpec, peers: Vec<String>) -> NodeConfig {
+        NodeConfig {
+            node_id: name.to_string(),
+            p2p_peers: peers,
+            node_key: test_key(),
+            peer_keys: vec![test_key().verifying_key(), fake_key().verifying_key()],
+            ..test_support::config(&format!("sync-{}", name), genesis)
+        }
+    }
+
+    /// A follower node of `genesis`, connected to `peers`
+    async fn node(name: &str, genesis: GenesisSpec, peers: Vec<String>) -> Node {
+        let node = Node::new(config(name, genesis, peers)).unwrap();
+        node.connect_peers().await;
+        node
+    }
+
+    /// Nodes of `genesis` listening on loopback, node `i` connecting to the
+    /// nodes `links[i]` lists. The first node seals blocks with `validator`.
+    async fn live_nodes(name: &str, genesis: GenesisSpec, validator: SigningKey, links: &[&[usize]]) -> Vec<Node> {
+        start_nodes(name, genesis, links, |i, config| {
+            if i == 0 {
+                config.validator_key = Some(validator.clone());
+            }
+        })
+        .await
+    }
+
+    /// Nodes linked as in [`live_nodes`], node `i` configured by `configure(i, ..)`
+    async fn start_nodes(name: &str, genesis: GenesisSpec, links: &[&[usize]], configure: impl Fn(usize, &mut NodeConfig)) -> Vec<Node> {
+        let mut listeners = vec![];
+        for _ in links {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+        let addrs: Vec<String> = listeners.iter().map(|listener| listener.local_addr().unwrap().to_string()).collect();
+        // Each node has its own node key, as the nodes that dial in are known by it
+        let keys: Vec<SigningKey> = (0..links.len()).map(|i| SigningKey::from_bytes(&[10 + i as u8; 32])).collect();
+        let mut nodes = vec![];
+        for (i, (listener, peers)) in listeners.into_iter().zip(links).enumerate() {
+            let mut config = config(&format!("{}-{}", name, i), genesis.clone(), peers.iter().map(|&peer| addrs[peer].clone()).collect());
+            config.node_key = keys[i].clone();
+            config.peer_keys = keys.iter().map(SigningKey::verifying_key).collect();
+            configure(i, &mut config);
+            let node = Node::new(config).unwrap();
+            let listening = node.clone();
+            tokio::spawn(async move { listening.listen(listener).await });
+            nodes.push(node);
+        }
+        for node in &nodes {
+            node.connect_peers().await;
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            let expected = links[i].len() + links.iter().filter(|peers| peers.contains(&i)).count();
+            // Peer exchange may link nodes further
+            assert!(eventually(|| node.connections.lock().unwrap().len() >= expected).await, "node {} is not fully connected", i);
+        }
+        nodes
+    }
+
+    /// Wait up to 5 seconds for `condition` to hold
+    async fn eventually(condition: impl Fn() -> bool) -> bool {
+        for _ in 0..100 {
+            if condition() {
+                return true;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        condition()
+    }
+
+    /// Genesis with a single validator, and a valid chain of `blocks` blocks on it
+    fn honest_chain(blocks: usize) -> (GenesisSpec, SigningKey, Vec<Block>) {
+        let validator = generate_keypair();
+        let genesis = GenesisSpec::with_authorities(vec![validator.verifying_key().to_bytes().to_vec()]);
+        let mut chain = Blockchain::from_genesis(genesis.clone()).unwrap();
+        chain.set_validator_key(validator.clone());
+        for _ in 0..blocks {
+            chain.add_block(vec![]).unwrap();
+        }
+        (genesis, validator, chain.chain)
+    }
+
+    /// `blocks` with the blocks from `from` on replaced by other ones
+    fn tampered(blocks: &[Block], from: usize) -> Vec<Block> {
+        let mut blocks = blocks.to_vec();
+        for block in &mut blocks[from..] {
+            block.nonce += 1;
+        }
+        blocks
+    }
+
+    #[tokio::test]
+    async fn test_sync_downloads_ranges_from_every_peer_at_the_tip() {
+        let (genesis, _, blocks) = honest_chain(3 * MAX_BLOCKS_PER_REQUEST as usize);
+        let (first, first_hits) = fake_peer(blocks.clone(), blocks.clone()).await;
+        let (second, second_hits) = fake_peer(blocks.clone(), blocks.clone()).await;
+        let node = node("parallel", genesis, vec![first.clone(), second.clone()]).await;
+
+        node.sync_round().await;
+        assert_eq!(node.blockchain.lock().unwrap().chain.len(), blocks.len());
+        assert_eq!(first_hits.ranges.load(Ordering::SeqCst), 2);
+        assert_eq!(second_hits.ranges.load(Ordering::SeqCst), 1);
+
+        // Nothing is downloaded once the tips are held
+        node.sync_round().await;
+        assert_eq!(first_hits.ranges.load(Ordering::SeqCst) + second_hits.ranges.load(Ordering::SeqCst), 3);
+        assert_eq!(node.peer_scores.lock().unwrap().score(&first), 0);
+        assert_eq!(node.peer_scores.lock().unwrap().score(&second), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sync_only_fetches_missing_blocks() {
+        let (genesis, _, blocks) = honest_chain(4);
+        let (peer, hits) = fake_peer(blocks.clone(), blocks[..3].to_vec()).await;
+        let node = node("incremental", genesis, vec![peer.clone()]).await;
+        for block in &blocks[1..3] {
+            node.blockchain.lock().unwrap().append_block(block.clone()).unwrap();
+        }
+
+        // The peer only serves the blocks the node already has, so the range asked for is 3..5
+        node.sync_round().await;
+        assert_eq!(node.blockchain.lock().unwrap().chain.len(), 3);
+        assert_eq!(hits.ranges.load(Ordering::SeqCst), 1);
+        assert_eq!(node.peer_scores.lock().unwrap().score(&peer), 0, "Serving fewer blocks is not misbehavior");
+    }
+
+    #[tokio::test]
+    async fn test_sync_bans_peers_serving_invalid_chains() {
+        let (genesis, validator, blocks) = honest_chain(2);
+
+        let (_, _, other_genesis) = honest_chain(3);
+        let mut forged_seal = blocks.clone();
+        forged_seal[1].sign(&generate_keypair());
+        let mut broken_link = blocks.clone();
+        broken_link[2].prev_hash = "00".repeat(32);
+        broken_link[2].sign(&validator);
+
+        let mut peers = vec![];
+        for chain in [other_genesis, forged_seal, broken_link] {
+            peers.push(fake_peer(chain.clone(), chain).await);
+        }
+        let garbage_hits = Arc::new(Hits::default());
+        let counter = garbage_hits.clone();
+        let garbage = serve(fake_key(), fake_hello(&blocks, 0), move |request| match request {
+            Request::Peers => Response::Peers(vec![]),
+            _ => {
+                counter.tips.fetch_add(1, Ordering::SeqCst);
+                Response::Blocks(vec![])
+            }
+        })
+        .await;
+        peers.push((garbage, garbage_hits));
+        let node = node("adversarial", genesis, peers.iter().map(|(addr, _)| addr.clone()).collect()).await;
+
+        node.sync_round().await;
+        node.sync_round().await;
+        assert_eq!(node.blockchain.lock().unwrap().chain.len(), 1, "No block of an invalid chain should be adopted");
+        let scores = node.peer_scores.lock().unwrap();
+        for (i, (addr, hits)) in peers[..3].iter().enumerate() {
+            assert!(scores.is_banned(addr), "{} should be banned", addr);
+            // A peer on another genesis is refused at the handshake, before any request
+            let asked = if i == 0 { 0 } else { 1 };
+            assert_eq!(hits.tips.load(Ordering::SeqCst), asked, "Banned peers should not be asked again");
+            assert_eq!(hits.ranges.load(Ordering::SeqCst), 0, "Invalid headers should stop the sync before any download");
+            assert!(node.connection(addr).is_none(), "Banned peers are disconnected");
+        }
+        let (garbage, hits) = &peers[3];
+        assert!(!scores.is_banned(garbage));
+        assert_eq!(scores.score(garbage), -50);
+        assert_eq!(hits.tips.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_refetches_ranges_a_source_lied_about() {
+        let (genesis, _, blocks) = honest_chain(3 * MAX_BLOCKS_PER_REQUEST as usize);
+        let (honest, _) = fake_peer(blocks.clone(), blocks.clone()).await;
+        let (liar, liar_hits) = fake_peer(blocks.clone(), tampered(&blocks, 1)).await;
+        let node = node("liar", genesis, vec![honest.clone(), liar.clone()]).await;
+
+        node.sync_round().await;
+        assert_eq!(node.blockchain.lock().unwrap().chain.len(), blocks.len());
+        assert_eq!(liar_hits.ranges.load(Ordering::SeqCst), 1);
+        let scores = node.peer_scores.lock().unwrap();
+        assert!(scores.is_banned(&liar));
+        assert_eq!(scores.score(&honest), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sync_keeps_blocks_before_one_that_does_not_match_its_header() {
+        let (genesis, _, blocks) = honest_chain(3);
+        let (peer, _) = fake_peer(blocks.clone(), tampered(&blocks, 3)).await;
+        let node = node("bad-tail", genesis, vec![peer.clone()]).await;
+
+        node.sync_round().await;
+        let blockchain = node.blockchain.lock().unwrap();
+        assert_eq!(blockchain.chain.len(), 3);
+        assert_eq!(blockchain.validate_chain(), Ok(()));
+        assert!(node.peer_scores.lock().unwrap().is_banned(&peer));
+    }
+
+    #[tokio::test]
+    async fn test_announced_blocks_reach_every_node_without_polling() {
+        let (genesis, validator, _) = honest_chain(0);
+        // A line: the block must be relayed by the middle node, over the connections either end opened
+        let nodes = live_nodes("announce-block", genesis, validator, &[&[1], &[2], &[]]).await;
+
+        let block = nodes[0].blockchain.lock().unwrap().add_block(vec![]).unwrap().clone();
+        nodes[0].announce(vec![InvItem::block(&block)], None);
+        for node in &nodes[1..] {
+            assert!(eventually(|| node.blockchain.lock().unwrap().chain.len() == 2).await, "{} never got the block", node.config.node_id);
+            assert_eq!(node.blockchain.lock().unwrap().chain[1].calculate_hash(), block.calculate_hash());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_announced_transactions_reach_every_node() {
+        let (mut genesis, validator, _) = honest_chain(0);
+        let doctor = generate_keypair();
+        genesis.doctors.push(GenesisDoctor {
+            doctor_id: "doctor1".to_string(),
+            license_number: "MD-1".to_string(),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        });
+        let nodes = live_nodes("announce-tx", genesis.clone(), validator, &[&[1], &[2], &[]]).await;
+        let mut tx = Transaction {
+            chain_id: genesis.chain_id.clone(),
+            doctor_id: "doctor1".to_string(),
+            patient_id: "patient1".to_string(),
+            drug: "Aspirin".to_string(),
+            dosage: "1 tablet daily".to_string(),
+            issued_at: 1_700_000_000,
+            expires_at: 1_702_592_000,
+            nonce: 1,
+            signature: vec![],
+            pubkey: vec![],
+        };
+        tx.sign(&doctor);
+        let id = tx.id();
+
+        nodes[2].submit_transaction(tx.clone(), None).unwrap();
+        for node in &nodes[..2] {
+            assert!(eventually(|| node.mempool.lock().unwrap().contains(&id)).await);
+        }
+        tx.drug = "Oxycodone".to_string();
+        assert!(matches!(nodes[2].submit_transaction(tx, None), Err(MempoolError::Invalid(ValidationError::BadSignature { .. }))));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_announcements_are_fetched_once() {
+        let (genesis, _, blocks) = honest_chain(1);
+        let (peer, hits) = fake_peer(blocks.clone(), blocks.clone()).await;
+        let node = node("announce-dedup", genesis, vec![peer.clone()]).await;
+        let items = vec![InvItem::block(&blocks[1])];
+
+        node.handle_inventory(&peer, items.clone()).await;
+        node.handle_inventory(&peer, items).await;
+        assert_eq!(node.blockchain.lock().unwrap().chain.len(), 2);
+        assert_eq!(hits.getdata.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_announced_block_with_unknown_parent_triggers_catch_up() {
+        let (genesis, _, blocks) = honest_chain(5);
+        let (peer, hits) = fake_peer(blocks.clone(), blocks.clone()).await;
+        let node = node("announce-gap", genesis, vec![peer.clone()]).await;
+
+        node.handle_inventory(&peer, vec![InvItem::block(&blocks[5])]).await;
+        assert_eq!(node.blockchain.lock().unwrap().chain.len(), 6);
+        assert_eq!(hits.ranges.load(Ordering::SeqCst), 1, "The missing blocks are synced headers-first");
+        assert_eq!(node.peer_scores.lock().unwrap().score(&peer), 0);
+    }
+
+    #[tokio::test]
+    async fn test_peers_ahead_are_synced_on_connect() {
+        let (genesis, _, blocks) = honest_chain(4);
+        let (peer, hits) = fake_peer_at(blocks.clone(), blocks.clone(), 4).await;
+        let node = node("handshake-ahead", genesis, vec![peer.clone()]).await;
+
+        assert!(eventually(|| node.blockchain.lock().unwrap().chain.len() == 5).await);
+        assert_eq!(hits.tips.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_frames_disconnect_and_penalize() {
+        let (genesis, _, blocks) = honest_chain(0);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let hello = fake_hello(&blocks, 0);
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let identity = Identity::new(fake_key(), &[test_key().verifying_key()]);
+            let (_, mut secured) = p2p::handshake(&mut stream, &hello, &identity, false).await.unwrap();
+            let garbage = b"not a message";
+            let frame = [&(garbage.len() as u32).to_be_bytes()[..], garbage].concat();
+            stream.write_all(&secured.sealer.seal(&frame)).await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+        });
+        let node = node("malformed-frame", genesis, vec![addr.clone()]).await;
+
+        assert!(eventually(|| node.connection(&addr).is_none()).await);
+        assert_eq!(node.peer_scores.lock().unwrap().score(&addr), -25);
+    }
+
+    #[tokio::test]
+    async fn test_peers_with_keys_not_allowed_are_refused() {
+        let (genesis, _, blocks) = honest_chain(0);
+        let stranger = generate_keypair();
+        let addr = serve(stranger.clone(), fake_hello(&blocks, 0), |_| Response::Blocks(vec![])).await;
+        let node = node("not-allowed", genesis, vec![addr.clone()]).await;
+        assert!(node.connection(&addr).is_none(), "Nodes do not connect to peers missing from PEER_KEYS");
+        assert_eq!(node.peer_scores.lock().unwrap().score(&addr), 0, "A key missing from PEER_KEYS is not misbehavior");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listen_addr = listener.local_addr().unwrap();
+        let listening = node.clone();
+        tokio::spawn(async move { listening.listen(listener).await });
+        let mut stream = TcpStream::connect(listen_addr).await.unwrap();
+        let identity = Identity::new(stranger, &[test_key().verifying_key()]);
+        assert!(p2p::handshake(&mut stream, &fake_hello(&blocks, 0), &identity, true).await.is_err());
+        assert!(node.connections.lock().unwrap().is_empty(), "Nodes accept no connection from peers missing from PEER_KEYS");
+    }
+
+    #[tokio::test]
+    async fn test_raft_messages_travel_over_p2p_links() {
+        let (genesis, validator, _) = honest_chain(0);
+        let id = |i: usize| format!("raft-link-{}", i);
+        let nodes = start_nodes("raft-link", genesis, &[&[1, 2], &[2], &[]], |i, config| {
+            config.consensus = ConsensusMode::Raft;
+            config.validator_key = Some(validator.clone());
+            config.peers = (0..3).filter(|&j| j != i).map(id).collect();
+        })
+        .await;
+        for node in &nodes {
+            node.start_raft();
+        }
+
+        let agreed = || {
+            let leaders: Vec<Option<String>> =
+                nodes.iter().map(|node| node.raft.as_ref().unwrap().lock().unwrap().leader().map(str::to_string)).collect();
+            leaders[0].is_some() && leaders.iter().all(|leader| leader == &leaders[0])
+        };
+        assert!(eventually(agreed).await, "The nodes elect a leader over their encrypted links");
+    }
+
+    #[tokio::test]
+    async fn test_peers_learned_through_exchange_are_dialed() {
+        let (genesis, validator, _) = honest_chain(0);
+        let nodes = live_nodes("exchange", genesis, validator, &[&[], &[0, 2], &[]]).await;
+        let (own, other) = (nodes[1].config.p2p_peers[0].clone(), nodes[1].config.p2p_peers[1].clone());
+        nodes[0].exchange_peers().await;
+        assert!(nodes[0].peer_table.lock().unwrap().get(&other).is_some(), "Node 1 shares the address of node 2");
+
+        nodes[0].connect_peers().await;
+        assert!(nodes[0].connection(&other).is_some());
+        assert!(nodes[0].peer_table.lock().unwrap().get(&own).is_none(), "Nodes forget their own address");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_peers_are_dialed_with_backoff() {
+        let (genesis, _, _) = honest_chain(0);
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+        let node = node("backoff", genesis, vec![closed.clone()]).await;
+        assert_eq!(node.peer_table.lock().unwrap().get(&closed).unwrap().failures, 1);
+        assert!(node.peer_table.lock().unwrap().due().is_empty(), "Failed peers wait before the next dial");
+    }
+
+    #[tokio::test]
+    async fn test_peer_table_is_managed_at_runtime_and_survives_restarts() {
+        let (genesis, _, blocks) = honest_chain(0);
+        let (addr, _) = fake_peer(blocks.clone(), blocks).await;
+        let config = config("peer-table", genesis, vec![]);
+        let node = Node::new(config.clone()).unwrap();
+        assert!(node.add_peer("not an address").is_err());
+        assert_eq!(node.add_peer(&addr), Ok(true));
+        assert!(eventually(|| node.connection(&addr).is_some()).await, "Added peers are dialed right away");
+        assert_eq!(node.add_peer(&addr), Ok(false));
+        assert_eq!(node.add_peer("node9:9000"), Ok(true));
+        assert!(node.remove_peer("node9:9000"));
+        assert!(!node.remove_peer("node9:9000"));
+
+        node.penalize(&addr, Misbehavior::MalformedResponse);
+        node.save_peer_table();
+        let statuses = node.peer_statuses();
+        assert_eq!(statuses.len(), 1);
+        assert!(statuses[0].connected && !statuses[0].banned);
+        assert_eq!(statuses[0].entry.score, -25);
+
+        let restarted = Node::new(config).unwrap();
+        let entry = restarted.peer_table.lock().unwrap().get(&addr).cloned().unwrap();
+        assert_eq!(entry.source, PeerSource::Admin);
+        assert!(entry.last_seen.is_some());
+        assert_eq!(restarted.peer_scores.lock().unwrap().score(&addr), -25, "Scores are restored from the peer table");
+        assert!(restarted.peer_table.lock().unwrap().get("node9:9000").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_banned_keys_are_refused_under_another_node_id() {
+        let (genesis, _, blocks) = honest_chain(0);
+        let node = node("banned-key", genesis, vec![]).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listen_addr = listener.local_addr().unwrap();
+        let listening = node.clone();
+        tokio::spawn(async move { listening.listen(listener).await });
+        let identity = Identity::new(fake_key(), &[test_key().verifying_key()]);
+        let key = hex::encode(fake_key().verifying_key().to_bytes());
+
+        let mut first = TcpStream::connect(listen_addr).await.unwrap();
+        p2p::handshake(&mut first, &fake_hello(&blocks, 0), &identity, true).await.unwrap();
+        assert!(eventually(|| node.connection(&key).is_some()).await, "Inbound peers are known by their node key");
+        node.penalize(&key, Misbehavior::InvalidBlock);
+        assert!(eventually(|| node.connection(&key).is_none()).await);
+
+        let mut renamed = fake_hello(&blocks, 0);
+        renamed.node_id = "innocent".to_string();
+        let mut second = TcpStream::connect(listen_addr).await.unwrap();
+        let (_, mut secured) = p2p::handshake(&mut second, &renamed, &identity, true).await.unwrap();
+        let refused = timeout(Duration::from_secs(5), p2p::read_frame(&mut second, &mut secured.opener)).await;
+        assert!(matches!(refused, Ok(Err(_))), "The node hangs up on a banned key");
+        assert!(node.connections.lock().unwrap().is_empty(), "A new node_id does not lift a ban");
+    }
+
+    /// A peer on `blocks` that handshakes, then acts on its connection with `behave`
+    async fn scripted_peer<F: Future<Output = ()> + Send>(
+        blocks: &[Block],
+        behave: impl FnOnce(Connection, mpsc::Receiver<Incoming>) -> F + Send + 'static,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let hello = fake_hello(blocks, 0);
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let identity = Identity::new(fake_key(), &[test_key().verifying_key()]);
+            let (theirs, secured) = p2p::handshake(&mut stream, &hello, &identity, false).await.unwrap();
+            let (connection, incoming) = Connection::start(stream, secured, theirs.node_id.clone(), theirs, true);
+            behave(connection, incoming).await;
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_peers_leaving_requests_unanswered_are_penalized() {
+        let (genesis, _, blocks) = honest_chain(0);
+        let addr = scripted_peer(&blocks, |connection, mut incoming| async move {
+            // Take requests in, never answer them
+            while incoming.recv().await.is_some() {}
+            drop(connection);
+        })
+        .await;
+        let node = node("slow-peer", genesis, vec![addr.clone()]).await;
+
+        assert!(node.fetch_tip(&addr).await.is_none());
+        assert_eq!(node.peer_scores.lock().unwrap().score(&addr), -10);
+        assert!(node.connection(&addr).is_some(), "Slow peers stay connected until banned");
+    }
+
+    #[tokio::test]
+    async fn test_flooding_peers_are_disconnected_and_bans_survive_restarts() {
+        let (genesis, _, blocks) = honest_chain(0);
+        let addr = scripted_peer(&blocks, |connection, incoming| async move {
+            for _ in 0..=p2p::MAX_MESSAGES_PER_SECOND {
+                connection.send(Message::Inv(vec![]));
+            }
+            sleep(Duration::from_secs(1)).await;
+            drop(incoming);
+        })
+        .await;
+        let config = config("flood", genesis, vec![addr.clone()]);
+        let node = Node::new(config.clone()).unwrap();
+        node.connect_peers().await;
+
+        assert!(eventually(|| node.connection(&addr).is_none()).await);
+        assert_eq!(node.peer_scores.lock().unwrap().score(&addr), -20);
+        node.penalize(&addr, Misbehavior::InvalidBlock);
+        assert!(node.peer_scores.lock().unwrap().is_banned(&addr));
+
+        let restarted = Node::new(config).unwrap();
+        assert!(restarted.peer_scores.lock().unwrap().is_banned(&addr), "Bans are restored after a restart");
+        restarted.connect_peers().await;
+        assert!(restarted.connection(&addr).is_none(), "Banned peers are not dialed");
     }
 }
@@ -1,18 +1,175 @@
-use crate::config::NodeConfig;
+use crate::announce::SeenCache;
+use crate::bft::{BftEngine, BftTimeouts};
+use crate::config::{ConsensusMode, NodeConfig};
+use crate::noise::Identity;
+use crate::p2p::Connection;
+use crate::peer_table::{PeerSource, PeerTable, PEER_TABLE_FILE};
+use crate::peers::{Misbehavior, PeerScores, BANS_FILE};
+use crate::raft::{PersistentState, RaftConfig, RaftNode};
+use securerx_core::crypto::random_nonce;
+use securerx_core::fork_choice::{BlockTree, Reorg};
+use securerx_core::mempool::Mempool;
+use securerx_core::transaction::Transaction;
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use securerx_core::blockchain::Blockchain;
+use tokio::sync::{broadcast, Notify};
+use securerx_core::blockchain::{Blockchain, ChainError};
+use securerx_core::storage::{open_store, StorageError};
 
 #[derive(Clone)]
 pub struct Node {
     pub config: NodeConfig,
     pub blockchain: Arc<Mutex<Blockchain>>,
+    /// Competing branches received from peers; lock `blockchain` first
+    pub fork_choice: Arc<Mutex<BlockTree>>,
+    /// Scores and bans of the peers, bans saved under `data_dir`, see [`crate::peers`]
+    pub peer_scores: Arc<Mutex<PeerScores>>,
+    /// Keys the P2P links are authenticated with, see [`crate::noise`]
+    pub identity: Arc<Identity>,
+    /// Known P2P addresses, saved under `data_dir`, see [`crate::peer_table`]
+    pub peer_table: Arc<Mutex<PeerTable>>,
+    /// P2P connections by the name their peer is scored under
+    pub connections: Arc<Mutex<HashMap<String, Connection>>>,
+    /// Announced items seen, see [`crate::announce`]
+    pub seen: Arc<Mutex<SeenCache>>,
+    /// Prescriptions waiting for a block, see [`securerx_core::mempool`]; lock `blockchain` first
+    pub mempool: Arc<Mutex<Mempool>>,
+    /// Wakes the block producer once a full block is pending, see
+    /// [`Node::start_producer`] and [`Node::produce_block`]
+    pub(crate) block_ready: Arc<Notify>,
+    /// Every switch of the chain to a competing branch, see [`Node::subscribe_reorgs`]
+    reorgs: broadcast::Sender<Reorg>,
+    /// BFT consensus state, when running in BFT mode as a validator
+    pub bft: Option<Arc<Mutex<BftEngine>>>,
+    /// Raft replication state, when running in Raft mode
+    pub raft: Option<Arc<Mutex<RaftNode>>>,
 }
 
 impl Node {
-    pub fn new(config: NodeConfig) -> Self {
-        Self {
+    /// Load the chain persisted under `config.data_dir`, starting a new one if it is empty,
+    /// and seal proposed blocks with `config.validator_key`
+    pub fn new(config: NodeConfig) -> Result<Self, ChainError> {
+        let store = open_store(Path::new(&config.data_dir), config.storage_backend)?;
+        let mut blockchain = Blockchain::open(config.genesis.clone(), store)?;
+        let mempool = Arc::new(Mutex::new(Mempool::new(config.mempool_size)));
+        let mut bft = None;
+        if let Some(key) = &config.validator_key {
+            if !blockchain.validators().contains(&key.verifying_key().to_bytes().to_vec()) {
+                eprintln!("WARNING: VALIDATOR_KEY is not a genesis authority, this node will not propose blocks");
+            } else if config.consensus == ConsensusMode::Bft {
+                let engine = BftEngine::new(key.clone(), BftTimeouts::default(), mempool.clone(), config.max_block_txs);
+                bft = Some(Arc::new(Mutex::new(engine)));
+            }
+            blockchain.set_validator_key(key.clone());
+        }
+        let mut raft = None;
+        if config.consensus == ConsensusMode::Raft {
+            if config.validator_key.is_none() {
+                eprintln!("WARNING: no VALIDATOR_KEY, this node cannot seal blocks if elected Raft leader");
+            }
+            let state = PersistentState::load(&Path::new(&config.data_dir).join("raft.json")).map_err(StorageError::from)?;
+            let node = RaftNode::new(config.node_id.clone(), config.peers.clone(), RaftConfig::default(), state, random_nonce());
+            raft = Some(Arc::new(Mutex::new(node)));
+        }
+        if config.peer_keys.is_empty() {
+            eprintln!("WARNING: PEER_KEYS is empty, no other node can connect to this one");
+        }
+        let identity = Identity::new(config.node_key.clone(), &config.peer_keys);
+        let mut peer_table = PeerTable::load(&Path::new(&config.data_dir).join(PEER_TABLE_FILE)).map_err(StorageError::from)?;
+        for addr in &config.p2p_peers {
+            peer_table.add(addr, PeerSource::Config);
+        }
+        let mut peer_scores = PeerScores::default();
+        for entry in peer_table.entries() {
+            peer_scores.restore(&entry.addr, entry.score);
+        }
+        peer_scores.load_bans(&Path::new(&config.data_dir).join(BANS_FILE)).map_err(StorageError::from)?;
+        crate::metrics::PEERS_BANNED.set(peer_scores.banned_count() as i64);
+        crate::metrics::PEERS_KNOWN.set(peer_table.len() as i64);
+        crate::metrics::CHAIN_HEIGHT.set(blockchain.chain.len() as i64);
+        let fork_choice = BlockTree::new(config.fork_choice.rule());
+        Ok(Self {
             config,
-            blockchain: Arc::new(Mutex::new(Blockchain::new())),
+            blockchain: Arc::new(Mutex::new(blockchain)),
+            fork_choice: Arc::new(Mutex::new(fork_choice)),
+            peer_scores: Arc::new(Mutex::new(peer_scores)),
+            identity: Arc::new(identity),
+            peer_table: Arc::new(Mutex::new(peer_table)),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            seen: Arc::new(Mutex::new(SeenCache::default())),
+            mempool,
+            block_ready: Arc::new(Notify::new()),
+            reorgs: broadcast::channel(REORG_EVENTS).0,
+            bft,
+            raft,
+        })
+    }
+
+    /// Receive every later switch of the chain to a competing branch, e.g. to
+    /// resubmit the prescriptions of reverted blocks
+    pub fn subscribe_reorgs(&self) -> broadcast::Receiver<Reorg> {
+        self.reorgs.subscribe()
+    }
+
+    /// Lower the score of `peer` for misbehaving, banning it and hanging up
+    /// on it at the threshold
+    pub(crate) fn penalize(&self, peer: &str, misbehavior: Misbehavior) {
+        let (banned, score) = {
+            let mut peer_scores = self.peer_scores.lock().unwrap();
+            (peer_scores.penalize(peer, misbehavior), peer_scores.score(peer))
+        };
+        crate::metrics::PEER_PENALTIES.with_label_values(&[misbehavior.name()]).inc();
+        crate::metrics::PEER_SCORE.with_label_values(&[peer]).set(score);
+        if banned {
+            eprintln!("Banned peer {} for {:?}", peer, misbehavior);
+            crate::metrics::PEER_BANS.inc();
+            if let Some(connection) = self.connections.lock().unwrap().get(peer) {
+                connection.close();
+            }
+            self.save_bans();
         }
     }
+
+    /// Save the current bans under `data_dir`, so a restart does not lift them
+    pub(crate) fn save_bans(&self) {
+        let peer_scores = self.peer_scores.lock().unwrap();
+        crate::metrics::PEERS_BANNED.set(peer_scores.banned_count() as i64);
+        if let Err(e) = peer_scores.save_bans(&Path::new(&self.config.data_dir).join(BANS_FILE)) {
+            eprintln!("Failed to save the bans: {}", e);
+        }
+    }
+
+    /// Bring the peer score metrics up to date with the points won back and the bans lifted
+    pub(crate) fn update_score_metrics(&self) {
+        let peer_scores = self.peer_scores.lock().unwrap();
+        crate::metrics::PEER_SCORE.reset();
+        for (peer, score) in peer_scores.scores() {
+            crate::metrics::PEER_SCORE.with_label_values(&[&peer]).set(score);
+        }
+        crate::metrics::PEERS_BANNED.set(peer_scores.banned_count() as i64);
+    }
+
+    /// Drop the pending prescriptions `blockchain` now includes, and return
+    /// the `reverted` ones of blocks a reorg rolled back to the mempool
+    pub(crate) fn refresh_mempool(&self, blockchain: &Blockchain, reverted: Vec<Transaction>) {
+        let mut mempool = self.mempool.lock().unwrap();
+        mempool.remove_confirmed(blockchain);
+        for tx in reverted {
+            // Prescriptions the new branch includes too are refused as duplicates
+            let _ = mempool.insert(tx, 0, blockchain);
+        }
+        crate::metrics::MEMPOOL_SIZE.set(mempool.len() as i64);
+    }
+
+    /// Record a switch to a competing branch and notify subscribers
+    pub(crate) fn emit_reorg(&self, reorg: Reorg) {
+        crate::metrics::REORGS.inc();
+        crate::metrics::LAST_REORG_DEPTH.set(reorg.depth() as i64);
+        // Without subscribers the event is only counted
+        let _ = self.reorgs.send(reorg);
+    }
 }
+
+/// Reorg events kept for subscribers that fall behind
+const REORG_EVENTS: usize = 16;
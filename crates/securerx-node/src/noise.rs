@@ -0,0 +1,308 @@
+//! Encryption and mutual authentication of node links.
+//!
+//! Every P2P connection starts with a Noise `XX` handshake
+//! ([`NOISE_PARAMS`]), after which all traffic is encrypted and authenticated
+//! with ChaCha20-Poly1305. Each node makes a fresh X25519 key for Noise and
+//! proves it belongs to its Ed25519 node key (`NODE_KEY`) by signing it in
+//! its handshake payload, so the Ed25519 key never doubles as an encryption
+//! key. A peer whose node key is not on the allow-list (`PEER_KEYS`) is
+//! refused before it can send a single message.
+//!
+//! On the wire each Noise message is a 2-byte big-endian length followed by
+//! at most [`MAX_MESSAGE_LEN`] bytes.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use snow::{Builder, StatelessTransportState};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Handshake pattern, key exchange, cipher and hash of every link
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Largest Noise message, in bytes
+pub const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Most plaintext bytes one Noise message carries, the rest being its authentication tag
+pub const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - 16;
+
+/// Mixed into the handshake so only nodes of this protocol complete it
+const PROLOGUE: &[u8] = b"securerx-p2p/1";
+
+/// Prefix of the message a node signs to claim its Noise key
+const KEY_CLAIM_CONTEXT: &[u8] = b"securerx-noise-key:";
+
+/// Why a Noise handshake failed
+#[derive(Debug)]
+pub enum NoiseError {
+    Io(std::io::Error),
+    /// A handshake message was malformed or failed to decrypt
+    Protocol(snow::Error),
+    /// The peer's payload is not its node key signing its Noise key
+    BadKeyClaim,
+    /// The peer's node key, in hex, is not on the allow-list
+    NotAllowed(String),
+}
+
+impl fmt::Display for NoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoiseError::Io(e) => write!(f, "connection error: {}", e),
+            NoiseError::Protocol(e) => write!(f, "noise handshake failed: {}", e),
+            NoiseError::BadKeyClaim => write!(f, "the peer did not prove its node key"),
+            NoiseError::NotAllowed(key) => write!(f, "node key {} is not in PEER_KEYS", key),
+        }
+    }
+}
+
+impl std::error::Error for NoiseError {}
+
+impl From<std::io::Error> for NoiseError {
+    fn from(e: std::io::Error) -> Self {
+        NoiseError::Io(e)
+    }
+}
+
+impl From<snow::Error> for NoiseError {
+    fn from(e: snow::Error) -> Self {
+        NoiseError::Protocol(e)
+    }
+}
+
+/// A node's keys for the handshake and the node keys it accepts
+pub struct Identity {
+    node_key: SigningKey,
+    noise_private: Vec<u8>,
+    noise_public: Vec<u8>,
+    allowed: HashSet<[u8; 32]>,
+}
+
+impl Identity {
+    /// Identity of the node holding `node_key`, accepting peers with one of the `allowed` keys
+    pub fn new(node_key: SigningKey, allowed: &[VerifyingKey]) -> Self {
+        let keypair = builder().generate_keypair().expect("the default resolver generates X25519 keys");
+        Self {
+            node_key,
+            noise_private: keypair.private,
+            noise_public: keypair.public,
+            allowed: allowed.iter().map(VerifyingKey::to_bytes).collect(),
+        }
+    }
+
+    pub fn node_key(&self) -> VerifyingKey {
+        self.node_key.verifying_key()
+    }
+
+    /// Our node key and its signature over our Noise key
+    fn key_claim(&self) -> Vec<u8> {
+        let signature = self.node_key.sign(&claimed(&self.noise_public));
+        let mut payload = self.node_key.verifying_key().to_bytes().to_vec();
+        payload.extend_from_slice(&signature.to_bytes());
+        payload
+    }
+
+    /// The allowed node key that signed the peer's Noise key `noise_key` in `payload`
+    fn check_claim(&self, payload: &[u8], noise_key: &[u8]) -> Result<VerifyingKey, NoiseError> {
+        if payload.len() != 32 + 64 {
+            return Err(NoiseError::BadKeyClaim);
+        }
+        let (key, signature) = payload.split_at(32);
+        let key = VerifyingKey::try_from(key).map_err(|_| NoiseError::BadKeyClaim)?;
+        let signature = Signature::from_slice(signature).map_err(|_| NoiseError::BadKeyClaim)?;
+        key.verify(&claimed(noise_key), &signature).map_err(|_| NoiseError::BadKeyClaim)?;
+        if !self.allowed.contains(&key.to_bytes()) {
+            return Err(NoiseError::NotAllowed(hex::encode(key.to_bytes())));
+        }
+        Ok(key)
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity").field("node_key", &hex::encode(self.node_key().to_bytes())).finish_non_exhaustive()
+    }
+}
+
+/// What a node signs to claim `noise_key`
+fn claimed(noise_key: &[u8]) -> Vec<u8> {
+    [KEY_CLAIM_CONTEXT, noise_key].concat()
+}
+
+fn builder() -> Builder<'static> {
+    Builder::new(NOISE_PARAMS.parse().expect("NOISE_PARAMS is a valid pattern"))
+}
+
+/// Encrypts our side of a link
+pub struct Sealer {
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl Sealer {
+    /// Encrypt `plaintext` into as many Noise messages as it takes, each
+    /// prefixed with its length, ready to be written to the link
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut wire = Vec::with_capacity(plaintext.len() + plaintext.len() / MAX_PLAINTEXT_LEN * 18 + 18);
+        let mut message = vec![0u8; MAX_MESSAGE_LEN];
+        for chunk in plaintext.chunks(MAX_PLAINTEXT_LEN) {
+            let len = self.transport.write_message(self.nonce, chunk, &mut message).expect("chunks fit in a Noise message");
+            self.nonce += 1;
+            wire.extend_from_slice(&(len as u16).to_be_bytes());
+            wire.extend_from_slice(&message[..len]);
+        }
+        wire
+    }
+}
+
+/// Decrypts the peer's side of a link
+pub struct Opener {
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl Opener {
+    /// Read the next Noise message from `reader` and decrypt it. Messages
+    /// that were tampered with, replayed or reordered fail to decrypt.
+    pub async fn open<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> Result<Vec<u8>, NoiseError> {
+        let message = read_message(reader).await?;
+        let mut plaintext = vec![0u8; message.len()];
+        let len = self.transport.read_message(self.nonce, &message, &mut plaintext)?;
+        self.nonce += 1;
+        plaintext.truncate(len);
+        Ok(plaintext)
+    }
+}
+
+/// Both directions of a link after the handshake
+pub struct Secured {
+    pub sealer: Sealer,
+    pub opener: Opener,
+    /// The peer's node key, checked against the allow-list
+    pub peer_key: VerifyingKey,
+}
+
+impl fmt::Debug for Secured {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Secured").field("peer_key", &hex::encode(self.peer_key.to_bytes())).finish_non_exhaustive()
+    }
+}
+
+/// Run the Noise handshake on `stream`, as the node that dialed if `initiator`
+pub async fn handshake<S>(stream: &mut S, identity: &Identity, initiator: bool) -> Result<Secured, NoiseError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let builder = builder().local_private_key(&identity.noise_private).prologue(PROLOGUE);
+    let mut buffer = vec![0u8; MAX_MESSAGE_LEN];
+    let mut payload = vec![0u8; MAX_MESSAGE_LEN];
+    // -> e; <- e, ee, s, es + responder's claim; -> s, se + initiator's claim
+    let (state, peer_key) = if initiator {
+        let mut state = builder.build_initiator()?;
+        let len = state.write_message(&[], &mut buffer)?;
+        write_message(stream, &buffer[..len]).await?;
+        let len = state.read_message(&read_message(stream).await?, &mut payload)?;
+        let peer_key = identity.check_claim(&payload[..len], state.get_remote_static().unwrap_or_default())?;
+        let len = state.write_message(&identity.key_claim(), &mut buffer)?;
+        write_message(stream, &buffer[..len]).await?;
+        (state, peer_key)
+    } else {
+        let mut state = builder.build_responder()?;
+        state.read_message(&read_message(stream).await?, &mut payload)?;
+        let len = state.write_message(&identity.key_claim(), &mut buffer)?;
+        write_message(stream, &buffer[..len]).await?;
+        let len = state.read_message(&read_message(stream).await?, &mut payload)?;
+        let peer_key = identity.check_claim(&payload[..len], state.get_remote_static().unwrap_or_default())?;
+        (state, peer_key)
+    };
+    let transport = Arc::new(state.into_stateless_transport_mode()?);
+    Ok(Secured {
+        sealer: Sealer { transport: transport.clone(), nonce: 0 },
+        opener: Opener { transport, nonce: 0 },
+        peer_key,
+    })
+}
+
+/// Write one length-prefixed Noise message
+async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(message.len() as u16).to_be_bytes()).await?;
+    writer.write_all(message).await?;
+    writer.flush().await
+}
+
+/// Read one length-prefixed Noise message
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    reader.read_exact(&mut len).await?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use securerx_core::crypto::generate_keypair;
+
+    /// Handshake results of a node holding `a` dialing one holding `b`
+    async fn connect(a: &Identity, b: &Identity) -> (Result<Secured, NoiseError>, Result<Secured, NoiseError>) {
+        let (mut left, mut right) = tokio::io::duplex(4096);
+        // Each side hangs up as soon as its handshake ends, failed or not
+        tokio::join!(
+            async move { handshake(&mut left, a, true).await },
+            async move { handshake(&mut right, b, false).await },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_handshake_authenticates_both_node_keys() {
+        let (a, b) = (generate_keypair(), generate_keypair());
+        let alice = Identity::new(a.clone(), &[b.verifying_key()]);
+        let bob = Identity::new(b.clone(), &[a.verifying_key()]);
+        let (dialer, listener) = connect(&alice, &bob).await;
+        let (mut dialer, mut listener) = (dialer.unwrap(), listener.unwrap());
+        assert_eq!(dialer.peer_key, b.verifying_key());
+        assert_eq!(listener.peer_key, a.verifying_key());
+
+        let secret = b"prescription for patient 42";
+        let wire = dialer.sealer.seal(secret);
+        assert!(!wire.windows(secret.len()).any(|window| window == secret), "Traffic must be encrypted");
+        assert_eq!(listener.opener.open(&mut wire.as_slice()).await.unwrap(), secret);
+
+        let long = vec![7u8; MAX_PLAINTEXT_LEN * 2 + 1];
+        let mut wire = listener.sealer.seal(&long);
+        wire.extend(listener.sealer.seal(b"next"));
+        let mut reader = wire.as_slice();
+        let mut opened = vec![];
+        for _ in 0..3 {
+            opened.extend(dialer.opener.open(&mut reader).await.unwrap());
+        }
+        assert_eq!(opened, long);
+        assert_eq!(dialer.opener.open(&mut reader).await.unwrap(), b"next");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_refuses_keys_not_allowed() {
+        let (a, b) = (generate_keypair(), generate_keypair());
+        let alice = Identity::new(a.clone(), &[b.verifying_key()]);
+        let stranger = Identity::new(b.clone(), &[]);
+        let (dialer, listener) = connect(&alice, &stranger).await;
+        assert!(dialer.is_ok(), "The dialer accepted an allowed listener");
+        assert!(matches!(listener, Err(NoiseError::NotAllowed(key)) if key == hex::encode(a.verifying_key().to_bytes())));
+
+        let (dialer, _) = connect(&stranger, &alice).await;
+        assert!(matches!(dialer, Err(NoiseError::NotAllowed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_messages_fail_to_open() {
+        let (a, b) = (generate_keypair(), generate_keypair());
+        let alice = Identity::new(a.clone(), &[b.verifying_key()]);
+        let bob = Identity::new(b, &[a.verifying_key()]);
+        let (dialer, listener) = connect(&alice, &bob).await;
+        let (mut dialer, mut listener) = (dialer.unwrap(), listener.unwrap());
+        let mut wire = dialer.sealer.seal(b"dose: 5mg");
+        wire[4] ^= 1;
+        assert!(matches!(listener.opener.open(&mut wire.as_slice()).await, Err(NoiseError::Protocol(_))));
+    }
+}
@@ -0,0 +1,636 @@
+//! Framed TCP transport between nodes.
+//!
+//! Nodes keep one TCP connection per peer, accepted on `P2P_ADDR` or dialed
+//! from `P2P_PEERS`. Connections are encrypted and both ends authenticated by
+//! their node keys, see [`crate::noise`]. Every message is a frame: a 4-byte
+//! big-endian length, then that many bytes of JSON, at most
+//! [`MAX_FRAME_SIZE`], sealed into Noise messages. Both ends open with a
+//! [`Hello`] and hang up unless the protocol version, chain id and genesis
+//! hash match. Requests carry an id their response echoes, so several
+//! can be in flight on one connection. Each end pings the other every
+//! [`PING_INTERVAL`] and drops a connection silent for [`IDLE_TIMEOUT`], or
+//! flooding it with more than [`MAX_MESSAGES_PER_SECOND`].
+
+use crate::announce::{Data, GetData, InvItem};
+use crate::bft::ConsensusMessage;
+use crate::noise::{self, Identity, NoiseError, Opener, Sealer, Secured};
+use crate::peers::Misbehavior;
+use crate::raft::RaftMessage;
+use crate::sync::{BlockRange, ChainTip, HeadersRequest};
+use securerx_core::block::{Block, BlockHeader};
+use ed25519_dalek::VerifyingKey;
+use securerx_core::blockchain::Blockchain;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+
+/// Version of the messages below; peers must speak the same one
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Largest frame accepted, in bytes
+pub const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
+/// How long a peer has to complete the Noise handshake and send its [`Hello`]
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a request waits for its response
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often each end of a connection pings the other
+pub const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a connection may stay silent before it is dropped
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Most connections accepted from other nodes
+pub const MAX_INBOUND: usize = 32;
+
+/// Most connections a node opens to its configured peers
+pub const MAX_OUTBOUND: usize = 8;
+
+/// Most requests, announcements and consensus messages a peer may send per
+/// second before it is disconnected for flooding
+pub const MAX_MESSAGES_PER_SECOND: u32 = 200;
+
+/// Frames queued for sending on a connection before further ones are dropped
+const SEND_QUEUE: usize = 256;
+
+/// First message on a connection, in both directions
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Hello {
+    pub version: u32,
+    pub node_id: String,
+    pub chain_id: String,
+    pub genesis_hash: String,
+    /// Index of the sender's last block
+    pub best_height: u64,
+}
+
+impl Hello {
+    /// Greeting of node `node_id` holding `chain`
+    pub fn new(node_id: &str, chain: &Blockchain) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            node_id: node_id.to_string(),
+            chain_id: chain.genesis().chain_id.clone(),
+            genesis_hash: chain.genesis_hash(),
+            best_height: chain.chain.len() as u64 - 1,
+        }
+    }
+
+    /// Check that the node that sent `theirs` is another node of our network
+    pub fn check(&self, theirs: &Hello) -> Result<(), HandshakeError> {
+        if theirs.version != self.version {
+            return Err(HandshakeError::Version(theirs.version));
+        }
+        if theirs.chain_id != self.chain_id {
+            return Err(HandshakeError::ChainId(theirs.chain_id.clone()));
+        }
+        if theirs.genesis_hash != self.genesis_hash {
+            return Err(HandshakeError::Genesis(theirs.genesis_hash.clone()));
+        }
+        if theirs.node_id == self.node_id {
+            return Err(HandshakeError::SelfConnection);
+        }
+        Ok(())
+    }
+}
+
+/// A frame's content
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Message {
+    Hello(Hello),
+    Ping(u64),
+    Pong(u64),
+    Request { id: u64, request: Request },
+    Response { id: u64, response: Response },
+    /// Blocks and transactions the sender just accepted, see [`crate::announce`]
+    Inv(Vec<InvItem>),
+    /// BFT proposal or vote, see [`crate::bft`]
+    Consensus(ConsensusMessage),
+    /// Raft message, see [`crate::raft`]
+    Raft(RaftMessage),
+}
+
+/// What a node asks a peer for
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    Tip,
+    Headers(HeadersRequest),
+    Blocks(BlockRange),
+    GetData(GetData),
+    /// Addresses of other nodes, see [`crate::peer_table`]
+    Peers,
+}
+
+/// Answer to the [`Request`] of the same name
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Tip(ChainTip),
+    Headers(Vec<BlockHeader>),
+    Blocks(Vec<Block>),
+    Data(Data),
+    Peers(Vec<String>),
+}
+
+impl Response {
+    pub fn tip(self) -> Option<ChainTip> {
+        match self {
+            Response::Tip(tip) => Some(tip),
+            _ => None,
+        }
+    }
+
+    pub fn headers(self) -> Option<Vec<BlockHeader>> {
+        match self {
+            Response::Headers(headers) => Some(headers),
+            _ => None,
+        }
+    }
+
+    pub fn blocks(self) -> Option<Vec<Block>> {
+        match self {
+            Response::Blocks(blocks) => Some(blocks),
+            _ => None,
+        }
+    }
+
+    pub fn data(self) -> Option<Data> {
+        match self {
+            Response::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn peers(self) -> Option<Vec<String>> {
+        match self {
+            Response::Peers(addrs) => Some(addrs),
+            _ => None,
+        }
+    }
+}
+
+/// Why a frame could not be read
+#[derive(Debug)]
+pub enum FrameError {
+    /// The connection failed or was closed
+    Io(std::io::Error),
+    /// The announced length exceeds [`MAX_FRAME_SIZE`]
+    TooLarge(usize),
+    /// The frame is not a JSON [`Message`]
+    Malformed(String),
+    /// A Noise message failed to decrypt: it was tampered with, replayed or reordered
+    Decrypt,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "connection error: {}", e),
+            FrameError::TooLarge(len) => write!(f, "frame of {} bytes exceeds the {} byte limit", len, MAX_FRAME_SIZE),
+            FrameError::Malformed(e) => write!(f, "malformed message: {}", e),
+            FrameError::Decrypt => write!(f, "message failed to decrypt"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<std::io::Error> for FrameError {
+    fn from(e: std::io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+impl From<NoiseError> for FrameError {
+    fn from(e: NoiseError) -> Self {
+        match e {
+            NoiseError::Io(e) => FrameError::Io(e),
+            _ => FrameError::Decrypt,
+        }
+    }
+}
+
+/// Why a connection was refused during the handshake
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// The Noise handshake failed or the peer's node key is not allowed
+    Noise(NoiseError),
+    Frame(FrameError),
+    /// No [`Hello`] arrived within [`HANDSHAKE_TIMEOUT`]
+    Timeout,
+    /// The first message was not a [`Hello`]
+    NotHello,
+    /// The peer speaks another protocol version
+    Version(u32),
+    /// The peer is on another network
+    ChainId(String),
+    /// The peer's chain starts from another genesis block
+    Genesis(String),
+    /// The node dialed itself
+    SelfConnection,
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Noise(e) => write!(f, "{}", e),
+            HandshakeError::Frame(e) => write!(f, "{}", e),
+            HandshakeError::Timeout => write!(f, "no handshake within {:?}", HANDSHAKE_TIMEOUT),
+            HandshakeError::NotHello => write!(f, "the first message is not a hello"),
+            HandshakeError::Version(version) => {
+                write!(f, "protocol version {} is not the supported version {}", version, PROTOCOL_VERSION)
+            }
+            HandshakeError::ChainId(chain_id) => write!(f, "peer is on chain '{}'", chain_id),
+            HandshakeError::Genesis(hash) => write!(f, "peer's genesis block {} is not ours", hash),
+            HandshakeError::SelfConnection => write!(f, "connected to ourselves"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<NoiseError> for HandshakeError {
+    fn from(e: NoiseError) -> Self {
+        HandshakeError::Noise(e)
+    }
+}
+
+impl From<FrameError> for HandshakeError {
+    fn from(e: FrameError) -> Self {
+        HandshakeError::Frame(e)
+    }
+}
+
+/// Write `message` as one frame, encrypted with `sealer`
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, sealer: &mut Sealer, message: &Message) -> std::io::Result<()> {
+    let payload = serde_json::to_vec(message).map_err(std::io::Error::other)?;
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "message exceeds the frame size limit"));
+    }
+    let frame = [&(payload.len() as u32).to_be_bytes()[..], &payload].concat();
+    writer.write_all(&sealer.seal(&frame)).await?;
+    writer.flush().await
+}
+
+/// Read one frame decrypted with `opener`, refusing frames over
+/// [`MAX_FRAME_SIZE`] before reading their payload
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R, opener: &mut Opener) -> Result<Message, FrameError> {
+    let mut frame = opener.open(reader).await?;
+    if frame.len() < 4 {
+        return Err(FrameError::Malformed("frame shorter than its length".to_string()));
+    }
+    let len = u32::from_be_bytes(frame[..4].try_into().unwrap()) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge(len));
+    }
+    while frame.len() < 4 + len {
+        frame.extend(opener.open(reader).await?);
+    }
+    if frame.len() != 4 + len {
+        return Err(FrameError::Malformed("frame longer than its length".to_string()));
+    }
+    serde_json::from_slice(&frame[4..]).map_err(|e| FrameError::Malformed(e.to_string()))
+}
+
+/// Run the Noise handshake on a new connection, as the node that dialed if
+/// `initiator`, then exchange [`Hello`]s. Returns the peer's once it is
+/// checked to be on our network, along with the encrypted link.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    ours: &Hello,
+    identity: &Identity,
+    initiator: bool,
+) -> Result<(Hello, Secured), HandshakeError> {
+    let exchange = async {
+        let mut secured = noise::handshake(stream, identity, initiator).await?;
+        write_frame(stream, &mut secured.sealer, &Message::Hello(ours.clone())).await.map_err(FrameError::Io)?;
+        match read_frame(stream, &mut secured.opener).await? {
+            Message::Hello(theirs) => Ok((theirs, secured)),
+            _ => Err(HandshakeError::NotHello),
+        }
+    };
+    let (theirs, secured) = timeout(HANDSHAKE_TIMEOUT, exchange).await.map_err(|_| HandshakeError::Timeout)??;
+    ours.check(&theirs)?;
+    Ok((theirs, secured))
+}
+
+/// Messages from a peer that the node has to act on
+#[derive(Debug)]
+pub enum Incoming {
+    Request { id: u64, request: Request },
+    Inv(Vec<InvItem>),
+    Consensus(ConsensusMessage),
+    Raft(RaftMessage),
+    /// The peer broke the protocol and was disconnected
+    Violation(Misbehavior, String),
+}
+
+/// Why a request got no response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestError {
+    /// The connection is closed or its send queue is full
+    Closed,
+    /// No response within [`REQUEST_TIMEOUT`]
+    Timeout,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Closed => write!(f, "connection closed"),
+            RequestError::Timeout => write!(f, "no response within {:?}", REQUEST_TIMEOUT),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Response>>>>;
+
+/// Handshaken connection to a peer. Clones share the connection, which
+/// closes once every clone is dropped or [`Connection::close`] is called.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    /// Name the peer is scored under: the address dialed, or the hex-encoded
+    /// node key for a connection the peer opened
+    pub peer: String,
+    /// The peer's greeting
+    pub hello: Hello,
+    /// The peer's node key, proven in the handshake
+    pub key: VerifyingKey,
+    /// Whether the peer opened the connection
+    pub inbound: bool,
+    outgoing: mpsc::Sender<Message>,
+    pending: Pending,
+    next_id: Arc<AtomicU64>,
+    closed: Arc<watch::Sender<bool>>,
+}
+
+impl Connection {
+    /// Run a connection over `stream`, handshaken into `secured`. Pings are answered and
+    /// responses matched to their requests here; requests and announcements
+    /// are passed on through the returned receiver, which ends when the
+    /// connection closes.
+    pub fn start(stream: TcpStream, secured: Secured, peer: String, hello: Hello, inbound: bool) -> (Self, mpsc::Receiver<Incoming>) {
+        let (outgoing, mut queue) = mpsc::channel(SEND_QUEUE);
+        let (incoming, receiver) = mpsc::channel(SEND_QUEUE);
+        let connection = Self {
+            peer,
+            hello,
+            key: secured.peer_key,
+            inbound,
+            outgoing,
+            pending: Arc::default(),
+            next_id: Arc::default(),
+            closed: Arc::new(watch::channel(false).0),
+        };
+        let (mut reader, mut writer) = stream.into_split();
+        let (mut sealer, mut opener) = (secured.sealer, secured.opener);
+
+        let (mut closed, closed_tx) = (connection.closed.subscribe(), connection.closed.clone());
+        tokio::spawn(async move {
+            let mut pings = interval(PING_INTERVAL);
+            pings.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut nonce = 0u64;
+            loop {
+                let message = tokio::select! {
+                    message = queue.recv() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = pings.tick() => {
+                        nonce += 1;
+                        Message::Ping(nonce)
+                    }
+                    _ = closed.changed() => break,
+                };
+                if write_frame(&mut writer, &mut sealer, &message).await.is_err() {
+                    break;
+                }
+            }
+            closed_tx.send_replace(true);
+        });
+
+        // The reader only keeps a weak sender, so dropping every handle ends the writer
+        let (outgoing, pending) = (connection.outgoing.downgrade(), connection.pending.clone());
+        let (mut closed, closed_tx) = (connection.closed.subscribe(), connection.closed.clone());
+        tokio::spawn(async move {
+            let (mut window, mut received) = (Instant::now(), 0u32);
+            loop {
+                let frame = tokio::select! {
+                    frame = timeout(IDLE_TIMEOUT, read_frame(&mut reader, &mut opener)) => frame,
+                    _ = closed.changed() => break,
+                };
+                let message = match frame {
+                    Ok(Ok(message)) => message,
+                    Ok(Err(FrameError::Io(_))) | Err(_) => break,
+                    Ok(Err(e)) => {
+                        let _ = incoming.send(Incoming::Violation(Misbehavior::MalformedResponse, e.to_string())).await;
+                        break;
+                    }
+                };
+                if !matches!(message, Message::Ping(_) | Message::Pong(_) | Message::Response { .. }) {
+                    if window.elapsed() >= Duration::from_secs(1) {
+                        (window, received) = (Instant::now(), 0);
+                    }
+                    received += 1;
+                    if received > MAX_MESSAGES_PER_SECOND {
+                        let flood = format!("more than {} messages per second", MAX_MESSAGES_PER_SECOND);
+                        let _ = incoming.send(Incoming::Violation(Misbehavior::Spam, flood)).await;
+                        break;
+                    }
+                }
+                match message {
+                    Message::Ping(nonce) => {
+                        if let Some(outgoing) = outgoing.upgrade() {
+                            let _ = outgoing.try_send(Message::Pong(nonce));
+                        }
+                    }
+                    Message::Pong(_) => {}
+                    Message::Response { id, response } => {
+                        // Responses to requests that timed out are dropped
+                        if let Some(waiter) = pending.lock().unwrap().remove(&id) {
+                            let _ = waiter.send(response);
+                        }
+                    }
+                    Message::Request { id, request } => {
+                        if incoming.send(Incoming::Request { id, request }).await.is_err() {
+                            break;
+                        }
+                    }
+                    Message::Inv(items) => {
+                        if incoming.send(Incoming::Inv(items)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Message::Consensus(message) => {
+                        if incoming.send(Incoming::Consensus(message)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Message::Raft(message) => {
+                        if incoming.send(Incoming::Raft(message)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Message::Hello(_) => {
+                        let _ = incoming.send(Incoming::Violation(Misbehavior::MalformedResponse, "second hello".to_string())).await;
+                        break;
+                    }
+                }
+            }
+            closed_tx.send_replace(true);
+            pending.lock().unwrap().clear();
+        });
+        (connection, receiver)
+    }
+
+    /// Queue `message` for sending, returning false if the connection is
+    /// closed or its send queue is full
+    pub fn send(&self, message: Message) -> bool {
+        !self.is_closed() && self.outgoing.try_send(message).is_ok()
+    }
+
+    /// Send `request` and wait up to [`REQUEST_TIMEOUT`] for its response
+    pub async fn request(&self, request: Request) -> Result<Response, RequestError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (waiter, response) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, waiter);
+        if !self.send(Message::Request { id, request }) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(RequestError::Closed);
+        }
+        let response = timeout(REQUEST_TIMEOUT, response).await;
+        self.pending.lock().unwrap().remove(&id);
+        // The waiter is dropped without an answer when the connection closes
+        response.map_err(|_| RequestError::Timeout)?.map_err(|_| RequestError::Closed)
+    }
+
+    /// Answer request `id` of the peer
+    pub fn respond(&self, id: u64, response: Response) -> bool {
+        self.send(Message::Response { id, response })
+    }
+
+    /// Hang up
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    /// Whether `other` is a handle to this same connection
+    pub fn is(&self, other: &Connection) -> bool {
+        Arc::ptr_eq(&self.closed, &other.closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use securerx_core::crypto::generate_keypair;
+    use securerx_core::genesis::GenesisSpec;
+    use tokio::net::TcpListener;
+
+    fn hello(node_id: &str, genesis: &GenesisSpec) -> Hello {
+        Hello::new(node_id, &Blockchain::from_genesis(genesis.clone()).unwrap())
+    }
+
+    /// Identities of two nodes that allow each other
+    fn identities() -> (Identity, Identity) {
+        let (a, b) = (generate_keypair(), generate_keypair());
+        (Identity::new(a.clone(), &[b.verifying_key()]), Identity::new(b, &[a.verifying_key()]))
+    }
+
+    #[tokio::test]
+    async fn test_frames_round_trip_and_are_size_limited() {
+        let (alice, bob) = identities();
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (ours, theirs) = tokio::join!(noise::handshake(&mut a, &alice, true), noise::handshake(&mut b, &bob, false));
+        let (mut ours, mut theirs) = (ours.unwrap(), theirs.unwrap());
+        write_frame(&mut a, &mut ours.sealer, &Message::Ping(7)).await.unwrap();
+        assert!(matches!(read_frame(&mut b, &mut theirs.opener).await, Ok(Message::Ping(7))));
+
+        a.write_all(&ours.sealer.seal(&(MAX_FRAME_SIZE as u32 + 1).to_be_bytes())).await.unwrap();
+        assert!(matches!(read_frame(&mut b, &mut theirs.opener).await, Err(FrameError::TooLarge(_))));
+
+        let garbage = b"not json";
+        a.write_all(&ours.sealer.seal(&[&(garbage.len() as u32).to_be_bytes()[..], garbage].concat())).await.unwrap();
+        assert!(matches!(read_frame(&mut b, &mut theirs.opener).await, Err(FrameError::Malformed(_))));
+
+        let mut forged = ours.sealer.seal(&[0, 0, 0, 0]);
+        *forged.last_mut().unwrap() ^= 1;
+        a.write_all(&forged).await.unwrap();
+        assert!(matches!(read_frame(&mut b, &mut theirs.opener).await, Err(FrameError::Decrypt)));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_checks_network() {
+        let genesis = GenesisSpec::default();
+        let ours = hello("node1", &genesis);
+
+        let (alice, bob) = identities();
+        let (mut a, mut b) = tokio::io::duplex(4096);
+        let theirs = hello("node2", &genesis);
+        let peer = tokio::spawn(async move { handshake(&mut b, &theirs, &bob, false).await.map(|(hello, _)| hello) });
+        assert_eq!(handshake(&mut a, &ours, &alice, true).await.unwrap().0.node_id, "node2");
+        assert!(peer.await.unwrap().is_ok());
+
+        let other = GenesisSpec::with_authorities(vec![generate_keypair().verifying_key().to_bytes().to_vec()]);
+        assert!(matches!(refused(&ours, hello("node2", &other)).await, HandshakeError::Genesis(_)));
+        let mut old = hello("node2", &genesis);
+        old.version = PROTOCOL_VERSION + 1;
+        assert!(matches!(refused(&ours, old).await, HandshakeError::Version(_)));
+        assert!(matches!(refused(&ours, hello("node1", &genesis)).await, HandshakeError::SelfConnection));
+    }
+
+    /// Why a handshake with a peer greeting with `theirs` fails
+    async fn refused(ours: &Hello, theirs: Hello) -> HandshakeError {
+        let (alice, bob) = identities();
+        let (mut a, mut b) = tokio::io::duplex(4096);
+        tokio::spawn(async move { handshake(&mut b, &theirs, &bob, false).await.map(|_| ()) });
+        handshake(&mut a, ours, &alice, true).await.unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_matched_to_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tip = ChainTip { height: 3, hash: "ab".repeat(32) };
+        let served = tip.clone();
+        let genesis = GenesisSpec::default();
+        let (ours, theirs) = (hello("node1", &genesis), hello("node2", &genesis));
+        let (alice, bob) = identities();
+        let bob_key = bob.node_key();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (hello, secured) = handshake(&mut stream, &theirs, &bob, false).await.unwrap();
+            let (connection, mut incoming) = Connection::start(stream, secured, "node1".to_string(), hello, true);
+            while let Some(Incoming::Request { id, .. }) = incoming.recv().await {
+                connection.respond(id, Response::Tip(served.clone()));
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let (hello, secured) = handshake(&mut stream, &ours, &alice, true).await.unwrap();
+        assert_eq!(secured.peer_key, bob_key);
+        let (connection, _incoming) = Connection::start(stream, secured, addr.to_string(), hello, false);
+        for _ in 0..3 {
+            assert_eq!(connection.request(Request::Tip).await.ok().and_then(Response::tip), Some(tip.clone()));
+        }
+        connection.close();
+        assert_eq!(connection.request(Request::Tip).await.unwrap_err(), RequestError::Closed, "Closed connections answer nothing");
+    }
+}
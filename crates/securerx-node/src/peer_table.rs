@@ -0,0 +1,306 @@
+//! Addresses of the nodes this node knows about.
+//!
+//! The table starts from `P2P_PEERS`, grows with addresses added through the
+//! admin endpoints and shared by connected peers (peer exchange), and is saved
+//! to `DATA_DIR/peers.json` with each address's last successful handshake and
+//! score, so a restarted node finds the network again. Addresses that cannot
+//! be reached are dialed again after a backoff that doubles with every
+//! failure, from [`MIN_BACKOFF`] up to [`MAX_BACKOFF`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// File the table is saved to under `DATA_DIR`
+pub const PEER_TABLE_FILE: &str = "peers.json";
+
+/// Most addresses a node keeps
+pub const MAX_KNOWN_PEERS: usize = 1_000;
+
+/// Most addresses shared with a peer at once
+pub const MAX_SHARED_PEERS: usize = 100;
+
+/// Wait before dialing an address again after its first failure
+pub const MIN_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Longest wait between two dials of an unreachable address
+pub const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Failed dials after which an address learned from a peer, and never reached, is forgotten
+const FORGET_AFTER_FAILURES: u32 = 8;
+
+/// An address that is not `host:port`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPeerAddr(pub String);
+
+impl fmt::Display for InvalidPeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not a host:port address", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPeerAddr {}
+
+/// How an address became known
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerSource {
+    /// Listed in `P2P_PEERS`
+    Config,
+    /// Added through `POST /peers`
+    Admin,
+    /// Shared by a connected peer
+    Exchange,
+}
+
+/// A known address
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PeerEntry {
+    /// P2P address, `host:port`
+    pub addr: String,
+    pub source: PeerSource,
+    /// Unix time of the last successful handshake with the address
+    pub last_seen: Option<u64>,
+    /// Score of the address when the table was last saved
+    pub score: i64,
+    /// Dials that failed since the last successful handshake
+    #[serde(skip)]
+    pub failures: u32,
+    /// When the address may be dialed again; now if unset
+    #[serde(skip)]
+    retry_at: Option<Instant>,
+}
+
+impl PeerEntry {
+    fn new(addr: &str, source: PeerSource) -> Self {
+        Self { addr: addr.to_string(), source, last_seen: None, score: 0, failures: 0, retry_at: None }
+    }
+}
+
+/// A known address and whether it is connected or banned, as listed by `GET /peers`
+#[derive(Serialize, Clone, Debug)]
+pub struct PeerStatus {
+    #[serde(flatten)]
+    pub entry: PeerEntry,
+    pub connected: bool,
+    pub banned: bool,
+}
+
+/// The known addresses, by address
+#[derive(Debug, Default)]
+pub struct PeerTable {
+    peers: BTreeMap<String, PeerEntry>,
+    /// Addresses found to lead back to this node, never dialed or learned again
+    own_addrs: HashSet<String>,
+    /// Whether there are changes to save
+    changed: bool,
+}
+
+impl PeerTable {
+    /// Load the table saved at `path`, or an empty one if there is none
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let entries: Vec<PeerEntry> = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(std::io::Error::other)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+        let peers = entries.into_iter().map(|entry| (entry.addr.clone(), entry)).collect();
+        Ok(Self { peers, ..Self::default() })
+    }
+
+    /// Save the table to `path`, replacing the previous one atomically
+    pub fn save(&mut self, path: &Path) -> std::io::Result<()> {
+        let entries: Vec<&PeerEntry> = self.peers.values().collect();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&entries)?)?;
+        std::fs::rename(tmp, path)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Whether the table changed since it was loaded or saved
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn get(&self, addr: &str) -> Option<&PeerEntry> {
+        self.peers.get(addr)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &PeerEntry> {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Add `addr`, returning whether it is new. Configured and admin-added
+    /// addresses take precedence over learned ones and are kept even when the
+    /// table is full.
+    pub fn add(&mut self, addr: &str, source: PeerSource) -> bool {
+        if let Some(entry) = self.peers.get_mut(addr) {
+            if entry.source == PeerSource::Exchange && source != PeerSource::Exchange {
+                entry.source = source;
+                self.changed = true;
+            }
+            return false;
+        }
+        if source == PeerSource::Exchange && (self.peers.len() >= MAX_KNOWN_PEERS || self.own_addrs.contains(addr)) {
+            return false;
+        }
+        self.peers.insert(addr.to_string(), PeerEntry::new(addr, source));
+        self.changed = true;
+        true
+    }
+
+    /// Add the valid addresses among `addrs` a peer shared, returning how many are new
+    pub fn learn(&mut self, addrs: &[String]) -> usize {
+        addrs
+            .iter()
+            .take(MAX_SHARED_PEERS)
+            .filter(|addr| is_peer_addr(addr) && self.add(addr, PeerSource::Exchange))
+            .count()
+    }
+
+    /// Forget `addr`, returning its entry
+    pub fn remove(&mut self, addr: &str) -> Option<PeerEntry> {
+        let entry = self.peers.remove(addr);
+        self.changed |= entry.is_some();
+        entry
+    }
+
+    /// Forget `addr`, which turned out to lead back to this node
+    pub fn remove_own(&mut self, addr: &str) {
+        self.remove(addr);
+        self.own_addrs.insert(addr.to_string());
+    }
+
+    /// Addresses that may be dialed now: configured ones first, then the most recently seen
+    pub fn due(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut due: Vec<&PeerEntry> = self.peers.values().filter(|entry| entry.retry_at.is_none_or(|at| at <= now)).collect();
+        due.sort_by_key(|entry| (entry.source != PeerSource::Config, std::cmp::Reverse(entry.last_seen)));
+        due.into_iter().map(|entry| entry.addr.clone()).collect()
+    }
+
+    /// Record a successful handshake with `addr`
+    pub fn connected(&mut self, addr: &str) {
+        if let Some(entry) = self.peers.get_mut(addr) {
+            entry.last_seen = Some(unix_now());
+            entry.failures = 0;
+            entry.retry_at = None;
+            self.changed = true;
+        }
+    }
+
+    /// Record a failed dial of `addr` and back off from it. Learned
+    /// addresses that were never reached are forgotten after a few failures.
+    pub fn failed(&mut self, addr: &str) {
+        let Some(entry) = self.peers.get_mut(addr) else {
+            return;
+        };
+        entry.failures += 1;
+        entry.retry_at = Some(Instant::now() + backoff(entry.failures));
+        if entry.source == PeerSource::Exchange && entry.last_seen.is_none() && entry.failures >= FORGET_AFTER_FAILURES {
+            self.remove(addr);
+        }
+    }
+
+    /// Record `score` as the current score of `addr`
+    pub fn set_score(&mut self, addr: &str, score: i64) {
+        if let Some(entry) = self.peers.get_mut(addr).filter(|entry| entry.score != score) {
+            entry.score = score;
+            self.changed = true;
+        }
+    }
+
+    /// Addresses worth sharing with a peer: those reached before, most recently seen first
+    pub fn shareable(&self) -> Vec<String> {
+        let mut seen: Vec<&PeerEntry> = self.peers.values().filter(|entry| entry.last_seen.is_some()).collect();
+        seen.sort_by_key(|entry| std::cmp::Reverse(entry.last_seen));
+        seen.into_iter().take(MAX_SHARED_PEERS).map(|entry| entry.addr.clone()).collect()
+    }
+}
+
+/// Wait before the next dial of an address that failed `failures` times in a row
+pub fn backoff(failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(1).min(16);
+    (MIN_BACKOFF * 2u32.pow(doublings)).min(MAX_BACKOFF)
+}
+
+/// Whether `addr` looks like a dialable `host:port`
+pub fn is_peer_addr(addr: &str) -> bool {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => {
+            !host.is_empty()
+                && addr.len() <= 255
+                && !addr.contains(|c: char| c.is_whitespace() || c == ',' || c == '/')
+                && port.parse::<u16>().is_ok_and(|port| port != 0)
+        }
+        None => false,
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_limit() {
+        assert_eq!(backoff(1), MIN_BACKOFF);
+        assert_eq!(backoff(2), MIN_BACKOFF * 2);
+        assert_eq!(backoff(4), MIN_BACKOFF * 8);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_failed_addresses_wait_and_learned_ones_are_forgotten() {
+        let mut table = PeerTable::default();
+        table.add("node2:9000", PeerSource::Config);
+        assert_eq!(table.learn(&["node3:9000".to_string(), "not an address".to_string(), "node2:9000".to_string()]), 1);
+        assert_eq!(table.due(), vec!["node2:9000", "node3:9000"], "Configured peers are dialed first");
+
+        table.failed("node2:9000");
+        assert_eq!(table.due(), vec!["node3:9000"]);
+        table.connected("node2:9000");
+        assert_eq!(table.due().len(), 2, "A handshake resets the backoff");
+
+        for _ in 0..FORGET_AFTER_FAILURES {
+            table.failed("node3:9000");
+            table.failed("node2:9000");
+        }
+        assert!(table.get("node3:9000").is_none());
+        assert!(table.get("node2:9000").is_some(), "Configured peers are never forgotten");
+        assert_eq!(table.shareable(), vec!["node2:9000"]);
+    }
+
+    #[test]
+    fn test_table_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("securerx-peer-table-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(PEER_TABLE_FILE);
+        let mut table = PeerTable::default();
+        table.add("node2:9000", PeerSource::Admin);
+        table.connected("node2:9000");
+        table.set_score("node2:9000", -25);
+        assert!(table.is_changed());
+        table.save(&path).unwrap();
+
+        let loaded = PeerTable::load(&path).unwrap();
+        assert_eq!(loaded.get("node2:9000"), table.get("node2:9000"));
+        assert!(!loaded.is_changed());
+        assert!(PeerTable::load(&dir.join("missing.json")).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! Standing of the peers this node talks to.
+//!
+//! Every peer starts at score 0 and loses points each time it serves data
+//! that cannot come from an honest node on this network, floods the node or
+//! leaves its requests unanswered. Lost points come back at one per
+//! [`SCORE_RECOVERY`]. A peer whose score falls to [`BAN_SCORE`] is
+//! disconnected and ignored until its ban expires, after which it starts over
+//! at 0; bans are saved so a restart does not lift them. Unreachable peers are
+//! not penalized.
+
+use crate::peer_table::unix_now;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Score at which a peer is banned
+pub const BAN_SCORE: i64 = -100;
+
+/// How long a banned peer is ignored by default
+pub const BAN_DURATION: Duration = Duration::from_secs(600);
+
+/// Time it takes a peer to win back one lost point
+pub const SCORE_RECOVERY: Duration = Duration::from_secs(60);
+
+/// File the bans are saved to under `DATA_DIR`
+pub const BANS_FILE: &str = "bans.json";
+
+/// Protocol violations a peer is penalized for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// The peer's chain starts from another genesis block
+    WrongGenesis,
+    /// A block failed validation, does not link to the blocks before it, or reverts a finalized block
+    InvalidBlock,
+    /// A relayed transaction is forged or meant for another network
+    InvalidTransaction,
+    /// The response could not be parsed, or holds data that was not asked for
+    MalformedResponse,
+    /// A request went unanswered for [`crate::p2p::REQUEST_TIMEOUT`]
+    SlowResponse,
+    /// The peer sent messages faster than [`crate::p2p::MAX_MESSAGES_PER_SECOND`],
+    /// or announced more than [`crate::announce::MAX_INV_ITEMS`] items at once
+    Spam,
+}
+
+impl Misbehavior {
+    /// Points the peer loses
+    pub fn penalty(self) -> i64 {
+        match self {
+            Misbehavior::WrongGenesis | Misbehavior::InvalidBlock | Misbehavior::InvalidTransaction => 100,
+            Misbehavior::MalformedResponse => 25,
+            Misbehavior::Spam => 20,
+            Misbehavior::SlowResponse => 10,
+        }
+    }
+
+    /// Label of the misbehavior in metrics
+    pub fn name(self) -> &'static str {
+        match self {
+            Misbehavior::WrongGenesis => "wrong_genesis",
+            Misbehavior::InvalidBlock => "invalid_block",
+            Misbehavior::InvalidTransaction => "invalid_transaction",
+            Misbehavior::MalformedResponse => "malformed_response",
+            Misbehavior::SlowResponse => "slow_response",
+            Misbehavior::Spam => "spam",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Standing {
+    /// Score when last penalized, before recovery since `updated`
+    score: i64,
+    updated: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl Standing {
+    fn new(score: i64) -> Self {
+        Self { score, updated: Instant::now(), banned_until: None }
+    }
+
+    /// Score after the points won back since the last penalty
+    fn current(&self, now: Instant) -> i64 {
+        let recovered = now.saturating_duration_since(self.updated).as_secs() / SCORE_RECOVERY.as_secs();
+        (self.score + recovered as i64).min(0)
+    }
+}
+
+/// A saved ban
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Ban {
+    peer: String,
+    /// Unix time the ban expires
+    until: u64,
+}
+
+/// Scores and bans of the peers this node talks to
+#[derive(Debug)]
+pub struct PeerScores {
+    ban_duration: Duration,
+    peers: HashMap<String, Standing>,
+}
+
+impl PeerScores {
+    /// Scores whose bans last `ban_duration`
+    pub fn new(ban_duration: Duration) -> Self {
+        Self { ban_duration, peers: HashMap::new() }
+    }
+
+    /// Current score of `peer`, 0 if it never misbehaved or won every point back
+    pub fn score(&self, peer: &str) -> i64 {
+        self.peers.get(peer).map_or(0, |standing| standing.current(Instant::now()))
+    }
+
+    /// Every peer with points to win back, and its current score
+    pub fn scores(&self) -> Vec<(String, i64)> {
+        let now = Instant::now();
+        self.peers
+            .iter()
+            .map(|(peer, standing)| (peer.clone(), standing.current(now)))
+            .filter(|(_, score)| *score < 0)
+            .collect()
+    }
+
+    /// Number of peers currently banned
+    pub fn banned_count(&self) -> usize {
+        self.peers.keys().filter(|peer| self.is_banned(peer)).count()
+    }
+
+    /// Whether `peer` is banned and should not be synced from
+    pub fn is_banned(&self, peer: &str) -> bool {
+        self.peers
+            .get(peer)
+            .and_then(|standing| standing.banned_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Resume `peer` at `score`, e.g. as saved before a restart
+    pub fn restore(&mut self, peer: &str, score: i64) {
+        if score != 0 {
+            self.peers.entry(peer.to_string()).or_insert_with(|| Standing::new(0)).score = score;
+        }
+    }
+
+    /// Lower the score of `peer` for `misbehavior`, returning whether this banned it
+    pub fn penalize(&mut self, peer: &str, misbehavior: Misbehavior) -> bool {
+        let now = Instant::now();
+        let standing = self.peers.entry(peer.to_string()).or_insert_with(|| Standing::new(0));
+        if standing.banned_until.is_some_and(|until| until <= now) {
+            // The ban expired: start over
+            *standing = Standing::new(0);
+        }
+        if standing.banned_until.is_none() {
+            standing.score = standing.current(now);
+            standing.updated = now;
+        }
+        standing.score -= misbehavior.penalty();
+        if standing.score <= BAN_SCORE && standing.banned_until.is_none() {
+            standing.banned_until = Some(now + self.ban_duration);
+            return true;
+        }
+        false
+    }
+}
+
+impl PeerScores {
+    /// Restore the bans saved at `path` that have not expired
+    pub fn load_bans(&mut self, path: &Path) -> std::io::Result<()> {
+        let bans: Vec<Ban> = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(std::io::Error::other)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let (now, unix) = (Instant::now(), unix_now());
+        for ban in bans.into_iter().filter(|ban| ban.until > unix) {
+            let mut standing = Standing::new(BAN_SCORE);
+            standing.banned_until = Some(now + Duration::from_secs(ban.until - unix));
+            self.peers.insert(ban.peer, standing);
+        }
+        Ok(())
+    }
+
+    /// Save the current bans to `path`, replacing the previous ones atomically
+    pub fn save_bans(&self, path: &Path) -> std::io::Result<()> {
+        let (now, unix) = (Instant::now(), unix_now());
+        let bans: Vec<Ban> = self
+            .peers
+            .iter()
+            .filter_map(|(peer, standing)| {
+                let left = standing.banned_until?.checked_duration_since(now)?;
+                Some(Ban { peer: peer.clone(), until: unix + left.as_secs().max(1) })
+            })
+            .collect();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&bans)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+impl Default for PeerScores {
+    fn default() -> Self {
+        Self::new(BAN_DURATION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_misbehaving_peers_are_banned() {
+        let mut scores = PeerScores::default();
+        assert!(!scores.penalize("node2:8081", Misbehavior::MalformedResponse));
+        assert_eq!(scores.score("node2:8081"), -25);
+        assert!(!scores.is_banned("node2:8081"));
+
+        assert!(scores.penalize("node3:8081", Misbehavior::InvalidBlock));
+        assert!(scores.is_banned("node3:8081"));
+        assert!(!scores.penalize("node3:8081", Misbehavior::InvalidBlock), "A ban is only reported once");
+        assert!(!scores.is_banned("node4:8081"));
+    }
+
+    #[test]
+    fn test_bans_expire() {
+        let mut scores = PeerScores::new(Duration::ZERO);
+        assert!(scores.penalize("node2:8081", Misbehavior::WrongGenesis));
+        assert!(!scores.is_banned("node2:8081"));
+        assert!(!scores.penalize("node2:8081", Misbehavior::MalformedResponse));
+        assert_eq!(scores.score("node2:8081"), -25, "An expired ban should reset the score");
+    }
+
+    #[test]
+    fn test_scores_recover_over_time() {
+        let mut scores = PeerScores::default();
+        scores.penalize("node2:8081", Misbehavior::SlowResponse);
+        let standing = scores.peers.get_mut("node2:8081").unwrap();
+        standing.updated -= SCORE_RECOVERY * 4;
+        assert_eq!(scores.score("node2:8081"), -6);
+        assert_eq!(scores.scores(), vec![("node2:8081".to_string(), -6)]);
+
+        scores.peers.get_mut("node2:8081").unwrap().updated -= SCORE_RECOVERY * 100;
+        assert_eq!(scores.score("node2:8081"), 0, "Scores never rise above 0");
+        assert!(scores.scores().is_empty());
+        scores.penalize("node2:8081", Misbehavior::Spam);
+        assert_eq!(scores.score("node2:8081"), -20, "Recovered points are kept by later penalties");
+    }
+
+    #[test]
+    fn test_bans_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("securerx-bans-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(BANS_FILE);
+        let mut scores = PeerScores::default();
+        scores.penalize("node2:8081", Misbehavior::InvalidBlock);
+        scores.penalize("node3:8081", Misbehavior::MalformedResponse);
+        scores.save_bans(&path).unwrap();
+
+        let mut restarted = PeerScores::default();
+        restarted.load_bans(&path).unwrap();
+        assert!(restarted.is_banned("node2:8081"));
+        assert!(!restarted.is_banned("node3:8081"), "Only bans are saved");
+        assert_eq!(restarted.banned_count(), 1);
+
+        let mut expired = PeerScores::new(Duration::ZERO);
+        expired.penalize("node2:8081", Misbehavior::InvalidBlock);
+        expired.save_bans(&path).unwrap();
+        let mut restarted = PeerScores::default();
+        restarted.load_bans(&path).unwrap();
+        assert_eq!(restarted.banned_count(), 0, "Expired bans are not restored");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
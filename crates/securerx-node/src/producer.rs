@@ -0,0 +1,129 @@
+//! Block production from the mempool.
+//!
+//! In Proof-of-Authority mode the validator whose turn it is seals the
+//! pending prescriptions into a block every `BLOCK_INTERVAL_MS`, or as soon as
+//! `MAX_BLOCK_TXS` of them are pending, and announces it to its peers. BFT
+//! validators propose from the same mempool in their rounds (see
+//! [`crate::bft`]); a Raft leader builds blocks from `/raft/submit` instead.
+
+use crate::announce::InvItem;
+use crate::config::ConsensusMode;
+use crate::node::Node;
+use tokio::time::sleep;
+
+impl Node {
+    /// Start producing blocks, if this node is a Proof-of-Authority validator
+    pub fn start_producer(&self) {
+        if self.config.consensus != ConsensusMode::Poa || self.config.validator_key.is_none() {
+            return;
+        }
+        let node = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = sleep(node.config.block_interval) => {}
+                    _ = node.block_ready.notified() => {}
+                }
+                node.produce_block();
+            }
+        });
+    }
+
+    /// Seal the next prescriptions of the mempool into a block if it is this
+    /// node's turn and any are pending, returning the block's index
+    pub fn produce_block(&self) -> Option<u64> {
+        let block = {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            if !blockchain.is_next_proposer() {
+                return None;
+            }
+            let transactions = self.mempool.lock().unwrap().next_block(&blockchain, self.config.max_block_txs);
+            if transactions.is_empty() {
+                return None;
+            }
+            let block = match blockchain.add_block(transactions) {
+                Ok(block) => block.clone(),
+                Err(e) => {
+                    eprintln!("Failed to produce block {}: {}", blockchain.chain.len(), e);
+                    return None;
+                }
+            };
+            self.refresh_mempool(&blockchain, vec![]);
+            crate::metrics::BLOCKS_PROCESSED.inc();
+            crate::metrics::CHAIN_HEIGHT.set(blockchain.chain.len() as i64);
+            block
+        };
+        println!("Produced block {} with {} prescriptions", block.index, block.transactions.len());
+        self.announce(vec![InvItem::block(&block)], None);
+        Some(block.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::NodeConfig;
+    use crate::node::Node;
+    use crate::test_support;
+    use ed25519_dalek::SigningKey;
+    use securerx_core::crypto::generate_keypair;
+    use securerx_core::genesis::{GenesisDoctor, GenesisSpec};
+    use securerx_core::mempool::DEFAULT_BLOCK_INTERVAL;
+    use securerx_core::test_support::prescription;
+    use std::time::Duration;
+
+    /// A validator node of a single-authority network with `doctor` registered as doctor1
+    fn validator(name: &str, doctor: &SigningKey, max_block_txs: usize, block_interval: Duration) -> Node {
+        let validator = generate_keypair();
+        let mut genesis = GenesisSpec::with_authorities(vec![validator.verifying_key().to_bytes().to_vec()]);
+        genesis.doctors.push(GenesisDoctor {
+            doctor_id: "doctor1".to_string(),
+            license_number: "MD-1".to_string(),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        });
+        Node::new(NodeConfig {
+            node_key: validator.clone(),
+            validator_key: Some(validator),
+            mempool_size: 100,
+            max_block_txs,
+            block_interval,
+            ..test_support::config(&format!("producer-{}", name), genesis)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_blocks_batch_pending_prescriptions() {
+        let doctor = generate_keypair();
+        let node = validator("batch", &doctor, 2, DEFAULT_BLOCK_INTERVAL);
+        assert_eq!(node.produce_block(), None, "No block without pending prescriptions");
+        for i in 0..3 {
+            node.submit_transaction(prescription(&doctor, &format!("patient{}", i)), None).unwrap();
+        }
+        assert_eq!(node.mempool.lock().unwrap().len(), 3);
+
+        assert_eq!(node.produce_block(), Some(1));
+        assert_eq!(node.produce_block(), Some(2));
+        let blockchain = node.blockchain.lock().unwrap();
+        assert_eq!(blockchain.chain[1].transactions.len(), 2, "Blocks hold at most MAX_BLOCK_TXS prescriptions");
+        assert_eq!(blockchain.chain[2].transactions.len(), 1);
+        assert!(node.mempool.lock().unwrap().is_empty(), "Included prescriptions leave the mempool");
+    }
+
+    #[tokio::test]
+    async fn test_blocks_are_produced_on_the_interval_or_once_full() {
+        let doctor = generate_keypair();
+        let node = validator("interval", &doctor, 3, Duration::from_millis(200));
+        node.start_producer();
+        node.submit_transaction(prescription(&doctor, "patient1"), None).unwrap();
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(node.blockchain.lock().unwrap().chain.len(), 2, "Pending prescriptions are sealed on the interval");
+
+        let node = validator("full", &doctor, 3, Duration::from_secs(3600));
+        node.start_producer();
+        for i in 0..3 {
+            node.submit_transaction(prescription(&doctor, &format!("patient{}", i)), None).unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(node.blockchain.lock().unwrap().chain[1].transactions.len(), 3, "A full block is sealed right away");
+    }
+}
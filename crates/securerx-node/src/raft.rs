@@ -0,0 +1,1009 @@
+//! Raft-replicated ledger for deployments that trust every node.
+//!
+//! The nodes elect a leader, which builds blocks from submitted transactions
+//! and replicates them through the Raft log; a block is appended to the chain
+//! once a majority of the nodes stored its log entry. This tolerates crashed
+//! nodes, not malicious ones. The leader seals blocks with its validator key,
+//! so every node of the cluster holds the key of the single genesis authority.
+//!
+//! The chain itself is the snapshot: once the log grows past
+//! [`RaftConfig::snapshot_threshold`] applied entries it is truncated, and a
+//! follower that fell behind the truncated prefix is sent the blocks of the
+//! leader's chain it lacks, one block range (see [`sync::block_range`]) per
+//! message so each fits in a P2P frame.
+//!
+//! [`RaftNode`] is a deterministic state machine driven by ticks and messages;
+//! transport, timers and persistence of [`PersistentState`] are left to the
+//! caller.
+
+use crate::sync::{self, BlockRange};
+use securerx_core::block::Block;
+use securerx_core::blockchain::Blockchain;
+use securerx_core::registry::RegistryTransaction;
+use securerx_core::transaction::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/// Most entries sent in one `AppendEntries` message
+const MAX_ENTRIES_PER_MESSAGE: usize = 64;
+
+/// Timing and compaction settings, in ticks of the caller's clock
+#[derive(Clone, Debug)]
+pub struct RaftConfig {
+    /// Followers start an election after between one and two times this many ticks without a leader
+    pub election_ticks: u64,
+    /// The leader sends heartbeats every this many ticks
+    pub heartbeat_ticks: u64,
+    /// Applied entries kept in the log before it is compacted into a snapshot
+    pub snapshot_threshold: usize,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self { election_ticks: 10, heartbeat_ticks: 2, snapshot_threshold: 1000 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// A log entry: a block to append, or a no-op a new leader commits to learn
+/// which earlier entries are committed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    pub term: u64,
+    pub block: Option<Block>,
+}
+
+/// Transactions submitted for the next block
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Submission {
+    #[serde(default)]
+    pub transactions: Vec<Transaction>,
+    #[serde(default)]
+    pub registry_txs: Vec<RegistryTransaction>,
+}
+
+/// Message exchanged between cluster members
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RaftMessage {
+    RequestVote { term: u64, candidate: String, last_log_index: u64, last_log_term: u64 },
+    VoteResponse { term: u64, from: String, granted: bool },
+    AppendEntries {
+        term: u64,
+        leader: String,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    },
+    /// `match_index` is the last entry the follower now shares with the leader
+    /// on success, and a hint where to retry from otherwise
+    AppendResponse { term: u64, from: String, success: bool, match_index: u64 },
+    /// Part of the leader's chain up to the snapshot, `height` blocks long, for
+    /// a follower missing compacted entries. `blocks` continue the chain from
+    /// the height the follower last reported; none ask it for that height.
+    InstallSnapshot { term: u64, leader: String, last_index: u64, last_term: u64, height: u64, blocks: Vec<Block> },
+    /// Length of the chain a follower holds while it installs a snapshot
+    SnapshotResponse { term: u64, from: String, height: u64 },
+}
+
+/// Something the caller has to do on the node's behalf
+#[derive(Clone, Debug)]
+pub enum Output {
+    /// Deliver `message` to cluster member `to`
+    Send { to: String, message: RaftMessage },
+    /// Write [`RaftNode::persistent_state`] to stable storage before sending the messages that follow
+    Persist,
+    /// The block at `height` was appended to the chain
+    Applied { height: u64 },
+}
+
+/// Raft state that must survive a restart
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PersistentState {
+    pub term: u64,
+    pub voted_for: Option<String>,
+    /// Entries after the snapshot
+    pub log: Vec<LogEntry>,
+    pub snapshot_index: u64,
+    pub snapshot_term: u64,
+    /// Chain length covered by the snapshot
+    pub snapshot_height: u64,
+}
+
+impl PersistentState {
+    /// Load the state saved at `path`, or the initial state if there is none
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(std::io::Error::other),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save the state to `path`, replacing the previous state atomically. The
+    /// state is on disk once this returns, so a vote or entry it holds can be
+    /// acted on.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(tmp, path)?;
+        sync_dir(path)
+    }
+}
+
+/// Flush the directory entry of `path`, so a rename into it survives a crash
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Returned by [`RaftNode::submit`] on a node that is not the leader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotLeader {
+    /// The leader this node last heard from, if any
+    pub leader: Option<String>,
+}
+
+impl fmt::Display for NotLeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.leader {
+            Some(leader) => write!(f, "not the leader, the leader is {}", leader),
+            None => write!(f, "not the leader, no leader is known"),
+        }
+    }
+}
+
+impl std::error::Error for NotLeader {}
+
+/// One member of a Raft cluster
+pub struct RaftNode {
+    id: String,
+    peers: Vec<String>,
+    config: RaftConfig,
+    state: PersistentState,
+    role: Role,
+    leader: Option<String>,
+    commit_index: u64,
+    last_applied: u64,
+    votes: HashSet<String>,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    /// Chain length each follower installing a snapshot last reported
+    snapshot_progress: HashMap<String, u64>,
+    elapsed: u64,
+    election_timeout: u64,
+    rng: u64,
+    pending: Submission,
+}
+
+impl RaftNode {
+    /// Member `id` of a cluster with `peers`, resuming from `state` after a
+    /// restart. `seed` randomizes election timeouts.
+    pub fn new(id: String, peers: Vec<String>, config: RaftConfig, state: PersistentState, seed: u64) -> Self {
+        let mut node = Self {
+            id,
+            peers,
+            config,
+            commit_index: state.snapshot_index,
+            last_applied: state.snapshot_index,
+            state,
+            role: Role::Follower,
+            leader: None,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            snapshot_progress: HashMap::new(),
+            elapsed: 0,
+            election_timeout: 0,
+            rng: seed | 1,
+            pending: Submission::default(),
+        };
+        node.reset_election_timer();
+        node
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn term(&self) -> u64 {
+        self.state.term
+    }
+
+    /// The leader this node last heard from
+    pub fn leader(&self) -> Option<&str> {
+        self.leader.as_deref()
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    pub fn persistent_state(&self) -> &PersistentState {
+        &self.state
+    }
+
+    /// Queue transactions for the next block. Only the leader accepts them.
+    pub fn submit(&mut self, chain: &mut Blockchain, submission: Submission) -> Result<Vec<Output>, NotLeader> {
+        if self.role != Role::Leader {
+            return Err(NotLeader { leader: self.leader.clone() });
+        }
+        self.pending.transactions.extend(submission.transactions);
+        self.pending.registry_txs.extend(submission.registry_txs);
+        let mut out = vec![];
+        self.propose(chain, &mut out);
+        Ok(out)
+    }
+
+    /// Advance the clock by one tick
+    pub fn tick(&mut self, chain: &mut Blockchain) -> Vec<Output> {
+        let mut out = vec![];
+        self.elapsed += 1;
+        if self.role == Role::Leader {
+            if self.elapsed >= self.config.heartbeat_ticks {
+                self.elapsed = 0;
+                self.replicate(chain, &mut out);
+            }
+        } else if self.elapsed >= self.election_timeout {
+            self.start_election(chain, &mut out);
+        }
+        out
+    }
+
+    /// Handle a message from another cluster member
+    pub fn handle(&mut self, chain: &mut Blockchain, message: RaftMessage) -> Vec<Output> {
+        let mut out = vec![];
+        let term = match &message {
+            RaftMessage::RequestVote { term, .. }
+            | RaftMessage::VoteResponse { term, .. }
+            | RaftMessage::AppendEntries { term, .. }
+            | RaftMessage::AppendResponse { term, .. }
+            | RaftMessage::InstallSnapshot { term, .. }
+            | RaftMessage::SnapshotResponse { term, .. } => *term,
+        };
+        if term > self.state.term {
+            self.become_follower(term, &mut out);
+        }
+        match message {
+            RaftMessage::RequestVote { term, candidate, last_log_index, last_log_term } => {
+                let up_to_date = (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+                let free = self.state.voted_for.is_none() || self.state.voted_for.as_ref() == Some(&candidate);
+                let granted = term == self.state.term && free && up_to_date;
+                if granted && self.state.voted_for.is_none() {
+                    self.state.voted_for = Some(candidate.clone());
+                    out.push(Output::Persist);
+                }
+                if granted {
+                    self.reset_election_timer();
+                }
+                let response = RaftMessage::VoteResponse { term: self.state.term, from: self.id.clone(), granted };
+                out.push(Output::Send { to: candidate, message: response });
+            }
+            RaftMessage::VoteResponse { term, from, granted } => {
+                if self.role == Role::Candidate && term == self.state.term && granted {
+                    self.votes.insert(from);
+                    if self.votes.len() >= self.majority() {
+                        self.become_leader(chain, &mut out);
+                    }
+                }
+            }
+            RaftMessage::AppendEntries { term, leader, prev_log_index, prev_log_term, entries, leader_commit } => {
+                let (success, match_index) = if term < self.state.term {
+                    (false, 0)
+                } else {
+                    self.follow(&leader);
+                    self.append_entries(prev_log_index, prev_log_term, entries, &mut out)
+                };
+                if success {
+                    self.commit_to(chain, leader_commit.min(match_index), &mut out);
+                }
+                let response = RaftMessage::AppendResponse { term: self.state.term, from: self.id.clone(), success, match_index };
+                out.push(Output::Send { to: leader, message: response });
+            }
+            RaftMessage::AppendResponse { term, from, success, match_index } => {
+                if self.role == Role::Leader && term == self.state.term {
+                    if success {
+                        self.snapshot_progress.remove(&from);
+                        let matched = self.match_index.entry(from.clone()).or_default();
+                        *matched = (*matched).max(match_index);
+                        self.next_index.insert(from.clone(), match_index + 1);
+                        self.advance_commit(chain, &mut out);
+                        if self.next_index[&from] <= self.last_index() {
+                            self.send_append(chain, &from, &mut out);
+                        }
+                    } else {
+                        let next = self.next_index.get(&from).copied().unwrap_or(1);
+                        self.next_index.insert(from.clone(), (match_index + 1).min(next.saturating_sub(1)).max(1));
+                        self.send_append(chain, &from, &mut out);
+                    }
+                }
+            }
+            RaftMessage::InstallSnapshot { term, leader, last_index, last_term, height, blocks } => {
+                let response = if term < self.state.term {
+                    RaftMessage::AppendResponse { term: self.state.term, from: self.id.clone(), success: false, match_index: 0 }
+                } else {
+                    self.follow(&leader);
+                    self.install_snapshot(chain, last_index, last_term, height, blocks, &mut out)
+                };
+                out.push(Output::Send { to: leader, message: response });
+            }
+            RaftMessage::SnapshotResponse { term, from, height } => {
+                if self.role == Role::Leader && term == self.state.term {
+                    self.snapshot_progress.insert(from.clone(), height);
+                    self.send_append(chain, &from, &mut out);
+                }
+            }
+        }
+        out
+    }
+
+    fn majority(&self) -> usize {
+        let members = self.peers.len() + 1;
+        members / 2 + 1
+    }
+
+    fn last_index(&self) -> u64 {
+        self.state.snapshot_index + self.state.log.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.state.log.last().map_or(self.state.snapshot_term, |entry| entry.term)
+    }
+
+    /// Term of the entry at `index`, if it is in the log or is the last one in the snapshot
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.state.snapshot_index {
+            return Some(self.state.snapshot_term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    fn entry(&self, index: u64) -> Option<&LogEntry> {
+        let offset = index.checked_sub(self.state.snapshot_index + 1)?;
+        self.state.log.get(offset as usize)
+    }
+
+    /// Pick a new election timeout between one and two times `election_ticks`
+    fn reset_election_timer(&mut self) {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.elapsed = 0;
+        self.election_timeout = self.config.election_ticks + self.rng % self.config.election_ticks.max(1);
+    }
+
+    fn become_follower(&mut self, term: u64, out: &mut Vec<Output>) {
+        self.state.term = term;
+        self.state.voted_for = None;
+        self.role = Role::Follower;
+        self.leader = None;
+        out.push(Output::Persist);
+    }
+
+    /// Accept `leader` as the leader of the current term
+    fn follow(&mut self, leader: &str) {
+        self.role = Role::Follower;
+        self.leader = Some(leader.to_string());
+        self.reset_election_timer();
+    }
+
+    fn start_election(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) {
+        self.state.term += 1;
+        self.state.voted_for = Some(self.id.clone());
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.votes = HashSet::from([self.id.clone()]);
+        self.reset_election_timer();
+        out.push(Output::Persist);
+        if self.votes.len() >= self.majority() {
+            self.become_leader(chain, out);
+            return;
+        }
+        for peer in &self.peers {
+            let message = RaftMessage::RequestVote {
+                term: self.state.term,
+                candidate: self.id.clone(),
+                last_log_index: self.last_index(),
+                last_log_term: self.last_term(),
+            };
+            out.push(Output::Send { to: peer.clone(), message });
+        }
+    }
+
+    fn become_leader(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) {
+        self.role = Role::Leader;
+        self.leader = Some(self.id.clone());
+        self.elapsed = 0;
+        let next = self.last_index() + 1;
+        self.next_index = self.peers.iter().map(|peer| (peer.clone(), next)).collect();
+        self.match_index = self.peers.iter().map(|peer| (peer.clone(), 0)).collect();
+        self.snapshot_progress.clear();
+        self.state.log.push(LogEntry { term: self.state.term, block: None });
+        out.push(Output::Persist);
+        self.advance_commit(chain, out);
+        self.replicate(chain, out);
+    }
+
+    /// Send every follower the entries it is missing, or an empty heartbeat
+    fn replicate(&self, chain: &Blockchain, out: &mut Vec<Output>) {
+        for peer in &self.peers {
+            self.send_append(chain, peer, out);
+        }
+    }
+
+    fn send_append(&self, chain: &Blockchain, peer: &str, out: &mut Vec<Output>) {
+        let next = self.next_index.get(peer).copied().unwrap_or(self.last_index() + 1);
+        let message = if next <= self.state.snapshot_index {
+            let height = self.state.snapshot_height;
+            let from = self.snapshot_progress.get(peer).copied().unwrap_or(height);
+            RaftMessage::InstallSnapshot {
+                term: self.state.term,
+                leader: self.id.clone(),
+                last_index: self.state.snapshot_index,
+                last_term: self.state.snapshot_term,
+                height,
+                blocks: sync::block_range(&chain.chain[..height as usize], BlockRange { from, to: height }),
+            }
+        } else {
+            let prev_log_index = next - 1;
+            let entries = (next..=self.last_index())
+                .take(MAX_ENTRIES_PER_MESSAGE)
+                .filter_map(|index| self.entry(index).cloned())
+                .collect();
+            RaftMessage::AppendEntries {
+                term: self.state.term,
+                leader: self.id.clone(),
+                prev_log_index,
+                prev_log_term: self.term_at(prev_log_index).unwrap_or_default(),
+                entries,
+                leader_commit: self.commit_index,
+            }
+        };
+        out.push(Output::Send { to: peer.to_string(), message });
+    }
+
+    /// Store the leader's entries after `prev_log_index`, returning whether the
+    /// logs matched and the last index now shared with the leader
+    fn append_entries(
+        &mut self,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        out: &mut Vec<Output>,
+    ) -> (bool, u64) {
+        if prev_log_index > self.last_index() {
+            return (false, self.last_index());
+        }
+        // Entries up to the snapshot are committed, so they match the leader's
+        let skip = self.state.snapshot_index.saturating_sub(prev_log_index);
+        if skip == 0 && self.term_at(prev_log_index) != Some(prev_log_term) {
+            return (false, prev_log_index.saturating_sub(1).max(self.commit_index));
+        }
+        let mut changed = false;
+        let mut index = prev_log_index;
+        for entry in entries {
+            index += 1;
+            if index <= self.state.snapshot_index {
+                continue;
+            }
+            match self.term_at(index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    self.state.log.truncate((index - self.state.snapshot_index - 1) as usize);
+                    self.state.log.push(entry);
+                }
+                None => self.state.log.push(entry),
+            }
+            changed = true;
+        }
+        if changed {
+            out.push(Output::Persist);
+        }
+        (true, index.max(self.state.snapshot_index))
+    }
+
+    /// Extend the chain with the leader's snapshot blocks and, once it holds
+    /// the whole snapshot, drop the log entries it covers. Returns the answer
+    /// to the leader: the height reached while blocks are missing, else
+    /// whether the snapshot was installed.
+    fn install_snapshot(
+        &mut self,
+        chain: &mut Blockchain,
+        last_index: u64,
+        last_term: u64,
+        height: u64,
+        blocks: Vec<Block>,
+        out: &mut Vec<Output>,
+    ) -> RaftMessage {
+        let response = |node: &Self, match_index: u64| RaftMessage::AppendResponse {
+            term: node.state.term,
+            from: node.id.clone(),
+            success: match_index > 0,
+            match_index,
+        };
+        if last_index <= self.commit_index {
+            return response(self, self.commit_index);
+        }
+        // Only committed blocks are in any chain, so the snapshot extends ours
+        let mut appended = None;
+        for block in blocks {
+            let index = block.index;
+            if index < chain.chain.len() as u64 {
+                continue;
+            }
+            if index > chain.chain.len() as u64 {
+                break;
+            }
+            if let Err(e) = chain.append_block(block) {
+                eprintln!("Refusing snapshot from {}: {}", self.leader.as_deref().unwrap_or("?"), e);
+                return response(self, 0);
+            }
+            appended = Some(index);
+        }
+        if let Some(height) = appended {
+            out.push(Output::Applied { height });
+        }
+        let held = chain.chain.len() as u64;
+        if held < height {
+            return RaftMessage::SnapshotResponse { term: self.state.term, from: self.id.clone(), height: held };
+        }
+        // Keep the entries that follow the snapshot if they agree with it
+        if self.term_at(last_index) == Some(last_term) {
+            self.state.log.drain(..(last_index - self.state.snapshot_index) as usize);
+        } else {
+            self.state.log.clear();
+        }
+        self.state.snapshot_index = last_index;
+        self.state.snapshot_term = last_term;
+        self.state.snapshot_height = height;
+        self.commit_index = last_index;
+        self.last_applied = last_index;
+        out.push(Output::Persist);
+        response(self, last_index)
+    }
+
+    /// Commit the entries a majority stored, once one of them is from the current term
+    fn advance_commit(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) {
+        let mut matched: Vec<u64> = self.match_index.values().copied().collect();
+        matched.push(self.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let replicated = matched[self.majority() - 1];
+        if replicated > self.commit_index && self.term_at(replicated) == Some(self.state.term) {
+            self.commit_to(chain, replicated, out);
+        }
+    }
+
+    /// Advance the commit index and append the newly committed blocks to the
+    /// chain. A block that cannot be appended, e.g. because the store failed,
+    /// stops the applying: it and the entries after it are retried on the next
+    /// commit, and the leader proposes nothing until they are applied.
+    fn commit_to(&mut self, chain: &mut Blockchain, index: u64, out: &mut Vec<Output>) {
+        if index <= self.commit_index {
+            return;
+        }
+        self.commit_index = index.min(self.last_index());
+        self.apply_committed(chain, out);
+        self.compact(chain, out);
+        if self.role == Role::Leader {
+            self.propose(chain, out);
+        }
+    }
+
+    /// Append the committed blocks not yet applied, stopping at the first that fails
+    fn apply_committed(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) {
+        while self.last_applied < self.commit_index {
+            let next = self.last_applied + 1;
+            let block = self.entry(next).and_then(|entry| entry.block.clone());
+            // Blocks applied before a restart are already in the chain
+            if let Some(block) = block.filter(|block| block.index >= chain.chain.len() as u64) {
+                let height = block.index;
+                if let Err(e) = chain.append_block(block) {
+                    eprintln!("Failed to apply committed block {}, holding back the entries after it: {}", height, e);
+                    return;
+                }
+                out.push(Output::Applied { height });
+            }
+            self.last_applied = next;
+        }
+    }
+
+    /// Fold the applied prefix of the log into the snapshot once it grows too long
+    fn compact(&mut self, chain: &Blockchain, out: &mut Vec<Output>) {
+        let applied = (self.last_applied - self.state.snapshot_index) as usize;
+        if applied <= self.config.snapshot_threshold {
+            return;
+        }
+        self.state.snapshot_term = self.term_at(self.last_applied).unwrap_or_default();
+        self.state.log.drain(..applied);
+        self.state.snapshot_index = self.last_applied;
+        self.state.snapshot_height = chain.chain.len() as u64;
+        out.push(Output::Persist);
+    }
+
+    /// Append a block of the pending transactions to the log, once every
+    /// earlier entry is applied so the block extends the tip of the chain.
+    /// Transactions that cannot go in the next block are dropped one by one.
+    fn propose(&mut self, chain: &mut Blockchain, out: &mut Vec<Output>) {
+        let idle = self.pending.transactions.is_empty() && self.pending.registry_txs.is_empty();
+        if idle || self.last_applied < self.last_index() {
+            return;
+        }
+        let mut pending = std::mem::take(&mut self.pending);
+        let mut ids = HashSet::new();
+        pending.transactions.retain(|tx| match chain.check_transaction(tx) {
+            Ok(()) => ids.insert(tx.id()),
+            Err(e) => {
+                eprintln!("Dropping submitted prescription: {}", e);
+                false
+            }
+        });
+        pending.registry_txs.retain(|tx| match chain.check_registry_transaction(tx) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Dropping submitted registry transaction: {}", e);
+                false
+            }
+        });
+        if pending.transactions.is_empty() && pending.registry_txs.is_empty() {
+            return;
+        }
+        let block = match chain.propose_block(pending.transactions, pending.registry_txs, 0) {
+            Ok(block) => block,
+            Err(e) => {
+                eprintln!("Cannot propose a block: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = chain.validate_proposal(&block, 0) {
+            eprintln!("Dropping submitted transactions that do not make a valid block: {}", e);
+            return;
+        }
+        self.state.log.push(LogEntry { term: self.state.term, block: Some(block) });
+        out.push(Output::Persist);
+        self.advance_commit(chain, out);
+        self.replicate(chain, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use securerx_core::crypto::generate_keypair;
+    use securerx_core::genesis::{GenesisDoctor, GenesisSpec};
+    use securerx_core::test_support::prescription;
+    use std::collections::VecDeque;
+
+    struct SimNode {
+        chain: Blockchain,
+        raft: RaftNode,
+        online: bool,
+        /// State as of the last `Persist` output
+        persisted: PersistentState,
+    }
+
+    /// In-process cluster delivering messages in order between ticks
+    struct SimCluster {
+        nodes: Vec<SimNode>,
+        messages: VecDeque<(usize, RaftMessage)>,
+        genesis: GenesisSpec,
+        key: SigningKey,
+        config: RaftConfig,
+        /// Number of blocks in each `InstallSnapshot` sent
+        snapshot_chunks: Vec<usize>,
+    }
+
+    impl SimCluster {
+        fn new(n: usize, config: RaftConfig) -> (Self, SigningKey) {
+            let key = generate_keypair();
+            let doctor = generate_keypair();
+            let mut genesis = GenesisSpec::with_authorities(vec![key.verifying_key().to_bytes().to_vec()]);
+            genesis.doctors.push(GenesisDoctor {
+                doctor_id: "doctor1".to_string(),
+                license_number: "LIC-1".to_string(),
+                pubkey: doctor.verifying_key().to_bytes().to_vec(),
+            });
+            let mut cluster = Self { nodes: vec![], messages: VecDeque::new(), genesis, key, config, snapshot_chunks: vec![] };
+            for i in 0..n {
+                let chain = cluster.new_chain();
+                let raft = cluster.new_raft(i, n, PersistentState::default());
+                cluster.nodes.push(SimNode { chain, raft, online: true, persisted: PersistentState::default() });
+            }
+            (cluster, doctor)
+        }
+
+        fn new_chain(&self) -> Blockchain {
+            let mut chain = Blockchain::from_genesis(self.genesis.clone()).unwrap();
+            chain.set_validator_key(self.key.clone());
+            chain
+        }
+
+        fn new_raft(&self, i: usize, n: usize, state: PersistentState) -> RaftNode {
+            let peers = (0..n).filter(|j| *j != i).map(|j| format!("n{}", j)).collect();
+            RaftNode::new(format!("n{}", i), peers, self.config.clone(), state, 7919 * (i as u64 + 1))
+        }
+
+        fn dispatch(&mut self, from: usize, outputs: Vec<Output>) {
+            for output in outputs {
+                match output {
+                    Output::Send { to, message } => {
+                        if let RaftMessage::InstallSnapshot { blocks, .. } = &message {
+                            self.snapshot_chunks.push(blocks.len());
+                        }
+                        let to = to[1..].parse().unwrap();
+                        self.messages.push_back((to, message));
+                    }
+                    Output::Persist => self.nodes[from].persisted = self.nodes[from].raft.persistent_state().clone(),
+                    Output::Applied { .. } => {}
+                }
+            }
+        }
+
+        /// Deliver every message in flight, then tick every node
+        fn step(&mut self) {
+            while let Some((to, message)) = self.messages.pop_front() {
+                let node = &mut self.nodes[to];
+                if node.online {
+                    let outputs = node.raft.handle(&mut node.chain, message);
+                    self.dispatch(to, outputs);
+                }
+            }
+            for i in 0..self.nodes.len() {
+                let node = &mut self.nodes[i];
+                if node.online {
+                    let outputs = node.raft.tick(&mut node.chain);
+                    self.dispatch(i, outputs);
+                }
+            }
+        }
+
+        fn run_until(&mut self, done: impl Fn(&Self) -> bool) {
+            for _ in 0..1000 {
+                if done(self) {
+                    return;
+                }
+                self.step();
+            }
+            panic!("cluster made no progress");
+        }
+
+        fn leader(&self) -> Option<usize> {
+            self.nodes.iter().position(|n| n.online && n.raft.role() == Role::Leader)
+        }
+
+        fn submit(&mut self, tx: Transaction) {
+            let leader = self.leader().expect("a leader is elected");
+            let node = &mut self.nodes[leader];
+            let submission = Submission { transactions: vec![tx], registry_txs: vec![] };
+            let outputs = node.raft.submit(&mut node.chain, submission).unwrap();
+            self.dispatch(leader, outputs);
+        }
+
+        /// Lowest chain length among the online nodes
+        fn height(&self) -> usize {
+            self.nodes.iter().filter(|n| n.online).map(|n| n.chain.chain.len()).min().unwrap()
+        }
+
+        fn assert_same_chains(&self) {
+            let expected: Vec<_> = self.nodes[0].chain.chain.iter().map(Block::calculate_hash).collect();
+            for node in &self.nodes {
+                let hashes: Vec<_> = node.chain.chain.iter().map(Block::calculate_hash).collect();
+                assert_eq!(hashes, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_elects_one_leader_and_replicates_blocks() {
+        let (mut cluster, doctor) = SimCluster::new(3, RaftConfig::default());
+        cluster.run_until(|c| c.leader().is_some());
+        let leader = cluster.leader().unwrap();
+        let term = cluster.nodes[leader].raft.term();
+        assert_eq!(cluster.nodes.iter().filter(|n| n.raft.role() == Role::Leader).count(), 1);
+
+        for i in 0..3 {
+            cluster.submit(prescription(&doctor, &format!("patient{}", i)));
+            let height = cluster.nodes[leader].chain.chain.len();
+            cluster.run_until(|c| c.height() > height);
+        }
+        cluster.run_until(|c| c.height() == 4);
+        cluster.assert_same_chains();
+        for node in &cluster.nodes {
+            assert_eq!(node.chain.validate_chain(), Ok(()));
+            assert_eq!(node.raft.leader(), Some(format!("n{}", leader).as_str()));
+        }
+        assert_eq!(cluster.nodes[leader].raft.term(), term, "A healthy leader keeps its term");
+    }
+
+    #[test]
+    fn test_followers_redirect_to_the_leader() {
+        let (mut cluster, doctor) = SimCluster::new(3, RaftConfig::default());
+        cluster.run_until(|c| c.leader().is_some() && c.nodes.iter().all(|n| n.raft.leader().is_some()));
+        let leader = cluster.leader().unwrap();
+        let follower = (leader + 1) % 3;
+
+        let node = &mut cluster.nodes[follower];
+        let submission = Submission { transactions: vec![prescription(&doctor, "patient1")], registry_txs: vec![] };
+        let err = node.raft.submit(&mut node.chain, submission).unwrap_err();
+        assert_eq!(err.leader, Some(format!("n{}", leader)));
+        assert_eq!(node.chain.chain.len(), 1);
+    }
+
+    #[test]
+    fn test_new_leader_after_crash_keeps_committed_blocks() {
+        let (mut cluster, doctor) = SimCluster::new(3, RaftConfig::default());
+        cluster.run_until(|c| c.leader().is_some());
+        cluster.submit(prescription(&doctor, "patient1"));
+        cluster.run_until(|c| c.height() == 2);
+
+        let old_leader = cluster.leader().unwrap();
+        let committed = cluster.nodes[old_leader].chain.chain[1].calculate_hash();
+        cluster.nodes[old_leader].online = false;
+        cluster.run_until(|c| c.leader().is_some());
+        assert_ne!(cluster.leader(), Some(old_leader));
+
+        cluster.submit(prescription(&doctor, "patient2"));
+        cluster.run_until(|c| c.height() == 3);
+        for node in cluster.nodes.iter().filter(|n| n.online) {
+            assert_eq!(node.chain.chain[1].calculate_hash(), committed);
+        }
+
+        // The old leader rejoins as a follower and catches up
+        cluster.nodes[old_leader].online = true;
+        cluster.run_until(|c| c.nodes[old_leader].chain.chain.len() == 3);
+        assert_eq!(cluster.nodes[old_leader].raft.role(), Role::Follower);
+        cluster.assert_same_chains();
+    }
+
+    #[test]
+    fn test_lagging_follower_installs_snapshot() {
+        let config = RaftConfig { snapshot_threshold: 2, ..RaftConfig::default() };
+        let (mut cluster, doctor) = SimCluster::new(3, config);
+        cluster.run_until(|c| c.leader().is_some());
+        let leader = cluster.leader().unwrap();
+        let lagging = (leader + 1) % 3;
+        cluster.nodes[lagging].online = false;
+
+        for i in 0..5 {
+            cluster.submit(prescription(&doctor, &format!("patient{}", i)));
+            let height = cluster.nodes[leader].chain.chain.len();
+            cluster.run_until(|c| c.nodes[leader].chain.chain.len() > height);
+        }
+        let state = cluster.nodes[leader].raft.persistent_state();
+        assert!(state.snapshot_index > 0, "The log was compacted");
+        assert!(state.log.len() <= 3);
+
+        cluster.nodes[lagging].online = true;
+        cluster.run_until(|c| c.nodes[lagging].chain.chain.len() == 6);
+        cluster.assert_same_chains();
+        assert!(cluster.nodes[lagging].raft.persistent_state().snapshot_index > 0);
+    }
+
+    #[test]
+    fn test_long_snapshots_are_sent_in_ranges() {
+        let config = RaftConfig { snapshot_threshold: 2, ..RaftConfig::default() };
+        let (mut cluster, doctor) = SimCluster::new(3, config);
+        cluster.run_until(|c| c.leader().is_some());
+        let leader = cluster.leader().unwrap();
+        let lagging = (leader + 1) % 3;
+        cluster.nodes[lagging].online = false;
+
+        let blocks = sync::MAX_BLOCKS_PER_REQUEST as usize + 10;
+        for i in 0..blocks {
+            cluster.submit(prescription(&doctor, &format!("patient{}", i)));
+            let height = cluster.nodes[leader].chain.chain.len();
+            cluster.run_until(|c| c.nodes[leader].chain.chain.len() > height);
+        }
+        cluster.snapshot_chunks.clear();
+
+        cluster.nodes[lagging].online = true;
+        cluster.run_until(|c| c.nodes[lagging].chain.chain.len() == blocks + 1);
+        cluster.assert_same_chains();
+        let chunks = &cluster.snapshot_chunks;
+        assert!(chunks.iter().all(|len| *len <= sync::MAX_BLOCKS_PER_REQUEST as usize));
+        assert!(chunks.iter().filter(|len| **len > 0).count() >= 2, "The snapshot takes more than one message");
+    }
+
+    #[test]
+    fn test_restart_resumes_from_persistent_state() {
+        let (mut cluster, doctor) = SimCluster::new(3, RaftConfig::default());
+        cluster.run_until(|c| c.leader().is_some());
+        cluster.submit(prescription(&doctor, "patient1"));
+        cluster.run_until(|c| c.height() == 2);
+
+        // Restart a follower with its persisted Raft state and chain, e.g. from its block store
+        let follower = (cluster.leader().unwrap() + 1) % 3;
+        let persisted = cluster.nodes[follower].persisted.clone();
+        let term = persisted.term;
+        assert!(!persisted.log.is_empty());
+        cluster.nodes[follower].raft = cluster.new_raft(follower, 3, persisted);
+        assert_eq!(cluster.nodes[follower].raft.term(), term);
+
+        cluster.submit(prescription(&doctor, "patient2"));
+        cluster.run_until(|c| c.height() == 3);
+        cluster.assert_same_chains();
+        assert_eq!(cluster.nodes[follower].chain.validate_chain(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_submissions_are_dropped_one_by_one() {
+        let (mut cluster, doctor) = SimCluster::new(3, RaftConfig::default());
+        cluster.run_until(|c| c.leader().is_some());
+        let leader = cluster.leader().unwrap();
+
+        let valid = prescription(&doctor, "patient1");
+        let unregistered = prescription(&generate_keypair(), "patient2");
+        let node = &mut cluster.nodes[leader];
+        let submission = Submission { transactions: vec![unregistered, valid.clone(), valid.clone()], registry_txs: vec![] };
+        let outputs = node.raft.submit(&mut node.chain, submission).unwrap();
+        cluster.dispatch(leader, outputs);
+        cluster.run_until(|c| c.height() == 2);
+
+        let block = &cluster.nodes[0].chain.chain[1];
+        assert_eq!(block.transactions.iter().map(Transaction::id).collect::<Vec<_>>(), vec![valid.id()]);
+        cluster.assert_same_chains();
+    }
+
+    #[test]
+    fn test_failed_apply_holds_back_later_entries() {
+        let (mut cluster, doctor) = SimCluster::new(3, RaftConfig::default());
+        cluster.run_until(|c| c.leader().is_some());
+        let follower = (cluster.leader().unwrap() + 1) % 3;
+
+        // A chain from another genesis cannot take the committed blocks
+        let mut other = cluster.genesis.clone();
+        other.chain_id = "securerx-other".to_string();
+        cluster.nodes[follower].chain = Blockchain::from_genesis(other).unwrap();
+        cluster.submit(prescription(&doctor, "patient1"));
+        cluster.run_until(|c| c.nodes.iter().all(|n| n.raft.commit_index() >= 2));
+        assert_eq!(cluster.nodes[follower].chain.chain.len(), 1);
+        assert!(cluster.nodes[follower].raft.last_applied < 2, "The failed block must not count as applied");
+
+        // Once the chain can take them, the held back blocks are applied with the next commit
+        cluster.nodes[follower].chain = cluster.new_chain();
+        cluster.submit(prescription(&doctor, "patient2"));
+        cluster.run_until(|c| c.nodes[follower].chain.chain.len() == 3);
+        cluster.assert_same_chains();
+    }
+
+    #[test]
+    fn test_persistent_state_is_saved_and_loaded() {
+        let dir = std::env::temp_dir().join(format!("securerx-raft-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("raft.json");
+        assert_eq!(PersistentState::load(&path).unwrap().term, 0, "A missing file is the initial state");
+
+        let state = PersistentState { term: 3, voted_for: Some("n1".to_string()), ..PersistentState::default() };
+        state.save(&path).unwrap();
+        let loaded = PersistentState::load(&path).unwrap();
+        assert_eq!((loaded.term, loaded.voted_for), (3, Some("n1".to_string())));
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
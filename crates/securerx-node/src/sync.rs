@@ -0,0 +1,167 @@
+//! Headers-first block sync between nodes.
+//!
+//! A node syncs from a peer that greets it with a higher best height, and
+//! every catch-up round from each connected peer whose tip it does not hold.
+//! It sends the peer a locator, hashes of its own chain from the tip back to
+//! genesis at doubling distances, and gets the headers after the last block
+//! both share, in batches of at most [`MAX_HEADERS`]. Once the headers link up
+//! and are sealed by validators, the blocks are fetched in ranges of at most
+//! [`MAX_BLOCKS_PER_REQUEST`] (and [`MAX_BLOCKS_BYTES`]) from every peer that advertised the same tip,
+//! [`PARALLEL_REQUESTS`] at a time, and imported through the block tree. The
+//! requests travel over the P2P transport, see [`crate::p2p`].
+
+use crate::p2p::MAX_FRAME_SIZE;
+use securerx_core::block::{Block, BlockHeader};
+use serde::{Deserialize, Serialize};
+
+/// Most headers served per request
+pub const MAX_HEADERS: u64 = 500;
+
+/// Most blocks served per range request
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 50;
+
+/// Most bytes of encoded blocks served per range request, leaving room in
+/// the frame for the rest of the response
+pub const MAX_BLOCKS_BYTES: usize = MAX_FRAME_SIZE / 2;
+
+/// Range requests a syncing node keeps in flight
+pub const PARALLEL_REQUESTS: usize = 4;
+
+/// Height and hash of the last block of a node's chain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChainTip {
+    pub height: u64,
+    pub hash: String,
+}
+
+/// Headers request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeadersRequest {
+    /// Hashes of the requester's chain, tip first, see [`locator`]
+    pub locator: Vec<String>,
+    /// Most headers wanted; capped at [`MAX_HEADERS`]
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+/// Blocks request: blocks `from` up to but excluding `to`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockRange {
+    pub from: u64,
+    pub to: u64,
+}
+
+/// Tip of `chain`
+pub fn tip(chain: &[Block]) -> ChainTip {
+    let block = chain.last().expect("a chain holds at least its genesis block");
+    ChainTip { height: block.index, hash: block.calculate_hash() }
+}
+
+/// Hashes of the blocks of `chain` at the tip, then 1, 2, 4, 8, ... blocks
+/// below it, ending with genesis
+pub fn locator(chain: &[Block]) -> Vec<String> {
+    let mut hashes = vec![];
+    let mut height = chain.len() - 1;
+    let mut step = 1;
+    while height > 0 {
+        hashes.push(chain[height].calculate_hash());
+        height = height.saturating_sub(step);
+        if hashes.len() > 1 {
+            step *= 2;
+        }
+    }
+    hashes.push(chain[0].calculate_hash());
+    hashes
+}
+
+/// Headers of `chain` after the first block of `request.locator` it holds,
+/// or after genesis if it holds none of them. `height_of` looks a hash up in
+/// `chain`, see [`Blockchain::height_of`](securerx_core::blockchain::Blockchain::height_of).
+pub fn headers_after(chain: &[Block], request: &HeadersRequest, height_of: impl Fn(&str) -> Option<u64>) -> Vec<BlockHeader> {
+    let fork_height = request.locator.iter().find_map(|hash| height_of(hash)).unwrap_or(0) as usize;
+    let limit = request.limit.unwrap_or(MAX_HEADERS).min(MAX_HEADERS) as usize;
+    chain.iter().skip(fork_height + 1).take(limit).map(Block::header).collect()
+}
+
+/// Blocks of `chain` in `range`, at most [`MAX_BLOCKS_PER_REQUEST`] of them
+/// and, past the first, no more than [`MAX_BLOCKS_BYTES`] once encoded
+pub fn block_range(chain: &[Block], range: BlockRange) -> Vec<Block> {
+    let to = range.to.min(range.from.saturating_add(MAX_BLOCKS_PER_REQUEST)).min(chain.len() as u64);
+    let mut size = 0;
+    let mut blocks = vec![];
+    for block in chain.get(range.from as usize..to as usize).unwrap_or_default() {
+        size += serde_json::to_vec(block).map_or(usize::MAX, |encoded| encoded.len());
+        if size > MAX_BLOCKS_BYTES && !blocks.is_empty() {
+            break;
+        }
+        blocks.push(block.clone());
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use securerx_core::blockchain::Blockchain;
+    use securerx_core::crypto::generate_keypair;
+
+    /// Height of the block of `chain` with hash `hash`, found by a scan
+    fn scan(chain: &[Block]) -> impl Fn(&str) -> Option<u64> + '_ {
+        |hash| chain.iter().position(|block| block.calculate_hash() == hash).map(|height| height as u64)
+    }
+
+    fn chain(blocks: usize) -> Vec<Block> {
+        let validator = generate_keypair();
+        let mut blockchain = Blockchain::with_admins(vec![validator.verifying_key().to_bytes().to_vec()]);
+        blockchain.set_validator_key(validator);
+        for _ in 0..blocks {
+            blockchain.add_block(vec![]).unwrap();
+        }
+        blockchain.chain
+    }
+
+    #[test]
+    fn test_locator_spacing() {
+        let chain = chain(20);
+        let heights: Vec<usize> = locator(&chain)
+            .iter()
+            .map(|hash| chain.iter().position(|block| &block.calculate_hash() == hash).unwrap())
+            .collect();
+        assert_eq!(heights, vec![20, 19, 18, 16, 12, 4, 0]);
+        assert_eq!(locator(&chain[..1]), vec![chain[0].calculate_hash()]);
+    }
+
+    #[test]
+    fn test_headers_after_locator() {
+        let ours = chain(5);
+        let request = |locator: Vec<String>, limit| HeadersRequest { locator, limit };
+
+        let headers = headers_after(&ours, &request(locator(&ours[..3]), None), scan(&ours));
+        assert_eq!(headers.iter().map(|h| h.index).collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert_eq!(headers[0].prev_hash, ours[2].calculate_hash());
+        assert!(headers_after(&ours, &request(locator(&ours), None), scan(&ours)).is_empty(), "Nothing follows our tip");
+        assert_eq!(headers_after(&ours, &request(vec![], Some(2)), scan(&ours)).len(), 2);
+        assert_eq!(headers_after(&ours, &request(locator(&chain(3)), None), scan(&ours)).len(), 5, "Unknown locators start at genesis");
+    }
+
+    #[test]
+    fn test_block_range_is_capped() {
+        let chain = chain(60);
+        assert_eq!(block_range(&chain, BlockRange { from: 1, to: 4 }).len(), 3);
+        assert_eq!(block_range(&chain, BlockRange { from: 0, to: 1000 }).len(), MAX_BLOCKS_PER_REQUEST as usize);
+        assert!(block_range(&chain, BlockRange { from: 70, to: 80 }).is_empty());
+        assert!(block_range(&chain, BlockRange { from: 5, to: 2 }).is_empty());
+    }
+
+    #[test]
+    fn test_block_range_is_capped_by_size() {
+        let mut chain = chain(3);
+        for block in &mut chain {
+            block.signature = vec![0; MAX_BLOCKS_BYTES / 4];
+        }
+        // Each zero signature byte takes two characters ("0,") once encoded
+        assert_eq!(block_range(&chain, BlockRange { from: 0, to: 3 }).len(), 1);
+        chain[0].signature = vec![0; MAX_BLOCKS_BYTES];
+        assert_eq!(block_range(&chain, BlockRange { from: 0, to: 3 }).len(), 1, "An oversized first block is still served");
+    }
+}
@@ -0,0 +1,33 @@
+//! Fixtures shared by the node's tests.
+
+use crate::config::{ConsensusMode, ForkChoice, NodeConfig};
+use securerx_core::crypto::generate_keypair;
+use securerx_core::genesis::GenesisSpec;
+use securerx_core::mempool::{DEFAULT_BLOCK_INTERVAL, DEFAULT_MAX_BLOCK_TXS, DEFAULT_MEMPOOL_SIZE};
+use securerx_core::storage::StorageBackend;
+
+/// Configuration of a Proof-of-Authority follower node `name` of `genesis`,
+/// with an empty data directory of its own, a new node key and no peers
+pub fn config(name: &str, genesis: GenesisSpec) -> NodeConfig {
+    let dir = std::env::temp_dir().join(format!("securerx-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    NodeConfig {
+        node_id: name.to_string(),
+        data_dir: dir.to_string_lossy().into_owned(),
+        storage_backend: StorageBackend::File,
+        api_addr: "127.0.0.1:0".to_string(),
+        peers: vec![],
+        p2p_addr: "127.0.0.1:0".to_string(),
+        p2p_peers: vec![],
+        node_key: generate_keypair(),
+        peer_keys: vec![],
+        genesis,
+        validator_key: None,
+        consensus: ConsensusMode::Poa,
+        fork_choice: ForkChoice::Heaviest,
+        mempool_size: DEFAULT_MEMPOOL_SIZE,
+        max_block_txs: DEFAULT_MAX_BLOCK_TXS,
+        block_interval: DEFAULT_BLOCK_INTERVAL,
+        admin_token: None,
+    }
+}
@@ -2,15 +2,52 @@
 /// This test validates that multiple nodes can reach consensus on the blockchain state
 
 use securerx_core::blockchain::Blockchain;
-use securerx_core::transaction::Transaction;
-use securerx_core::crypto::{generate_keypair, sign_message};
+use securerx_core::transaction::{Transaction, DEFAULT_CHAIN_ID};
+use securerx_core::crypto::generate_keypair;
+use securerx_core::registry::{RegistryOp, RegistryTransaction};
+use ed25519_dalek::SigningKey;
+
+fn register(admin: &SigningKey, doctor_id: &str, doctor: &SigningKey) -> RegistryTransaction {
+    let mut tx = RegistryTransaction {
+        chain_id: DEFAULT_CHAIN_ID.to_string(),
+        op: RegistryOp::RegisterDoctor {
+            doctor_id: doctor_id.to_string(),
+            license_number: format!("LIC-{}", doctor_id),
+            pubkey: doctor.verifying_key().to_bytes().to_vec(),
+        },
+        nonce: 1,
+        admin_pubkey: vec![],
+        signature: vec![],
+    };
+    tx.sign(admin);
+    tx
+}
+
+fn signed_tx(keypair: &SigningKey, doctor_id: &str, patient_id: &str, drug: &str) -> Transaction {
+    let mut tx = Transaction {
+        chain_id: DEFAULT_CHAIN_ID.to_string(),
+        doctor_id: doctor_id.to_string(),
+        patient_id: patient_id.to_string(),
+        drug: drug.to_string(),
+        dosage: "1 tablet daily".to_string(),
+        issued_at: 1_700_000_000,
+        expires_at: 1_702_592_000,
+        nonce: 1,
+        signature: vec![],
+        pubkey: vec![],
+    };
+    tx.sign(keypair);
+    tx
+}
 
 #[test]
 fn test_consensus_validation() {
-    // Simulate three nodes starting with empty blockchains
-    let mut node1 = Blockchain::new();
-    let mut node2 = Blockchain::new();
-    let mut node3 = Blockchain::new();
+    // Simulate three nodes starting with empty blockchains and the same registry admin
+    let admin = generate_keypair();
+    let admin_keys = vec![admin.verifying_key().to_bytes().to_vec()];
+    let mut node1 = Blockchain::with_admins(admin_keys.clone());
+    let mut node2 = Blockchain::with_admins(admin_keys.clone());
+    let mut node3 = Blockchain::with_admins(admin_keys);
 
     // All nodes should start with the same genesis block
     assert_eq!(node1.chain.len(), 1);
@@ -20,34 +57,30 @@ fn test_consensus_validation() {
     assert_eq!(node2.chain[0].index, 0);
     assert_eq!(node3.chain[0].index, 0);
 
-    // Node 1 adds a transaction
+    // Node 1 is the only validator: it registers a doctor and adds a transaction
+    node1.set_validator_key(admin.clone());
     let keypair1 = generate_keypair();
-    let sig1 = sign_message(&keypair1, b"Aspirin");
-    let tx1 = Transaction {
-        doctor_id: "doctor1".to_string(),
-        patient_id: "patient1".to_string(),
-        drug: "Aspirin".to_string(),
-        signature: sig1.to_bytes().to_vec(),
-        pubkey: keypair1.public.to_bytes().to_vec(),
-    };
-    node1.add_block(vec![tx1.clone()]);
+    node1.add_registry_block(vec![register(&admin, "doctor1", &keypair1)]).unwrap();
+    let tx1 = signed_tx(&keypair1, "doctor1", "patient1", "Aspirin");
+    node1.add_block(vec![tx1.clone()]).unwrap();
 
     // Simulate gossip: nodes sync with node1
-    // In a real scenario, node1 would broadcast the new block
-    // Here we simulate by manually adding the same block to other nodes
-    let node1_block1 = node1.chain[1].clone();
-    node2.chain.push(node1_block1.clone());
-    node3.chain.push(node1_block1.clone());
+    // In a real scenario, node1 would broadcast the new blocks
+    // Here we simulate by manually adding the same blocks to other nodes
+    for block in &node1.chain[1..] {
+        node2.chain.push(block.clone());
+        node3.chain.push(block.clone());
+    }
 
     // All nodes should now have the same chain length
-    assert_eq!(node1.chain.len(), 2);
-    assert_eq!(node2.chain.len(), 2);
-    assert_eq!(node3.chain.len(), 2);
+    assert_eq!(node1.chain.len(), 3);
+    assert_eq!(node2.chain.len(), 3);
+    assert_eq!(node3.chain.len(), 3);
 
     // All nodes should validate the chain
-    assert!(node1.validate_chain(), "Node1 chain should be valid");
-    assert!(node2.validate_chain(), "Node2 chain should be valid");
-    assert!(node3.validate_chain(), "Node3 chain should be valid");
+    assert!(node1.validate_chain().is_ok(), "Node1 chain should be valid");
+    assert!(node2.validate_chain().is_ok(), "Node2 chain should be valid");
+    assert!(node3.validate_chain().is_ok(), "Node3 chain should be valid");
 
     // Verify consensus: all nodes have the same block hash
     assert_eq!(
@@ -64,35 +97,27 @@ fn test_consensus_validation() {
 
 #[test]
 fn test_chain_validation_integrity() {
-    let mut blockchain = Blockchain::new();
+    let admin = generate_keypair();
+    let mut blockchain = Blockchain::with_admins(vec![admin.verifying_key().to_bytes().to_vec()]);
+    blockchain.set_validator_key(admin.clone());
     
-    // Add multiple valid transactions
+    // Register two doctors and add multiple valid transactions
     let keypair1 = generate_keypair();
     let keypair2 = generate_keypair();
+    blockchain.add_registry_block(vec![
+        register(&admin, "doctor1", &keypair1),
+        register(&admin, "doctor2", &keypair2),
+    ]).unwrap();
     
-    let sig1 = sign_message(&keypair1, b"Aspirin");
-    let tx1 = Transaction {
-        doctor_id: "doctor1".to_string(),
-        patient_id: "patient1".to_string(),
-        drug: "Aspirin".to_string(),
-        signature: sig1.to_bytes().to_vec(),
-        pubkey: keypair1.public.to_bytes().to_vec(),
-    };
+    let tx1 = signed_tx(&keypair1, "doctor1", "patient1", "Aspirin");
     
-    let sig2 = sign_message(&keypair2, b"Ibuprofen");
-    let tx2 = Transaction {
-        doctor_id: "doctor2".to_string(),
-        patient_id: "patient2".to_string(),
-        drug: "Ibuprofen".to_string(),
-        signature: sig2.to_bytes().to_vec(),
-        pubkey: keypair2.public.to_bytes().to_vec(),
-    };
+    let tx2 = signed_tx(&keypair2, "doctor2", "patient2", "Ibuprofen");
 
-    blockchain.add_block(vec![tx1]);
-    blockchain.add_block(vec![tx2]);
+    blockchain.add_block(vec![tx1]).unwrap();
+    blockchain.add_block(vec![tx2]).unwrap();
 
     // Chain should be valid
-    assert!(blockchain.validate_chain(), "Multi-block chain should be valid");
-    assert_eq!(blockchain.chain.len(), 3, "Chain should have 3 blocks (genesis + 2)");
+    assert!(blockchain.validate_chain().is_ok(), "Multi-block chain should be valid");
+    assert_eq!(blockchain.chain.len(), 4, "Chain should have 4 blocks (genesis + registry + 2)");
 }
 